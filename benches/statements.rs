@@ -0,0 +1,85 @@
+// times storing bursts of ops with and without reusing a prepared statement, against a database of
+// its own like the integration tests use (see tests/common); skipped if there's no postgres server
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::TestServer;
+use serde_json::json;
+
+/// Ops stored in each burst.
+const OPS: u32 = 100;
+
+const BURSTS: u32 = 10;
+
+// the same insert operation_service::add makes
+const INSERT_OPERATION: &str = "INSERT INTO
+     operation(
+         checkpoint_id,
+         jsonbval
+     )
+     VALUES($1, $2)
+     RETURNING operation_id, creation_time
+    ";
+
+#[tokio::main]
+async fn main() {
+    let Some(server) = TestServer::start(&[]).await else {
+        return;
+    };
+    let client = server.connect_database().await;
+
+    let checkpoint_id: i64 = client
+        .query_one(
+            "INSERT INTO checkpoint(creator_user_id, jsonval)
+             VALUES(1, '{\"live\":[],\"finished\":[]}')
+             RETURNING checkpoint_id",
+            &[],
+        )
+        .await
+        .unwrap()
+        .get(0);
+
+    let uncached = bench_op_inserts(&client, checkpoint_id, false).await;
+    let cached = bench_op_inserts(&client, checkpoint_id, true).await;
+    println!("uncached: {uncached:?} per op");
+    println!("cached: {cached:?} per op");
+}
+
+// the mean time it takes to store an op, over bursts stored in transactions
+async fn bench_op_inserts(
+    client: &tokio_postgres::Client,
+    checkpoint_id: i64,
+    cache: bool,
+) -> Duration {
+    let mut elapsed = Duration::ZERO;
+    for _ in 0..BURSTS {
+        client.batch_execute("BEGIN").await.unwrap();
+        let start = Instant::now();
+        // a query given as a string is prepared again every time it's run
+        let statement = if cache {
+            Some(client.prepare(INSERT_OPERATION).await.unwrap())
+        } else {
+            None
+        };
+        for i in 0..OPS {
+            let op = json!({
+                "alleged_time": 0,
+                "kind": { "InsLiveTask": { "id": format!("benchmark-{i}"), "value": "benchmark" } },
+            });
+            match statement {
+                Some(ref statement) => client.query_one(statement, &[&checkpoint_id, &op]).await,
+                None => {
+                    client
+                        .query_one(INSERT_OPERATION, &[&checkpoint_id, &op])
+                        .await
+                }
+            }
+            .unwrap();
+        }
+        elapsed += start.elapsed();
+        client.batch_execute("COMMIT").await.unwrap();
+    }
+    elapsed / (OPS * BURSTS)
+}
@@ -0,0 +1,24 @@
+-- initial schema, written so that databases created from 1-todoproxy.sql are left untouched
+
+create table if not exists checkpoint(
+  checkpoint_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null,
+  jsonval text not null
+);
+
+create or replace view recent_checkpoint_by_user_id as
+  select c.* from checkpoint c
+  inner join (
+    select max(checkpoint_id) id
+    from checkpoint
+    group by creator_user_id
+  ) maxids
+  on maxids.id = c.checkpoint_id;
+
+create table if not exists operation(
+  operation_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  checkpoint_id bigint not null references checkpoint(checkpoint_id),
+  jsonval text not null
+);
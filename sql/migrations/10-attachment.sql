@@ -0,0 +1,16 @@
+-- files attached to tasks; the objects themselves are in the attachment bucket
+
+create table if not exists attachment(
+  attachment_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null,
+  task_id text not null,
+  -- picked by the server, so it's unguessable
+  object_key text not null unique,
+  filename text not null,
+  content_type text not null,
+  -- in bytes; uploads must be exactly this long
+  size bigint not null
+);
+
+create index if not exists attachment_creator_user_id_task_id on attachment(creator_user_id, task_id);
@@ -0,0 +1,9 @@
+-- each user's settings, like their time zone
+
+create table if not exists preference(
+  preference_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null unique,
+  -- the user's Preferences; settings added later take their default until set
+  jsonval text not null
+);
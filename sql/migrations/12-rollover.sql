@@ -0,0 +1,24 @@
+-- the end of each user's day, when their finished list is moved into their history
+
+-- finished tasks that were rolled over, under the day they were finished on
+create table if not exists archived_task(
+  archived_task_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null,
+  -- the user's local date, yyyy-mm-dd
+  day text not null,
+  task_id text not null,
+  value text not null,
+  -- the json encoding of the TaskStatus
+  status_json text not null
+);
+
+create index if not exists archived_task_creator_user_id_day on archived_task(creator_user_id, day);
+
+-- the last day each user rolled over, so no day is rolled over twice
+create table if not exists rollover(
+  rollover_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null unique,
+  day text not null
+);
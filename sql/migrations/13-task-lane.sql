@@ -0,0 +1,11 @@
+-- the kanban lane each live task is in; tasks without a row are in the default lane
+-- rows outlive the task being live, so a restored task goes back to its lane
+
+create table if not exists task_lane(
+  task_lane_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null,
+  task_id text not null,
+  lane text not null,
+  unique(creator_user_id, task_id)
+);
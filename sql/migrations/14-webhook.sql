@@ -0,0 +1,14 @@
+-- tokens other services post to, to create live tasks without an api key
+
+create table if not exists webhook(
+  webhook_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null,
+  -- sha256 of the token, which is only shown once
+  token_hash text not null unique,
+  -- put in front of the tasks the webhook creates, so the user can tell where they came from
+  source text,
+  max_per_hour bigint not null
+);
+
+create index if not exists webhook_creator_user_id on webhook(creator_user_id);
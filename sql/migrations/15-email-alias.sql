@@ -0,0 +1,11 @@
+-- addresses users can email tasks to, as plus addresses of the gateway's mailbox
+
+create table if not exists email_alias(
+  email_alias_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null,
+  -- the part after the +, in lower case
+  alias text not null unique
+);
+
+create index if not exists email_alias_creator_user_id on email_alias(creator_user_id);
@@ -0,0 +1,32 @@
+-- repos whose issues assigned to the user are synced with their live tasks
+
+create table if not exists github_link(
+  github_link_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null,
+  -- owner/name
+  repo text not null,
+  -- a personal access token that can read and write the repo's issues
+  token text not null,
+  -- the github user the token belongs to, whose assigned issues are synced
+  login text not null,
+  -- whether finishing a task closes its issue, rather than commenting on it
+  close_issues boolean not null,
+  unique(creator_user_id, repo)
+);
+
+-- which live task stands for something in another service, so each is only made once
+-- rows are kept after the task is finished, until the thing itself goes away
+create table if not exists external_task(
+  external_task_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null,
+  -- the service, e.g. github
+  source text not null,
+  -- what the service calls it, e.g. owner/name#123
+  external_id text not null,
+  task_id text not null,
+  unique(creator_user_id, source, external_id)
+);
+
+create index if not exists external_task_creator_user_id_task_id on external_task(creator_user_id, task_id);
@@ -0,0 +1,18 @@
+-- frontends or orgs sharing the deployment; each has an admin who can use the admin api for its users
+
+create table if not exists tenant(
+  tenant_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  name text not null unique,
+  admin_user_id bigint not null unique
+);
+
+-- which tenant each user belongs to; users without a row belong to the deployment itself
+create table if not exists tenant_user(
+  tenant_user_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null unique,
+  tenant_id bigint not null references tenant(tenant_id)
+);
+
+create index if not exists tenant_user_tenant_id on tenant_user(tenant_id);
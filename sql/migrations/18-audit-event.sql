@@ -0,0 +1,17 @@
+-- changes made to each user's account, and where they came from, so users can look back over them
+
+create table if not exists audit_event(
+  audit_event_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null,
+  -- the op's type, e.g. InsLiveTask, or the change, e.g. webhook_new
+  kind text not null,
+  -- what was changed, e.g. the seq of the op or the repo that was linked
+  detail text,
+  -- the websocket connection or request the change came from
+  session_id text,
+  -- as reported by proxies, if there are any
+  source_ip text
+);
+
+create index if not exists audit_event_creator_user_id on audit_event(creator_user_id, audit_event_id);
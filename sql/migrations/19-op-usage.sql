@@ -0,0 +1,11 @@
+-- how many ops each user applied on each day, so daily quotas hold across instances and restarts
+
+create table if not exists op_usage(
+  op_usage_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null,
+  -- the utc date, yyyy-mm-dd
+  day text not null,
+  ops bigint not null,
+  unique (creator_user_id, day)
+);
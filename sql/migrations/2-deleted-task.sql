@@ -0,0 +1,12 @@
+-- soft deleted live tasks, kept so they can be restored until they're purged
+
+create table if not exists deleted_task(
+  deleted_task_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null,
+  task_id text not null,
+  value text not null
+);
+
+create index if not exists deleted_task_creator_user_id on deleted_task(creator_user_id, task_id);
+create index if not exists deleted_task_creation_time on deleted_task(creation_time);
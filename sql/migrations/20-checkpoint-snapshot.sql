@@ -0,0 +1,15 @@
+-- checkpoints are stored compressed, as a format byte followed by the encoded state
+-- older checkpoints keep their jsonval until the server gets round to compressing them
+
+alter table checkpoint add column if not exists snapshot bytea;
+alter table checkpoint alter column jsonval drop not null;
+
+-- a view's columns are fixed when it's made, so it has to be remade to include snapshot
+create or replace view recent_checkpoint_by_user_id as
+  select c.* from checkpoint c
+  inner join (
+    select max(checkpoint_id) id
+    from checkpoint
+    group by creator_user_id
+  ) maxids
+  on maxids.id = c.checkpoint_id;
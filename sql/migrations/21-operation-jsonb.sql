@@ -0,0 +1,6 @@
+-- ops are stored as jsonb, so queries can look inside them instead of the server reading every op
+-- rewriting the table in place would lock it for as long as that took, so existing ops are moved
+-- over from jsonval by the server a batch at a time; until then queries read whichever is set
+
+alter table operation add column if not exists jsonbval jsonb;
+alter table operation alter column jsonval drop not null;
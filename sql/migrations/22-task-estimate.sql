@@ -0,0 +1,11 @@
+-- how much effort each live task is estimated to take; tasks without a row have no estimate
+-- like lanes, rows outlive the task being live, so a restored task keeps its estimate
+
+create table if not exists task_estimate(
+  task_estimate_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null,
+  task_id text not null,
+  estimate bigint not null,
+  unique(creator_user_id, task_id)
+);
@@ -0,0 +1,14 @@
+-- how long each task was focused on; the one the user is focused on now has focused_since set
+-- millis only counts time up to when focus last moved away from the task
+
+create table if not exists task_focus(
+  task_focus_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null,
+  task_id text not null,
+  millis bigint not null default 0,
+  focused_since bigint,
+  unique(creator_user_id, task_id)
+);
+
+create unique index if not exists task_focus_focused on task_focus(creator_user_id) where focused_since is not null;
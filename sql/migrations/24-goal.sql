@@ -0,0 +1,24 @@
+-- goals the user's tasks can count toward
+
+create table if not exists goal(
+  goal_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null,
+  name text not null
+);
+
+create index if not exists goal_creator_user_id on goal(creator_user_id);
+
+-- the goal each task counts toward; completed follows the task as it's finished and restored, so
+-- tasks that were rolled over into the history still count
+create table if not exists task_goal(
+  task_goal_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null,
+  task_id text not null,
+  goal_id bigint not null references goal(goal_id) on delete cascade,
+  completed boolean not null default false,
+  unique(creator_user_id, task_id)
+);
+
+create index if not exists task_goal_goal_id on task_goal(goal_id);
@@ -0,0 +1,21 @@
+-- habits the user checks in on daily, kept apart from tasks
+-- habit_id is picked by the client, like task ids
+
+create table if not exists habit_definition(
+  habit_definition_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null,
+  habit_id text not null,
+  value text not null,
+  unique(creator_user_id, habit_id)
+);
+
+-- the days (yyyy-mm-dd, in the user's time zone) each habit was done on
+create table if not exists habit_check_in(
+  habit_check_in_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null,
+  habit_id text not null,
+  day text not null,
+  unique(creator_user_id, habit_id, day)
+);
@@ -0,0 +1,7 @@
+-- the last day each user was pushed a digest, so no day gets two
+create table if not exists notification_digest(
+  notification_digest_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null unique,
+  day text not null
+);
@@ -0,0 +1,15 @@
+-- the devices each user has opened websockets from
+-- device_id is picked by the client, so a device's sessions are remembered as one
+-- revoked devices are kept, so they can't connect again under the same id
+
+create table if not exists known_device(
+  known_device_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null,
+  device_id text not null,
+  label text,
+  platform text,
+  last_seen_time bigint not null,
+  revoked bool not null default false,
+  unique(creator_user_id, device_id)
+);
@@ -0,0 +1,6 @@
+-- github tokens are sealed with the server's credential key rather than stored as they are
+-- the server can't seal tokens from here, since only it has the key, so it seals the tokens stored
+-- before this when it starts with one, and clears token
+
+alter table github_link add column if not exists sealed_token bytea;
+alter table github_link alter column token drop not null;
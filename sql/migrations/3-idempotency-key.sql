@@ -0,0 +1,11 @@
+-- keys clients attached to ops, so retried ops are only applied once
+
+create table if not exists idempotency_key(
+  idempotency_key_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null,
+  key text not null,
+  unique(creator_user_id, key)
+);
+
+create index if not exists idempotency_key_creation_time on idempotency_key(creation_time);
@@ -0,0 +1,8 @@
+-- api keys their owners restricted to viewing, stored as hex encoded sha256 hashes
+
+create table if not exists read_only_api_key(
+  read_only_api_key_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null,
+  api_key_hash text not null unique
+);
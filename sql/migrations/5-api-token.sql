@@ -0,0 +1,8 @@
+-- api keys issued by this server in standalone mode, stored as hex encoded sha256 hashes
+
+create table if not exists api_token(
+  api_token_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null,
+  api_token_hash text not null unique
+);
@@ -0,0 +1,15 @@
+-- work to do off the hot path; failed jobs are retried with backoff, and dead lettered after too many attempts
+
+create table if not exists job(
+  job_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  -- the serialized job_queue::Job
+  jsonval text not null,
+  attempts bigint not null default 0,
+  -- not claimed before this time; pushed forward while a job runs, so a crashed instance's jobs get retried
+  run_after bigint not null default extract(epoch from now()) * 1000,
+  last_error text,
+  dead boolean not null default false
+);
+
+create index if not exists job_run_after on job(run_after) where not dead;
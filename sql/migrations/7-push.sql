@@ -0,0 +1,25 @@
+-- web push subscriptions, and reminders that are pushed to them when due
+
+create table if not exists push_subscription(
+  push_subscription_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null,
+  endpoint text not null unique,
+  -- the browser's keys for encrypting payloads, base64url encoded
+  p256dh text not null,
+  auth text not null
+);
+
+create index if not exists push_subscription_creator_user_id on push_subscription(creator_user_id);
+
+-- at most one reminder per task; snoozing moves remind_time later
+create table if not exists reminder(
+  reminder_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null,
+  task_id text not null,
+  remind_time bigint not null,
+  unique(creator_user_id, task_id)
+);
+
+create index if not exists reminder_remind_time on reminder(remind_time);
@@ -0,0 +1,12 @@
+-- named templates that expand into live tasks
+
+create table if not exists template(
+  template_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null,
+  name text not null,
+  -- the template's value, tags, priority and checklist
+  jsonval text not null
+);
+
+create index if not exists template_creator_user_id on template(creator_user_id);
@@ -0,0 +1,14 @@
+-- comments on tasks, written by CommentAdd ops and removed by CommentDel ops
+
+create table if not exists task_comment(
+  task_comment_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null,
+  -- picked by the client, like task ids
+  comment_id text not null,
+  task_id text not null,
+  value text not null,
+  unique(creator_user_id, comment_id)
+);
+
+create index if not exists task_comment_creator_user_id_task_id on task_comment(creator_user_id, task_id);
@@ -0,0 +1,261 @@
+// the activity feed: what the user did to their tasks, from the op log, in words people can read
+// only ops that change which tasks there are or what they say make entries, so reordering and lane
+// moves are left out, and edits to the same task in quick succession make just one
+// tasks are named by their value when the op was applied, found by replaying the log up to it
+use std::time::Duration;
+
+use todoproxy_api::{StateSnapshot, TaskStatus, WebsocketOpKind};
+
+use crate::db_types::Operation;
+use crate::handlers::{self, AppError};
+use crate::operation_service;
+use crate::protocol::response::{ActivityEntry, ActivityKind, ActivityPage};
+use crate::protocol::{ExtOpKind, Op, OpKind};
+use crate::state_machine::apply_operation;
+use crate::user_worker;
+
+/// Edits to the same task no further apart than this are one entry.
+const EDIT_COALESCE_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// How many ops are read from the log at a time while filling a page.
+const BATCH_SIZE: i64 = 200;
+
+/// How much of a task's value goes in an entry's text, in chars.
+const TEXT_VALUE_CHARS: usize = 80;
+
+struct LoggedOp {
+    seq: i64,
+    time: i64,
+    checkpoint_id: i64,
+    kind: OpKind,
+}
+
+fn logged_op(x: Operation) -> Result<LoggedOp, AppError> {
+    let op =
+        serde_json::from_str::<Op>(&x.jsonval).map_err(handlers::report_internal_serde_error)?;
+    Ok(LoggedOp {
+        seq: x.operation_id,
+        time: x.creation_time,
+        checkpoint_id: x.checkpoint_id,
+        kind: op.kind,
+    })
+}
+
+// whether the op makes an entry; ops that are only ever stored as others aren't listed
+fn is_visible(kind: &OpKind) -> bool {
+    match kind {
+        OpKind::Base(WebsocketOpKind::MvLiveTask { .. })
+        | OpKind::Base(WebsocketOpKind::RevLiveTask { .. }) => false,
+        OpKind::Base(_) => true,
+        OpKind::Ext(
+            ExtOpKind::ClearFinishedTasks
+            | ExtOpKind::FinishManyLiveTasks { .. }
+            | ExtOpKind::InsLiveTaskBetween { .. }
+            | ExtOpKind::InsLiveTasks { .. }
+            | ExtOpKind::CommentAdd { .. }
+            | ExtOpKind::RolloverDay { .. },
+        ) => true,
+        OpKind::Ext(_) => false,
+    }
+}
+
+fn edited_id(kind: &OpKind) -> Option<&str> {
+    match kind {
+        OpKind::Base(WebsocketOpKind::EditLiveTask { id, .. }) => Some(id),
+        _ => None,
+    }
+}
+
+// splits ops, newest first, into the ops each entry is made of, by index and newest first
+fn group(ops: &[LoggedOp]) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = vec![];
+    for (i, op) in ops.iter().enumerate() {
+        if !is_visible(&op.kind) {
+            continue;
+        }
+        if let Some(group) = groups.last_mut() {
+            let newer = &ops[*group.last().unwrap()];
+            let same_task =
+                edited_id(&op.kind).is_some() && edited_id(&op.kind) == edited_id(&newer.kind);
+            if same_task && newer.time - op.time <= EDIT_COALESCE_WINDOW.as_millis() as i64 {
+                group.push(i);
+                continue;
+            }
+        }
+        groups.push(vec![i]);
+    }
+    groups
+}
+
+fn value_of(snapshot: &StateSnapshot, id: &str) -> Option<String> {
+    let live = snapshot.live.iter().find(|x| x.id == id).map(|x| &x.value);
+    let finished = || {
+        snapshot
+            .finished
+            .iter()
+            .find(|x| x.id == id)
+            .map(|x| &x.value)
+    };
+    live.or_else(finished).cloned()
+}
+
+fn finished_kind(status: &TaskStatus) -> ActivityKind {
+    match status {
+        TaskStatus::Succeeded => ActivityKind::Completed,
+        TaskStatus::Failed => ActivityKind::Failed,
+        TaskStatus::Obsoleted => ActivityKind::Obsoleted,
+    }
+}
+
+// what the op did, given the state just before it: the kind of entry, the task (if there's just
+// one), its value, and how many tasks there were
+fn describe(
+    snapshot: &StateSnapshot,
+    kind: &OpKind,
+) -> (ActivityKind, Option<String>, Option<String>, usize) {
+    let one = |kind, id: &str, value: Option<String>| {
+        let value = value.or_else(|| value_of(snapshot, id));
+        (kind, Some(id.to_owned()), value, 1)
+    };
+    match kind {
+        OpKind::Base(WebsocketOpKind::InsLiveTask { id, value })
+        | OpKind::Ext(ExtOpKind::InsLiveTaskBetween { id, value, .. }) => {
+            one(ActivityKind::Added, id, Some(value.clone()))
+        }
+        OpKind::Base(WebsocketOpKind::EditLiveTask { id, value }) => {
+            one(ActivityKind::Edited, id, Some(value.clone()))
+        }
+        OpKind::Base(WebsocketOpKind::RestoreFinishedTask { id }) => {
+            one(ActivityKind::Restored, id, None)
+        }
+        OpKind::Base(WebsocketOpKind::DelLiveTask { id }) => one(ActivityKind::Deleted, id, None),
+        OpKind::Base(WebsocketOpKind::FinishLiveTask { id, status }) => {
+            one(finished_kind(status), id, None)
+        }
+        OpKind::Ext(ExtOpKind::CommentAdd { task_id, .. }) => {
+            one(ActivityKind::Commented, task_id, None)
+        }
+        OpKind::Ext(ExtOpKind::FinishManyLiveTasks { ids, status }) => match &ids[..] {
+            [id] => one(finished_kind(status), id, None),
+            _ => (finished_kind(status), None, None, ids.len()),
+        },
+        OpKind::Ext(ExtOpKind::InsLiveTasks { tasks }) => match &tasks[..] {
+            [task] => one(ActivityKind::Added, &task.id, Some(task.value.clone())),
+            _ => (ActivityKind::Added, None, None, tasks.len()),
+        },
+        OpKind::Ext(ExtOpKind::ClearFinishedTasks) => (
+            ActivityKind::ClearedFinished,
+            None,
+            None,
+            snapshot.finished.len(),
+        ),
+        // the day goes where a task's value would
+        OpKind::Ext(ExtOpKind::RolloverDay { day, .. }) => (
+            ActivityKind::RolledOver,
+            None,
+            Some(day.clone()),
+            snapshot.finished.len(),
+        ),
+        OpKind::Base(WebsocketOpKind::OverwriteState(x)) => (
+            ActivityKind::Replaced,
+            None,
+            None,
+            x.live.len() + x.finished.len(),
+        ),
+        // not visible
+        _ => (ActivityKind::Edited, None, None, 0),
+    }
+}
+
+fn text(kind: ActivityKind, value: Option<&str>, tasks: usize) -> String {
+    let what = match (value, tasks) {
+        (Some(value), 1) => match value.char_indices().nth(TEXT_VALUE_CHARS) {
+            Some((end, _)) => format!("{}…", &value[..end]),
+            None => value.to_owned(),
+        },
+        (None, 1) => "a task".to_owned(),
+        (_, n) => format!("{n} tasks"),
+    };
+    match kind {
+        ActivityKind::Added => format!("added {what}"),
+        ActivityKind::Edited => format!("edited {what}"),
+        ActivityKind::Completed => format!("completed {what}"),
+        ActivityKind::Failed => format!("failed {what}"),
+        ActivityKind::Obsoleted => format!("marked {what} obsolete"),
+        ActivityKind::Restored => format!("restored {what}"),
+        ActivityKind::Deleted => format!("deleted {what}"),
+        ActivityKind::Commented => format!("commented on {what}"),
+        ActivityKind::ClearedFinished => format!("cleared {tasks} finished tasks"),
+        ActivityKind::RolledOver => format!("rolled over {}", value.unwrap_or_default()),
+        ActivityKind::Replaced => format!("replaced everything with {tasks} tasks"),
+    }
+}
+
+// a page of the feed, newest first, made of the ops before before_seq
+pub async fn page(
+    con: &mut deadpool_postgres::Client,
+    user_id: i64,
+    before_seq: i64,
+    limit: usize,
+) -> Result<ActivityPage, AppError> {
+    // read until there's an entry past the page, so the last one on it has all of its edits
+    let mut ops = vec![];
+    loop {
+        let before = ops.last().map_or(before_seq, |x: &LoggedOp| x.seq);
+        let batch = operation_service::get_operations_by_user_id_before(
+            &mut *con, user_id, before, BATCH_SIZE,
+        )
+        .await
+        .map_err(handlers::report_postgres_err)?;
+        let done = batch.len() < BATCH_SIZE as usize;
+        for x in batch {
+            ops.push(logged_op(x)?);
+        }
+        if done || group(&ops).len() > limit {
+            break;
+        }
+    }
+    let mut groups = group(&ops);
+    let more = groups.len() > limit;
+    groups.truncate(limit);
+
+    let oldest = match groups.last().and_then(|x| x.last()) {
+        Some(&i) => &ops[i],
+        None => {
+            return Ok(ActivityPage {
+                entries: vec![],
+                more,
+            })
+        }
+    };
+    let mut snapshot = user_worker::load_state_before(con, oldest.checkpoint_id, oldest.seq)
+        .await?
+        .snapshot;
+
+    // invisible ops don't change which tasks there are or their values, so the visible ones are
+    // all that need replaying
+    let mut entries = vec![];
+    for group in groups.iter().rev() {
+        for &i in group.iter().rev() {
+            let op = &ops[i];
+            // an entry about a run of edits is about the last
+            if i == group[0] {
+                let (kind, task_id, value, tasks) = describe(&snapshot, &op.kind);
+                entries.push(ActivityEntry {
+                    first_seq: ops[*group.last().unwrap()].seq,
+                    seq: op.seq,
+                    time: op.time,
+                    kind,
+                    text: text(kind, value.as_deref(), tasks),
+                    task_id,
+                    value,
+                    tasks,
+                    ops: group.len(),
+                });
+            }
+            apply_operation(&mut snapshot, op.kind.clone());
+        }
+    }
+    entries.reverse();
+    Ok(ActivityPage { entries, more })
+}
@@ -0,0 +1,84 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for ApiToken {
+    // select * from api_token order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> ApiToken {
+        ApiToken {
+            api_token_id: row.get("api_token_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            api_token_hash: row.get("api_token_hash"),
+        }
+    }
+}
+
+#[tracing::instrument(skip(con, api_token_hash))]
+pub async fn add(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    api_token_hash: String,
+) -> Result<ApiToken, tokio_postgres::Error> {
+    let row = con
+        .query_one(
+            "INSERT INTO
+             api_token(
+                 creator_user_id,
+                 api_token_hash
+             )
+             VALUES($1, $2)
+             RETURNING api_token_id, creation_time
+            ",
+            &[&creator_user_id, &api_token_hash],
+        )
+        .await?;
+
+    // return api token
+    Ok(ApiToken {
+        api_token_id: row.get(0),
+        creation_time: row.get(1),
+        creator_user_id,
+        api_token_hash,
+    })
+}
+
+#[tracing::instrument(skip(con, api_token_hash))]
+pub async fn get_by_api_token_hash(
+    con: &mut impl GenericClient,
+    api_token_hash: &str,
+) -> Result<Option<ApiToken>, tokio_postgres::Error> {
+    let result = con
+        .query_opt(
+            "SELECT * FROM api_token WHERE api_token_hash=$1",
+            &[&api_token_hash],
+        )
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+// returns the number of tokens revoked
+#[tracing::instrument(skip(con, api_token_hash))]
+pub async fn remove_by_api_token_hash(
+    con: &mut impl GenericClient,
+    api_token_hash: &str,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM api_token WHERE api_token_hash=$1",
+        &[&api_token_hash],
+    )
+    .await
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM api_token WHERE creator_user_id = $1",
+        &[&creator_user_id],
+    )
+    .await
+}
@@ -0,0 +1,117 @@
+use super::db_types::*;
+use todoproxy_api::FinishedTask;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for ArchivedTask {
+    // select * from archived_task order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> ArchivedTask {
+        ArchivedTask {
+            archived_task_id: row.get("archived_task_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            day: row.get("day"),
+            task_id: row.get("task_id"),
+            value: row.get("value"),
+            status_json: row.get("status_json"),
+        }
+    }
+}
+
+#[tracing::instrument(skip(con, task))]
+pub async fn add(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    day: String,
+    task: FinishedTask,
+) -> Result<ArchivedTask, tokio_postgres::Error> {
+    let status_json = serde_json::to_string(&task.status).unwrap();
+
+    let row = con
+        .query_one(
+            "INSERT INTO
+             archived_task(
+                 creator_user_id,
+                 day,
+                 task_id,
+                 value,
+                 status_json
+             )
+             VALUES($1, $2, $3, $4, $5)
+             RETURNING archived_task_id, creation_time
+            ",
+            &[&creator_user_id, &day, &task.id, &task.value, &status_json],
+        )
+        .await?;
+
+    // return archived task
+    Ok(ArchivedTask {
+        archived_task_id: row.get(0),
+        creation_time: row.get(1),
+        creator_user_id,
+        day,
+        task_id: task.id,
+        value: task.value,
+        status_json,
+    })
+}
+
+// in the order they were in the finished list, most recently finished first
+#[tracing::instrument(skip(con))]
+pub async fn get_all_by_day(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    day: &str,
+) -> Result<Vec<ArchivedTask>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT *
+             FROM archived_task
+             WHERE creator_user_id = $1 AND day = $2
+             ORDER BY archived_task_id
+            ",
+            &[&creator_user_id, &day],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+
+    Ok(result)
+}
+
+// everything rolled over on or after day
+#[tracing::instrument(skip(con))]
+pub async fn get_all_by_user_id_since(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    day: &str,
+) -> Result<Vec<ArchivedTask>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT *
+             FROM archived_task
+             WHERE creator_user_id = $1 AND day >= $2
+             ORDER BY archived_task_id
+            ",
+            &[&creator_user_id, &day],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+
+    Ok(result)
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM archived_task WHERE creator_user_id = $1",
+        &[&creator_user_id],
+    )
+    .await
+}
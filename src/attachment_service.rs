@@ -0,0 +1,187 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for Attachment {
+    // select * from attachment order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> Attachment {
+        Attachment {
+            attachment_id: row.get("attachment_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            task_id: row.get("task_id"),
+            object_key: row.get("object_key"),
+            filename: row.get("filename"),
+            content_type: row.get("content_type"),
+            size: row.get("size"),
+        }
+    }
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn add(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    task_id: String,
+    object_key: String,
+    filename: String,
+    content_type: String,
+    size: i64,
+) -> Result<Attachment, tokio_postgres::Error> {
+    let row = con
+        .query_one(
+            "INSERT INTO
+             attachment(
+                 creator_user_id,
+                 task_id,
+                 object_key,
+                 filename,
+                 content_type,
+                 size
+             )
+             VALUES($1, $2, $3, $4, $5, $6)
+             RETURNING attachment_id, creation_time
+            ",
+            &[
+                &creator_user_id,
+                &task_id,
+                &object_key,
+                &filename,
+                &content_type,
+                &size,
+            ],
+        )
+        .await?;
+
+    // return attachment
+    Ok(Attachment {
+        attachment_id: row.get(0),
+        creation_time: row.get(1),
+        creator_user_id,
+        task_id,
+        object_key,
+        filename,
+        content_type,
+        size,
+    })
+}
+
+// only finds the user's own attachments
+#[tracing::instrument(skip(con))]
+pub async fn get_by_attachment_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    attachment_id: i64,
+) -> Result<Option<Attachment>, tokio_postgres::Error> {
+    let result = con
+        .query_opt(
+            "SELECT * FROM attachment WHERE creator_user_id=$1 AND attachment_id=$2",
+            &[&creator_user_id, &attachment_id],
+        )
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn get_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<Vec<Attachment>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT * FROM attachment WHERE creator_user_id=$1 ORDER BY attachment_id",
+            &[&creator_user_id],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// in bytes, as declared when the attachments were added
+#[tracing::instrument(skip(con))]
+pub async fn total_size_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<i64, tokio_postgres::Error> {
+    let row = con
+        .query_one(
+            "SELECT coalesce(sum(size), 0)::bigint FROM attachment WHERE creator_user_id=$1",
+            &[&creator_user_id],
+        )
+        .await?;
+    Ok(row.get(0))
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn get_all_by_task_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    task_id: &str,
+) -> Result<Vec<Attachment>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT * FROM attachment WHERE creator_user_id=$1 AND task_id=$2 ORDER BY attachment_id",
+            &[&creator_user_id, &task_id],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// returns the attachment, if the user had one with this id, so its object can be deleted
+#[tracing::instrument(skip(con))]
+pub async fn remove(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    attachment_id: i64,
+) -> Result<Option<Attachment>, tokio_postgres::Error> {
+    let result = con
+        .query_opt(
+            "DELETE FROM attachment WHERE creator_user_id = $1 AND attachment_id = $2 RETURNING *",
+            &[&creator_user_id, &attachment_id],
+        )
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+// returns the attachments removed, so their objects can be deleted
+#[tracing::instrument(skip(con))]
+pub async fn remove_by_task_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    task_id: &str,
+) -> Result<Vec<Attachment>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "DELETE FROM attachment WHERE creator_user_id = $1 AND task_id = $2 RETURNING *",
+            &[&creator_user_id, &task_id],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// returns the attachments removed, so their objects can be deleted
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<Vec<Attachment>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "DELETE FROM attachment WHERE creator_user_id = $1 RETURNING *",
+            &[&creator_user_id],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
@@ -0,0 +1,76 @@
+// every applied op and integration change is recorded in the user's audit log, along with the
+// websocket or request it came from and its address, so users can look back over their account
+use std::future::Future;
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::{Error, HttpMessage, HttpRequest};
+
+use crate::audit_event_service;
+use crate::request_id::RequestId;
+
+tokio::task_local! {
+    static ORIGIN: Origin;
+}
+
+// where a change came from
+#[derive(Clone, Debug, Default)]
+pub struct Origin {
+    // the websocket's connection id, or the request's id
+    pub session_id: Option<String>,
+    // as reported by proxies in Forwarded or X-Forwarded-For, if there are any
+    pub source_ip: Option<String>,
+}
+
+// where the change being made came from; changes made by the server itself don't have an origin
+pub fn current() -> Origin {
+    ORIGIN.try_with(|x| x.clone()).unwrap_or_default()
+}
+
+// for handlers that outlive the request (websockets) or aren't served by actix (grpc)
+pub fn scope<F: Future>(origin: Origin, f: F) -> impl Future<Output = F::Output> {
+    ORIGIN.scope(origin, f)
+}
+
+fn origin_of(req: &HttpRequest) -> Origin {
+    Origin {
+        session_id: req.extensions().get::<RequestId>().map(|x| x.0.clone()),
+        source_ip: req
+            .connection_info()
+            .realip_remote_addr()
+            .map(|x| x.to_owned()),
+    }
+}
+
+// records a change made by the current origin
+pub async fn record(
+    con: &mut impl tokio_postgres::GenericClient,
+    user_id: i64,
+    kind: &str,
+    detail: Option<String>,
+) -> Result<(), tokio_postgres::Error> {
+    let Origin {
+        session_id,
+        source_ip,
+    } = current();
+    audit_event_service::add(
+        &mut *con,
+        user_id,
+        kind.to_owned(),
+        detail,
+        session_id,
+        source_ip,
+    )
+    .await?;
+    Ok(())
+}
+
+pub fn middleware<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    let origin = origin_of(req.request());
+    ORIGIN.scope(origin, srv.call(req))
+}
@@ -0,0 +1,119 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for AuditEvent {
+    // select * from audit_event order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> AuditEvent {
+        AuditEvent {
+            audit_event_id: row.get("audit_event_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            kind: row.get("kind"),
+            detail: row.get("detail"),
+            session_id: row.get("session_id"),
+            source_ip: row.get("source_ip"),
+        }
+    }
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn add(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    kind: String,
+    detail: Option<String>,
+    session_id: Option<String>,
+    source_ip: Option<String>,
+) -> Result<AuditEvent, tokio_postgres::Error> {
+    let row = con
+        .query_one(
+            "INSERT INTO
+             audit_event(
+                 creator_user_id,
+                 kind,
+                 detail,
+                 session_id,
+                 source_ip
+             )
+             VALUES($1, $2, $3, $4, $5)
+             RETURNING audit_event_id, creation_time
+            ",
+            &[&creator_user_id, &kind, &detail, &session_id, &source_ip],
+        )
+        .await?;
+
+    // return audit event
+    Ok(AuditEvent {
+        audit_event_id: row.get(0),
+        creation_time: row.get(1),
+        creator_user_id,
+        kind,
+        detail,
+        session_id,
+        source_ip,
+    })
+}
+
+// newest first, up to limit events older than before_id (or the newest, if it's unset)
+// filters that are unset match every event
+#[tracing::instrument(skip(con))]
+pub async fn get_page(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    before_id: Option<i64>,
+    kind: Option<&str>,
+    since_time: Option<i64>,
+    until_time: Option<i64>,
+    limit: i64,
+) -> Result<Vec<AuditEvent>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT * FROM audit_event
+             WHERE creator_user_id = $1
+             AND ($2::bigint IS NULL OR audit_event_id < $2)
+             AND ($3::text IS NULL OR kind = $3)
+             AND ($4::bigint IS NULL OR creation_time >= $4)
+             AND ($5::bigint IS NULL OR creation_time < $5)
+             ORDER BY audit_event_id DESC
+             LIMIT $6",
+            &[
+                &creator_user_id,
+                &before_id,
+                &kind,
+                &since_time,
+                &until_time,
+                &limit,
+            ],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn purge_created_before(
+    con: &mut impl GenericClient,
+    creation_time: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM audit_event WHERE creation_time < $1",
+        &[&creation_time],
+    )
+    .await
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM audit_event WHERE creator_user_id = $1",
+        &[&creator_user_id],
+    )
+    .await
+}
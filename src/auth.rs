@@ -0,0 +1,183 @@
+// where api keys are checked
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use auth_service_api::client::AuthService;
+use auth_service_api::response::{AuthError, Info};
+use dashmap::DashMap;
+
+use crate::circuit_breaker::{CallError, CircuitBreaker};
+use crate::db_pool;
+use crate::handlers::{self, AppError};
+use crate::jwt::JwtValidator;
+use crate::{api_token_service, utils};
+
+#[derive(Clone)]
+pub enum AuthBackend {
+    // by the auth service
+    Service {
+        client: AuthService,
+        breaker: Arc<CircuitBreaker>,
+    },
+    // against our own api_token table, so self-hosters only need postgres
+    Standalone,
+    // api keys are JWTs, checked against the issuer's keys
+    Jwt(Arc<JwtValidator>),
+}
+
+impl AuthBackend {
+    pub async fn get_user_id_by_api_key(
+        &self,
+        pool: &db_pool::Pool,
+        api_key: String,
+    ) -> Result<i64, AppError> {
+        match self {
+            AuthBackend::Service { client, breaker } => breaker
+                .call(
+                    client.get_user_by_api_key_if_valid(api_key),
+                    auth_service_failed,
+                )
+                .await
+                .map(|user| user.user_id)
+                .map_err(report_auth_call_err),
+            AuthBackend::Standalone => {
+                let con: &mut tokio_postgres::Client =
+                    &mut *pool.get().await.map_err(handlers::report_pool_err)?;
+                api_token_service::get_by_api_token_hash(
+                    &mut *con,
+                    &utils::sha256_hex(api_key.as_bytes()),
+                )
+                .await
+                .map_err(handlers::report_postgres_err)?
+                .map(|x| x.creator_user_id)
+                .ok_or(AppError::Unauthorized)
+            }
+            AuthBackend::Jwt(validator) => validator.get_user_id(&api_key).await,
+        }
+    }
+
+    // None without an auth service to point clients at
+    pub async fn info(&self) -> Result<Option<Info>, AppError> {
+        match self {
+            AuthBackend::Service { client, breaker } => breaker
+                .call(client.info(), auth_service_failed)
+                .await
+                .map(Some)
+                .map_err(report_auth_call_err),
+            AuthBackend::Standalone | AuthBackend::Jwt(_) => Ok(None),
+        }
+    }
+}
+
+// errors that mean the auth service is in trouble, rather than that the api key is bad
+fn auth_service_failed(e: &AuthError) -> bool {
+    matches!(e, AuthError::Network | AuthError::InternalServerError)
+}
+
+// the auth service being down or slow is Unavailable, so callers can fall back to cached keys
+fn report_auth_call_err(e: CallError<AuthError>) -> AppError {
+    match e {
+        CallError::Open => AppError::Unavailable,
+        CallError::TimedOut => {
+            log::error!("auth: timed out");
+            AppError::Unavailable
+        }
+        CallError::Failed(e) if auth_service_failed(&e) => {
+            log::error!("auth: {}", e);
+            AppError::Unavailable
+        }
+        CallError::Failed(e) => handlers::report_auth_err(e),
+    }
+}
+
+// remembers which user valid api keys belong to for a little while, so reconnecting clients don't
+// each have to wait on the auth backend; only valid keys are cached, so new keys work right away
+// a key revoked elsewhere keeps working on this instance until it expires, or is invalidated here
+// expired keys are kept for stale_for more, in case the auth backend is unavailable when they're
+// next used
+pub struct AuthCache {
+    ttl: Duration,
+    stale_for: Duration,
+    max_entries: usize,
+    // by api key hash
+    entries: DashMap<String, CachedUser>,
+}
+
+struct CachedUser {
+    user_id: i64,
+    expiry: Instant,
+}
+
+impl AuthCache {
+    pub fn new(ttl: Duration, stale_for: Duration, max_entries: usize) -> AuthCache {
+        AuthCache {
+            ttl,
+            stale_for,
+            max_entries,
+            entries: DashMap::new(),
+        }
+    }
+
+    pub fn get(&self, api_key: &str) -> Option<i64> {
+        self.get_within(api_key, Duration::ZERO)
+    }
+
+    // for when the auth backend can't be asked
+    pub fn get_stale(&self, api_key: &str) -> Option<i64> {
+        self.get_within(api_key, self.stale_for)
+    }
+
+    fn get_within(&self, api_key: &str, past_expiry: Duration) -> Option<i64> {
+        let key = utils::sha256_hex(api_key.as_bytes());
+        let now = Instant::now();
+        let user_id = self
+            .entries
+            .get(&key)
+            .filter(|x| x.expiry + past_expiry > now)
+            .map(|x| x.user_id);
+        if user_id.is_none() {
+            self.entries
+                .remove_if(&key, |_, x| x.expiry + self.stale_for <= now);
+        }
+        user_id
+    }
+
+    pub fn insert(&self, api_key: &str, user_id: i64) {
+        if self.entries.len() >= self.max_entries {
+            let now = Instant::now();
+            self.entries.retain(|_, x| x.expiry + self.stale_for > now);
+            // still full of live entries, so make room by dropping an arbitrary one
+            if self.entries.len() >= self.max_entries {
+                let victim = self.entries.iter().next().map(|x| x.key().clone());
+                if let Some(victim) = victim {
+                    self.entries.remove(&victim);
+                }
+            }
+        }
+        self.entries.insert(
+            utils::sha256_hex(api_key.as_bytes()),
+            CachedUser {
+                user_id,
+                expiry: Instant::now() + self.ttl,
+            },
+        );
+    }
+
+    pub fn invalidate(&self, api_key: &str) {
+        self.entries.remove(&utils::sha256_hex(api_key.as_bytes()));
+    }
+
+    // returns how many keys were forgotten
+    pub fn invalidate_user(&self, user_id: i64) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|_, x| x.user_id != user_id);
+        before - self.entries.len()
+    }
+
+    // returns how many keys were forgotten
+    pub fn clear(&self) -> usize {
+        let before = self.entries.len();
+        self.entries.clear();
+        before
+    }
+}
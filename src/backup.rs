@@ -0,0 +1,367 @@
+// a portable dump of every table, one json object per line, for deployments without pg_dump expertise
+// a backup is a header, a row line for each row, and an end line with the row count
+use actix_web::web::Bytes;
+use actix_web::{web, HttpResponse, Responder};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio::sync::mpsc;
+use tokio_postgres::types::{ToSql, Type};
+use tokio_postgres::IsolationLevel;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::handlers::{self, AppError};
+use crate::{migrations, protocol, utils, AppData};
+
+/// Bumped whenever the line format changes.
+const BACKUP_FORMAT_VERSION: i64 = 1;
+
+// (table, primary key), in the order they must be restored so foreign keys are satisfied
+static TABLES: &[(&str, &str)] = &[
+    ("checkpoint", "checkpoint_id"),
+    ("operation", "operation_id"),
+    ("deleted_task", "deleted_task_id"),
+    ("idempotency_key", "idempotency_key_id"),
+    ("read_only_api_key", "read_only_api_key_id"),
+    ("api_token", "api_token_id"),
+    ("push_subscription", "push_subscription_id"),
+    ("reminder", "reminder_id"),
+    ("template", "template_id"),
+    ("task_comment", "task_comment_id"),
+    ("attachment", "attachment_id"),
+    ("preference", "preference_id"),
+    ("archived_task", "archived_task_id"),
+    ("rollover", "rollover_id"),
+    ("task_lane", "task_lane_id"),
+    ("webhook", "webhook_id"),
+    ("email_alias", "email_alias_id"),
+    ("github_link", "github_link_id"),
+    ("external_task", "external_task_id"),
+    ("tenant", "tenant_id"),
+    ("tenant_user", "tenant_user_id"),
+    ("audit_event", "audit_event_id"),
+    ("op_usage", "op_usage_id"),
+    ("task_estimate", "task_estimate_id"),
+    ("task_focus", "task_focus_id"),
+    ("goal", "goal_id"),
+    ("task_goal", "task_goal_id"),
+    ("habit_definition", "habit_definition_id"),
+    ("habit_check_in", "habit_check_in_id"),
+    ("notification_digest", "notification_digest_id"),
+    ("known_device", "known_device_id"),
+];
+
+// (table, column) left out of backups
+// github tokens from before tokens were sealed are plaintext, until a server with a credential key
+// seals them; they have to be linked again after a restore
+static OMITTED_COLUMNS: &[(&str, &str)] = &[("github_link", "token")];
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Line {
+    Header {
+        format_version: i64,
+        // a backup may only be restored into a database with the same schema
+        schema_version: i64,
+    },
+    Row {
+        table: String,
+        row: Map<String, Value>,
+    },
+    // a backup without this was cut off
+    End {
+        rows: u64,
+    },
+}
+
+fn line(x: &Line) -> Bytes {
+    let mut jsonval = serde_json::to_vec(x).unwrap();
+    jsonval.push(b'\n');
+    Bytes::from(jsonval)
+}
+
+fn row_to_json(row: &tokio_postgres::Row) -> Result<Map<String, Value>, AppError> {
+    let mut map = Map::new();
+    for (i, column) in row.columns().iter().enumerate() {
+        let value = match *column.type_() {
+            Type::INT8 => Value::from(row.get::<_, Option<i64>>(i)),
+            Type::TEXT => Value::from(row.get::<_, Option<String>>(i)),
+            Type::BOOL => Value::from(row.get::<_, Option<bool>>(i)),
+            Type::JSONB => row.get::<_, Option<Value>>(i).unwrap_or(Value::Null),
+            // json has no bytes
+            Type::BYTEA => Value::from(row.get::<_, Option<Vec<u8>>>(i).map(|x| utils::hex(&x))),
+            _ => {
+                log::error!(
+                    "can't back up column {} of type {}",
+                    column.name(),
+                    column.type_()
+                );
+                return Err(AppError::InternalServerError);
+            }
+        };
+        map.insert(column.name().to_owned(), value);
+    }
+    Ok(map)
+}
+
+fn json_to_param(value: Value, type_: &Type) -> Result<Box<dyn ToSql + Sync>, AppError> {
+    if *type_ == Type::INT8 {
+        match value {
+            Value::Number(x) => Ok(Box::new(x.as_i64().ok_or(AppError::BadRequest)?)),
+            Value::Null => Ok(Box::new(None::<i64>)),
+            _ => Err(AppError::BadRequest),
+        }
+    } else if *type_ == Type::TEXT {
+        match value {
+            Value::String(x) => Ok(Box::new(x)),
+            Value::Null => Ok(Box::new(None::<String>)),
+            _ => Err(AppError::BadRequest),
+        }
+    } else if *type_ == Type::BOOL {
+        match value {
+            Value::Bool(x) => Ok(Box::new(x)),
+            Value::Null => Ok(Box::new(None::<bool>)),
+            _ => Err(AppError::BadRequest),
+        }
+    } else if *type_ == Type::JSONB {
+        match value {
+            Value::Null => Ok(Box::new(None::<Value>)),
+            x => Ok(Box::new(x)),
+        }
+    } else if *type_ == Type::BYTEA {
+        match value {
+            Value::String(x) => Ok(Box::new(utils::from_hex(&x).ok_or(AppError::BadRequest)?)),
+            Value::Null => Ok(Box::new(None::<Vec<u8>>)),
+            _ => Err(AppError::BadRequest),
+        }
+    } else {
+        Err(AppError::BadRequest)
+    }
+}
+
+async fn send_backup(
+    data: &AppData,
+    tx: &mpsc::Sender<Result<Bytes, std::io::Error>>,
+) -> Result<(), AppError> {
+    // read only, so it can come from the replica
+    let con: &mut tokio_postgres::Client = &mut *handlers::read_pool(data)
+        .get()
+        .await
+        .map_err(handlers::report_pool_err)?;
+    // every table as of the same instant
+    let dbtx = con
+        .build_transaction()
+        .isolation_level(IsolationLevel::RepeatableRead)
+        .read_only(true)
+        .start()
+        .await
+        .map_err(handlers::report_postgres_err)?;
+
+    let header = Line::Header {
+        format_version: BACKUP_FORMAT_VERSION,
+        schema_version: migrations::latest_version(),
+    };
+    // the client went away, so there's nobody to send the rest to
+    if tx.send(Ok(line(&header))).await.is_err() {
+        return Ok(());
+    }
+
+    let mut rows = 0;
+    for (table, pk) in TABLES {
+        let query = format!("SELECT * FROM {table} ORDER BY {pk}");
+        let stream = dbtx
+            .query_raw(query.as_str(), Vec::<i64>::new())
+            .await
+            .map_err(handlers::report_postgres_err)?;
+        tokio::pin!(stream);
+        while let Some(row) = stream.next().await {
+            let row = row.map_err(handlers::report_postgres_err)?;
+            let mut row = row_to_json(&row)?;
+            for (_, column) in OMITTED_COLUMNS.iter().filter(|(x, _)| x == table) {
+                row.remove(*column);
+            }
+            let x = Line::Row {
+                table: table.to_string(),
+                row,
+            };
+            if tx.send(Ok(line(&x))).await.is_err() {
+                return Ok(());
+            }
+            rows += 1;
+        }
+    }
+
+    let _ = tx.send(Ok(line(&Line::End { rows }))).await;
+    Ok(())
+}
+
+// stream a backup of the whole database
+#[tracing::instrument(skip_all)]
+pub async fn admin_backup(
+    req: web::Json<protocol::request::AdminBackup>,
+    data: web::Data<AppData>,
+) -> Result<HttpResponse, AppError> {
+    handlers::get_author_id_if_api_key_valid(&data, req.into_inner().api_key).await?;
+
+    let (tx, rx) = mpsc::channel(64);
+    tokio::spawn(async move {
+        if let Err(e) = send_backup(&data, &tx).await {
+            // aborts the response, so the client sees a truncated backup
+            let _ = tx.send(Err(std::io::Error::other(e.to_string()))).await;
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(ReceiverStream::new(rx)))
+}
+
+async fn restore_line(
+    dbtx: &tokio_postgres::Transaction<'_>,
+    x: Line,
+    rows: &mut u64,
+    ended: &mut bool,
+) -> Result<(), AppError> {
+    match x {
+        Line::Header { .. } => Err(AppError::BadRequest),
+        Line::End { rows: expected } => {
+            if expected != *rows {
+                return Err(AppError::BadRequest);
+            }
+            *ended = true;
+            Ok(())
+        }
+        Line::Row { table, mut row } => {
+            if !TABLES.iter().any(|(x, _)| *x == table) {
+                return Err(AppError::BadRequest);
+            }
+            // the columns come from the database, so they're safe to put in the query
+            let columns = dbtx
+                .prepare(&format!("SELECT * FROM {table} LIMIT 0"))
+                .await
+                .map_err(handlers::report_postgres_err)?
+                .columns()
+                .iter()
+                .map(|c| (c.name().to_owned(), c.type_().clone()))
+                .collect::<Vec<_>>();
+
+            let mut names = vec![];
+            let mut params = vec![];
+            for (name, type_) in columns {
+                if let Some(value) = row.remove(&name) {
+                    params.push(json_to_param(value, &type_)?);
+                    names.push(name);
+                }
+            }
+            // a column this schema doesn't have
+            if !row.is_empty() {
+                return Err(AppError::BadRequest);
+            }
+
+            let placeholders = (1..=names.len())
+                .map(|i| format!("${i}"))
+                .collect::<Vec<_>>();
+            let query = format!(
+                "INSERT INTO {table}({}) VALUES({})",
+                names.join(", "),
+                placeholders.join(", ")
+            );
+            let params = params.iter().map(|x| x.as_ref()).collect::<Vec<_>>();
+            dbtx.execute(query.as_str(), &params)
+                .await
+                .map_err(handlers::report_postgres_err)?;
+            *rows += 1;
+            Ok(())
+        }
+    }
+}
+
+// load a backup into an empty database
+#[tracing::instrument(skip_all)]
+pub async fn admin_restore(
+    query: web::Query<protocol::request::AdminRestore>,
+    mut payload: web::Payload,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    handlers::get_author_id_if_api_key_valid(&data, query.into_inner().api_key).await?;
+
+    let con: &mut tokio_postgres::Client =
+        &mut *data.pool.get().await.map_err(handlers::report_pool_err)?;
+    // all or nothing, so a bad backup doesn't leave a half restored database
+    let dbtx = con
+        .transaction()
+        .await
+        .map_err(handlers::report_postgres_err)?;
+
+    for (table, _) in TABLES {
+        let nonempty: bool = dbtx
+            .query_one(&format!("SELECT EXISTS(SELECT 1 FROM {table})"), &[])
+            .await
+            .map_err(handlers::report_postgres_err)?
+            .get(0);
+        if nonempty {
+            log::warn!("refusing to restore into a database with rows in {table}");
+            return Err(AppError::BadRequest);
+        }
+    }
+
+    let mut buf = Vec::new();
+    let mut header = false;
+    let mut ended = false;
+    let mut rows = 0;
+    loop {
+        let chunk = payload.next().await;
+        let eof = chunk.is_none();
+        if let Some(chunk) = chunk {
+            buf.extend_from_slice(&chunk.map_err(|_| AppError::BadRequest)?);
+        }
+        // a final line may not end in a newline
+        if eof && !buf.is_empty() {
+            buf.push(b'\n');
+        }
+        while let Some(i) = buf.iter().position(|x| *x == b'\n') {
+            let rest = buf.split_off(i + 1);
+            let text = std::mem::replace(&mut buf, rest);
+            if text.iter().all(|x| x.is_ascii_whitespace()) {
+                continue;
+            }
+            if ended {
+                return Err(AppError::BadRequest);
+            }
+            let x = serde_json::from_slice::<Line>(&text).map_err(|_| AppError::BadRequest)?;
+            if !header {
+                match x {
+                    Line::Header {
+                        format_version: BACKUP_FORMAT_VERSION,
+                        schema_version,
+                    } if schema_version == migrations::latest_version() => header = true,
+                    _ => return Err(AppError::BadRequest),
+                }
+                continue;
+            }
+            restore_line(&dbtx, x, &mut rows, &mut ended).await?;
+        }
+        if eof {
+            break;
+        }
+    }
+    if !ended {
+        return Err(AppError::BadRequest);
+    }
+
+    // the ids were restored as is, so move the sequences past them
+    for (table, pk) in TABLES {
+        dbtx.execute(
+            &format!(
+                "SELECT setval(pg_get_serial_sequence('{table}', '{pk}'), COALESCE(MAX({pk}), 0) + 1, false) FROM {table}"
+            ),
+            &[],
+        )
+        .await
+        .map_err(handlers::report_postgres_err)?;
+    }
+    dbtx.commit().await.map_err(handlers::report_postgres_err)?;
+
+    log::info!("restored {rows} rows from a backup");
+    Ok(web::Json(protocol::response::AdminRestore { rows }))
+}
@@ -1,6 +1,7 @@
 use super::db_types::*;
+use super::snapshot_codec;
+use deadpool_postgres::GenericClient;
 use todoproxy_api::StateSnapshot;
-use tokio_postgres::GenericClient;
 
 impl From<tokio_postgres::row::Row> for Checkpoint {
     // select * from checkpoint order only, otherwise it will fail
@@ -10,63 +11,193 @@ impl From<tokio_postgres::row::Row> for Checkpoint {
             creation_time: row.get("creation_time"),
             creator_user_id: row.get("creator_user_id"),
             jsonval: row.get("jsonval"),
+            snapshot: row.get("snapshot"),
         }
     }
 }
 
+#[tracing::instrument(skip(con, checkpoint))]
 pub async fn add(
     con: &mut impl GenericClient,
     creator_user_id: i64,
     checkpoint: StateSnapshot,
 ) -> Result<Checkpoint, tokio_postgres::Error> {
-    let jsonval = serde_json::to_string(&checkpoint).unwrap();
-    let row = con
-        .query_one(
+    let snapshot = snapshot_codec::encode(&checkpoint);
+    let statement = con
+        .prepare_cached(
             "INSERT INTO
              checkpoint(
                  creator_user_id,
-                 jsonval
+                 snapshot
              )
              VALUES($1, $2)
              RETURNING checkpoint_id, creation_time
             ",
-            &[&creator_user_id, &jsonval],
         )
         .await?;
+    let row = con
+        .query_one(&statement, &[&creator_user_id, &snapshot])
+        .await?;
 
     // return checkpoint
     Ok(Checkpoint {
         checkpoint_id: row.get(0),
         creation_time: row.get(1),
         creator_user_id,
-        jsonval,
+        jsonval: None,
+        snapshot: Some(snapshot),
     })
 }
 
+#[tracing::instrument(skip(con))]
 pub async fn get_by_checkpoint_id(
     con: &mut impl GenericClient,
     checkpoint_id: i64,
 ) -> Result<Option<Checkpoint>, tokio_postgres::Error> {
+    let statement = con
+        .prepare_cached("SELECT * FROM checkpoint WHERE checkpoint_id=$1")
+        .await?;
     let result = con
-        .query_opt(
-            "SELECT * FROM checkpoint WHERE checkpoint_id=$1",
-            &[&checkpoint_id],
-        )
+        .query_opt(&statement, &[&checkpoint_id])
         .await?
         .map(|x| x.into());
     Ok(result)
 }
 
+#[tracing::instrument(skip(con))]
 pub async fn get_recent_by_user_id(
     con: &mut impl GenericClient,
     creator_user_id: i64,
 ) -> Result<Option<Checkpoint>, tokio_postgres::Error> {
+    let statement = con
+        .prepare_cached("SELECT * FROM recent_checkpoint_by_user_id WHERE creator_user_id=$1")
+        .await?;
     let result = con
-        .query_opt(
-            "SELECT * FROM recent_checkpoint_by_user_id WHERE creator_user_id=$1",
-            &[&creator_user_id],
+        .query_opt(&statement, &[&creator_user_id])
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+// the latest checkpoint the user had at the given time
+#[tracing::instrument(skip(con))]
+pub async fn get_recent_by_user_id_at(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    time: i64,
+) -> Result<Option<Checkpoint>, tokio_postgres::Error> {
+    let statement = con
+        .prepare_cached(
+            "SELECT *
+             FROM checkpoint
+             WHERE creator_user_id = $1 AND creation_time <= $2
+             ORDER BY checkpoint_id DESC
+             LIMIT 1
+            ",
         )
+        .await?;
+    let result = con
+        .query_opt(&statement, &[&creator_user_id, &time])
         .await?
         .map(|x| x.into());
     Ok(result)
 }
+
+// every user that has a checkpoint
+#[tracing::instrument(skip(con))]
+pub async fn get_user_ids(con: &mut impl GenericClient) -> Result<Vec<i64>, tokio_postgres::Error> {
+    let statement = con
+        .prepare_cached("SELECT DISTINCT creator_user_id FROM checkpoint ORDER BY creator_user_id")
+        .await?;
+    let result = con
+        .query(&statement, &[])
+        .await?
+        .into_iter()
+        .map(|x| x.get(0))
+        .collect();
+    Ok(result)
+}
+
+// the user's checkpoints, oldest first
+#[tracing::instrument(skip(con))]
+pub async fn get_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<Vec<Checkpoint>, tokio_postgres::Error> {
+    let statement = con
+        .prepare_cached(
+            "SELECT * FROM checkpoint WHERE creator_user_id = $1 ORDER BY checkpoint_id",
+        )
+        .await?;
+    let result = con
+        .query(&statement, &[&creator_user_id])
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// replace the state stored in a checkpoint, returning whether it existed
+#[tracing::instrument(skip(con, checkpoint))]
+pub async fn update_snapshot(
+    con: &mut impl GenericClient,
+    checkpoint_id: i64,
+    checkpoint: &StateSnapshot,
+) -> Result<bool, tokio_postgres::Error> {
+    let snapshot = snapshot_codec::encode(checkpoint);
+    let statement = con
+        .prepare_cached(
+            "UPDATE checkpoint SET jsonval = NULL, snapshot = $2 WHERE checkpoint_id = $1",
+        )
+        .await?;
+    let rows = con
+        .execute(&statement, &[&checkpoint_id, &snapshot])
+        .await?;
+    Ok(rows > 0)
+}
+
+// checkpoints stored as json, from before checkpoints were compressed, oldest first
+#[tracing::instrument(skip(con))]
+pub async fn get_uncompressed(
+    con: &mut impl GenericClient,
+    limit: i64,
+) -> Result<Vec<Checkpoint>, tokio_postgres::Error> {
+    let statement = con
+        .prepare_cached(
+            "SELECT * FROM checkpoint WHERE snapshot IS NULL ORDER BY checkpoint_id LIMIT $1",
+        )
+        .await?;
+    let result = con
+        .query(&statement, &[&limit])
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    let statement = con
+        .prepare_cached("DELETE FROM checkpoint WHERE creator_user_id = $1")
+        .await?;
+    con.execute(&statement, &[&creator_user_id]).await
+}
+
+// just the id of the user's latest checkpoint, without its state
+#[tracing::instrument(skip(con))]
+pub async fn get_recent_id_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<Option<i64>, tokio_postgres::Error> {
+    let statement = con
+        .prepare_cached("SELECT MAX(checkpoint_id) FROM checkpoint WHERE creator_user_id = $1")
+        .await?;
+    let row = con.query_one(&statement, &[&creator_user_id]).await?;
+    Ok(row.get(0))
+}
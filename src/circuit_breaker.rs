@@ -0,0 +1,89 @@
+// stops calling an upstream that keeps failing, so callers fail fast instead of each waiting out
+// the timeout while it's down
+// after open_for, calls are let through again; the first one to fail opens it again
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub struct CircuitBreaker {
+    name: &'static str,
+    // each call gives up after this long
+    timeout: Duration,
+    // failures in a row that open the breaker (0 to never open it)
+    failure_threshold: u32,
+    open_for: Duration,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+pub enum CallError<E> {
+    // the breaker is open, so the upstream wasn't called
+    Open,
+    TimedOut,
+    Failed(E),
+}
+
+impl CircuitBreaker {
+    pub fn new(
+        name: &'static str,
+        timeout: Duration,
+        failure_threshold: u32,
+        open_for: Duration,
+    ) -> CircuitBreaker {
+        CircuitBreaker {
+            name,
+            timeout,
+            failure_threshold,
+            open_for,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.open_until.is_some_and(|x| x > Instant::now())
+    }
+
+    // is_failure says which errors mean the upstream is in trouble, rather than that it answered
+    pub async fn call<T, E>(
+        &self,
+        f: impl Future<Output = Result<T, E>>,
+        is_failure: impl Fn(&E) -> bool,
+    ) -> Result<T, CallError<E>> {
+        if self.is_open() {
+            return Err(CallError::Open);
+        }
+        let result = match tokio::time::timeout(self.timeout, f).await {
+            Ok(Ok(x)) => Ok(x),
+            Ok(Err(e)) => Err(CallError::Failed(e)),
+            Err(_) => Err(CallError::TimedOut),
+        };
+        match result {
+            Err(CallError::TimedOut) => self.record_failure(),
+            Err(CallError::Failed(ref e)) if is_failure(e) => self.record_failure(),
+            _ => self.state.lock().unwrap().failures = 0,
+        }
+        result
+    }
+
+    fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.failures += 1;
+        if self.failure_threshold > 0 && state.failures >= self.failure_threshold {
+            if !state.open_until.is_some_and(|x| x > Instant::now()) {
+                log::warn!(
+                    "{} failed {} times in a row; not calling it for {:?}",
+                    self.name,
+                    state.failures,
+                    self.open_for
+                );
+            }
+            state.open_until = Some(Instant::now() + self.open_for);
+        }
+    }
+}
@@ -0,0 +1,181 @@
+// one-off administrative tasks, run instead of the server
+use clap::Subcommand;
+
+use crate::db_pool;
+use crate::handlers::{self, AppError};
+use crate::state_machine::snapshot_checksum;
+use crate::user_worker;
+use crate::{api_token_service, checkpoint_service, snapshot_codec, utils};
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// manage the api tokens used in standalone mode
+    #[clap(subcommand)]
+    Token(TokenCommand),
+    /// replay each checkpoint's ops and check they produce the next checkpoint
+    Verify {
+        /// only verify this user's checkpoints
+        #[clap(long)]
+        user_id: Option<i64>,
+    },
+    /// regenerate checkpoints by replaying ops forward from a trusted checkpoint
+    ///
+    /// stop the server first, since running workers keep their own copy of the state,
+    /// and flush the snapshot cache if there is one
+    RebuildCheckpoints {
+        /// only rebuild this user's checkpoints
+        #[clap(long)]
+        user_id: Option<i64>,
+        /// the checkpoint to trust, instead of the user's first one
+        #[clap(long, requires = "user_id")]
+        from_checkpoint_id: Option<i64>,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum TokenCommand {
+    /// issue a new token for a user, and print it
+    Create {
+        #[clap(long)]
+        user_id: i64,
+    },
+    /// revoke a token, so it can no longer be used to connect
+    Revoke { token: String },
+}
+
+pub async fn run(command: Command, pool: &db_pool::Pool) -> Result<(), AppError> {
+    let mut con = pool.get().await.map_err(handlers::report_pool_err)?;
+    match command {
+        Command::Token(TokenCommand::Create { user_id }) => {
+            // only the hash is stored, so this is the one chance to see the token
+            let token = utils::random_string();
+            api_token_service::add(&mut **con, user_id, utils::sha256_hex(token.as_bytes()))
+                .await
+                .map_err(handlers::report_postgres_err)?;
+            println!("{token}");
+        }
+        Command::Token(TokenCommand::Revoke { token }) => {
+            let revoked = api_token_service::remove_by_api_token_hash(
+                &mut **con,
+                &utils::sha256_hex(token.as_bytes()),
+            )
+            .await
+            .map_err(handlers::report_postgres_err)?;
+            if revoked == 0 {
+                return Err(AppError::NotFound);
+            }
+        }
+        Command::Verify { user_id } => {
+            let user_ids = match user_id {
+                Some(user_id) => vec![user_id],
+                None => checkpoint_service::get_user_ids(&mut con)
+                    .await
+                    .map_err(handlers::report_postgres_err)?,
+            };
+            let mut divergences = 0;
+            for user_id in user_ids {
+                divergences += verify_user(&mut con, user_id).await?;
+            }
+            if divergences > 0 {
+                println!("found {divergences} divergent checkpoints");
+                std::process::exit(1);
+            }
+            println!("all checkpoints are consistent");
+        }
+        Command::RebuildCheckpoints {
+            user_id,
+            from_checkpoint_id,
+        } => {
+            let user_ids = match user_id {
+                Some(user_id) => vec![user_id],
+                None => checkpoint_service::get_user_ids(&mut con)
+                    .await
+                    .map_err(handlers::report_postgres_err)?,
+            };
+            for user_id in user_ids {
+                rebuild_user(&mut con, user_id, from_checkpoint_id).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// print each checkpoint that doesn't match its predecessor's replay, and return how many there were
+async fn verify_user(con: &mut deadpool_postgres::Client, user_id: i64) -> Result<usize, AppError> {
+    let checkpoints = checkpoint_service::get_all_by_user_id(&mut *con, user_id)
+        .await
+        .map_err(handlers::report_postgres_err)?;
+
+    // only read from, so it's rolled back when dropped
+    let tx = con
+        .transaction()
+        .await
+        .map_err(handlers::report_postgres_err)?;
+    let mut divergences = 0;
+    for pair in checkpoints.windows(2) {
+        let (prior, next) = (&pair[0], &pair[1]);
+        let replayed = user_worker::replay_since(&tx, prior.clone()).await?;
+        // compare checksums so formatting differences in the stored json don't matter
+        let expected = snapshot_codec::checkpoint_snapshot(next)?;
+        let expected_checksum = snapshot_checksum(&expected);
+        let replayed_checksum = snapshot_checksum(&replayed.snapshot);
+        if expected_checksum != replayed_checksum {
+            divergences += 1;
+            println!(
+                "user {user_id}: replaying checkpoint {} ({} ops) gives {replayed_checksum}, but checkpoint {} is {expected_checksum}",
+                prior.checkpoint_id, replayed.ops_since_checkpoint, next.checkpoint_id,
+            );
+        }
+    }
+    Ok(divergences)
+}
+
+// overwrite each of the user's checkpoints after the trusted one with its predecessor's replay
+async fn rebuild_user(
+    con: &mut deadpool_postgres::Client,
+    user_id: i64,
+    from_checkpoint_id: Option<i64>,
+) -> Result<(), AppError> {
+    let mut checkpoints = checkpoint_service::get_all_by_user_id(&mut *con, user_id)
+        .await
+        .map_err(handlers::report_postgres_err)?;
+    if let Some(from_checkpoint_id) = from_checkpoint_id {
+        let start = checkpoints
+            .iter()
+            .position(|x| x.checkpoint_id == from_checkpoint_id)
+            .ok_or(AppError::NotFound)?;
+        checkpoints.drain(..start);
+    }
+
+    let mut iter = checkpoints.into_iter();
+    let mut prior = match iter.next() {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+
+    // all or nothing, so a failure doesn't leave a half rebuilt history
+    let mut tx = con
+        .transaction()
+        .await
+        .map_err(handlers::report_postgres_err)?;
+    let mut rebuilt = 0;
+    for mut next in iter {
+        let replayed = user_worker::replay_since(&tx, prior).await?;
+        let expected = snapshot_codec::checkpoint_snapshot(&next)?;
+        if snapshot_checksum(&expected) != snapshot_checksum(&replayed.snapshot) {
+            checkpoint_service::update_snapshot(&mut tx, next.checkpoint_id, &replayed.snapshot)
+                .await
+                .map_err(handlers::report_postgres_err)?;
+            println!("user {user_id}: rebuilt checkpoint {}", next.checkpoint_id);
+            rebuilt += 1;
+        }
+        // the rebuilt state is what the following ops were applied to
+        next.jsonval = None;
+        next.snapshot = Some(snapshot_codec::encode(&replayed.snapshot));
+        prior = next;
+    }
+    tx.commit().await.map_err(handlers::report_postgres_err)?;
+
+    println!("user {user_id}: rebuilt {rebuilt} checkpoints");
+    Ok(())
+}
@@ -0,0 +1,71 @@
+// credentials for other services, like github tokens, are stored sealed with a key only the server
+// has, so that a leaked database or backup doesn't leak them too
+// a sealed credential is a format byte, then the nonce, the ciphertext and the tag
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+
+use crate::handlers::AppError;
+use crate::utils;
+
+/// Sealed with AES-256-GCM.
+const FORMAT_AES_256_GCM: u8 = 1;
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+pub struct CredentialKey([u8; KEY_LEN]);
+
+impl CredentialKey {
+    // None unless s is 32 bytes in hex, surrounding whitespace aside
+    pub fn from_hex(s: &str) -> Option<CredentialKey> {
+        let key = utils::from_hex(s.trim())?;
+        Some(CredentialKey(key.try_into().ok()?))
+    }
+
+    pub fn seal(&self, credential: &str) -> Vec<u8> {
+        let nonce = rand::random::<[u8; NONCE_LEN]>();
+        let mut tag = [0; TAG_LEN];
+        // only fails if openssl can't allocate
+        let ciphertext = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.0,
+            Some(&nonce),
+            &[],
+            credential.as_bytes(),
+            &mut tag,
+        )
+        .unwrap();
+        let mut bytes = Vec::with_capacity(1 + NONCE_LEN + ciphertext.len() + TAG_LEN);
+        bytes.push(FORMAT_AES_256_GCM);
+        bytes.extend(nonce);
+        bytes.extend(ciphertext);
+        bytes.extend(tag);
+        bytes
+    }
+
+    pub fn open(&self, bytes: &[u8]) -> Result<String, AppError> {
+        let sealed = match bytes.split_first() {
+            Some((&FORMAT_AES_256_GCM, sealed)) if sealed.len() >= NONCE_LEN + TAG_LEN => sealed,
+            _ => {
+                log::error!("malformed sealed credential");
+                return Err(AppError::InternalServerError);
+            }
+        };
+        let (nonce, rest) = sealed.split_at(NONCE_LEN);
+        let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+        let credential = decrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.0,
+            Some(nonce),
+            &[],
+            ciphertext,
+            tag,
+        )
+        .map_err(|e| {
+            // most likely sealed with a different key
+            log::error!("couldn't open sealed credential: {e}");
+            AppError::InternalServerError
+        })?;
+        String::from_utf8(credential).map_err(|_| AppError::InternalServerError)
+    }
+}
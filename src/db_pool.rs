@@ -0,0 +1,71 @@
+// the database connection pool, which keeps track of how long getting a connection takes, since
+// that's the first thing to grow when postgres or the pool can't keep up
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use deadpool_postgres::{PoolError, Status};
+
+#[derive(Default)]
+struct WaitStats {
+    gets: AtomicU64,
+    // total and longest, in microseconds
+    wait_micros: AtomicU64,
+    max_wait_micros: AtomicU64,
+    timeouts: AtomicU64,
+}
+
+#[derive(Clone)]
+pub struct Pool {
+    inner: deadpool_postgres::Pool,
+    stats: Arc<WaitStats>,
+}
+
+pub struct PoolStats {
+    pub status: Status,
+    // connections handed out since the pool was built
+    pub gets: u64,
+    pub wait: Duration,
+    pub max_wait: Duration,
+    // gets that gave up waiting for a connection
+    pub timeouts: u64,
+}
+
+impl Pool {
+    pub fn new(inner: deadpool_postgres::Pool) -> Pool {
+        Pool {
+            inner,
+            stats: Arc::new(WaitStats::default()),
+        }
+    }
+
+    pub async fn get(&self) -> Result<deadpool_postgres::Client, PoolError> {
+        let start = Instant::now();
+        let result = self.inner.get().await;
+        let waited = start.elapsed().as_micros() as u64;
+        match result {
+            Ok(_) => {
+                self.stats.gets.fetch_add(1, Ordering::Relaxed);
+                self.stats.wait_micros.fetch_add(waited, Ordering::Relaxed);
+                self.stats
+                    .max_wait_micros
+                    .fetch_max(waited, Ordering::Relaxed);
+            }
+            Err(PoolError::Timeout(_)) => {
+                self.stats.timeouts.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(_) => {}
+        }
+        result
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            status: self.inner.status(),
+            gets: self.stats.gets.load(Ordering::Relaxed),
+            wait: Duration::from_micros(self.stats.wait_micros.load(Ordering::Relaxed)),
+            max_wait: Duration::from_micros(self.stats.max_wait_micros.load(Ordering::Relaxed)),
+            timeouts: self.stats.timeouts.load(Ordering::Relaxed),
+        }
+    }
+}
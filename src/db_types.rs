@@ -1,14 +1,17 @@
 // a checkpoint may summarize the preceeding operations
 // it may also be directly imported from habitica
+// the state is encoded in snapshot, or is json in jsonval if the checkpoint hasn't been compressed yet
 #[derive(Clone, Debug)]
 pub struct Checkpoint {
     pub checkpoint_id: i64,
     pub creation_time: i64,
     pub creator_user_id: i64,
-    pub jsonval: String,
+    pub jsonval: Option<String>,
+    pub snapshot: Option<Vec<u8>>,
 }
 
 // the order of the operations in the database is the canonical order
+// jsonval is the op's json, from whichever column it's stored in
 #[derive(Clone, Debug)]
 pub struct Operation {
     pub operation_id: i64,
@@ -17,3 +20,314 @@ pub struct Operation {
     pub jsonval: String,
 }
 
+// a live task that was soft deleted, and can be restored until it's purged
+#[derive(Clone, Debug)]
+pub struct DeletedTask {
+    pub deleted_task_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub task_id: String,
+    pub value: String,
+}
+
+// a client supplied key for an op that was applied
+#[derive(Clone, Debug)]
+pub struct IdempotencyKey {
+    pub idempotency_key_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub key: String,
+}
+
+// an api key that may only be used to view state, never to change it
+#[derive(Clone, Debug)]
+pub struct ReadOnlyApiKey {
+    pub read_only_api_key_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub api_key_hash: String,
+}
+
+// an api key issued by this server, used instead of the auth service in standalone mode
+#[derive(Clone, Debug)]
+pub struct ApiToken {
+    pub api_token_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub api_token_hash: String,
+}
+
+// a unit of background work, claimed by one instance at a time
+#[derive(Clone, Debug)]
+pub struct Job {
+    pub job_id: i64,
+    pub creation_time: i64,
+    pub jsonval: String,
+    pub attempts: i64,
+    pub run_after: i64,
+    pub last_error: Option<String>,
+    pub dead: bool,
+}
+
+// a browser that wants web push notifications for the user
+#[derive(Clone, Debug)]
+pub struct PushSubscription {
+    pub push_subscription_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub endpoint: String,
+    pub p256dh: String,
+    pub auth: String,
+}
+
+// a push notification to send about a task at remind_time
+#[derive(Clone, Debug)]
+pub struct Reminder {
+    pub reminder_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub task_id: String,
+    pub remind_time: i64,
+}
+
+// a named template, which InstantiateTemplate expands into live tasks
+#[derive(Clone, Debug)]
+pub struct Template {
+    pub template_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub name: String,
+    pub jsonval: String,
+}
+
+// a comment on a task, which may be live or finished
+#[derive(Clone, Debug)]
+pub struct TaskComment {
+    pub task_comment_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub comment_id: String,
+    pub task_id: String,
+    pub value: String,
+}
+
+// a file attached to a task, stored in the attachment bucket under object_key
+#[derive(Clone, Debug)]
+pub struct Attachment {
+    pub attachment_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub task_id: String,
+    pub object_key: String,
+    pub filename: String,
+    pub content_type: String,
+    pub size: i64,
+}
+
+// a user's Preferences, as json
+#[derive(Clone, Debug)]
+pub struct Preference {
+    pub preference_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub jsonval: String,
+}
+
+// a finished task that was moved into the user's history at the end of their day
+#[derive(Clone, Debug)]
+pub struct ArchivedTask {
+    pub archived_task_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub day: String,
+    pub task_id: String,
+    pub value: String,
+    pub status_json: String,
+}
+
+// the last day the user rolled over
+#[derive(Clone, Debug)]
+pub struct Rollover {
+    pub rollover_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub day: String,
+}
+
+// a live task that was moved out of the default lane
+#[derive(Clone, Debug)]
+pub struct TaskLane {
+    pub task_lane_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub task_id: String,
+    pub lane: String,
+}
+
+// a token other services post to, to create live tasks for the user
+#[derive(Clone, Debug)]
+pub struct Webhook {
+    pub webhook_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub token_hash: String,
+    pub source: Option<String>,
+    pub max_per_hour: i64,
+}
+
+// an address the user can email tasks to
+#[derive(Clone, Debug)]
+pub struct EmailAlias {
+    pub email_alias_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub alias: String,
+}
+
+// a repo whose issues assigned to the user are synced with their live tasks
+#[derive(Clone, Debug)]
+pub struct GithubLink {
+    pub github_link_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub repo: String,
+    // the personal access token, sealed with the server's credential key
+    // None for links made before tokens were sealed, until the server seals them
+    pub sealed_token: Option<Vec<u8>>,
+    pub login: String,
+    pub close_issues: bool,
+}
+
+// the live task standing for something in another service
+#[derive(Clone, Debug)]
+pub struct ExternalTask {
+    pub external_task_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub source: String,
+    pub external_id: String,
+    pub task_id: String,
+}
+
+// a frontend or org sharing the deployment
+#[derive(Clone, Debug)]
+pub struct Tenant {
+    pub tenant_id: i64,
+    pub creation_time: i64,
+    pub name: String,
+    pub admin_user_id: i64,
+}
+
+// the tenant a user belongs to
+#[derive(Clone, Debug)]
+pub struct TenantUser {
+    pub tenant_user_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub tenant_id: i64,
+}
+
+// a change to the user's account, for their audit log
+#[derive(Clone, Debug)]
+pub struct AuditEvent {
+    pub audit_event_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub kind: String,
+    pub detail: Option<String>,
+    pub session_id: Option<String>,
+    pub source_ip: Option<String>,
+}
+
+// how many ops a user applied on a day, for their quota
+#[derive(Clone, Debug)]
+pub struct OpUsage {
+    pub op_usage_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub day: String,
+    pub ops: i64,
+}
+
+// how much effort a task is estimated to take, in the user's own unit
+#[derive(Clone, Debug)]
+pub struct TaskEstimate {
+    pub task_estimate_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub task_id: String,
+    pub estimate: i64,
+}
+
+// how long a task was focused on, and since when it's been focused if it is now
+#[derive(Clone, Debug)]
+pub struct TaskFocus {
+    pub task_focus_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub task_id: String,
+    pub millis: i64,
+    pub focused_since: Option<i64>,
+}
+
+// something the user is working toward, which tasks can be linked to
+#[derive(Clone, Debug)]
+pub struct Goal {
+    pub goal_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub name: String,
+}
+
+// the goal a task counts toward, and whether it's been completed
+#[derive(Clone, Debug)]
+pub struct TaskGoal {
+    pub task_goal_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub task_id: String,
+    pub goal_id: i64,
+    pub completed: bool,
+}
+
+// a habit, which is checked in on by day rather than finished
+#[derive(Clone, Debug)]
+pub struct HabitDefinition {
+    pub habit_definition_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub habit_id: String,
+    pub value: String,
+}
+
+// a day a habit was done on
+#[derive(Clone, Debug)]
+pub struct HabitCheckIn {
+    pub habit_check_in_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub habit_id: String,
+    pub day: String,
+}
+
+// the last day the user was pushed a digest
+#[derive(Clone, Debug)]
+pub struct NotificationDigest {
+    pub notification_digest_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub day: String,
+}
+
+// a device the user has opened websockets from
+#[derive(Clone, Debug)]
+pub struct KnownDevice {
+    pub known_device_id: i64,
+    pub creation_time: i64,
+    pub creator_user_id: i64,
+    pub device_id: String,
+    pub label: Option<String>,
+    pub platform: Option<String>,
+    pub last_seen_time: i64,
+    pub revoked: bool,
+}
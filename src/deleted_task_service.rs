@@ -0,0 +1,136 @@
+use super::db_types::*;
+use todoproxy_api::LiveTask;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for DeletedTask {
+    // select * from deleted_task order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> DeletedTask {
+        DeletedTask {
+            deleted_task_id: row.get("deleted_task_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            task_id: row.get("task_id"),
+            value: row.get("value"),
+        }
+    }
+}
+
+#[tracing::instrument(skip(con, task))]
+pub async fn add(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    task: LiveTask,
+) -> Result<DeletedTask, tokio_postgres::Error> {
+    let row = con
+        .query_one(
+            "INSERT INTO
+             deleted_task(
+                 creator_user_id,
+                 task_id,
+                 value
+             )
+             VALUES($1, $2, $3)
+             RETURNING deleted_task_id, creation_time
+            ",
+            &[&creator_user_id, &task.id, &task.value],
+        )
+        .await?;
+
+    // return deleted task
+    Ok(DeletedTask {
+        deleted_task_id: row.get(0),
+        creation_time: row.get(1),
+        creator_user_id,
+        task_id: task.id,
+        value: task.value,
+    })
+}
+
+// the same id may have been deleted more than once, so get the latest
+#[tracing::instrument(skip(con))]
+pub async fn get_recent_by_task_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    task_id: &str,
+) -> Result<Option<DeletedTask>, tokio_postgres::Error> {
+    let result = con
+        .query_opt(
+            "SELECT *
+             FROM deleted_task
+             WHERE creator_user_id = $1 AND task_id = $2
+             ORDER BY deleted_task_id DESC
+             LIMIT 1
+            ",
+            &[&creator_user_id, &task_id],
+        )
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+// most recently deleted first
+#[tracing::instrument(skip(con))]
+pub async fn get_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<Vec<DeletedTask>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT *
+             FROM deleted_task
+             WHERE creator_user_id = $1
+             ORDER BY deleted_task_id DESC
+            ",
+            &[&creator_user_id],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+
+    Ok(result)
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn remove(
+    con: &mut impl GenericClient,
+    deleted_task_id: i64,
+) -> Result<(), tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM deleted_task WHERE deleted_task_id = $1",
+        &[&deleted_task_id],
+    )
+    .await?;
+    Ok(())
+}
+
+// returns the tasks purged, so anything attached to them can be removed too
+#[tracing::instrument(skip(con))]
+pub async fn purge_created_before(
+    con: &mut impl GenericClient,
+    creation_time: i64,
+) -> Result<Vec<DeletedTask>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "DELETE FROM deleted_task WHERE creation_time < $1 RETURNING *",
+            &[&creation_time],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM deleted_task WHERE creator_user_id = $1",
+        &[&creator_user_id],
+    )
+    .await
+}
@@ -0,0 +1,66 @@
+// draining lets an instance be replaced without surprising its clients: open websockets are told
+// the server is restarting and closed once a grace period is over, and new ones are told when to
+// come back (by which time a load balancer should be sending them somewhere else)
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+
+pub struct Drain {
+    grace_period: Duration,
+    retry_after: Duration,
+    // set once the instance starts draining, which it doesn't stop doing until it exits
+    draining: watch::Sender<bool>,
+}
+
+impl Drain {
+    pub fn new(grace_period: Duration, retry_after: Duration) -> Drain {
+        Drain {
+            grace_period,
+            retry_after,
+            draining: watch::Sender::new(false),
+        }
+    }
+
+    // how long open websockets are kept open after draining starts
+    pub fn grace_period(&self) -> Duration {
+        self.grace_period
+    }
+
+    // how long clients turned away while draining should wait before reconnecting
+    pub fn retry_after(&self) -> Duration {
+        self.retry_after
+    }
+
+    pub fn is_draining(&self) -> bool {
+        *self.draining.borrow()
+    }
+
+    // false if the instance was already draining
+    pub fn start(&self) -> bool {
+        self.draining
+            .send_if_modified(|x| !std::mem::replace(x, true))
+    }
+
+    // sees draining start
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.draining.subscribe()
+    }
+}
+
+// starts draining when the process gets SIGUSR1, e.g. from a deploy script
+pub async fn on_signal(drain: Arc<Drain>) {
+    let mut signals = match signal(SignalKind::user_defined1()) {
+        Ok(signals) => signals,
+        Err(e) => {
+            log::error!("couldn't listen for SIGUSR1: {e}");
+            return;
+        }
+    };
+    while signals.recv().await.is_some() {
+        if drain.start() {
+            log::info!("got SIGUSR1; draining");
+        }
+    }
+}
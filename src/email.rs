@@ -0,0 +1,396 @@
+// turns emails sent to users' aliases into live tasks, by polling an imap mailbox
+// every alias is a plus address of the one mailbox (e.g. tasks+abc123@example.com), so a single
+// account receives mail for every user; the subject becomes the task and the body a comment on it
+// only the few imap commands needed are spoken, over implicit tls
+use std::io;
+use std::time::Duration;
+
+use mail_parser::{Message, MessageParser};
+use todoproxy_api::WebsocketOpKind;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_native_tls::{native_tls, TlsStream};
+
+use crate::handlers::{self, AppError};
+use crate::protocol::{ExtOpKind, Op, OpKind};
+use crate::push::{self, PushPayload};
+use crate::user_worker::{self, ApplyOutcome, Registration};
+use crate::{email_alias_service, preferences, utils, AppData};
+
+/// Only this much of each message is fetched; the rest of a long body is dropped.
+const MAX_MESSAGE_BYTES: usize = 1024 * 1024;
+
+/// How long to wait for the server to answer a command.
+const IMAP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Tasks made from emails without a subject get this value.
+const NO_SUBJECT: &str = "(no subject)";
+
+pub struct EmailGateway {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+    pub folder: String,
+    // the mailbox's own address, which aliases are plus addresses of
+    local_part: String,
+    domain: String,
+    pub poll_interval: Duration,
+}
+
+impl EmailGateway {
+    pub fn new(
+        host: String,
+        port: u16,
+        username: String,
+        password: String,
+        folder: String,
+        address: String,
+        poll_interval: Duration,
+    ) -> Result<EmailGateway, String> {
+        let (local_part, domain) = match address.split_once('@') {
+            Some((local_part, domain))
+                if !local_part.is_empty() && !local_part.contains('+') && !domain.is_empty() =>
+            {
+                (local_part.to_lowercase(), domain.to_lowercase())
+            }
+            _ => {
+                return Err(format!(
+                    "{address} isn't an address aliases can be added to"
+                ))
+            }
+        };
+        if [&username, &password, &folder]
+            .iter()
+            .any(|x| x.contains(['\r', '\n']))
+        {
+            return Err("imap credentials and folder can't contain line breaks".to_owned());
+        }
+        Ok(EmailGateway {
+            host,
+            port,
+            username,
+            password,
+            folder,
+            local_part,
+            domain,
+            poll_interval,
+        })
+    }
+
+    // where mail for the alias should be sent
+    pub fn address(&self, alias: &str) -> String {
+        format!("{}+{alias}@{}", self.local_part, self.domain)
+    }
+
+    // the alias an address is for, if it's one of ours
+    fn alias_of(&self, address: &str) -> Option<String> {
+        let address = address.trim().trim_start_matches('<').trim_end_matches('>');
+        let (local_part, domain) = address.rsplit_once('@')?;
+        if !domain.eq_ignore_ascii_case(&self.domain) {
+            return None;
+        }
+        let (local_part, alias) = local_part.split_once('+')?;
+        if !local_part.eq_ignore_ascii_case(&self.local_part) || alias.is_empty() {
+            return None;
+        }
+        Some(alias.to_lowercase())
+    }
+}
+
+// a new alias, in lower case since some mail servers don't keep the case of addresses
+pub fn new_alias() -> String {
+    utils::random_string().to_lowercase()
+}
+
+// checks for new mail every poll interval, until the server stops
+// mail is only marked seen once its task was made, so failures are retried next time
+pub async fn poll(data: AppData) {
+    let Some(gateway) = data.email.clone() else {
+        return;
+    };
+    let mut interval = tokio::time::interval(gateway.poll_interval);
+    loop {
+        interval.tick().await;
+        match poll_once(&data, &gateway).await {
+            Ok(0) => {}
+            Ok(n) => log::info!("made tasks from {n} emails"),
+            Err(e) => log::error!("couldn't check {} for mail: {e}", gateway.host),
+        }
+    }
+}
+
+// returns how many emails were turned into tasks
+async fn poll_once(data: &AppData, gateway: &EmailGateway) -> io::Result<usize> {
+    let mut imap = Imap::connect(&gateway.host, gateway.port).await?;
+    imap.command(&format!(
+        "LOGIN {} {}",
+        quote(&gateway.username),
+        quote(&gateway.password)
+    ))
+    .await?;
+    imap.command(&format!("SELECT {}", quote(&gateway.folder)))
+        .await?;
+
+    let mut uids = vec![];
+    for response in imap.command("UID SEARCH UNSEEN").await? {
+        if let Some(rest) = response.line.strip_prefix("* SEARCH") {
+            uids.extend(
+                rest.split_whitespace()
+                    .filter_map(|x| x.parse::<u32>().ok()),
+            );
+        }
+    }
+
+    let mut delivered = 0;
+    for uid in uids {
+        let responses = imap
+            .command(&format!(
+                "UID FETCH {uid} (BODY.PEEK[]<0.{MAX_MESSAGE_BYTES}>)"
+            ))
+            .await?;
+        let raw = responses
+            .into_iter()
+            .find(|x| x.line.contains(" FETCH "))
+            .and_then(|x| x.literals.into_iter().next());
+        let result = match raw {
+            Some(raw) => deliver(data, gateway, &raw).await,
+            // it was deleted while we were looking
+            None => Ok(false),
+        };
+        match result {
+            Ok(true) => delivered += 1,
+            Ok(false) => {}
+            // left unseen, to be tried again
+            Err(e) => {
+                log::error!("couldn't make a task from email {uid}: {e}");
+                continue;
+            }
+        }
+        imap.command(&format!("UID STORE {uid} +FLAGS.SILENT (\\Seen)"))
+            .await?;
+    }
+
+    imap.command("LOGOUT").await?;
+    Ok(delivered)
+}
+
+// returns whether a task was made from the email
+// mail that isn't to one of our aliases is ignored, and will be marked seen
+async fn deliver(data: &AppData, gateway: &EmailGateway, raw: &[u8]) -> Result<bool, AppError> {
+    let Some(message) = MessageParser::default().parse(raw) else {
+        log::warn!("ignoring email that couldn't be parsed");
+        return Ok(false);
+    };
+    let mut alias = None;
+    for address in recipients(&message) {
+        if let Some(x) = gateway.alias_of(&address) {
+            alias = Some(x);
+            break;
+        }
+    }
+    let Some(alias) = alias else {
+        return Ok(false);
+    };
+    let email_alias = {
+        let con: &mut tokio_postgres::Client =
+            &mut *data.pool.get().await.map_err(handlers::report_pool_err)?;
+        email_alias_service::get_by_alias(&mut *con, &alias)
+            .await
+            .map_err(handlers::report_postgres_err)?
+    };
+    // the alias was deleted, or never existed
+    let Some(email_alias) = email_alias else {
+        return Ok(false);
+    };
+    let user_id = email_alias.creator_user_id;
+
+    let max_len = data.task_limits.max_value_len;
+    let value = message
+        .subject()
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .unwrap_or(NO_SUBJECT);
+    let value = utils::truncate(value, max_len).to_owned();
+    let body = message
+        .body_text(0)
+        .map(|x| utils::truncate(x.trim(), max_len).to_owned())
+        .filter(|x| !x.is_empty());
+    // a message that's fetched again after a failure doesn't make a second task
+    let idempotency_key = message
+        .message_id()
+        .map(|x| format!("email-{x}"))
+        .unwrap_or_else(|| format!("email-{}", utils::sha256_hex(raw)));
+
+    let (worker, _) = user_worker::connect(data, user_id).await?;
+    let _registration = Registration(worker.clone());
+    let outcome = worker
+        .apply_op(Op {
+            alleged_time: utils::current_time_millis(),
+            kind: OpKind::Ext(ExtOpKind::InsLiveTaskNew {
+                value: value.clone(),
+            }),
+            seq: None,
+            checksum: None,
+            idempotency_key: Some(idempotency_key.clone()),
+            value_encoding: None,
+        })
+        .await?;
+    let task_id = match outcome {
+        ApplyOutcome::Applied(Op {
+            kind: OpKind::Base(WebsocketOpKind::InsLiveTask { id, .. }),
+            ..
+        }) => id,
+        // the task was made the last time it was fetched
+        ApplyOutcome::Duplicate => return Ok(false),
+        // new tasks are always stored as an InsLiveTask
+        ApplyOutcome::Applied(_) => return Ok(true),
+        ApplyOutcome::Rejected(reason) => {
+            log::warn!("email to user {user_id} was rejected: {reason:?}");
+            return Ok(false);
+        }
+    };
+    if let Some(body) = body {
+        let outcome = worker
+            .apply_op(Op {
+                alleged_time: utils::current_time_millis(),
+                kind: OpKind::Ext(ExtOpKind::CommentAdd {
+                    id: utils::random_string(),
+                    task_id: task_id.clone(),
+                    value: body,
+                }),
+                seq: None,
+                checksum: None,
+                idempotency_key: Some(format!("{idempotency_key}-body")),
+                value_encoding: None,
+            })
+            .await?;
+        if let ApplyOutcome::Rejected(reason) = outcome {
+            log::warn!("body of email to user {user_id} was rejected: {reason:?}");
+        }
+    }
+    if data.push.is_some() {
+        let con: &mut tokio_postgres::Client =
+            &mut *data.pool.get().await.map_err(handlers::report_pool_err)?;
+        let preferences = preferences::get(&mut *con, user_id).await?;
+        if preferences.notifications.email_push {
+            let payload = PushPayload::EmailTask { task_id, value };
+            push::queue(&mut *con, user_id, &preferences, &payload)
+                .await
+                .map_err(handlers::report_postgres_err)?;
+        }
+    }
+    Ok(true)
+}
+
+// every address the message was delivered to
+fn recipients(message: &Message) -> Vec<String> {
+    let mut addresses: Vec<String> = [message.to(), message.cc()]
+        .into_iter()
+        .flatten()
+        .flat_map(|x| x.iter())
+        .filter_map(|x| x.address.as_ref().map(|x| x.to_string()))
+        .collect();
+    // mail that was bcc'd to us only says who it was for in headers the server adds
+    for header in ["Delivered-To", "X-Original-To"] {
+        if let Some(x) = message.header_raw(header) {
+            addresses.push(x.to_owned());
+        }
+    }
+    addresses
+}
+
+// an imap quoted string
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// an untagged response, with the contents of any literals in it taken out
+struct Response {
+    line: String,
+    literals: Vec<Vec<u8>>,
+}
+
+struct Imap {
+    stream: BufReader<TlsStream<TcpStream>>,
+    next_tag: u32,
+}
+
+impl Imap {
+    async fn connect(host: &str, port: u16) -> io::Result<Imap> {
+        let tcp = TcpStream::connect((host, port)).await?;
+        let connector = native_tls::TlsConnector::new().map_err(io::Error::other)?;
+        let tls = tokio_native_tls::TlsConnector::from(connector)
+            .connect(host, tcp)
+            .await
+            .map_err(io::Error::other)?;
+        let mut imap = Imap {
+            stream: BufReader::new(tls),
+            next_tag: 0,
+        };
+        let greeting = imap.read_response().await?;
+        if !greeting.line.starts_with("* OK") {
+            return Err(io::Error::other(format!(
+                "unexpected greeting: {}",
+                greeting.line
+            )));
+        }
+        Ok(imap)
+    }
+
+    // sends the command and returns the untagged responses to it, if it succeeded
+    async fn command(&mut self, command: &str) -> io::Result<Vec<Response>> {
+        self.next_tag += 1;
+        let tag = format!("A{}", self.next_tag);
+        self.stream
+            .write_all(format!("{tag} {command}\r\n").as_bytes())
+            .await?;
+        self.stream.flush().await?;
+
+        let mut responses = vec![];
+        loop {
+            let response = tokio::time::timeout(IMAP_TIMEOUT, self.read_response())
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "imap server timed out"))??;
+            match response.line.strip_prefix(&format!("{tag} ")) {
+                Some(status) if status.starts_with("OK") => return Ok(responses),
+                // the credentials aren't logged
+                Some(status) => {
+                    let verb = command.split(' ').next().unwrap_or_default();
+                    return Err(io::Error::other(format!("{verb} failed: {status}")));
+                }
+                None => responses.push(response),
+            }
+        }
+    }
+
+    // a line ending in {n} is followed by n bytes, then the rest of the line
+    async fn read_response(&mut self) -> io::Result<Response> {
+        let mut line = String::new();
+        let mut literals = vec![];
+        loop {
+            let mut buf = vec![];
+            if self.stream.read_until(b'\n', &mut buf).await? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let part = String::from_utf8_lossy(&buf);
+            let part = part.trim_end_matches(['\r', '\n']);
+            let len = part
+                .strip_suffix('}')
+                .and_then(|x| x.rsplit_once('{'))
+                .and_then(|(_, n)| n.parse::<usize>().ok());
+            line.push_str(part);
+            match len {
+                Some(len) if len <= MAX_MESSAGE_BYTES => {
+                    let mut literal = vec![0; len];
+                    self.stream.read_exact(&mut literal).await?;
+                    literals.push(literal);
+                }
+                Some(len) => {
+                    return Err(io::Error::other(format!("{len} byte literal is too long")));
+                }
+                None => return Ok(Response { line, literals }),
+            }
+        }
+    }
+}
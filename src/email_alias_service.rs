@@ -0,0 +1,102 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for EmailAlias {
+    // select * from email_alias order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> EmailAlias {
+        EmailAlias {
+            email_alias_id: row.get("email_alias_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            alias: row.get("alias"),
+        }
+    }
+}
+
+#[tracing::instrument(skip(con, alias))]
+pub async fn add(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    alias: String,
+) -> Result<EmailAlias, tokio_postgres::Error> {
+    let row = con
+        .query_one(
+            "INSERT INTO
+             email_alias(
+                 creator_user_id,
+                 alias
+             )
+             VALUES($1, $2)
+             RETURNING email_alias_id, creation_time
+            ",
+            &[&creator_user_id, &alias],
+        )
+        .await?;
+
+    // return email alias
+    Ok(EmailAlias {
+        email_alias_id: row.get(0),
+        creation_time: row.get(1),
+        creator_user_id,
+        alias,
+    })
+}
+
+#[tracing::instrument(skip(con, alias))]
+pub async fn get_by_alias(
+    con: &mut impl GenericClient,
+    alias: &str,
+) -> Result<Option<EmailAlias>, tokio_postgres::Error> {
+    let result = con
+        .query_opt("SELECT * FROM email_alias WHERE alias=$1", &[&alias])
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+// oldest first
+#[tracing::instrument(skip(con))]
+pub async fn get_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<Vec<EmailAlias>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT * FROM email_alias WHERE creator_user_id = $1 ORDER BY email_alias_id",
+            &[&creator_user_id],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// returns whether the user had an alias with that id
+#[tracing::instrument(skip(con))]
+pub async fn remove(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    email_alias_id: i64,
+) -> Result<bool, tokio_postgres::Error> {
+    let removed = con
+        .execute(
+            "DELETE FROM email_alias WHERE creator_user_id = $1 AND email_alias_id = $2",
+            &[&creator_user_id, &email_alias_id],
+        )
+        .await?;
+    Ok(removed > 0)
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM email_alias WHERE creator_user_id = $1",
+        &[&creator_user_id],
+    )
+    .await
+}
@@ -0,0 +1,156 @@
+// publishes every op stored on this instance to kafka or nats, for analytics and automation that
+// would otherwise have to poll the op log
+// events are sent from a queue in the order they were stored, at most once: if the queue is full or
+// the broker can't be reached they're dropped, and consumers can fill gaps from the op log by seq
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::ClientConfig;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::protocol::Op;
+
+/// How many events may be waiting to be published before new ones are dropped.
+const QUEUE_CAPACITY: usize = 10000;
+
+/// How long kafka may take to acknowledge an event.
+const KAFKA_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum EventStreamKind {
+    // a topic, with events keyed by user id so each user's stay in order
+    Kafka,
+    // a subject per user: the configured one followed by .<user_id>
+    Nats,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TaskEvent {
+    pub user_id: i64,
+    // the operation_id the op was stored under, which increases with each op a user stores
+    pub seq: i64,
+    pub op: Op,
+}
+
+// since this instance started
+static EVENTS_PUBLISHED: AtomicU64 = AtomicU64::new(0);
+static EVENTS_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+pub fn events_published() -> u64 {
+    EVENTS_PUBLISHED.load(Ordering::Relaxed)
+}
+
+pub fn events_dropped() -> u64 {
+    EVENTS_DROPPED.load(Ordering::Relaxed)
+}
+
+// where events go
+pub trait EventPublisher: Send + Sync {
+    fn publish<'a>(&'a self, user_id: i64, payload: &'a [u8]) -> BoxFuture<'a, Result<(), String>>;
+}
+
+pub struct KafkaPublisher {
+    producer: FutureProducer,
+    topic: String,
+}
+
+impl KafkaPublisher {
+    // brokers is a comma separated list of host:port
+    pub fn new(brokers: &str, topic: String) -> Result<KafkaPublisher, rdkafka::error::KafkaError> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("message.timeout.ms", KAFKA_TIMEOUT.as_millis().to_string())
+            .create()?;
+        Ok(KafkaPublisher { producer, topic })
+    }
+}
+
+impl EventPublisher for KafkaPublisher {
+    fn publish<'a>(&'a self, user_id: i64, payload: &'a [u8]) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            let key = user_id.to_string();
+            let record = FutureRecord::to(&self.topic).key(&key).payload(payload);
+            self.producer
+                .send(record, KAFKA_TIMEOUT)
+                .await
+                .map_err(|(e, _)| e.to_string())?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+pub struct NatsPublisher {
+    pub client: async_nats::Client,
+    pub subject: String,
+}
+
+impl EventPublisher for NatsPublisher {
+    fn publish<'a>(&'a self, user_id: i64, payload: &'a [u8]) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            self.client
+                .publish(
+                    format!("{}.{user_id}", self.subject),
+                    payload.to_vec().into(),
+                )
+                .await
+                .map_err(|e| e.to_string())
+        }
+        .boxed()
+    }
+}
+
+// queues events for a task that publishes them, so storing ops never waits on the broker
+#[derive(Clone)]
+pub struct EventStream {
+    tx: mpsc::Sender<TaskEvent>,
+}
+
+impl EventStream {
+    pub fn new(publisher: Arc<dyn EventPublisher>) -> EventStream {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(run(publisher, rx));
+        EventStream { tx }
+    }
+
+    // call once the op is committed
+    pub fn emit(&self, event: TaskEvent) {
+        if let Err(e) = self.tx.try_send(event) {
+            let dropped = EVENTS_DROPPED.fetch_add(1, Ordering::Relaxed);
+            // once per thousand, so a broker that's down doesn't flood the log
+            if dropped.is_multiple_of(1000) {
+                log::warn!("dropping events, since the publisher can't keep up: {e}");
+            }
+        }
+    }
+}
+
+async fn run(publisher: Arc<dyn EventPublisher>, mut rx: mpsc::Receiver<TaskEvent>) {
+    while let Some(event) = rx.recv().await {
+        let payload = match serde_json::to_vec(&event) {
+            Ok(x) => x,
+            Err(e) => {
+                log::error!("couldn't serialize event: {e}");
+                continue;
+            }
+        };
+        match publisher.publish(event.user_id, &payload).await {
+            Ok(()) => {
+                EVENTS_PUBLISHED.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                EVENTS_DROPPED.fetch_add(1, Ordering::Relaxed);
+                log::error!(
+                    "couldn't publish op {} for user {}: {e}",
+                    event.seq,
+                    event.user_id
+                );
+            }
+        }
+    }
+}
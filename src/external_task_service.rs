@@ -0,0 +1,115 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for ExternalTask {
+    // select * from external_task order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> ExternalTask {
+        ExternalTask {
+            external_task_id: row.get("external_task_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            source: row.get("source"),
+            external_id: row.get("external_id"),
+            task_id: row.get("task_id"),
+        }
+    }
+}
+
+// returns None if the user already has a task for it
+#[tracing::instrument(skip(con))]
+pub async fn add(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    source: String,
+    external_id: String,
+    task_id: String,
+) -> Result<Option<ExternalTask>, tokio_postgres::Error> {
+    let row = con
+        .query_opt(
+            "INSERT INTO
+             external_task(
+                 creator_user_id,
+                 source,
+                 external_id,
+                 task_id
+             )
+             VALUES($1, $2, $3, $4)
+             ON CONFLICT DO NOTHING
+             RETURNING external_task_id, creation_time
+            ",
+            &[&creator_user_id, &source, &external_id, &task_id],
+        )
+        .await?;
+
+    // return external task
+    Ok(row.map(|row| ExternalTask {
+        external_task_id: row.get(0),
+        creation_time: row.get(1),
+        creator_user_id,
+        source,
+        external_id,
+        task_id,
+    }))
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn get_by_task_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    task_id: &str,
+) -> Result<Option<ExternalTask>, tokio_postgres::Error> {
+    let result = con
+        .query_opt(
+            "SELECT * FROM external_task WHERE creator_user_id = $1 AND task_id = $2",
+            &[&creator_user_id, &task_id],
+        )
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn get_all_by_source(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    source: &str,
+) -> Result<Vec<ExternalTask>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT * FROM external_task WHERE creator_user_id = $1 AND source = $2",
+            &[&creator_user_id, &source],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn remove(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    source: &str,
+    external_id: &str,
+) -> Result<(), tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM external_task WHERE creator_user_id = $1 AND source = $2 AND external_id = $3",
+        &[&creator_user_id, &source, &external_id],
+    )
+    .await?;
+    Ok(())
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM external_task WHERE creator_user_id = $1",
+        &[&creator_user_id],
+    )
+    .await
+}
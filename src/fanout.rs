@@ -0,0 +1,250 @@
+// keeps the workers of one user on different instances in sync
+// ops are stored under a per-user advisory lock, after the worker has replayed anything other
+// instances stored first, so every instance applies them in operation_id order.
+// the broadcast backend then tells every instance, whose worker for that user (if any) catches up
+use std::sync::Arc;
+use std::time::Duration;
+
+use dashmap::DashMap;
+use futures_util::future::BoxFuture;
+use futures_util::{stream, FutureExt, StreamExt};
+use redis::AsyncCommands;
+use tokio::sync::mpsc;
+use tokio_postgres::{AsyncMessage, GenericClient};
+
+use crate::db_pool;
+use crate::handlers::{self, AppError};
+use crate::user_worker::UserWorkerHandle;
+
+/// The postgres notification channel every instance listens on; the payload is a user id.
+const PG_CHANNEL: &str = "todoproxy_ops";
+
+/// Each user has their own redis channel, named this followed by their user id.
+const REDIS_CHANNEL_PREFIX: &str = "todoproxy:ops:";
+
+/// How long to wait before reconnecting a listener after losing it.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum BroadcastBackendKind {
+    // a single instance, where the workers' own broadcast channels are enough
+    Local,
+    // postgres LISTEN/NOTIFY, on the database the instances already share
+    Postgres,
+    // redis pub/sub, with a channel per user
+    Redis,
+}
+
+// how an instance hears that another one stored ops
+pub trait BroadcastBackend: Send + Sync {
+    // whether other instances may store ops for the same users, so ops have to be ordered
+    // through the database
+    fn is_shared(&self) -> bool;
+    // tells the other instances that ops were stored for the user; call after they're committed
+    fn publish(&self, user_id: i64) -> BoxFuture<'_, Result<(), AppError>>;
+    // makes local workers catch up whenever another instance publishes, for as long as the server runs
+    fn listen(
+        self: Arc<Self>,
+        user_worker_data: Arc<DashMap<i64, UserWorkerHandle>>,
+    ) -> BoxFuture<'static, ()>;
+}
+
+// held until the transaction ends, so only one instance stores ops for the user at a time
+pub async fn lock_user(
+    con: &mut impl GenericClient,
+    user_id: i64,
+) -> Result<(), tokio_postgres::Error> {
+    con.execute("SELECT pg_advisory_xact_lock($1)", &[&user_id])
+        .await?;
+    Ok(())
+}
+
+// users without a worker here will load the ops when they connect
+async fn catch_up_user(user_worker_data: &DashMap<i64, UserWorkerHandle>, user_id: i64) {
+    let worker = user_worker_data.get(&user_id).map(|x| x.value().clone());
+    if let Some(worker) = worker {
+        worker.catch_up().await;
+    }
+}
+
+// after (re)connecting a listener, since we may have missed messages while we weren't listening
+async fn catch_up_all(user_worker_data: &DashMap<i64, UserWorkerHandle>) {
+    let workers = user_worker_data
+        .iter()
+        .map(|x| x.value().clone())
+        .collect::<Vec<_>>();
+    for worker in workers {
+        worker.catch_up().await;
+    }
+}
+
+pub struct LocalBackend;
+
+impl BroadcastBackend for LocalBackend {
+    fn is_shared(&self) -> bool {
+        false
+    }
+
+    fn publish(&self, _user_id: i64) -> BoxFuture<'_, Result<(), AppError>> {
+        async { Ok(()) }.boxed()
+    }
+
+    fn listen(
+        self: Arc<Self>,
+        _user_worker_data: Arc<DashMap<i64, UserWorkerHandle>>,
+    ) -> BoxFuture<'static, ()> {
+        async {}.boxed()
+    }
+}
+
+pub struct PostgresBackend {
+    pub pool: db_pool::Pool,
+    // the listener needs a connection of its own, outside the pool
+    pub config: tokio_postgres::Config,
+}
+
+impl BroadcastBackend for PostgresBackend {
+    fn is_shared(&self) -> bool {
+        true
+    }
+
+    fn publish(&self, user_id: i64) -> BoxFuture<'_, Result<(), AppError>> {
+        async move {
+            let con: &mut tokio_postgres::Client =
+                &mut *self.pool.get().await.map_err(handlers::report_pool_err)?;
+            con.execute(
+                "SELECT pg_notify($1, $2)",
+                &[&PG_CHANNEL, &user_id.to_string()],
+            )
+            .await
+            .map_err(handlers::report_postgres_err)?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn listen(
+        self: Arc<Self>,
+        user_worker_data: Arc<DashMap<i64, UserWorkerHandle>>,
+    ) -> BoxFuture<'static, ()> {
+        async move {
+            loop {
+                if let Err(e) = self.listen_once(&user_worker_data).await {
+                    log::error!("lost the postgres op notification listener: {e}");
+                }
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+        .boxed()
+    }
+}
+
+impl PostgresBackend {
+    async fn listen_once(
+        &self,
+        user_worker_data: &DashMap<i64, UserWorkerHandle>,
+    ) -> Result<(), tokio_postgres::Error> {
+        let (client, mut connection) = self.config.connect(tokio_postgres::NoTls).await?;
+
+        // notifications only arrive by polling the connection, which also drives the client
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut messages = stream::poll_fn(move |cx| connection.poll_message(cx));
+        tokio::spawn(async move {
+            while let Some(message) = messages.next().await {
+                match message {
+                    Ok(AsyncMessage::Notification(n)) => {
+                        if tx.send(Ok(n)).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        let _ = tx.send(Err(e));
+                        break;
+                    }
+                }
+            }
+        });
+
+        client
+            .batch_execute(&format!("LISTEN {PG_CHANNEL}"))
+            .await?;
+        log::info!("listening for ops stored by other instances on postgres");
+        catch_up_all(user_worker_data).await;
+
+        while let Some(notification) = rx.recv().await {
+            if let Ok(user_id) = notification?.payload().parse::<i64>() {
+                catch_up_user(user_worker_data, user_id).await;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct RedisBackend {
+    pub client: redis::Client,
+    // multiplexed, so every worker can publish on it at once
+    pub con: redis::aio::MultiplexedConnection,
+}
+
+fn report_redis_err(e: redis::RedisError) -> AppError {
+    log::error!("{}", e);
+    AppError::InternalServerError
+}
+
+impl BroadcastBackend for RedisBackend {
+    fn is_shared(&self) -> bool {
+        true
+    }
+
+    fn publish(&self, user_id: i64) -> BoxFuture<'_, Result<(), AppError>> {
+        async move {
+            let mut con = self.con.clone();
+            let channel = format!("{REDIS_CHANNEL_PREFIX}{user_id}");
+            let _: () = con.publish(channel, "").await.map_err(report_redis_err)?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn listen(
+        self: Arc<Self>,
+        user_worker_data: Arc<DashMap<i64, UserWorkerHandle>>,
+    ) -> BoxFuture<'static, ()> {
+        async move {
+            loop {
+                if let Err(e) = self.listen_once(&user_worker_data).await {
+                    log::error!("lost the redis op subscription: {e}");
+                }
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+        .boxed()
+    }
+}
+
+impl RedisBackend {
+    async fn listen_once(
+        &self,
+        user_worker_data: &DashMap<i64, UserWorkerHandle>,
+    ) -> Result<(), redis::RedisError> {
+        let mut pubsub = self.client.get_async_pubsub().await?;
+        pubsub
+            .psubscribe(format!("{REDIS_CHANNEL_PREFIX}*"))
+            .await?;
+        log::info!("listening for ops stored by other instances on redis");
+        catch_up_all(user_worker_data).await;
+
+        let mut messages = pubsub.on_message();
+        while let Some(message) = messages.next().await {
+            let user_id = message
+                .get_channel_name()
+                .strip_prefix(REDIS_CHANNEL_PREFIX)
+                .and_then(|x| x.parse::<i64>().ok());
+            if let Some(user_id) = user_id {
+                catch_up_user(user_worker_data, user_id).await;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,404 @@
+// syncs the open issues assigned to a user in the repos they link with their live tasks
+// new issues become live tasks and closing an issue finishes its task; finishing the task closes
+// the issue, or comments on it if the user would rather close issues themselves
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use reqwest::header::{ACCEPT, USER_AGENT};
+use reqwest::{Method, StatusCode};
+use serde::Deserialize;
+use todoproxy_api::{TaskStatus, WebsocketOpKind};
+use tokio_postgres::GenericClient;
+
+use crate::credentials::CredentialKey;
+use crate::db_types::GithubLink;
+use crate::handlers::{self, AppError};
+use crate::protocol::{ExtOpKind, Op, OpKind};
+use crate::user_worker::{self, ApplyOutcome, Registration};
+use crate::{external_task_service, github_link_service, utils, AppData};
+
+/// What external_task rows for issues have as their source.
+pub const SOURCE: &str = "github";
+
+/// How often linked repos are checked for issues that were assigned or closed.
+const SYNC_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Issues are listed this many at a time, which is the most github allows.
+const ISSUES_PER_PAGE: usize = 100;
+
+/// Only this many pages of assigned issues are synced from each repo.
+const MAX_ISSUE_PAGES: usize = 10;
+
+pub struct Github {
+    // e.g. https://api.github.com, without a trailing slash
+    api_url: String,
+    client: reqwest::Client,
+    // tokens are sealed with this; repos can't be linked without one
+    credential_key: Option<CredentialKey>,
+}
+
+#[derive(Deserialize)]
+struct User {
+    login: String,
+}
+
+#[derive(Deserialize)]
+struct Issue {
+    number: i64,
+    title: String,
+    state: String,
+    state_reason: Option<String>,
+    // set if the issue is a pull request, which are listed with issues
+    pull_request: Option<serde_json::Value>,
+}
+
+impl Github {
+    pub fn new(api_url: String, credential_key: Option<CredentialKey>) -> Github {
+        Github {
+            api_url: api_url.trim_end_matches('/').to_owned(),
+            client: reqwest::Client::new(),
+            credential_key,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.credential_key.is_some()
+    }
+
+    // None if there's no credential key to seal it with
+    pub fn seal_token(&self, token: &str) -> Option<Vec<u8>> {
+        Some(self.credential_key.as_ref()?.seal(token))
+    }
+
+    pub fn token(&self, link: &GithubLink) -> Result<String, AppError> {
+        match (&self.credential_key, &link.sealed_token) {
+            (Some(key), Some(sealed_token)) => key.open(sealed_token),
+            _ => {
+                log::error!(
+                    "can't open the token of github link {}",
+                    link.github_link_id
+                );
+                Err(AppError::InternalServerError)
+            }
+        }
+    }
+
+    fn request(&self, method: Method, token: &str, path: &str) -> reqwest::RequestBuilder {
+        self.client
+            .request(method, format!("{}{path}", self.api_url))
+            .bearer_auth(token)
+            .header(ACCEPT, "application/vnd.github+json")
+            .header(USER_AGENT, crate::SERVICE)
+    }
+
+    // the user the token belongs to, or None if it isn't a valid token
+    pub async fn login(&self, token: &str) -> Result<Option<String>, reqwest::Error> {
+        let response = self.request(Method::GET, token, "/user").send().await?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            return Ok(None);
+        }
+        let user: User = response.error_for_status()?.json().await?;
+        Ok(Some(user.login))
+    }
+
+    // whether the token can see the repo
+    pub async fn can_access(&self, token: &str, repo: &str) -> Result<bool, reqwest::Error> {
+        let response = self
+            .request(Method::GET, token, &format!("/repos/{repo}"))
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        response.error_for_status()?;
+        Ok(true)
+    }
+
+    async fn assigned_open_issues(
+        &self,
+        link: &GithubLink,
+        token: &str,
+    ) -> Result<Vec<Issue>, reqwest::Error> {
+        let mut issues = vec![];
+        for page in 1..=MAX_ISSUE_PAGES {
+            let batch: Vec<Issue> = self
+                .request(Method::GET, token, &format!("/repos/{}/issues", link.repo))
+                .query(&[
+                    ("assignee", link.login.as_str()),
+                    ("state", "open"),
+                    ("per_page", &ISSUES_PER_PAGE.to_string()),
+                    ("page", &page.to_string()),
+                ])
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            let last = batch.len() < ISSUES_PER_PAGE;
+            issues.extend(batch.into_iter().filter(|x| x.pull_request.is_none()));
+            if last {
+                break;
+            }
+        }
+        Ok(issues)
+    }
+
+    // None if the issue was deleted or transferred away
+    async fn issue(
+        &self,
+        link: &GithubLink,
+        token: &str,
+        number: i64,
+    ) -> Result<Option<Issue>, reqwest::Error> {
+        let response = self
+            .request(
+                Method::GET,
+                token,
+                &format!("/repos/{}/issues/{number}", link.repo),
+            )
+            .send()
+            .await?;
+        if matches!(
+            response.status(),
+            StatusCode::NOT_FOUND | StatusCode::GONE | StatusCode::MOVED_PERMANENTLY
+        ) {
+            return Ok(None);
+        }
+        Ok(Some(response.error_for_status()?.json().await?))
+    }
+
+    // closes the issue as completed or not planned, or comments if it's neither, or if the user
+    // would rather close issues themselves
+    pub async fn finish_issue(
+        &self,
+        link: &GithubLink,
+        token: &str,
+        number: i64,
+        status: &TaskStatus,
+    ) -> Result<(), reqwest::Error> {
+        let path = format!("/repos/{}/issues/{number}", link.repo);
+        let state_reason = match status {
+            TaskStatus::Succeeded => Some("completed"),
+            TaskStatus::Obsoleted => Some("not_planned"),
+            TaskStatus::Failed => None,
+        };
+        match state_reason {
+            Some(state_reason) if link.close_issues => {
+                self.request(Method::PATCH, token, &path)
+                    .json(&serde_json::json!({
+                        "state": "closed",
+                        "state_reason": state_reason,
+                    }))
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+            _ => {
+                let status = match status {
+                    TaskStatus::Succeeded => "succeeded",
+                    TaskStatus::Failed => "failed",
+                    TaskStatus::Obsoleted => "obsoleted",
+                };
+                let body = format!("Marked {status} in {}.", crate::SERVICE);
+                self.request(Method::POST, token, &format!("{path}/comments"))
+                    .json(&serde_json::json!({ "body": body }))
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn report_github_err(e: reqwest::Error) -> AppError {
+    log::warn!("github request failed: {}", e);
+    AppError::DecodeError
+}
+
+// whether s looks like owner/name
+pub fn is_repo(s: &str) -> bool {
+    let valid = |x: &str| {
+        !x.is_empty()
+            && x.chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    };
+    match s.split_once('/') {
+        Some((owner, name)) => valid(owner) && valid(name),
+        None => false,
+    }
+}
+
+fn external_id(repo: &str, number: i64) -> String {
+    format!("{repo}#{number}")
+}
+
+// the repo and issue number of an external id
+pub fn parse_external_id(external_id: &str) -> Option<(&str, i64)> {
+    let (repo, number) = external_id.rsplit_once('#')?;
+    Some((repo, number.parse().ok()?))
+}
+
+// seals the tokens of links made before tokens were sealed, and returns how many there were
+pub async fn seal_plaintext_tokens(
+    con: &mut impl GenericClient,
+    key: &CredentialKey,
+) -> Result<usize, tokio_postgres::Error> {
+    let unsealed = github_link_service::get_all_unsealed(&mut *con).await?;
+    for (github_link_id, token) in &unsealed {
+        github_link_service::seal_token(&mut *con, *github_link_id, key.seal(token)).await?;
+    }
+    Ok(unsealed.len())
+}
+
+// syncs every linked repo every sync interval, until the server stops
+// every instance syncs, and the idempotency keys of the ops keep them from making a task twice
+pub async fn sync(data: AppData) {
+    let mut interval = tokio::time::interval(SYNC_INTERVAL);
+    loop {
+        interval.tick().await;
+        let result: Result<Vec<GithubLink>, AppError> = try {
+            let con: &mut tokio_postgres::Client =
+                &mut *data.pool.get().await.map_err(handlers::report_pool_err)?;
+            github_link_service::get_all(&mut *con)
+                .await
+                .map_err(handlers::report_postgres_err)?
+        };
+        let links = match result {
+            Ok(links) => links,
+            // we'll get them next time
+            Err(e) => {
+                log::error!("couldn't find github links to sync: {e}");
+                continue;
+            }
+        };
+        for link in links {
+            if let Err(e) = sync_link(&data, &link).await {
+                log::error!(
+                    "couldn't sync {} for user {}: {e}",
+                    link.repo,
+                    link.creator_user_id
+                );
+            }
+        }
+    }
+}
+
+async fn sync_link(data: &AppData, link: &GithubLink) -> Result<(), AppError> {
+    let user_id = link.creator_user_id;
+    let token = data.github.token(link)?;
+    let issues = data
+        .github
+        .assigned_open_issues(link, &token)
+        .await
+        .map_err(report_github_err)?;
+    // issues in this repo that already have a task, to their task
+    let known: HashMap<String, String> = {
+        let con: &mut tokio_postgres::Client =
+            &mut *data.pool.get().await.map_err(handlers::report_pool_err)?;
+        external_task_service::get_all_by_source(&mut *con, user_id, SOURCE)
+            .await
+            .map_err(handlers::report_postgres_err)?
+            .into_iter()
+            .filter(|x| {
+                parse_external_id(&x.external_id).map_or(false, |(repo, _)| repo == link.repo)
+            })
+            .map(|x| (x.external_id, x.task_id))
+            .collect()
+    };
+    let open: HashSet<String> = issues
+        .iter()
+        .map(|x| external_id(&link.repo, x.number))
+        .collect();
+    let new_issues: Vec<&Issue> = issues
+        .iter()
+        .filter(|x| !known.contains_key(&external_id(&link.repo, x.number)))
+        .collect();
+    let gone: Vec<(&String, &String)> = known.iter().filter(|(x, _)| !open.contains(*x)).collect();
+    // most syncs don't change anything, and don't need to load the user's state
+    if new_issues.is_empty() && gone.is_empty() {
+        return Ok(());
+    }
+
+    let (worker, _) = user_worker::connect(data, user_id).await?;
+    let _registration = Registration(worker.clone());
+
+    for issue in new_issues {
+        let external_id = external_id(&link.repo, issue.number);
+        let value = format!("{external_id} {}", issue.title.trim());
+        let value = utils::truncate(&value, data.task_limits.max_value_len).to_owned();
+        let outcome = worker
+            .apply_op(Op {
+                alleged_time: utils::current_time_millis(),
+                kind: OpKind::Ext(ExtOpKind::InsLiveTaskNew { value }),
+                seq: None,
+                checksum: None,
+                idempotency_key: Some(format!("{SOURCE}-{external_id}")),
+                value_encoding: None,
+            })
+            .await?;
+        match outcome {
+            ApplyOutcome::Applied(Op {
+                kind: OpKind::Base(WebsocketOpKind::InsLiveTask { id, .. }),
+                ..
+            }) => {
+                let con: &mut tokio_postgres::Client =
+                    &mut *data.pool.get().await.map_err(handlers::report_pool_err)?;
+                external_task_service::add(&mut *con, user_id, SOURCE.to_owned(), external_id, id)
+                    .await
+                    .map_err(handlers::report_postgres_err)?;
+            }
+            // another instance made it, and records the task
+            ApplyOutcome::Duplicate => {}
+            // new tasks are always stored as an InsLiveTask
+            ApplyOutcome::Applied(_) => {}
+            ApplyOutcome::Rejected(reason) => {
+                log::warn!("issue {external_id} for user {user_id} was rejected: {reason:?}")
+            }
+        }
+    }
+
+    for (external_id, task_id) in gone {
+        let Some((_, number)) = parse_external_id(external_id) else {
+            continue;
+        };
+        let issue = data
+            .github
+            .issue(link, &token, number)
+            .await
+            .map_err(report_github_err)?;
+        let status = match issue {
+            Some(issue) if issue.state == "closed" => match issue.state_reason.as_deref() {
+                Some("not_planned") => Some(TaskStatus::Obsoleted),
+                _ => Some(TaskStatus::Succeeded),
+            },
+            // it was unassigned, so it's left up to the user
+            _ => None,
+        };
+        // forgotten first, so finishing the task doesn't close the issue again
+        {
+            let con: &mut tokio_postgres::Client =
+                &mut *data.pool.get().await.map_err(handlers::report_pool_err)?;
+            external_task_service::remove(&mut *con, user_id, SOURCE, external_id)
+                .await
+                .map_err(handlers::report_postgres_err)?;
+        }
+        if let Some(status) = status {
+            // rejected if the task was already finished or deleted, which is fine
+            worker
+                .apply_op(Op {
+                    alleged_time: utils::current_time_millis(),
+                    kind: OpKind::Base(WebsocketOpKind::FinishLiveTask {
+                        id: task_id.clone(),
+                        status,
+                    }),
+                    seq: None,
+                    checksum: None,
+                    idempotency_key: None,
+                    value_encoding: None,
+                })
+                .await?;
+        }
+    }
+    Ok(())
+}
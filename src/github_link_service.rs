@@ -0,0 +1,173 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for GithubLink {
+    // select * from github_link order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> GithubLink {
+        GithubLink {
+            github_link_id: row.get("github_link_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            repo: row.get("repo"),
+            sealed_token: row.get("sealed_token"),
+            login: row.get("login"),
+            close_issues: row.get("close_issues"),
+        }
+    }
+}
+
+// links the repo, or replaces the token and settings if it's already linked
+#[tracing::instrument(skip(con, sealed_token))]
+pub async fn set(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    repo: String,
+    sealed_token: Vec<u8>,
+    login: String,
+    close_issues: bool,
+) -> Result<GithubLink, tokio_postgres::Error> {
+    let row = con
+        .query_one(
+            "INSERT INTO
+             github_link(
+                 creator_user_id,
+                 repo,
+                 sealed_token,
+                 login,
+                 close_issues
+             )
+             VALUES($1, $2, $3, $4, $5)
+             ON CONFLICT (creator_user_id, repo) DO UPDATE
+             SET token = NULL, sealed_token = $3, login = $4, close_issues = $5
+             RETURNING github_link_id, creation_time
+            ",
+            &[
+                &creator_user_id,
+                &repo,
+                &sealed_token,
+                &login,
+                &close_issues,
+            ],
+        )
+        .await?;
+
+    // return github link
+    Ok(GithubLink {
+        github_link_id: row.get(0),
+        creation_time: row.get(1),
+        creator_user_id,
+        repo,
+        sealed_token: Some(sealed_token),
+        login,
+        close_issues,
+    })
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn get_by_repo(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    repo: &str,
+) -> Result<Option<GithubLink>, tokio_postgres::Error> {
+    let result = con
+        .query_opt(
+            "SELECT * FROM github_link WHERE creator_user_id = $1 AND repo = $2",
+            &[&creator_user_id, &repo],
+        )
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+// oldest first
+#[tracing::instrument(skip(con))]
+pub async fn get_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<Vec<GithubLink>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT * FROM github_link WHERE creator_user_id = $1 ORDER BY github_link_id",
+            &[&creator_user_id],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// every user's links, for syncing
+#[tracing::instrument(skip(con))]
+pub async fn get_all(
+    con: &mut impl GenericClient,
+) -> Result<Vec<GithubLink>, tokio_postgres::Error> {
+    let result = con
+        .query("SELECT * FROM github_link ORDER BY github_link_id", &[])
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// (github_link_id, token) of links made before tokens were sealed
+#[tracing::instrument(skip(con))]
+pub async fn get_all_unsealed(
+    con: &mut impl GenericClient,
+) -> Result<Vec<(i64, String)>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT github_link_id, token FROM github_link WHERE sealed_token IS NULL",
+            &[],
+        )
+        .await?
+        .into_iter()
+        .map(|x| (x.get(0), x.get(1)))
+        .collect();
+    Ok(result)
+}
+
+// replaces the link's plaintext token with the sealed one
+#[tracing::instrument(skip(con, sealed_token))]
+pub async fn seal_token(
+    con: &mut impl GenericClient,
+    github_link_id: i64,
+    sealed_token: Vec<u8>,
+) -> Result<(), tokio_postgres::Error> {
+    con.execute(
+        "UPDATE github_link SET token = NULL, sealed_token = $2 WHERE github_link_id = $1",
+        &[&github_link_id, &sealed_token],
+    )
+    .await?;
+    Ok(())
+}
+
+// returns whether the user had a link with that id
+#[tracing::instrument(skip(con))]
+pub async fn remove(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    github_link_id: i64,
+) -> Result<bool, tokio_postgres::Error> {
+    let removed = con
+        .execute(
+            "DELETE FROM github_link WHERE creator_user_id = $1 AND github_link_id = $2",
+            &[&creator_user_id, &github_link_id],
+        )
+        .await?;
+    Ok(removed > 0)
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM github_link WHERE creator_user_id = $1",
+        &[&creator_user_id],
+    )
+    .await
+}
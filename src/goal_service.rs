@@ -0,0 +1,124 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for Goal {
+    // select * from goal order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> Goal {
+        Goal {
+            goal_id: row.get("goal_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            name: row.get("name"),
+        }
+    }
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn add(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    name: String,
+) -> Result<Goal, tokio_postgres::Error> {
+    let row = con
+        .query_one(
+            "INSERT INTO
+             goal(
+                 creator_user_id,
+                 name
+             )
+             VALUES($1, $2)
+             RETURNING goal_id, creation_time
+            ",
+            &[&creator_user_id, &name],
+        )
+        .await?;
+
+    // return goal
+    Ok(Goal {
+        goal_id: row.get(0),
+        creation_time: row.get(1),
+        creator_user_id,
+        name,
+    })
+}
+
+// only finds the user's own goals
+#[tracing::instrument(skip(con))]
+pub async fn get_by_goal_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    goal_id: i64,
+) -> Result<Option<Goal>, tokio_postgres::Error> {
+    let result = con
+        .query_opt(
+            "SELECT * FROM goal WHERE creator_user_id=$1 AND goal_id=$2",
+            &[&creator_user_id, &goal_id],
+        )
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn get_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<Vec<Goal>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT * FROM goal WHERE creator_user_id=$1 ORDER BY goal_id",
+            &[&creator_user_id],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// returns whether the user had a goal with this id
+#[tracing::instrument(skip(con))]
+pub async fn update(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    goal_id: i64,
+    name: String,
+) -> Result<bool, tokio_postgres::Error> {
+    let updated = con
+        .execute(
+            "UPDATE goal SET name = $3 WHERE creator_user_id = $1 AND goal_id = $2",
+            &[&creator_user_id, &goal_id, &name],
+        )
+        .await?;
+    Ok(updated > 0)
+}
+
+// returns whether the user had a goal with this id; the tasks linked to it are unlinked
+#[tracing::instrument(skip(con))]
+pub async fn remove(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    goal_id: i64,
+) -> Result<bool, tokio_postgres::Error> {
+    let removed = con
+        .execute(
+            "DELETE FROM goal WHERE creator_user_id = $1 AND goal_id = $2",
+            &[&creator_user_id, &goal_id],
+        )
+        .await?;
+    Ok(removed > 0)
+}
+
+// returns the number of rows removed
+// task_goal references goal, so remove those first
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM goal WHERE creator_user_id = $1",
+        &[&creator_user_id],
+    )
+    .await
+}
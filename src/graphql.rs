@@ -0,0 +1,358 @@
+// a graphql api over the same workers as the websocket protocol, for clients that prefer it
+// the api key is a bearer token over http, and {"api_key": ...} in connection_init over websockets
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::{rt, web, HttpRequest, HttpResponse, Responder};
+use actix_ws::{CloseCode, CloseReason, Message};
+use async_graphql::http::{WebSocketProtocols, WsMessage};
+use async_graphql::{Context, Json, Object, Schema, SimpleObject, Subscription};
+use futures_util::{stream, Stream, StreamExt};
+use serde::Deserialize;
+use todoproxy_api::{TaskStatus, WebsocketOpKind};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::handlers::{self, AppError};
+use crate::protocol::{Op, OpKind, OpRejectReason};
+use crate::task_updates::SlowConsumerPolicy;
+use crate::user_worker::{self, ApplyOutcome, Registration, Update};
+use crate::{attachment_service, task_comment_service, AppData};
+
+pub type TodoSchema = Schema<Query, Mutation, Subscription>;
+
+pub fn schema(data: AppData) -> TodoSchema {
+    Schema::build(Query, Mutation, Subscription)
+        .data(data)
+        .finish()
+}
+
+// the api key the request was made with
+struct ApiKey(String);
+
+#[derive(Deserialize)]
+struct ConnectionInitPayload {
+    api_key: String,
+}
+
+async fn authenticate(ctx: &Context<'_>) -> Result<(AppData, i64), AppError> {
+    let data = ctx.data_unchecked::<AppData>().clone();
+    let api_key = ctx.data_opt::<ApiKey>().ok_or(AppError::Unauthorized)?;
+    let user_id = handlers::get_user_id_if_api_key_valid(&data, api_key.0.clone()).await?;
+    Ok((data, user_id))
+}
+
+#[derive(SimpleObject)]
+struct LiveTask {
+    id: String,
+    value: String,
+    comment_count: i64,
+    attachments: Vec<Attachment>,
+    // unset in the default lane
+    lane: Option<String>,
+    estimate: Option<i64>,
+}
+
+#[derive(SimpleObject)]
+struct FinishedTask {
+    id: String,
+    value: String,
+    status: Json<TaskStatus>,
+    comment_count: i64,
+    attachments: Vec<Attachment>,
+}
+
+// download urls are fetched from the rest api
+#[derive(SimpleObject)]
+struct Attachment {
+    attachment_id: i64,
+    creation_time: i64,
+    filename: String,
+    content_type: String,
+    size: i64,
+}
+
+#[derive(SimpleObject)]
+struct Snapshot {
+    live: Vec<LiveTask>,
+    // the first `finished_limit` finished tasks after `finished_offset`, most recent first
+    finished: Vec<FinishedTask>,
+    finished_total: usize,
+    // ops up to and including this seq are reflected here
+    seq: i64,
+    checksum: String,
+}
+
+#[derive(SimpleObject)]
+struct OpResult {
+    applied: bool,
+    // set if the op was applied
+    seq: Option<i64>,
+    // the id of the inserted task, if the op inserted one
+    id: Option<String>,
+    // set if the op was rejected
+    reject_reason: Option<Json<OpRejectReason>>,
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    async fn snapshot(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 0)] finished_offset: usize,
+        #[graphql(default = 100)] finished_limit: usize,
+    ) -> async_graphql::Result<Snapshot> {
+        let (data, user_id) = authenticate(ctx).await?;
+        let (worker, subscription) = user_worker::connect(&data, user_id).await?;
+        let _registration = Registration(worker);
+
+        let op = subscription.initial_op;
+        let snapshot = match op.kind {
+            OpKind::Base(WebsocketOpKind::OverwriteState(snapshot)) => snapshot,
+            _ => return Err(AppError::InternalServerError.into()),
+        };
+        let con: &mut tokio_postgres::Client =
+            &mut *data.pool.get().await.map_err(handlers::report_pool_err)?;
+        let comment_counts = task_comment_service::count_by_task_id(&mut *con, user_id, i64::MAX)
+            .await
+            .map_err(handlers::report_postgres_err)?;
+        let comment_count = |id: &String| comment_counts.get(id).copied().unwrap_or(0);
+        let mut lanes = handlers::lanes_by_task_id(con, user_id, &snapshot.live).await?;
+        let mut estimates = handlers::estimates_by_task_id(con, user_id, &snapshot.live).await?;
+        let mut attachments = HashMap::<_, Vec<_>>::new();
+        for x in attachment_service::get_all_by_user_id(&mut *con, user_id)
+            .await
+            .map_err(handlers::report_postgres_err)?
+        {
+            attachments.entry(x.task_id).or_default().push(Attachment {
+                attachment_id: x.attachment_id,
+                creation_time: x.creation_time,
+                filename: x.filename,
+                content_type: x.content_type,
+                size: x.size,
+            });
+        }
+        Ok(Snapshot {
+            live: snapshot
+                .live
+                .into_iter()
+                .map(|x| LiveTask {
+                    comment_count: comment_count(&x.id),
+                    attachments: attachments.remove(&x.id).unwrap_or_default(),
+                    lane: lanes.remove(&x.id),
+                    estimate: estimates.remove(&x.id),
+                    id: x.id,
+                    value: x.value,
+                })
+                .collect(),
+            finished_total: snapshot.finished.len(),
+            finished: snapshot
+                .finished
+                .into_iter()
+                .skip(finished_offset)
+                .take(finished_limit)
+                .map(|x| FinishedTask {
+                    comment_count: comment_count(&x.id),
+                    attachments: attachments.remove(&x.id).unwrap_or_default(),
+                    id: x.id,
+                    value: x.value,
+                    status: Json(x.status),
+                })
+                .collect(),
+            seq: op.seq.unwrap_or_default(),
+            checksum: op.checksum.unwrap_or_default(),
+        })
+    }
+}
+
+pub struct Mutation;
+
+#[Object]
+impl Mutation {
+    // takes an op exactly as it would be sent over the websocket protocol
+    async fn apply_op(&self, ctx: &Context<'_>, op: Json<Op>) -> async_graphql::Result<OpResult> {
+        let (data, user_id) = authenticate(ctx).await?;
+        let api_key = &ctx.data_unchecked::<ApiKey>().0;
+        if handlers::api_key_is_read_only(&data.pool, api_key).await? {
+            return Ok(OpResult {
+                applied: false,
+                seq: None,
+                id: None,
+                reject_reason: Some(Json(OpRejectReason::ReadOnly)),
+            });
+        }
+
+        let (worker, _) = user_worker::connect(&data, user_id).await?;
+        let _registration = Registration(worker.clone());
+
+        Ok(match worker.apply_op(op.0).await? {
+            ApplyOutcome::Applied(op) => OpResult {
+                applied: true,
+                seq: op.seq,
+                id: match op.kind {
+                    OpKind::Base(WebsocketOpKind::InsLiveTask { id, .. }) => Some(id),
+                    _ => None,
+                },
+                reject_reason: None,
+            },
+            ApplyOutcome::Duplicate => OpResult {
+                applied: false,
+                seq: None,
+                id: None,
+                reject_reason: None,
+            },
+            ApplyOutcome::Rejected(reason) => OpResult {
+                applied: false,
+                seq: None,
+                id: None,
+                reject_reason: Some(Json(reason)),
+            },
+        })
+    }
+}
+
+pub struct Subscription;
+
+#[Subscription]
+impl Subscription {
+    // an OverwriteState with the current state, then every op applied after it
+    // subscribers that fall behind are resynced or ended, depending on the slow consumer policy
+    async fn updates(
+        &self,
+        ctx: &Context<'_>,
+    ) -> async_graphql::Result<impl Stream<Item = Json<Op>>> {
+        let (data, user_id) = authenticate(ctx).await?;
+        let (worker, subscription) = user_worker::connect(&data, user_id).await?;
+        let registration = Registration(worker);
+        let policy = data.settings.get().slow_consumer_policy;
+        let last_seq_sent = subscription.initial_op.seq.unwrap_or_default();
+
+        let updates = stream::unfold(
+            (subscription.updates_rx, registration, last_seq_sent),
+            move |(mut rx, registration, mut last_seq_sent)| async move {
+                loop {
+                    let op = match rx.recv().await {
+                        // already included in a resync
+                        Ok(Update::Op(op)) if op.seq.is_some_and(|seq| seq <= last_seq_sent) => {
+                            continue
+                        }
+                        Ok(Update::Op(op)) => op.into_op(),
+                        // subscribers only get ops
+                        Ok(Update::Event(_)) => continue,
+                        // the client resubscribes when it can keep up
+                        Err(RecvError::Lagged(_))
+                            if matches!(policy, SlowConsumerPolicy::Disconnect) =>
+                        {
+                            return None
+                        }
+                        // we dropped ops the client needed, so send the current state instead
+                        Err(RecvError::Lagged(_)) => match registration.0.resync().await {
+                            Ok(op) => op,
+                            Err(_) => return None,
+                        },
+                        // the worker was evicted; the client should resubscribe
+                        Err(RecvError::Closed) => return None,
+                    };
+                    if let Some(seq) = op.seq {
+                        last_seq_sent = seq;
+                    }
+                    return Some((Json(op), (rx, registration, last_seq_sent)));
+                }
+            },
+        );
+        Ok(stream::once(async { Json(subscription.initial_op) }).chain(updates))
+    }
+}
+
+fn bearer_token(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(String::from)
+}
+
+// execute a query or mutation
+#[tracing::instrument(skip_all)]
+pub async fn graphql(
+    schema: web::Data<TodoSchema>,
+    req: HttpRequest,
+    body: web::Json<async_graphql::Request>,
+) -> impl Responder {
+    let mut request = body.into_inner();
+    if let Some(api_key) = bearer_token(&req) {
+        request = request.data(ApiKey(api_key));
+    }
+    web::Json(schema.execute(request).await)
+}
+
+// start a graphql websocket, for subscriptions
+pub async fn graphql_ws(
+    schema: web::Data<TodoSchema>,
+    req: HttpRequest,
+    stream: web::Payload,
+) -> Result<HttpResponse, actix_web::Error> {
+    // the first protocol the client offers that we speak
+    let protocol = req
+        .headers()
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| {
+            x.split(',')
+                .find_map(|p| WebSocketProtocols::from_str(p.trim()).ok())
+        })
+        .ok_or_else(|| actix_web::error::ErrorBadRequest("unsupported websocket subprotocol"))?;
+
+    let (mut res, mut session, msg_stream) = actix_ws::handle(&req, stream)?;
+    res.headers_mut().insert(
+        header::SEC_WEBSOCKET_PROTOCOL,
+        HeaderValue::from_static(protocol.sec_websocket_protocol()),
+    );
+
+    let input = msg_stream
+        .take_while(|x| std::future::ready(!matches!(x, Ok(Message::Close(_)) | Err(_))))
+        .filter_map(|x| async move {
+            match x {
+                Ok(Message::Text(text)) => Some(text.to_string()),
+                _ => None,
+            }
+        });
+
+    let schema = schema.get_ref().clone();
+    let output = async_graphql::http::WebSocket::new(schema, input, protocol).on_connection_init(
+        |payload| async move {
+            let payload: ConnectionInitPayload = serde_json::from_value(payload)?;
+            let mut data = async_graphql::Data::default();
+            data.insert(ApiKey(payload.api_key));
+            Ok(data)
+        },
+    );
+
+    rt::spawn(async move {
+        tokio::pin!(output);
+        while let Some(msg) = output.next().await {
+            match msg {
+                WsMessage::Text(text) => {
+                    if session.text(text).await.is_err() {
+                        return;
+                    }
+                }
+                WsMessage::Close(code, description) => {
+                    let _ = session
+                        .close(Some(CloseReason {
+                            code: CloseCode::Other(code),
+                            description: Some(description),
+                        }))
+                        .await;
+                    return;
+                }
+            }
+        }
+        let _ = session.close(None).await;
+    });
+
+    Ok(res)
+}
@@ -0,0 +1,395 @@
+// a grpc surface over the same workers as the websocket protocol, for backend services
+use std::pin::Pin;
+
+use futures_util::Stream;
+use todoproxy_api::WebsocketOpKind;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::handlers::{self, AppError};
+use crate::protocol::{
+    ClientFrame, ClientRequest, ExtOpKind, Op, OpKind, OpRejectReason, ServerEvent, ServerNotice,
+};
+use crate::task_updates::{check_reauth, op_notice, SlowConsumerPolicy, MAX_FINISHED_CHUNK};
+use crate::user_worker::{self, ApplyOutcome, Registration, Update, UserWorkerHandle};
+use crate::{audit, deleted_task_service, read_only_api_key_service, utils, AppData};
+
+pub mod pb {
+    tonic::include_proto!("todoproxy");
+}
+
+use pb::task_service_server::{TaskService, TaskServiceServer};
+
+/// How many messages may be queued for a stream before we stop reading from the worker.
+const OUTBOUND_CHANNEL_CAPACITY: usize = 100;
+
+pub fn service(data: AppData) -> TaskServiceServer<GrpcService> {
+    TaskServiceServer::new(GrpcService { data })
+}
+
+pub struct GrpcService {
+    data: AppData,
+}
+
+fn report_app_err(e: AppError) -> Status {
+    match e {
+        AppError::Unauthorized => Status::unauthenticated(e.to_string()),
+        AppError::BadRequest | AppError::DecodeError => Status::invalid_argument(e.to_string()),
+        AppError::NotFound => Status::not_found(e.to_string()),
+        AppError::TooManyRequests | AppError::QuotaExceeded => {
+            Status::resource_exhausted(e.to_string())
+        }
+        AppError::Unavailable => Status::unavailable(e.to_string()),
+        AppError::InternalServerError | AppError::Unknown => Status::internal(e.to_string()),
+    }
+}
+
+fn api_key<T>(request: &Request<T>) -> Result<String, Status> {
+    request
+        .metadata()
+        .get("authorization")
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.strip_prefix("Bearer "))
+        .map(String::from)
+        .ok_or_else(|| Status::unauthenticated("missing bearer api key"))
+}
+
+// grpc calls get a fresh session id, since they don't go through the request id middleware
+fn origin<T>(request: &Request<T>) -> audit::Origin {
+    audit::Origin {
+        session_id: Some(utils::random_string()),
+        source_ip: request.remote_addr().map(|x| x.ip().to_string()),
+    }
+}
+
+impl GrpcService {
+    // takes the api key rather than the request, since streaming requests aren't Sync
+    async fn authenticate(&self, api_key: String) -> Result<(String, i64), Status> {
+        let user_id = handlers::get_user_id_if_api_key_valid(&self.data, api_key.clone())
+            .await
+            .map_err(report_app_err)?;
+        Ok((api_key, user_id))
+    }
+}
+
+fn apply_op_response(outcome: ApplyOutcome) -> pb::ApplyOpResponse {
+    match outcome {
+        ApplyOutcome::Applied(op) => pb::ApplyOpResponse {
+            applied: true,
+            seq: op.seq,
+            id: match op.kind {
+                OpKind::Base(WebsocketOpKind::InsLiveTask { id, .. }) => Some(id),
+                _ => None,
+            },
+            reject_reason_json: None,
+        },
+        ApplyOutcome::Duplicate => pb::ApplyOpResponse {
+            applied: false,
+            seq: None,
+            id: None,
+            reject_reason_json: None,
+        },
+        ApplyOutcome::Rejected(reason) => pb::ApplyOpResponse {
+            applied: false,
+            seq: None,
+            id: None,
+            reject_reason_json: Some(serde_json::to_string(&reason).unwrap()),
+        },
+    }
+}
+
+#[tonic::async_trait]
+impl TaskService for GrpcService {
+    #[tracing::instrument(skip_all)]
+    async fn get_snapshot(
+        &self,
+        request: Request<pb::GetSnapshotRequest>,
+    ) -> Result<Response<pb::Snapshot>, Status> {
+        let (_, user_id) = self.authenticate(api_key(&request)?).await?;
+        let (worker, subscription) = user_worker::connect(&self.data, user_id)
+            .await
+            .map_err(report_app_err)?;
+        let _registration = Registration(worker);
+
+        let op = subscription.initial_op;
+        let snapshot = match op.kind {
+            OpKind::Base(WebsocketOpKind::OverwriteState(snapshot)) => snapshot,
+            _ => {
+                return Err(Status::internal(
+                    "worker sent something other than a snapshot",
+                ))
+            }
+        };
+        Ok(Response::new(pb::Snapshot {
+            live: snapshot
+                .live
+                .into_iter()
+                .map(|x| pb::LiveTask {
+                    id: x.id,
+                    value: x.value,
+                })
+                .collect(),
+            finished: snapshot
+                .finished
+                .into_iter()
+                .map(|x| pb::FinishedTask {
+                    id: x.id,
+                    value: x.value,
+                    status_json: serde_json::to_string(&x.status).unwrap(),
+                })
+                .collect(),
+            seq: op.seq.unwrap_or_default(),
+            checksum: op.checksum.unwrap_or_default(),
+        }))
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn apply_op(
+        &self,
+        request: Request<pb::ApplyOpRequest>,
+    ) -> Result<Response<pb::ApplyOpResponse>, Status> {
+        let (api_key, user_id) = self.authenticate(api_key(&request)?).await?;
+        let origin = origin(&request);
+        let op = serde_json::from_str::<Op>(&request.into_inner().op_json)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+        if handlers::api_key_is_read_only(&self.data.pool, &api_key)
+            .await
+            .map_err(report_app_err)?
+        {
+            return Ok(Response::new(apply_op_response(ApplyOutcome::Rejected(
+                OpRejectReason::ReadOnly,
+            ))));
+        }
+
+        let (worker, _) = user_worker::connect(&self.data, user_id)
+            .await
+            .map_err(report_app_err)?;
+        let _registration = Registration(worker.clone());
+        let outcome = audit::scope(origin, worker.apply_op(op))
+            .await
+            .map_err(report_app_err)?;
+        Ok(Response::new(apply_op_response(outcome)))
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn view_trash(
+        &self,
+        request: Request<pb::ViewTrashRequest>,
+    ) -> Result<Response<pb::ViewTrashResponse>, Status> {
+        let (_, user_id) = self.authenticate(api_key(&request)?).await?;
+        let con: &mut tokio_postgres::Client = &mut *self
+            .data
+            .pool
+            .get()
+            .await
+            .map_err(|e| report_app_err(handlers::report_pool_err(e)))?;
+        let deleted_tasks = deleted_task_service::get_all_by_user_id(&mut *con, user_id)
+            .await
+            .map_err(|e| report_app_err(handlers::report_postgres_err(e)))?
+            .into_iter()
+            .map(|x| pb::DeletedTask {
+                id: x.task_id,
+                value: x.value,
+                deletion_time: x.creation_time,
+            })
+            .collect();
+        Ok(Response::new(pb::ViewTrashResponse { deleted_tasks }))
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn make_api_key_read_only(
+        &self,
+        request: Request<pb::MakeApiKeyReadOnlyRequest>,
+    ) -> Result<Response<pb::MakeApiKeyReadOnlyResponse>, Status> {
+        let (api_key, user_id) = self.authenticate(api_key(&request)?).await?;
+        let con: &mut tokio_postgres::Client = &mut *self
+            .data
+            .pool
+            .get()
+            .await
+            .map_err(|e| report_app_err(handlers::report_pool_err(e)))?;
+        read_only_api_key_service::add(&mut *con, user_id, utils::sha256_hex(api_key.as_bytes()))
+            .await
+            .map_err(|e| report_app_err(handlers::report_postgres_err(e)))?;
+        Ok(Response::new(pb::MakeApiKeyReadOnlyResponse {}))
+    }
+
+    type StreamUpdatesStream =
+        Pin<Box<dyn Stream<Item = Result<pb::ServerMessage, Status>> + Send>>;
+
+    #[tracing::instrument(skip_all)]
+    async fn stream_updates(
+        &self,
+        request: Request<Streaming<pb::ClientMessage>>,
+    ) -> Result<Response<Self::StreamUpdatesStream>, Status> {
+        let (api_key, user_id) = self.authenticate(api_key(&request)?).await?;
+        let read_only = handlers::api_key_is_read_only(&self.data.pool, &api_key)
+            .await
+            .map_err(report_app_err)?;
+        let (worker, subscription) = user_worker::connect(&self.data, user_id)
+            .await
+            .map_err(report_app_err)?;
+
+        let (out_tx, out_rx) = mpsc::channel(OUTBOUND_CHANNEL_CAPACITY);
+        let origin = origin(&request);
+        tokio::spawn(audit::scope(
+            origin,
+            stream_updates(
+                self.data.clone(),
+                Registration(worker),
+                user_id,
+                read_only,
+                subscription,
+                request.into_inner(),
+                out_tx,
+            ),
+        ));
+        Ok(Response::new(Box::pin(ReceiverStream::new(out_rx))))
+    }
+}
+
+fn server_message(x: &impl serde::Serialize) -> Result<pb::ServerMessage, Status> {
+    Ok(pb::ServerMessage {
+        message_json: serde_json::to_string(x).unwrap(),
+    })
+}
+
+// runs until the client hangs up, or the worker goes away
+async fn stream_updates(
+    data: AppData,
+    registration: Registration,
+    user_id: i64,
+    mut read_only: bool,
+    subscription: user_worker::Subscription,
+    mut inbound: Streaming<pb::ClientMessage>,
+    out_tx: mpsc::Sender<Result<pb::ServerMessage, Status>>,
+) {
+    let worker: &UserWorkerHandle = &registration.0;
+    let user_worker::Subscription {
+        mut updates_rx,
+        initial_op,
+    } = subscription;
+
+    // ops at or below this seq are already reflected in what the client has
+    // identifies the stream in the Editing events it sends
+    let session_id = audit::current().session_id.unwrap_or_default();
+
+    let mut last_seq_sent = initial_op.seq.unwrap_or_default();
+    if out_tx.send(server_message(&initial_op)).await.is_err() {
+        return;
+    }
+
+    loop {
+        let message = tokio::select! {
+            frame = inbound.next() => {
+                let frame = match frame {
+                    Some(Ok(frame)) => frame,
+                    // the client hung up
+                    Some(Err(_)) | None => return,
+                };
+                match serde_json::from_str::<ClientFrame>(&frame.frame_json) {
+                    Ok(ClientFrame::Op(op)) => {
+                        let wants_id = matches!(op.kind, OpKind::Ext(ExtOpKind::InsLiveTaskNew { .. }));
+                        let outcome = if read_only {
+                            Ok(ApplyOutcome::Rejected(OpRejectReason::ReadOnly))
+                        } else {
+                            worker.apply_op(op).await
+                        };
+                        match outcome {
+                            Ok(outcome) => op_notice(outcome, wants_id).map(|x| server_message(&x)),
+                            Err(e) => Some(Err(report_app_err(e))),
+                        }
+                    }
+                    Ok(ClientFrame::Request(ClientRequest::Resync)) => match worker.resync().await {
+                        Ok(op) => {
+                            last_seq_sent = op.seq.unwrap_or_default();
+                            Some(server_message(&op))
+                        }
+                        Err(e) => Some(Err(report_app_err(e))),
+                    },
+                    Ok(ClientFrame::Request(ClientRequest::RequestMoreFinished { after_id, limit })) => {
+                        let limit = limit.min(MAX_FINISHED_CHUNK);
+                        match worker.finished_chunk(after_id.clone(), limit).await {
+                            Ok(Some((tasks, more))) => Some(server_message(&ServerNotice::FinishedChunk {
+                                after_id,
+                                tasks,
+                                more,
+                            })),
+                            // after_id was restored or cleared, so the client's list is out of date
+                            Ok(None) => match worker.resync().await {
+                                Ok(op) => {
+                                    last_seq_sent = op.seq.unwrap_or_default();
+                                    Some(server_message(&op))
+                                }
+                                Err(e) => Some(Err(report_app_err(e))),
+                            },
+                            Err(e) => Some(Err(report_app_err(e))),
+                        }
+                    }
+                    Ok(ClientFrame::Request(ClientRequest::Editing { .. })) if read_only => None,
+                    Ok(ClientFrame::Request(ClientRequest::Editing { task_id })) => {
+                        worker
+                            .send_event(ServerEvent::Editing {
+                                session_id: session_id.clone(),
+                                user_id,
+                                task_id,
+                            })
+                            .await;
+                        None
+                    }
+                    // the stream keeps the key it was opened with, but picks up the new one's scope
+                    Ok(ClientFrame::Request(ClientRequest::Reauth { api_key })) => {
+                        match check_reauth(&data, user_id, &api_key).await {
+                            Ok(Some(new_read_only)) => {
+                                read_only = new_read_only;
+                                Some(server_message(&ServerNotice::Reauthed { read_only }))
+                            }
+                            Ok(None) => Some(server_message(&ServerNotice::ReauthRejected)),
+                            Err(e) => Some(Err(report_app_err(e))),
+                        }
+                    }
+                    Err(e) => Some(Err(Status::invalid_argument(e.to_string()))),
+                }
+            }
+            update = updates_rx.recv() => match update {
+                // the client knows what it's editing
+                Ok(Update::Event(ServerEvent::Editing { session_id: ref x, .. })) if *x == session_id => None,
+                Ok(Update::Event(event)) => Some(server_message(&event)),
+                // already included in a resync
+                Ok(Update::Op(op)) if op.seq.is_some_and(|seq| seq <= last_seq_sent) => None,
+                Ok(Update::Op(op)) => {
+                    last_seq_sent = op.seq.unwrap_or(last_seq_sent);
+                    Some(Ok(pb::ServerMessage {
+                        message_json: op.serialized().into_owned(),
+                    }))
+                }
+                Err(RecvError::Lagged(_))
+                    if matches!(data.settings.get().slow_consumer_policy, SlowConsumerPolicy::Disconnect) =>
+                {
+                    Some(Err(Status::resource_exhausted("client fell too far behind")))
+                }
+                // we dropped ops the client needed, so send the current state instead
+                Err(RecvError::Lagged(_)) => match worker.resync().await {
+                    Ok(op) => {
+                        last_seq_sent = op.seq.unwrap_or_default();
+                        Some(server_message(&op))
+                    }
+                    Err(e) => Some(Err(report_app_err(e))),
+                },
+                // the worker was evicted; the client should reconnect
+                Err(RecvError::Closed) => Some(Err(Status::unavailable("server is reloading state"))),
+            },
+        };
+
+        if let Some(message) = message {
+            let is_err = message.is_err();
+            if out_tx.send(message).await.is_err() || is_err {
+                return;
+            }
+        }
+    }
+}
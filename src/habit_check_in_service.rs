@@ -0,0 +1,117 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for HabitCheckIn {
+    // select * from habit_check_in order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> HabitCheckIn {
+        HabitCheckIn {
+            habit_check_in_id: row.get("habit_check_in_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            habit_id: row.get("habit_id"),
+            day: row.get("day"),
+        }
+    }
+}
+
+// checking in twice on the same day is the same as once
+#[tracing::instrument(skip(con))]
+pub async fn add(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    habit_id: &str,
+    day: &str,
+) -> Result<(), tokio_postgres::Error> {
+    con.execute(
+        "INSERT INTO
+         habit_check_in(
+             creator_user_id,
+             habit_id,
+             day
+         )
+         VALUES($1, $2, $3)
+         ON CONFLICT DO NOTHING
+        ",
+        &[&creator_user_id, &habit_id, &day],
+    )
+    .await?;
+    Ok(())
+}
+
+// oldest first
+#[tracing::instrument(skip(con))]
+pub async fn get_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<Vec<HabitCheckIn>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT * FROM habit_check_in WHERE creator_user_id = $1 ORDER BY day",
+            &[&creator_user_id],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn get_all_by_habit_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    habit_id: &str,
+) -> Result<Vec<HabitCheckIn>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT * FROM habit_check_in WHERE creator_user_id = $1 AND habit_id = $2",
+            &[&creator_user_id, &habit_id],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn remove(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    habit_id: &str,
+    day: &str,
+) -> Result<(), tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM habit_check_in WHERE creator_user_id = $1 AND habit_id = $2 AND day = $3",
+        &[&creator_user_id, &habit_id, &day],
+    )
+    .await?;
+    Ok(())
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_habit_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    habit_id: &str,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM habit_check_in WHERE creator_user_id = $1 AND habit_id = $2",
+        &[&creator_user_id, &habit_id],
+    )
+    .await
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM habit_check_in WHERE creator_user_id = $1",
+        &[&creator_user_id],
+    )
+    .await
+}
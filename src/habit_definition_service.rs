@@ -0,0 +1,129 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for HabitDefinition {
+    // select * from habit_definition order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> HabitDefinition {
+        HabitDefinition {
+            habit_definition_id: row.get("habit_definition_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            habit_id: row.get("habit_id"),
+            value: row.get("value"),
+        }
+    }
+}
+
+// returns None if the user already has a habit with this id
+#[tracing::instrument(skip(con, value))]
+pub async fn add(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    habit_id: String,
+    value: String,
+) -> Result<Option<HabitDefinition>, tokio_postgres::Error> {
+    let row = con
+        .query_opt(
+            "INSERT INTO
+             habit_definition(
+                 creator_user_id,
+                 habit_id,
+                 value
+             )
+             VALUES($1, $2, $3)
+             ON CONFLICT DO NOTHING
+             RETURNING habit_definition_id, creation_time
+            ",
+            &[&creator_user_id, &habit_id, &value],
+        )
+        .await?;
+
+    // return habit
+    Ok(row.map(|row| HabitDefinition {
+        habit_definition_id: row.get(0),
+        creation_time: row.get(1),
+        creator_user_id,
+        habit_id,
+        value,
+    }))
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn get_by_habit_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    habit_id: &str,
+) -> Result<Option<HabitDefinition>, tokio_postgres::Error> {
+    let result = con
+        .query_opt(
+            "SELECT * FROM habit_definition WHERE creator_user_id=$1 AND habit_id=$2",
+            &[&creator_user_id, &habit_id],
+        )
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+// oldest first
+#[tracing::instrument(skip(con))]
+pub async fn get_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<Vec<HabitDefinition>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT * FROM habit_definition WHERE creator_user_id=$1 ORDER BY habit_definition_id",
+            &[&creator_user_id],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// returns whether the user had a habit with this id
+#[tracing::instrument(skip(con, value))]
+pub async fn update(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    habit_id: &str,
+    value: String,
+) -> Result<bool, tokio_postgres::Error> {
+    let updated = con
+        .execute(
+            "UPDATE habit_definition SET value = $3 WHERE creator_user_id = $1 AND habit_id = $2",
+            &[&creator_user_id, &habit_id, &value],
+        )
+        .await?;
+    Ok(updated > 0)
+}
+
+// returns whether the user had a habit with this id
+#[tracing::instrument(skip(con))]
+pub async fn remove(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    habit_id: &str,
+) -> Result<bool, tokio_postgres::Error> {
+    let removed = con
+        .execute(
+            "DELETE FROM habit_definition WHERE creator_user_id = $1 AND habit_id = $2",
+            &[&creator_user_id, &habit_id],
+        )
+        .await?;
+    Ok(removed > 0)
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM habit_definition WHERE creator_user_id = $1",
+        &[&creator_user_id],
+    )
+    .await
+}
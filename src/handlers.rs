@@ -1,15 +1,69 @@
+use super::activity;
+use super::archived_task_service;
+use super::attachment_service;
+use super::audit;
+use super::audit_event_service;
+use super::db_pool;
+use super::db_types;
+use super::deleted_task_service;
+use super::email;
+use super::email_alias_service;
+use super::event_stream;
+use super::github;
+use super::github_link_service;
+use super::goal_service;
+use super::habit_check_in_service;
+use super::habit_definition_service;
+use super::job_queue::{self, Job};
+use super::job_service;
+use super::known_device_service;
+use super::op_usage_service;
+use super::operation_service;
+use super::preference_service;
+use super::preferences;
+use super::protocol;
+use super::protocol::{Op, OpKind, OpRejectReason};
+use super::push_subscription_service;
+use super::quick_add;
+use super::quota;
+use super::rate_limit;
+use super::read_only_api_key_service;
+use super::reminder_service;
+use super::request_id::{self, RequestId};
+use super::snapshot_codec;
+use super::state_machine;
+use super::stats;
+use super::task_comment_service;
+use super::task_estimate_service;
+use super::task_focus_service;
+use super::task_goal_service;
+use super::task_lane_service;
 use super::task_updates;
+use super::template_service;
+use super::tenant_service;
+use super::tenant_user_service;
+use super::user_worker::{self, ApplyOutcome, Registration, Update};
+use super::utils;
+use super::webhook_service;
 use super::AppData;
 
 use actix_web::{
-    http::StatusCode, rt, web, Error, HttpRequest, HttpResponse, Responder, ResponseError,
+    http::{
+        header::{ETag, EntityTag, Header, IfNoneMatch, CACHE_CONTROL, RETRY_AFTER},
+        StatusCode,
+    },
+    rt, web, Either, Error, HttpMessage, HttpRequest, HttpResponse, Responder, ResponseError,
 };
-use auth_service_api::response::{AuthError, User};
+use auth_service_api::response::AuthError;
 use derive_more::Display;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tracing::Instrument;
 
-use todoproxy_api::request;
 use todoproxy_api::response;
+use todoproxy_api::{FinishedTask, LiveTask, WebsocketOpKind};
 
 #[derive(Clone, Debug, Serialize, Deserialize, Display)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
@@ -19,12 +73,26 @@ pub enum AppError {
     Unauthorized,
     BadRequest,
     NotFound,
+    TooManyRequests,
+    QuotaExceeded,
+    // the database is too busy to get a connection to
+    Unavailable,
     Unknown,
 }
 
+#[derive(Serialize)]
+struct AppErrorResponse<'a> {
+    kind: &'a AppError,
+    // the request id, so the user can report it
+    error_id: Option<String>,
+}
+
 impl ResponseError for AppError {
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code()).json(self)
+        HttpResponse::build(self.status_code()).json(AppErrorResponse {
+            kind: self,
+            error_id: request_id::current(),
+        })
     }
     fn status_code(&self) -> StatusCode {
         match *self {
@@ -33,6 +101,9 @@ impl ResponseError for AppError {
             AppError::Unauthorized => StatusCode::UNAUTHORIZED,
             AppError::BadRequest => StatusCode::BAD_REQUEST,
             AppError::NotFound => StatusCode::NOT_FOUND,
+            AppError::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            AppError::QuotaExceeded => StatusCode::FORBIDDEN,
+            AppError::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
             AppError::Unknown => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -43,9 +114,17 @@ pub fn report_postgres_err(e: tokio_postgres::Error) -> AppError {
     AppError::InternalServerError
 }
 
+// for reads that can be a little behind, which go to the read replica if there is one
+pub fn read_pool(data: &AppData) -> &db_pool::Pool {
+    data.read_pool.as_ref().unwrap_or(&data.pool)
+}
+
 pub fn report_pool_err(e: deadpool_postgres::PoolError) -> AppError {
     log::error!("{}", e);
-    AppError::InternalServerError
+    match e {
+        deadpool_postgres::PoolError::Timeout(_) => AppError::Unavailable,
+        _ => AppError::InternalServerError,
+    }
 }
 
 pub fn report_internal_serde_error(e: serde_json::Error) -> AppError {
@@ -76,44 +155,2736 @@ pub fn report_auth_err(e: AuthError) -> AppError {
     }
 }
 
-pub async fn get_user_if_api_key_valid(
-    auth_service: &auth_service_api::client::AuthService,
+// returns the id of the user the api key belongs to
+// this is where requests are counted against the key's rate limit
+pub async fn get_user_id_if_api_key_valid(
+    data: &AppData,
+    api_key: String,
+) -> Result<i64, AppError> {
+    let settings = data.settings.get();
+    if settings.rate_limit_requests > 0 {
+        data.rate_limiter.check(
+            &api_key,
+            settings.rate_limit_requests,
+            settings.rate_limit_window(),
+        )?;
+    }
+    if let Some(ref auth_cache) = data.auth_cache {
+        if let Some(user_id) = auth_cache.get(&api_key) {
+            return Ok(user_id);
+        }
+    }
+    let user_id = match data
+        .auth
+        .get_user_id_by_api_key(&data.pool, api_key.clone())
+        .await
+    {
+        // keys the auth backend said were valid a little while ago still are while it's down
+        Err(AppError::Unavailable) => {
+            return match data.auth_cache.as_ref().and_then(|x| x.get_stale(&api_key)) {
+                Some(user_id) => {
+                    log::warn!("auth backend unavailable; using a stale cached api key");
+                    Ok(user_id)
+                }
+                None => Err(AppError::Unavailable),
+            };
+        }
+        result => result?,
+    };
+    if let Some(ref auth_cache) = data.auth_cache {
+        auth_cache.insert(&api_key, user_id);
+    }
+    Ok(user_id)
+}
+
+// checks the api key with the auth backend even if it's cached, for sessions that outlive the cache
+// it isn't counted against the key's rate limit
+pub async fn revalidate_api_key(data: &AppData, api_key: String) -> Result<i64, AppError> {
+    let result = data
+        .auth
+        .get_user_id_by_api_key(&data.pool, api_key.clone())
+        .await;
+    if let Some(ref auth_cache) = data.auth_cache {
+        match result {
+            Ok(user_id) => auth_cache.insert(&api_key, user_id),
+            // kept to fall back on while the auth backend is down
+            Err(AppError::Unavailable) => {}
+            Err(_) => auth_cache.invalidate(&api_key),
+        }
+    }
+    result
+}
+
+// the auth service has no notion of scopes, so keys are restricted on our side, by hash
+pub async fn api_key_is_read_only(pool: &db_pool::Pool, api_key: &str) -> Result<bool, AppError> {
+    let con: &mut tokio_postgres::Client = &mut *pool.get().await.map_err(report_pool_err)?;
+    let read_only_api_key = read_only_api_key_service::get_by_api_key_hash(
+        &mut *con,
+        &utils::sha256_hex(api_key.as_bytes()),
+    )
+    .await
+    .map_err(report_postgres_err)?;
+    Ok(read_only_api_key.is_some())
+}
+
+// like get_user_id_if_api_key_valid, but only lets the deployment's author through
+pub async fn get_author_id_if_api_key_valid(
+    data: &AppData,
+    api_key: String,
+) -> Result<i64, AppError> {
+    let user_id = get_user_id_if_api_key_valid(data, api_key).await?;
+    if Some(user_id) != data.author_id {
+        return Err(AppError::Unauthorized);
+    }
+    Ok(user_id)
+}
+
+// whose users an admin may manage
+#[derive(Clone, Copy, Debug)]
+pub enum AdminScope {
+    // everyone's, for the deployment's author
+    Deployment,
+    // the tenant's, for its admin
+    Tenant(i64),
+}
+
+// like get_author_id_if_api_key_valid, but also lets tenants' admins through
+pub async fn get_admin_scope_if_api_key_valid(
+    data: &AppData,
     api_key: String,
-) -> Result<User, AppError> {
-    auth_service
-        .get_user_by_api_key_if_valid(api_key)
+) -> Result<AdminScope, AppError> {
+    let user_id = get_user_id_if_api_key_valid(data, api_key).await?;
+    if Some(user_id) == data.author_id {
+        return Ok(AdminScope::Deployment);
+    }
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let tenant = tenant_service::get_by_admin_user_id(&mut *con, user_id)
+        .await
+        .map_err(report_postgres_err)?;
+    match tenant {
+        Some(tenant) => Ok(AdminScope::Tenant(tenant.tenant_id)),
+        None => Err(AppError::Unauthorized),
+    }
+}
+
+// the tenant the user belongs to, or None if they belong to the deployment itself
+pub async fn tenant_id_of(data: &AppData, user_id: i64) -> Result<Option<i64>, AppError> {
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let tenant_user = tenant_user_service::get_by_user_id(&mut *con, user_id)
         .await
-        .map_err(report_auth_err)
+        .map_err(report_postgres_err)?;
+    Ok(tenant_user.map(|x| x.tenant_id))
+}
+
+// the users the admin may manage, or None if they may manage everyone
+async fn admin_scope_users(
+    data: &AppData,
+    scope: AdminScope,
+) -> Result<Option<HashSet<i64>>, AppError> {
+    match scope {
+        AdminScope::Deployment => Ok(None),
+        AdminScope::Tenant(tenant_id) => {
+            let con: &mut tokio_postgres::Client =
+                &mut *data.pool.get().await.map_err(report_pool_err)?;
+            let tenant_users = tenant_user_service::get_all_by_tenant_id(&mut *con, tenant_id)
+                .await
+                .map_err(report_postgres_err)?;
+            Ok(Some(
+                tenant_users
+                    .into_iter()
+                    .map(|x| x.creator_user_id)
+                    .collect(),
+            ))
+        }
+    }
+}
+
+// users outside the admin's tenant look like they don't exist
+async fn check_admin_scope(
+    data: &AppData,
+    scope: AdminScope,
+    user_id: i64,
+) -> Result<(), AppError> {
+    match scope {
+        AdminScope::Deployment => Ok(()),
+        AdminScope::Tenant(tenant_id) if tenant_id_of(data, user_id).await? == Some(tenant_id) => {
+            Ok(())
+        }
+        AdminScope::Tenant(_) => Err(AppError::NotFound),
+    }
 }
 
 // respond with info about stuff
+#[tracing::instrument(skip_all)]
 pub async fn info(data: web::Data<AppData>) -> Result<impl Responder, AppError> {
-    let info = data.auth_service.info().await.map_err(report_auth_err)?;
-    return Ok(web::Json(response::Info {
-        service: String::from(super::SERVICE),
-        version_major: super::VERSION_MAJOR,
-        version_minor: super::VERSION_MINOR,
-        version_rev: super::VERSION_REV,
-        app_pub_origin: data.app_pub_origin.clone(),
-        auth_pub_api_href: info.app_pub_api_href,
-        auth_authenticator_href: info.app_authenticator_href,
+    let info = data.auth.info().await?;
+    // standalone deployments hand out api keys themselves
+    let (auth_pub_api_href, auth_authenticator_href) = match info {
+        Some(info) => (info.app_pub_api_href, info.app_authenticator_href),
+        None => (String::new(), String::new()),
+    };
+    let settings = data.settings.get();
+    return Ok(web::Json(protocol::response::Info {
+        base: response::Info {
+            service: String::from(super::SERVICE),
+            version_major: super::VERSION_MAJOR,
+            version_minor: super::VERSION_MINOR,
+            version_rev: super::VERSION_REV,
+            app_pub_origin: data.app_pub_origin.clone(),
+            auth_pub_api_href,
+            auth_authenticator_href,
+        },
+        heartbeat_interval_ms: settings.heartbeat_interval().as_millis() as u64,
+        client_timeout_ms: settings.client_timeout().as_millis() as u64,
+        encrypted_value_prefix: String::from(protocol::ENCRYPTED_VALUE_PREFIX),
+        protocol_versions: (protocol::MIN_PROTOCOL_VERSION..=protocol::PROTOCOL_VERSION).collect(),
+        vapid_public_key: data.push.as_ref().map(|x| x.vapid_public_key.clone()),
+        max_attachment_bytes: data.storage.as_ref().map(|_| data.max_attachment_bytes),
+        email_aliases: data.email.is_some(),
+        github_links: data.github.enabled(),
     }));
 }
 
-// start websocket connection
-pub async fn ws_task_updates(
+// restrict an api key so that connections using it can't apply ops
+// there's no way back, since anyone holding the key could otherwise lift the restriction
+#[tracing::instrument(skip_all)]
+pub async fn api_key_make_read_only(
+    req: web::Json<protocol::request::ApiKeyMakeReadOnly>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let api_key = req.into_inner().api_key;
+    let user_id = get_user_id_if_api_key_valid(&data, api_key.clone()).await?;
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    read_only_api_key_service::add(&mut *con, user_id, utils::sha256_hex(api_key.as_bytes()))
+        .await
+        .map_err(report_postgres_err)?;
+    audit::record(&mut *con, user_id, "api_key_make_read_only", None)
+        .await
+        .map_err(report_postgres_err)?;
+    Ok(web::Json(()))
+}
+
+// apply an op without a websocket, e.g. alongside the event stream
+#[tracing::instrument(skip_all)]
+pub async fn apply_op(
+    req: web::Json<protocol::request::ApplyOp>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::ApplyOp { api_key, op } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key.clone()).await?;
+
+    let outcome = if api_key_is_read_only(&data.pool, &api_key).await? {
+        ApplyOutcome::Rejected(OpRejectReason::ReadOnly)
+    } else {
+        let (worker, _) = user_worker::connect(&data, user_id).await?;
+        let _registration = Registration(worker.clone());
+        worker.apply_op(op).await?
+    };
+
+    Ok(web::Json(apply_op_response(outcome)))
+}
+
+fn apply_op_response(outcome: ApplyOutcome) -> protocol::response::ApplyOp {
+    match outcome {
+        ApplyOutcome::Applied(op) => protocol::response::ApplyOp {
+            applied: true,
+            seq: op.seq,
+            id: match op.kind {
+                OpKind::Base(WebsocketOpKind::InsLiveTask { id, .. }) => Some(id),
+                _ => None,
+            },
+            reject_reason: None,
+        },
+        ApplyOutcome::Duplicate => protocol::response::ApplyOp {
+            applied: false,
+            seq: None,
+            id: None,
+            reject_reason: None,
+        },
+        ApplyOutcome::Rejected(reason) => protocol::response::ApplyOp {
+            applied: false,
+            seq: None,
+            id: None,
+            reject_reason: Some(reason),
+        },
+    }
+}
+
+// turn what the user typed into a task, so every client reads it the same way
+#[tracing::instrument(skip_all)]
+pub async fn quick_add(
+    req: web::Json<protocol::request::QuickAdd>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::QuickAdd {
+        api_key,
+        text,
+        utc_offset_mins,
+        idempotency_key,
+    } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key.clone()).await?;
+
+    let now = chrono::Utc::now();
+    let quick_add::ParsedTask {
+        value,
+        tags,
+        priority,
+        deadline,
+    } = match utc_offset_mins {
+        Some(utc_offset_mins) => {
+            let offset =
+                chrono::FixedOffset::east_opt(utc_offset_mins * 60).ok_or(AppError::BadRequest)?;
+            quick_add::parse(&text, now.with_timezone(&offset))
+        }
+        // in the time zone the user set
+        None => {
+            let con: &mut tokio_postgres::Client =
+                &mut *data.pool.get().await.map_err(report_pool_err)?;
+            let timezone = preferences::timezone(&preferences::get(con, user_id).await?);
+            quick_add::parse(&text, now.with_timezone(&timezone))
+        }
+    };
+    if value.is_empty() {
+        return Err(AppError::BadRequest);
+    }
+
+    let outcome = if api_key_is_read_only(&data.pool, &api_key).await? {
+        ApplyOutcome::Rejected(OpRejectReason::ReadOnly)
+    } else {
+        let (worker, _) = user_worker::connect(&data, user_id).await?;
+        let _registration = Registration(worker.clone());
+        worker
+            .apply_op(Op {
+                alleged_time: utils::current_time_millis(),
+                kind: OpKind::Ext(protocol::ExtOpKind::InsLiveTaskNew {
+                    value: value.clone(),
+                }),
+                seq: None,
+                checksum: None,
+                idempotency_key,
+                value_encoding: None,
+            })
+            .await?
+    };
+    let op = apply_op_response(outcome);
+
+    // tasks don't have deadlines, so it's when they're reminded of it
+    if let (Some(id), Some(deadline)) = (&op.id, deadline) {
+        let con: &mut tokio_postgres::Client =
+            &mut *data.pool.get().await.map_err(report_pool_err)?;
+        reminder_service::set(&mut *con, user_id, id.clone(), deadline)
+            .await
+            .map_err(report_postgres_err)?;
+    }
+
+    Ok(web::Json(protocol::response::QuickAdd {
+        op,
+        value,
+        tags,
+        priority,
+        deadline,
+    }))
+}
+
+/// The most days one calendar request may cover.
+const MAX_CALENDAR_DAYS: i64 = 366;
+
+/// The most days one burndown request may cover.
+const MAX_BURNDOWN_DAYS: i64 = 366;
+
+// how much estimated effort was left at the end of each of the user's days, in their time zone
+#[tracing::instrument(skip_all)]
+pub async fn burndown(
+    query: web::Query<protocol::request::Burndown>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::Burndown { api_key, from, to } = query.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key).await?;
+    let from = chrono::NaiveDate::parse_from_str(&from, protocol::DAY_FORMAT)
+        .map_err(|_| AppError::BadRequest)?;
+    let to = chrono::NaiveDate::parse_from_str(&to, protocol::DAY_FORMAT)
+        .map_err(|_| AppError::BadRequest)?;
+    if to < from || (to - from).num_days() >= MAX_BURNDOWN_DAYS {
+        return Err(AppError::BadRequest);
+    }
+
+    let mut con = read_pool(&data).get().await.map_err(report_pool_err)?;
+    let preferences = preferences::get(&mut **con, user_id).await?;
+    let days = stats::burndown(&mut con, user_id, &preferences, from, to).await?;
+    Ok(web::Json(days))
+}
+
+// the user's week in review, put together here so every client shows the same one
+#[tracing::instrument(skip_all)]
+pub async fn review(
+    query: web::Query<protocol::request::Review>,
     data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::Review { api_key, week } = query.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key).await?;
+    // the week's monday
+    let from = chrono::NaiveDate::parse_from_str(
+        &format!("{week}-1"),
+        &format!("{}-%u", protocol::WEEK_FORMAT),
+    )
+    .map_err(|_| AppError::BadRequest)?;
+
+    let mut con = read_pool(&data).get().await.map_err(report_pool_err)?;
+    let preferences = preferences::get(&mut **con, user_id).await?;
+    // there's nothing to review yet
+    if from > preferences::day_of(&preferences, chrono::Utc::now()) {
+        return Err(AppError::BadRequest);
+    }
+    let review = stats::review(&mut con, user_id, &preferences, from).await?;
+    Ok(web::Json(review))
+}
+
+/// How many live tasks the widget shows if the request doesn't say.
+const DEFAULT_WIDGET_TASKS: usize = 5;
+/// The most live tasks the widget may show.
+const MAX_WIDGET_TASKS: usize = 50;
+/// How long dashboards may show a widget before fetching it again.
+const WIDGET_MAX_AGE: Duration = Duration::from_secs(60);
+
+// a small view of the user's list for dashboards and start pages to embed: the first few live
+// tasks and what was completed today
+// any origin may fetch it, so only read-only keys are accepted, since the key ends up wherever
+// the widget is configured
+#[tracing::instrument(skip_all)]
+pub async fn widget(
     req: HttpRequest,
-    stream: web::Payload,
-    query: web::Query<request::WebsocketInitMessage>,
-) -> Result<impl Responder, Error> {
-    let (res, session, msg_stream) = actix_ws::handle(&req, stream)?;
-    // spawn websocket handler (and don't await it) so that the response is returned immediately
-    rt::spawn(task_updates::manage_updates_ws(
-        data,
-        query.into_inner(),
-        session,
-        msg_stream,
+    query: web::Query<protocol::request::Widget>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::Widget { api_key, limit } = query.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key.clone()).await?;
+    if !api_key_is_read_only(&data.pool, &api_key).await? {
+        return Err(AppError::Unauthorized);
+    }
+    let limit = limit.unwrap_or(DEFAULT_WIDGET_TASKS).min(MAX_WIDGET_TASKS);
+
+    let mut con = read_pool(&data).get().await.map_err(report_pool_err)?;
+    let preferences = preferences::get(&mut **con, user_id).await?;
+    let now = chrono::Utc::now();
+    let today = preferences::day_of(&preferences, now);
+    let user_worker::LoadedState {
+        snapshot, last_seq, ..
+    } = user_worker::read_state(&mut con, user_id).await?;
+
+    // it only changes when an op is applied or the day does
+    let etag = EntityTag::new_strong(format!(
+        "{last_seq}-{}-{limit}",
+        today.format(protocol::DAY_FORMAT)
     ));
+    let cache_control = format!("private, max-age={}", WIDGET_MAX_AGE.as_secs());
+    // caches in front of the widget may have weakened the tag, which still counts as a match
+    let unchanged = match IfNoneMatch::parse(&req) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|x| x.weak_eq(&etag)),
+        Err(_) => false,
+    };
+    if unchanged {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(ETag(etag))
+            .insert_header((CACHE_CONTROL, cache_control))
+            .finish());
+    }
+
+    // tasks that were restored or cleared since aren't completed anymore
+    // and tasks finished more than once today are only listed once
+    let mut completed_today = vec![];
+    let mut seen = HashSet::new();
+    for x in operation_service::get_finish_operations_by_user_id_between(
+        &mut con,
+        user_id,
+        preferences::day_start(&preferences, today).timestamp_millis(),
+        now.timestamp_millis() + 1,
+    )
+    .await
+    .map_err(report_postgres_err)?
+    {
+        let op = serde_json::from_str::<Op>(&x.jsonval).map_err(report_internal_serde_error)?;
+        for id in stats::succeeded_ids(&op.kind) {
+            if !seen.insert(id.clone()) {
+                continue;
+            }
+            if let Some(task) = snapshot.finished.iter().find(|t| &t.id == id) {
+                completed_today.push(protocol::response::WidgetTask {
+                    id: task.id.clone(),
+                    value: task.value.clone(),
+                });
+            }
+        }
+    }
+
+    let widget = protocol::response::Widget {
+        day: today.format(protocol::DAY_FORMAT).to_string(),
+        live_count: snapshot.live.len(),
+        live: snapshot
+            .live
+            .iter()
+            .take(limit)
+            .map(|x| protocol::response::WidgetTask {
+                id: x.id.clone(),
+                value: x.value.clone(),
+            })
+            .collect(),
+        completed_today,
+    };
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .insert_header((CACHE_CONTROL, cache_control))
+        .json(widget))
+}
+
+// live tasks by the day they're due and finished tasks by the day they were finished,
+// in the user's time zone, so calendar views don't have to fetch and bucket everything
+// tasks don't have deadlines, so live tasks are due when their reminder is, and reminders that
+// already went off aren't kept
+#[tracing::instrument(skip_all)]
+pub async fn task_calendar(
+    query: web::Query<protocol::request::TaskCalendar>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::TaskCalendar { api_key, from, to } = query.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key).await?;
+    let from = chrono::NaiveDate::parse_from_str(&from, protocol::DAY_FORMAT)
+        .map_err(|_| AppError::BadRequest)?;
+    let to = chrono::NaiveDate::parse_from_str(&to, protocol::DAY_FORMAT)
+        .map_err(|_| AppError::BadRequest)?;
+    if to < from || (to - from).num_days() >= MAX_CALENDAR_DAYS {
+        return Err(AppError::BadRequest);
+    }
+
+    let mut con = read_pool(&data).get().await.map_err(report_pool_err)?;
+    let preferences = preferences::get(&mut **con, user_id).await?;
+    let start_time = preferences::day_start(&preferences, from).timestamp_millis();
+    let end_time = preferences::day_start(&preferences, to.succ_opt().ok_or(AppError::BadRequest)?)
+        .timestamp_millis();
+    let user_worker::LoadedState { snapshot, .. } =
+        user_worker::read_state(&mut con, user_id).await?;
+
+    let mut days = BTreeMap::new();
+
+    let mut reminders = reminder_service::get_all_by_user_id(&mut **con, user_id)
+        .await
+        .map_err(report_postgres_err)?;
+    reminders.retain(|x| x.remind_time >= start_time && x.remind_time < end_time);
+    reminders.sort_by_key(|x| x.remind_time);
+    for x in reminders {
+        if let Some(task) = snapshot.live.iter().find(|t| t.id == x.task_id) {
+            calendar_day(&mut days, &preferences, x.remind_time)
+                .live
+                .push(protocol::response::CalendarLiveTask {
+                    id: task.id.clone(),
+                    value: task.value.clone(),
+                    deadline: x.remind_time,
+                });
+        }
+    }
+
+    // when each task was last finished, from the op log
+    let mut finish_times = HashMap::new();
+    for x in operation_service::get_finish_operations_by_user_id_between(
+        &mut con, user_id, start_time, end_time,
+    )
+    .await
+    .map_err(report_postgres_err)?
+    {
+        let op = serde_json::from_str::<Op>(&x.jsonval).map_err(report_internal_serde_error)?;
+        let ids = match op.kind {
+            OpKind::Base(WebsocketOpKind::FinishLiveTask { id, .. }) => vec![id],
+            OpKind::Ext(protocol::ExtOpKind::FinishManyLiveTasks { ids, .. }) => ids,
+            _ => vec![],
+        };
+        for id in ids {
+            finish_times.insert(id, x.creation_time);
+        }
+    }
+    // tasks that were rolled over since are only in the history
+    let mut finished = HashMap::new();
+    for x in archived_task_service::get_all_by_user_id_since(
+        &mut **con,
+        user_id,
+        &from.format(protocol::DAY_FORMAT).to_string(),
+    )
+    .await
+    .map_err(report_postgres_err)?
+    {
+        let task = archived_task_response(x)?;
+        finished.insert(task.id.clone(), task);
+    }
+    for task in snapshot.finished {
+        finished.insert(task.id.clone(), task);
+    }
+    let mut finish_times = finish_times.into_iter().collect::<Vec<_>>();
+    finish_times.sort_by_key(|(_, finish_time)| *finish_time);
+    for (id, finish_time) in finish_times {
+        // tasks that were restored or cleared since aren't finished anymore
+        if let Some(task) = finished.remove(&id) {
+            calendar_day(&mut days, &preferences, finish_time)
+                .finished
+                .push(protocol::response::CalendarFinishedTask {
+                    id: task.id,
+                    value: task.value,
+                    status: task.status,
+                    finish_time,
+                });
+        }
+    }
+
+    Ok(web::Json(days.into_values().collect::<Vec<_>>()))
+}
+
+// the calendar entry for the user's day at time, added if it isn't there yet
+fn calendar_day<'a>(
+    days: &'a mut BTreeMap<chrono::NaiveDate, protocol::response::CalendarDay>,
+    preferences: &protocol::Preferences,
+    time: i64,
+) -> &'a mut protocol::response::CalendarDay {
+    let time = chrono::DateTime::from_timestamp_millis(time).unwrap_or_default();
+    let day = preferences::day_of(preferences, time);
+    days.entry(day)
+        .or_insert_with(|| protocol::response::CalendarDay {
+            day: day.format(protocol::DAY_FORMAT).to_string(),
+            live: vec![],
+            finished: vec![],
+        })
+}
+
+/// How long a poll waits for new ops if the client doesn't say.
+const DEFAULT_POLL_TIMEOUT_SECS: u64 = 25;
+/// The longest a poll may wait, so proxies don't give up on it first.
+const MAX_POLL_TIMEOUT_SECS: u64 = 60;
+/// A client further behind than this gets the whole state instead of the ops it missed.
+const MAX_POLL_OPS: i64 = 500;
+
+// wait for ops after since_seq, for clients that can use neither websockets nor event streams
+#[tracing::instrument(skip_all)]
+pub async fn poll_task_updates(
+    query: web::Query<protocol::request::PollTaskUpdates>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::PollTaskUpdates {
+        api_key,
+        since_seq,
+        timeout_secs,
+    } = query.into_inner();
+    let timeout = Duration::from_secs(
+        timeout_secs
+            .unwrap_or(DEFAULT_POLL_TIMEOUT_SECS)
+            .min(MAX_POLL_TIMEOUT_SECS),
+    );
+    let user_id = get_user_id_if_api_key_valid(&data, api_key).await?;
+
+    let (worker, subscription) = user_worker::connect(&data, user_id).await?;
+    let _registration = Registration(worker);
+    let user_worker::Subscription {
+        mut updates_rx,
+        initial_op,
+    } = subscription;
+    let current_seq = initial_op.seq.unwrap_or_default();
+
+    // the client has everything, so wait for the next op
+    if since_seq >= current_seq {
+        let mut ops = vec![];
+        // events aren't delivered to pollers
+        let next_op = async {
+            loop {
+                match updates_rx.recv().await {
+                    Ok(Update::Op(op)) => return Some(op.into_op()),
+                    Ok(Update::Event(_)) => {}
+                    Err(_) => return None,
+                }
+            }
+        };
+        if let Ok(Some(op)) = tokio::time::timeout(timeout, next_op).await {
+            ops.push(op);
+            // and anything that arrived with it
+            while let Ok(update) = updates_rx.try_recv() {
+                if let Update::Op(op) = update {
+                    ops.push(op.into_op());
+                }
+            }
+        }
+        return Ok(web::Json(protocol::response::PollTaskUpdates { ops }));
+    }
+
+    // the client is behind, so send what it missed from the log
+    let mut con = data.pool.get().await.map_err(report_pool_err)?;
+    let ops = match missed_ops(&mut con, user_id, since_seq, &initial_op, MAX_POLL_OPS).await? {
+        Some(ops) => ops,
+        None => vec![initial_op],
+    };
+    Ok(web::Json(protocol::response::PollTaskUpdates { ops }))
+}
+
+// the ops after since_seq up to the current one, read back from the log
+// None if the client should be sent the whole state instead, because it has none, it's more than
+// max_ops behind, or the ops it missed aren't in the log
+pub async fn missed_ops(
+    con: &mut deadpool_postgres::Client,
+    user_id: i64,
+    since_seq: i64,
+    current: &Op,
+    max_ops: i64,
+) -> Result<Option<Vec<Op>>, AppError> {
+    let current_seq = current.seq.unwrap_or_default();
+    let operations =
+        operation_service::get_operations_by_user_id_after(&mut *con, user_id, since_seq, max_ops)
+            .await
+            .map_err(report_postgres_err)?;
+
+    let missed = operations
+        .iter()
+        .filter(|x| x.operation_id <= current_seq)
+        .count();
+    // since_seq of 0 means the client has no state to apply ops to
+    if since_seq == 0 || missed as i64 >= max_ops || missed == 0 {
+        return Ok(None);
+    }
+
+    let mut ops = vec![];
+    for x in operations.into_iter().take(missed) {
+        let mut op = serde_json::from_str::<Op>(&x.jsonval).map_err(report_internal_serde_error)?;
+        op.seq = Some(x.operation_id);
+        ops.push(op);
+    }
+    // the last op brings the client up to the current state
+    if let Some(op) = ops.last_mut() {
+        op.checksum = current.checksum.clone();
+    }
+    Ok(Some(ops))
+}
+
+// remove everything stored for the user, and disconnect all their sessions
+#[tracing::instrument(skip_all)]
+pub async fn account_delete(
+    req: web::Json<protocol::request::AccountDelete>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let api_key = req.into_inner().api_key;
+    let user_id = get_user_id_if_api_key_valid(&data, api_key.clone()).await?;
+    if api_key_is_read_only(&data.pool, &api_key).await? {
+        return Err(AppError::Unauthorized);
+    }
+
+    // the deletion goes through the worker, so no op can be persisted after it
+    let (worker, _) = user_worker::connect(&data, user_id).await?;
+    let _registration = Registration(worker.clone());
+    let receipt = worker.delete_account().await?;
+    // so no session can come back without the api key
+    data.resume_tokens.retain(|_, x| x.user_id != user_id);
+    if let Some(ref auth_cache) = data.auth_cache {
+        auth_cache.invalidate_user(user_id);
+    }
+    Ok(web::Json(receipt))
+}
+
+// get the user's current state without opening a websocket
+#[tracing::instrument(skip_all)]
+pub async fn state(
+    query: web::Query<protocol::request::State>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let user_id = get_user_id_if_api_key_valid(&data, query.into_inner().api_key).await?;
+
+    // every op is persisted before it is broadcast, so this matches any running worker, though a
+    // replica may be a moment behind it
+    let mut con = read_pool(&data).get().await.map_err(report_pool_err)?;
+    let user_worker::LoadedState {
+        snapshot, last_seq, ..
+    } = user_worker::read_state(&mut con, user_id).await?;
+    let comment_counts = task_comment_service::count_by_task_id(&mut **con, user_id, i64::MAX)
+        .await
+        .map_err(report_postgres_err)?;
+    let attachments = attachments_by_task_id(&mut con, user_id, i64::MAX).await?;
+    let lanes = lanes_by_task_id(&mut con, user_id, &snapshot.live).await?;
+    let estimates = estimates_by_task_id(&mut con, user_id, &snapshot.live).await?;
+    let focused_task = focused_task_id(&mut con, user_id, &snapshot.live).await?;
+
+    Ok(web::Json(protocol::response::State {
+        checksum: state_machine::snapshot_checksum(&snapshot),
+        snapshot,
+        seq: last_seq,
+        comment_counts,
+        attachments,
+        lanes,
+        estimates,
+        focused_task,
+    }))
+}
+
+// get the user's state as it was at some point in the past
+#[tracing::instrument(skip_all)]
+pub async fn state_at(
+    query: web::Query<protocol::request::StateAt>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::StateAt { api_key, timestamp } = query.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key).await?;
+
+    let mut con = read_pool(&data).get().await.map_err(report_pool_err)?;
+    let (snapshot, seq) = match user_worker::load_state_at(&mut con, user_id, timestamp).await? {
+        Some(x) => (x.snapshot, x.last_seq),
+        // the user had no tasks yet
+        None => (
+            todoproxy_api::StateSnapshot {
+                live: Default::default(),
+                finished: Default::default(),
+            },
+            0,
+        ),
+    };
+    // deleted comments are gone, so these only count the ones that are still around
+    let comment_counts = task_comment_service::count_by_task_id(&mut **con, user_id, timestamp)
+        .await
+        .map_err(report_postgres_err)?;
+    let attachments = attachments_by_task_id(&mut con, user_id, timestamp).await?;
+    // lanes aren't kept for the past, so tasks that are still live are in their current one
+    let lanes = lanes_by_task_id(&mut con, user_id, &snapshot.live).await?;
+    // and have their current estimate and focus
+    let estimates = estimates_by_task_id(&mut con, user_id, &snapshot.live).await?;
+    let focused_task = focused_task_id(&mut con, user_id, &snapshot.live).await?;
+
+    Ok(web::Json(protocol::response::State {
+        checksum: state_machine::snapshot_checksum(&snapshot),
+        snapshot,
+        seq,
+        comment_counts,
+        attachments,
+        lanes,
+        estimates,
+        focused_task,
+    }))
+}
+
+// register a browser to receive the user's notifications
+#[tracing::instrument(skip_all)]
+pub async fn push_subscribe(
+    req: web::Json<protocol::request::PushSubscribe>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::PushSubscribe {
+        api_key,
+        endpoint,
+        p256dh,
+        auth,
+    } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key).await?;
+    // we'd be making requests to whatever url we're given
+    if !endpoint.starts_with("https://") {
+        return Err(AppError::BadRequest);
+    }
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    push_subscription_service::add(&mut *con, user_id, endpoint, p256dh, auth)
+        .await
+        .map_err(report_postgres_err)?;
+    Ok(web::Json(()))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn push_unsubscribe(
+    req: web::Json<protocol::request::PushUnsubscribe>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::PushUnsubscribe { api_key, endpoint } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key).await?;
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    push_subscription_service::remove_by_endpoint(&mut *con, user_id, &endpoint)
+        .await
+        .map_err(report_postgres_err)?;
+    Ok(web::Json(()))
+}
+
+// remind the user of a task at the given time, with a push notification
+#[tracing::instrument(skip_all)]
+pub async fn reminder_set(
+    req: web::Json<protocol::request::ReminderSet>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::ReminderSet {
+        api_key,
+        task_id,
+        remind_time,
+    } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key.clone()).await?;
+    if api_key_is_read_only(&data.pool, &api_key).await? {
+        return Err(AppError::Unauthorized);
+    }
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    reminder_service::set(&mut *con, user_id, task_id, remind_time)
+        .await
+        .map_err(report_postgres_err)?;
+    Ok(web::Json(()))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn reminder_clear(
+    req: web::Json<protocol::request::ReminderClear>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::ReminderClear { api_key, task_id } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key.clone()).await?;
+    if api_key_is_read_only(&data.pool, &api_key).await? {
+        return Err(AppError::Unauthorized);
+    }
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    reminder_service::remove_by_task_id(&mut *con, user_id, &task_id)
+        .await
+        .map_err(report_postgres_err)?;
+    Ok(web::Json(()))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn reminder_view(
+    req: web::Json<protocol::request::ReminderView>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let user_id = get_user_id_if_api_key_valid(&data, req.into_inner().api_key).await?;
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let reminders = reminder_service::get_all_by_user_id(&mut *con, user_id)
+        .await
+        .map_err(report_postgres_err)?
+        .into_iter()
+        .map(|x| protocol::response::Reminder {
+            task_id: x.task_id,
+            remind_time: x.remind_time,
+        })
+        .collect::<Vec<_>>();
+    Ok(web::Json(reminders))
+}
+
+// templates are checked again when they're expanded, but it's better to find out when saving them
+fn check_template(data: &AppData, template: &protocol::TaskTemplate) -> Result<(), AppError> {
+    let max_len = data.task_limits.max_value_len;
+    if std::iter::once(&template.value)
+        .chain(&template.checklist)
+        .any(|x| x.len() > max_len)
+    {
+        return Err(AppError::BadRequest);
+    }
+    Ok(())
+}
+
+fn template_response(
+    x: crate::db_types::Template,
+) -> Result<protocol::response::Template, AppError> {
+    Ok(protocol::response::Template {
+        template_id: x.template_id,
+        creation_time: x.creation_time,
+        name: x.name,
+        template: serde_json::from_str(&x.jsonval).map_err(report_internal_serde_error)?,
+    })
+}
+
+// save a template, which InstantiateTemplate ops can then expand into tasks
+#[tracing::instrument(skip_all)]
+pub async fn template_new(
+    req: web::Json<protocol::request::TemplateNew>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::TemplateNew {
+        api_key,
+        name,
+        template,
+    } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key.clone()).await?;
+    if api_key_is_read_only(&data.pool, &api_key).await? {
+        return Err(AppError::Unauthorized);
+    }
+    check_template(&data, &template)?;
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let template = template_service::add(&mut *con, user_id, name, template)
+        .await
+        .map_err(report_postgres_err)?;
+    Ok(web::Json(template_response(template)?))
+}
+
+// replace a template; tasks it was already expanded into don't change
+#[tracing::instrument(skip_all)]
+pub async fn template_edit(
+    req: web::Json<protocol::request::TemplateEdit>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::TemplateEdit {
+        api_key,
+        template_id,
+        name,
+        template,
+    } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key.clone()).await?;
+    if api_key_is_read_only(&data.pool, &api_key).await? {
+        return Err(AppError::Unauthorized);
+    }
+    check_template(&data, &template)?;
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let updated = template_service::update(&mut *con, user_id, template_id, name, template)
+        .await
+        .map_err(report_postgres_err)?;
+    if !updated {
+        return Err(AppError::NotFound);
+    }
+    Ok(web::Json(()))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn template_view(
+    req: web::Json<protocol::request::TemplateView>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let user_id = get_user_id_if_api_key_valid(&data, req.into_inner().api_key).await?;
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let templates = template_service::get_all_by_user_id(&mut *con, user_id)
+        .await
+        .map_err(report_postgres_err)?
+        .into_iter()
+        .map(template_response)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(web::Json(templates))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn template_delete(
+    req: web::Json<protocol::request::TemplateDelete>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::TemplateDelete {
+        api_key,
+        template_id,
+    } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key.clone()).await?;
+    if api_key_is_read_only(&data.pool, &api_key).await? {
+        return Err(AppError::Unauthorized);
+    }
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let removed = template_service::remove(&mut *con, user_id, template_id)
+        .await
+        .map_err(report_postgres_err)?;
+    if !removed {
+        return Err(AppError::NotFound);
+    }
+    Ok(web::Json(()))
+}
+
+/// How many days of check-ins are sent if the client doesn't say since when.
+const DEFAULT_HABIT_DAYS: u64 = 28;
+
+#[tracing::instrument(skip_all)]
+pub async fn habit_view(
+    req: web::Json<protocol::request::HabitView>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::HabitView { api_key, since } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key).await?;
+
+    let mut con = read_pool(&data).get().await.map_err(report_pool_err)?;
+    let preferences = preferences::get(&mut **con, user_id).await?;
+    let today = preferences::day_of(&preferences, chrono::Utc::now());
+    let since = match since {
+        Some(since) => chrono::NaiveDate::parse_from_str(&since, protocol::DAY_FORMAT)
+            .map_err(|_| AppError::BadRequest)?,
+        None => today - chrono::Days::new(DEFAULT_HABIT_DAYS - 1),
+    };
+
+    let mut check_ins: HashMap<String, HashSet<chrono::NaiveDate>> = HashMap::new();
+    for x in habit_check_in_service::get_all_by_user_id(&mut **con, user_id)
+        .await
+        .map_err(report_postgres_err)?
+    {
+        // check-ins were checked to be days when they were stored
+        if let Ok(day) = chrono::NaiveDate::parse_from_str(&x.day, protocol::DAY_FORMAT) {
+            check_ins.entry(x.habit_id).or_default().insert(day);
+        }
+    }
+    let habits = habit_definition_service::get_all_by_user_id(&mut **con, user_id)
+        .await
+        .map_err(report_postgres_err)?
+        .into_iter()
+        .map(|x| {
+            let days = check_ins.remove(&x.habit_id).unwrap_or_default();
+            let mut recent = days.iter().filter(|x| **x >= since).collect::<Vec<_>>();
+            recent.sort_unstable();
+            protocol::response::Habit {
+                id: x.habit_id,
+                creation_time: x.creation_time,
+                value: x.value,
+                check_ins: recent
+                    .into_iter()
+                    .map(|x| x.format(protocol::DAY_FORMAT).to_string())
+                    .collect(),
+                streak: stats::current_streak(&days, today),
+                longest_streak: stats::longest_streak(&days),
+            }
+        })
+        .collect::<Vec<_>>();
+    Ok(web::Json(habits))
+}
+
+// save a goal, which LinkTaskGoal ops can then link tasks to
+#[tracing::instrument(skip_all)]
+pub async fn goal_new(
+    req: web::Json<protocol::request::GoalNew>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::GoalNew { api_key, name } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key.clone()).await?;
+    if api_key_is_read_only(&data.pool, &api_key).await? {
+        return Err(AppError::Unauthorized);
+    }
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let goal = goal_service::add(&mut *con, user_id, name)
+        .await
+        .map_err(report_postgres_err)?;
+    Ok(web::Json(protocol::response::Goal {
+        goal_id: goal.goal_id,
+        creation_time: goal.creation_time,
+        name: goal.name,
+        completed: 0,
+        total: 0,
+    }))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn goal_edit(
+    req: web::Json<protocol::request::GoalEdit>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::GoalEdit {
+        api_key,
+        goal_id,
+        name,
+    } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key.clone()).await?;
+    if api_key_is_read_only(&data.pool, &api_key).await? {
+        return Err(AppError::Unauthorized);
+    }
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let updated = goal_service::update(&mut *con, user_id, goal_id, name)
+        .await
+        .map_err(report_postgres_err)?;
+    if !updated {
+        return Err(AppError::NotFound);
+    }
+    Ok(web::Json(()))
+}
+
+// the user's goals, with how many of the tasks linked to each are completed
+#[tracing::instrument(skip_all)]
+pub async fn goal_view(
+    req: web::Json<protocol::request::GoalView>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let user_id = get_user_id_if_api_key_valid(&data, req.into_inner().api_key).await?;
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let mut progress: HashMap<i64, (i64, i64)> = HashMap::new();
+    for x in task_goal_service::get_all_by_user_id(&mut *con, user_id)
+        .await
+        .map_err(report_postgres_err)?
+    {
+        let (completed, total) = progress.entry(x.goal_id).or_default();
+        *completed += x.completed as i64;
+        *total += 1;
+    }
+    let goals = goal_service::get_all_by_user_id(&mut *con, user_id)
+        .await
+        .map_err(report_postgres_err)?
+        .into_iter()
+        .map(|x| {
+            let (completed, total) = progress.get(&x.goal_id).copied().unwrap_or_default();
+            protocol::response::Goal {
+                goal_id: x.goal_id,
+                creation_time: x.creation_time,
+                name: x.name,
+                completed,
+                total,
+            }
+        })
+        .collect::<Vec<_>>();
+    Ok(web::Json(goals))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn goal_delete(
+    req: web::Json<protocol::request::GoalDelete>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::GoalDelete { api_key, goal_id } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key.clone()).await?;
+    if api_key_is_read_only(&data.pool, &api_key).await? {
+        return Err(AppError::Unauthorized);
+    }
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let removed = goal_service::remove(&mut *con, user_id, goal_id)
+        .await
+        .map_err(report_postgres_err)?;
+    if !removed {
+        return Err(AppError::NotFound);
+    }
+    Ok(web::Json(()))
+}
+
+// the devices that have connected with an id, and haven't been revoked
+pub async fn device_view(
+    req: web::Json<protocol::request::DeviceView>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let user_id = get_user_id_if_api_key_valid(&data, req.into_inner().api_key).await?;
+
+    let con: &mut tokio_postgres::Client =
+        &mut *read_pool(&data).get().await.map_err(report_pool_err)?;
+    let devices = known_device_service::get_all_by_user_id(&mut *con, user_id)
+        .await
+        .map_err(report_postgres_err)?
+        .into_iter()
+        .map(|x| protocol::response::Device {
+            device_id: x.device_id,
+            creation_time: x.creation_time,
+            label: x.label,
+            platform: x.platform,
+            last_seen_time: x.last_seen_time,
+        })
+        .collect::<Vec<_>>();
+    Ok(web::Json(devices))
+}
+
+// sessions on this instance are closed now, and ones on others when they next revalidate
+pub async fn device_revoke(
+    req: web::Json<protocol::request::DeviceRevoke>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::DeviceRevoke { api_key, device_id } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key.clone()).await?;
+    if api_key_is_read_only(&data.pool, &api_key).await? {
+        return Err(AppError::Unauthorized);
+    }
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let revoked = known_device_service::revoke(&mut *con, user_id, &device_id)
+        .await
+        .map_err(report_postgres_err)?;
+    if !revoked {
+        return Err(AppError::NotFound);
+    }
+    let worker = data
+        .user_worker_data
+        .get(&user_id)
+        .map(|x| x.value().clone());
+    if let Some(worker) = worker {
+        worker
+            .send_event(protocol::ServerEvent::DeviceRevoked { device_id })
+            .await;
+    }
+    Ok(web::Json(()))
+}
+
+/// How many tasks a webhook may create in an hour if the user doesn't say.
+const DEFAULT_WEBHOOK_MAX_PER_HOUR: i64 = 60;
+/// The most tasks a user may let a webhook create in an hour.
+const MAX_WEBHOOK_MAX_PER_HOUR: i64 = 1000;
+/// Webhook sources are cut to this many characters.
+const MAX_WEBHOOK_SOURCE_LEN: usize = 64;
+/// Webhook rate limits are counted over windows this long.
+const WEBHOOK_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+// tasks a webhook created in the window starting at window_start
+pub struct WebhookUsage {
+    window_start: i64,
+    count: i64,
+}
+
+fn webhook_response(x: db_types::Webhook) -> protocol::response::Webhook {
+    protocol::response::Webhook {
+        webhook_id: x.webhook_id,
+        creation_time: x.creation_time,
+        source: x.source,
+        max_per_hour: x.max_per_hour,
+    }
+}
+
+// make a token that services like ifttt or zapier can post to, to create live tasks for the user
+#[tracing::instrument(skip_all)]
+pub async fn webhook_new(
+    req: web::Json<protocol::request::WebhookNew>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::WebhookNew {
+        api_key,
+        source,
+        max_per_hour,
+    } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key.clone()).await?;
+    if api_key_is_read_only(&data.pool, &api_key).await? {
+        return Err(AppError::Unauthorized);
+    }
+    let max_per_hour = max_per_hour.unwrap_or(DEFAULT_WEBHOOK_MAX_PER_HOUR);
+    if !(1..=MAX_WEBHOOK_MAX_PER_HOUR).contains(&max_per_hour) {
+        return Err(AppError::BadRequest);
+    }
+    let source = source
+        .map(|x| {
+            x.chars()
+                .filter(|c| !c.is_control())
+                .take(MAX_WEBHOOK_SOURCE_LEN)
+                .collect::<String>()
+                .trim()
+                .to_owned()
+        })
+        .filter(|x| !x.is_empty());
+
+    let token = utils::random_string();
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let webhook = webhook_service::add(
+        &mut *con,
+        user_id,
+        utils::sha256_hex(token.as_bytes()),
+        source,
+        max_per_hour,
+    )
+    .await
+    .map_err(report_postgres_err)?;
+    audit::record(
+        &mut *con,
+        user_id,
+        "webhook_new",
+        Some(webhook.webhook_id.to_string()),
+    )
+    .await
+    .map_err(report_postgres_err)?;
+    Ok(web::Json(protocol::response::WebhookNew {
+        webhook: webhook_response(webhook),
+        token,
+    }))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn webhook_view(
+    req: web::Json<protocol::request::WebhookView>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let user_id = get_user_id_if_api_key_valid(&data, req.into_inner().api_key).await?;
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let webhooks = webhook_service::get_all_by_user_id(&mut *con, user_id)
+        .await
+        .map_err(report_postgres_err)?
+        .into_iter()
+        .map(webhook_response)
+        .collect::<Vec<_>>();
+    Ok(web::Json(webhooks))
+}
+
+// the webhook's token stops working right away
+#[tracing::instrument(skip_all)]
+pub async fn webhook_delete(
+    req: web::Json<protocol::request::WebhookDelete>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::WebhookDelete {
+        api_key,
+        webhook_id,
+    } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key.clone()).await?;
+    if api_key_is_read_only(&data.pool, &api_key).await? {
+        return Err(AppError::Unauthorized);
+    }
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let removed = webhook_service::remove(&mut *con, user_id, webhook_id)
+        .await
+        .map_err(report_postgres_err)?;
+    if !removed {
+        return Err(AppError::NotFound);
+    }
+    audit::record(
+        &mut *con,
+        user_id,
+        "webhook_delete",
+        Some(webhook_id.to_string()),
+    )
+    .await
+    .map_err(report_postgres_err)?;
+    data.webhook_usage.remove(&webhook_id);
+    Ok(web::Json(()))
+}
+
+// counts a use of the webhook, unless it already hit its limit for this window
+// each instance counts on its own, so behind a load balancer the limit is per instance
+fn take_webhook_use(data: &AppData, webhook: &db_types::Webhook) -> bool {
+    let now = utils::current_time_millis();
+    let mut usage = data
+        .webhook_usage
+        .entry(webhook.webhook_id)
+        .or_insert(WebhookUsage {
+            window_start: now,
+            count: 0,
+        });
+    if now - usage.window_start >= WEBHOOK_WINDOW.as_millis() as i64 {
+        *usage = WebhookUsage {
+            window_start: now,
+            count: 0,
+        };
+    }
+    let allowed = usage.count < webhook.max_per_hour;
+    if allowed {
+        usage.count += 1;
+    }
+    rate_limit::report(
+        webhook.max_per_hour as u32,
+        (webhook.max_per_hour - usage.count) as u32,
+        WEBHOOK_WINDOW.saturating_sub(Duration::from_millis((now - usage.window_start) as u64)),
+    );
+    allowed
+}
+
+// create a live task from a json or form post by another service
+// the token in the path is all the authentication there is, so it only allows this
+#[tracing::instrument(skip_all)]
+pub async fn hook(
+    token: web::Path<String>,
+    body: Either<web::Json<protocol::request::HookPost>, web::Form<protocol::request::HookPost>>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::HookPost {
+        value,
+        idempotency_key,
+    } = match body {
+        Either::Left(x) => x.into_inner(),
+        Either::Right(x) => x.into_inner(),
+    };
+    let webhook = {
+        let con: &mut tokio_postgres::Client =
+            &mut *data.pool.get().await.map_err(report_pool_err)?;
+        webhook_service::get_by_token_hash(&mut *con, &utils::sha256_hex(token.as_bytes()))
+            .await
+            .map_err(report_postgres_err)?
+            .ok_or(AppError::Unauthorized)?
+    };
+    let value = value.trim();
+    if value.is_empty() {
+        return Err(AppError::BadRequest);
+    }
+    if !take_webhook_use(&data, &webhook) {
+        return Err(AppError::TooManyRequests);
+    }
+    let value = match webhook.source {
+        Some(source) => format!("[{source}] {value}"),
+        None => value.to_owned(),
+    };
+
+    let (worker, _) = user_worker::connect(&data, webhook.creator_user_id).await?;
+    let _registration = Registration(worker.clone());
+    let outcome = worker
+        .apply_op(Op {
+            alleged_time: utils::current_time_millis(),
+            kind: OpKind::Ext(protocol::ExtOpKind::InsLiveTaskNew { value }),
+            seq: None,
+            checksum: None,
+            idempotency_key,
+            value_encoding: None,
+        })
+        .await?;
+    Ok(web::Json(apply_op_response(outcome)))
+}
+
+fn email_alias_response(
+    data: &AppData,
+    x: db_types::EmailAlias,
+) -> Result<protocol::response::EmailAlias, AppError> {
+    let email = data.email.as_ref().ok_or(AppError::NotFound)?;
+    Ok(protocol::response::EmailAlias {
+        email_alias_id: x.email_alias_id,
+        creation_time: x.creation_time,
+        address: email.address(&x.alias),
+    })
+}
+
+// make an address the user can email tasks to
+#[tracing::instrument(skip_all)]
+pub async fn email_alias_new(
+    req: web::Json<protocol::request::EmailAliasNew>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    if data.email.is_none() {
+        return Err(AppError::NotFound);
+    }
+    let api_key = req.into_inner().api_key;
+    let user_id = get_user_id_if_api_key_valid(&data, api_key.clone()).await?;
+    if api_key_is_read_only(&data.pool, &api_key).await? {
+        return Err(AppError::Unauthorized);
+    }
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let email_alias = email_alias_service::add(&mut *con, user_id, email::new_alias())
+        .await
+        .map_err(report_postgres_err)?;
+    audit::record(
+        &mut *con,
+        user_id,
+        "email_alias_new",
+        Some(email_alias.email_alias_id.to_string()),
+    )
+    .await
+    .map_err(report_postgres_err)?;
+    Ok(web::Json(email_alias_response(&data, email_alias)?))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn email_alias_view(
+    req: web::Json<protocol::request::EmailAliasView>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let user_id = get_user_id_if_api_key_valid(&data, req.into_inner().api_key).await?;
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let email_aliases = email_alias_service::get_all_by_user_id(&mut *con, user_id)
+        .await
+        .map_err(report_postgres_err)?
+        .into_iter()
+        .map(|x| email_alias_response(&data, x))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(web::Json(email_aliases))
+}
+
+// mail to the alias is ignored from then on
+#[tracing::instrument(skip_all)]
+pub async fn email_alias_delete(
+    req: web::Json<protocol::request::EmailAliasDelete>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::EmailAliasDelete {
+        api_key,
+        email_alias_id,
+    } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key.clone()).await?;
+    if api_key_is_read_only(&data.pool, &api_key).await? {
+        return Err(AppError::Unauthorized);
+    }
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let removed = email_alias_service::remove(&mut *con, user_id, email_alias_id)
+        .await
+        .map_err(report_postgres_err)?;
+    if !removed {
+        return Err(AppError::NotFound);
+    }
+    audit::record(
+        &mut *con,
+        user_id,
+        "email_alias_delete",
+        Some(email_alias_id.to_string()),
+    )
+    .await
+    .map_err(report_postgres_err)?;
+    Ok(web::Json(()))
+}
+
+fn github_link_response(x: db_types::GithubLink) -> protocol::response::GithubLink {
+    protocol::response::GithubLink {
+        github_link_id: x.github_link_id,
+        creation_time: x.creation_time,
+        repo: x.repo,
+        login: x.login,
+        close_issues: x.close_issues,
+    }
+}
+
+// sync the issues assigned to the user in a repo with their live tasks
+// the token is checked against github before it's sealed and saved
+#[tracing::instrument(skip_all)]
+pub async fn github_link_new(
+    req: web::Json<protocol::request::GithubLinkNew>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::GithubLinkNew {
+        api_key,
+        repo,
+        token,
+        close_issues,
+    } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key.clone()).await?;
+    if api_key_is_read_only(&data.pool, &api_key).await? {
+        return Err(AppError::Unauthorized);
+    }
+    if !github::is_repo(&repo) {
+        return Err(AppError::BadRequest);
+    }
+    // the server wasn't given a key to seal tokens with
+    let sealed_token = data.github.seal_token(&token).ok_or(AppError::NotFound)?;
+    let login = data
+        .github
+        .login(&token)
+        .await
+        .map_err(github::report_github_err)?
+        .ok_or(AppError::BadRequest)?;
+    let can_access = data
+        .github
+        .can_access(&token, &repo)
+        .await
+        .map_err(github::report_github_err)?;
+    if !can_access {
+        return Err(AppError::NotFound);
+    }
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let github_link = github_link_service::set(
+        &mut *con,
+        user_id,
+        repo,
+        sealed_token,
+        login,
+        close_issues.unwrap_or(true),
+    )
+    .await
+    .map_err(report_postgres_err)?;
+    audit::record(
+        &mut *con,
+        user_id,
+        "github_link_new",
+        Some(github_link.repo.clone()),
+    )
+    .await
+    .map_err(report_postgres_err)?;
+    Ok(web::Json(github_link_response(github_link)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn github_link_view(
+    req: web::Json<protocol::request::GithubLinkView>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let user_id = get_user_id_if_api_key_valid(&data, req.into_inner().api_key).await?;
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let github_links = github_link_service::get_all_by_user_id(&mut *con, user_id)
+        .await
+        .map_err(report_postgres_err)?
+        .into_iter()
+        .map(github_link_response)
+        .collect::<Vec<_>>();
+    Ok(web::Json(github_links))
+}
+
+// tasks already made from the repo's issues are left as they are
+#[tracing::instrument(skip_all)]
+pub async fn github_link_delete(
+    req: web::Json<protocol::request::GithubLinkDelete>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::GithubLinkDelete {
+        api_key,
+        github_link_id,
+    } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key.clone()).await?;
+    if api_key_is_read_only(&data.pool, &api_key).await? {
+        return Err(AppError::Unauthorized);
+    }
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let removed = github_link_service::remove(&mut *con, user_id, github_link_id)
+        .await
+        .map_err(report_postgres_err)?;
+    if !removed {
+        return Err(AppError::NotFound);
+    }
+    audit::record(
+        &mut *con,
+        user_id,
+        "github_link_delete",
+        Some(github_link_id.to_string()),
+    )
+    .await
+    .map_err(report_postgres_err)?;
+    Ok(web::Json(()))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn preferences_view(
+    query: web::Query<protocol::request::PreferencesView>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let user_id = get_user_id_if_api_key_valid(&data, query.into_inner().api_key).await?;
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    Ok(web::Json(preferences::get(con, user_id).await?))
+}
+
+// replaces all of the user's preferences; any left out are reset to their default
+#[tracing::instrument(skip_all)]
+pub async fn preferences_set(
+    req: web::Json<protocol::request::PreferencesSet>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::PreferencesSet {
+        api_key,
+        preferences,
+    } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key.clone()).await?;
+    if api_key_is_read_only(&data.pool, &api_key).await? {
+        return Err(AppError::Unauthorized);
+    }
+    preferences::check(&preferences)?;
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    preference_service::set(&mut *con, user_id, &preferences)
+        .await
+        .map_err(report_postgres_err)?;
+    Ok(web::Json(preferences))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn notification_preferences_view(
+    query: web::Query<protocol::request::NotificationPreferencesView>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let user_id = get_user_id_if_api_key_valid(&data, query.into_inner().api_key).await?;
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    Ok(web::Json(
+        preferences::get(con, user_id).await?.notifications,
+    ))
+}
+
+// replaces the user's notification preferences, leaving the rest as they were
+#[tracing::instrument(skip_all)]
+pub async fn notification_preferences_set(
+    req: web::Json<protocol::request::NotificationPreferencesSet>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::NotificationPreferencesSet {
+        api_key,
+        notifications,
+    } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key.clone()).await?;
+    if api_key_is_read_only(&data.pool, &api_key).await? {
+        return Err(AppError::Unauthorized);
+    }
+    preferences::check_notifications(&notifications)?;
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let mut tx = con.transaction().await.map_err(report_postgres_err)?;
+    let mut preferences = preferences::get(&mut tx, user_id).await?;
+    preferences.notifications = notifications;
+    preference_service::set(&mut tx, user_id, &preferences)
+        .await
+        .map_err(report_postgres_err)?;
+    tx.commit().await.map_err(report_postgres_err)?;
+    Ok(web::Json(preferences.notifications))
+}
+
+/// How many comments are sent at once if the client doesn't say.
+const DEFAULT_COMMENT_PAGE: i64 = 50;
+/// The most comments sent at once.
+const MAX_COMMENT_PAGE: i64 = 500;
+
+// page through a task's comments, which are added and removed with ops
+#[tracing::instrument(skip_all)]
+pub async fn comment_view(
+    req: web::Json<protocol::request::CommentView>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::CommentView {
+        api_key,
+        task_id,
+        after_id,
+        limit,
+    } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key).await?;
+    let limit = limit
+        .unwrap_or(DEFAULT_COMMENT_PAGE)
+        .clamp(1, MAX_COMMENT_PAGE);
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let after = match after_id {
+        Some(after_id) => {
+            task_comment_service::get_by_comment_id(&mut *con, user_id, &after_id)
+                .await
+                .map_err(report_postgres_err)?
+                .filter(|x| x.task_id == task_id)
+                // the comment was deleted, so the client should start over
+                .ok_or(AppError::NotFound)?
+                .task_comment_id
+        }
+        None => 0,
+    };
+    // one more than asked for, to know if there are more
+    let mut comments =
+        task_comment_service::get_by_task_id_after(&mut *con, user_id, &task_id, after, limit + 1)
+            .await
+            .map_err(report_postgres_err)?;
+    let more = comments.len() as i64 > limit;
+    comments.truncate(limit as usize);
+
+    Ok(web::Json(protocol::response::CommentPage {
+        comments: comments
+            .into_iter()
+            .map(|x| protocol::response::Comment {
+                id: x.comment_id,
+                task_id: x.task_id,
+                creation_time: x.creation_time,
+                value: x.value,
+            })
+            .collect(),
+        more,
+    }))
+}
+
+/// How many of a task's ops are sent at once if the client doesn't say.
+const DEFAULT_TASK_OPS_PAGE: i64 = 100;
+/// The most of a task's ops sent at once.
+const MAX_TASK_OPS_PAGE: i64 = 500;
+
+// page through the ops that touched a task, oldest first
+#[tracing::instrument(skip_all)]
+pub async fn task_ops_view(
+    req: web::Json<protocol::request::TaskOpsView>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::TaskOpsView {
+        api_key,
+        task_id,
+        after_seq,
+        limit,
+    } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key).await?;
+    let limit = limit
+        .unwrap_or(DEFAULT_TASK_OPS_PAGE)
+        .clamp(1, MAX_TASK_OPS_PAGE);
+
+    let mut con = read_pool(&data).get().await.map_err(report_pool_err)?;
+    // one more than asked for, to know if there are more
+    let mut operations = operation_service::get_operations_by_task_id_after(
+        &mut con,
+        user_id,
+        &task_id,
+        after_seq.unwrap_or(0),
+        limit + 1,
+    )
+    .await
+    .map_err(report_postgres_err)?;
+    let more = operations.len() as i64 > limit;
+    operations.truncate(limit as usize);
+
+    let mut ops = vec![];
+    for x in operations {
+        let mut op = serde_json::from_str::<Op>(&x.jsonval).map_err(report_internal_serde_error)?;
+        op.seq = Some(x.operation_id);
+        ops.push(op);
+    }
+    Ok(web::Json(protocol::response::TaskOps { ops, more }))
+}
+
+/// How many activity entries are sent at once if the client doesn't say.
+const DEFAULT_ACTIVITY_PAGE: i64 = 50;
+/// The most activity entries sent at once.
+const MAX_ACTIVITY_PAGE: i64 = 200;
+
+// a page of what the user did to their tasks, newest first
+#[tracing::instrument(skip_all)]
+pub async fn activity(
+    req: web::Json<protocol::request::ActivityView>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::ActivityView {
+        api_key,
+        before_seq,
+        limit,
+    } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key).await?;
+    let limit = limit
+        .unwrap_or(DEFAULT_ACTIVITY_PAGE)
+        .clamp(1, MAX_ACTIVITY_PAGE);
+
+    let mut con = read_pool(&data).get().await.map_err(report_pool_err)?;
+    let page = activity::page(
+        &mut con,
+        user_id,
+        before_seq.unwrap_or(i64::MAX),
+        limit as usize,
+    )
+    .await?;
+    Ok(web::Json(page))
+}
+
+fn attachment_response(x: db_types::Attachment) -> protocol::response::Attachment {
+    protocol::response::Attachment {
+        attachment_id: x.attachment_id,
+        task_id: x.task_id,
+        creation_time: x.creation_time,
+        filename: x.filename,
+        content_type: x.content_type,
+        size: x.size,
+    }
+}
+
+// the lanes of the given live tasks, leaving out those in the default lane
+pub async fn lanes_by_task_id(
+    con: &mut tokio_postgres::Client,
+    user_id: i64,
+    live: &VecDeque<LiveTask>,
+) -> Result<HashMap<String, String>, AppError> {
+    let live_ids = live.iter().map(|x| &x.id).collect::<HashSet<_>>();
+    Ok(task_lane_service::get_all_by_user_id(&mut *con, user_id)
+        .await
+        .map_err(report_postgres_err)?
+        .into_iter()
+        .filter(|x| live_ids.contains(&x.task_id))
+        .map(|x| (x.task_id, x.lane))
+        .collect())
+}
+
+// the estimates of the given live tasks, leaving out those without one
+pub async fn estimates_by_task_id(
+    con: &mut tokio_postgres::Client,
+    user_id: i64,
+    live: &VecDeque<LiveTask>,
+) -> Result<HashMap<String, i64>, AppError> {
+    let live_ids = live.iter().map(|x| &x.id).collect::<HashSet<_>>();
+    Ok(
+        task_estimate_service::get_all_by_user_id(&mut *con, user_id)
+            .await
+            .map_err(report_postgres_err)?
+            .into_iter()
+            .filter(|x| live_ids.contains(&x.task_id))
+            .map(|x| (x.task_id, x.estimate))
+            .collect(),
+    )
+}
+
+// the focused task, if it's one of the given live tasks
+pub async fn focused_task_id(
+    con: &mut tokio_postgres::Client,
+    user_id: i64,
+    live: &VecDeque<LiveTask>,
+) -> Result<Option<String>, AppError> {
+    Ok(
+        task_focus_service::get_focused_by_user_id(&mut *con, user_id)
+            .await
+            .map_err(report_postgres_err)?
+            .map(|x| x.task_id)
+            .filter(|id| live.iter().any(|x| &x.id == id)),
+    )
+}
+
+// the focused task, and how long each task was focused for
+#[tracing::instrument(skip_all)]
+pub async fn focus_view(
+    req: web::Json<protocol::request::FocusView>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let user_id = get_user_id_if_api_key_valid(&data, req.into_inner().api_key).await?;
+
+    let con: &mut tokio_postgres::Client =
+        &mut *read_pool(&data).get().await.map_err(report_pool_err)?;
+    let mut focus = protocol::response::Focus {
+        live_task_id: None,
+        since: None,
+        totals: HashMap::new(),
+    };
+    for x in task_focus_service::get_all_by_user_id(&mut *con, user_id)
+        .await
+        .map_err(report_postgres_err)?
+    {
+        if x.focused_since.is_some() {
+            focus.live_task_id = Some(x.task_id.clone());
+            focus.since = x.focused_since;
+        }
+        if x.millis > 0 {
+            focus.totals.insert(x.task_id, x.millis);
+        }
+    }
+    Ok(web::Json(focus))
+}
+
+// the user's attachments created at or before created_before, grouped by task
+async fn attachments_by_task_id(
+    con: &mut tokio_postgres::Client,
+    user_id: i64,
+    created_before: i64,
+) -> Result<HashMap<String, Vec<protocol::response::Attachment>>, AppError> {
+    let mut attachments = HashMap::<_, Vec<_>>::new();
+    for x in attachment_service::get_all_by_user_id(&mut *con, user_id)
+        .await
+        .map_err(report_postgres_err)?
+    {
+        if x.creation_time <= created_before {
+            attachments
+                .entry(x.task_id.clone())
+                .or_default()
+                .push(attachment_response(x));
+        }
+    }
+    Ok(attachments)
+}
+
+/// Attachment filenames are cut to this many characters.
+const MAX_ATTACHMENT_FILENAME_LEN: usize = 255;
+
+// make room for a file on a task, which the client then uploads straight to the bucket
+#[tracing::instrument(skip_all)]
+pub async fn attachment_new(
+    req: web::Json<protocol::request::AttachmentNew>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::AttachmentNew {
+        api_key,
+        task_id,
+        filename,
+        content_type,
+        size,
+    } = req.into_inner();
+    let storage = data.storage.as_ref().ok_or(AppError::NotFound)?;
+    let user_id = get_user_id_if_api_key_valid(&data, api_key.clone()).await?;
+    if api_key_is_read_only(&data.pool, &api_key).await? {
+        return Err(AppError::Unauthorized);
+    }
+    if size <= 0 || size > data.max_attachment_bytes {
+        return Err(AppError::BadRequest);
+    }
+    let filename = filename
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_ATTACHMENT_FILENAME_LEN)
+        .collect::<String>();
+
+    // the task has to exist now, though it may be deleted later
+    let (worker, subscription) = user_worker::connect(&data, user_id).await?;
+    let _registration = Registration(worker);
+    let snapshot = match subscription.initial_op.kind {
+        OpKind::Base(WebsocketOpKind::OverwriteState(snapshot)) => snapshot,
+        _ => return Err(AppError::InternalServerError),
+    };
+    if !snapshot.live.iter().any(|x| x.id == task_id)
+        && !snapshot.finished.iter().any(|x| x.id == task_id)
+    {
+        return Err(AppError::NotFound);
+    }
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let max_storage_bytes = data.settings.get().max_storage_bytes;
+    if max_storage_bytes > 0 {
+        let stored = quota::storage_bytes(&mut *con, user_id, &snapshot)
+            .await
+            .map_err(report_postgres_err)?;
+        if (stored + size) as u64 > max_storage_bytes {
+            return Err(AppError::QuotaExceeded);
+        }
+    }
+    let object_key = format!("{user_id}/{}", utils::random_string());
+    let attachment = attachment_service::add(
+        &mut *con,
+        user_id,
+        task_id,
+        object_key,
+        filename,
+        content_type,
+        size,
+    )
+    .await
+    .map_err(report_postgres_err)?;
+    let upload_url = storage.presign_upload(&attachment.object_key, attachment.size);
+    Ok(web::Json(protocol::response::AttachmentNew {
+        attachment: attachment_response(attachment),
+        upload_url,
+    }))
+}
+
+// a task's attachments, with urls to download them from
+#[tracing::instrument(skip_all)]
+pub async fn attachment_view(
+    req: web::Json<protocol::request::AttachmentView>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::AttachmentView { api_key, task_id } = req.into_inner();
+    let storage = data.storage.as_ref().ok_or(AppError::NotFound)?;
+    let user_id = get_user_id_if_api_key_valid(&data, api_key).await?;
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let attachments = attachment_service::get_all_by_task_id(&mut *con, user_id, &task_id)
+        .await
+        .map_err(report_postgres_err)?
+        .into_iter()
+        .map(|x| protocol::response::AttachmentDownload {
+            download_url: storage.presign_download(&x.object_key),
+            attachment: attachment_response(x),
+        })
+        .collect::<Vec<_>>();
+    Ok(web::Json(attachments))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn attachment_delete(
+    req: web::Json<protocol::request::AttachmentDelete>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::AttachmentDelete {
+        api_key,
+        attachment_id,
+    } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key.clone()).await?;
+    if api_key_is_read_only(&data.pool, &api_key).await? {
+        return Err(AppError::Unauthorized);
+    }
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let mut tx = con.transaction().await.map_err(report_postgres_err)?;
+    let attachment = attachment_service::remove(&mut tx, user_id, attachment_id)
+        .await
+        .map_err(report_postgres_err)?
+        .ok_or(AppError::NotFound)?;
+    // the object is deleted in the background, and retried if the bucket is unavailable
+    let job = Job::DeleteObject {
+        object_key: attachment.object_key,
+    };
+    job_queue::enqueue(&mut tx, &job)
+        .await
+        .map_err(report_postgres_err)?;
+    tx.commit().await.map_err(report_postgres_err)?;
+    Ok(web::Json(()))
+}
+
+// list the user's soft deleted tasks that haven't been purged yet
+#[tracing::instrument(skip_all)]
+pub async fn trash_view(
+    req: web::Json<protocol::request::TrashView>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let user_id = get_user_id_if_api_key_valid(&data, req.into_inner().api_key).await?;
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let deleted_tasks = deleted_task_service::get_all_by_user_id(&mut *con, user_id)
+        .await
+        .map_err(report_postgres_err)?
+        .into_iter()
+        .map(|x| protocol::response::DeletedTask {
+            id: x.task_id,
+            value: x.value,
+            deletion_time: x.creation_time,
+        })
+        .collect::<Vec<_>>();
+    Ok(web::Json(deleted_tasks))
+}
+
+// list the tasks that were moved into the user's history when the day was rolled over
+#[tracing::instrument(skip_all)]
+pub async fn history_view(
+    req: web::Json<protocol::request::HistoryView>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::HistoryView { api_key, day } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key).await?;
+
+    let con: &mut tokio_postgres::Client =
+        &mut *read_pool(&data).get().await.map_err(report_pool_err)?;
+    let tasks = archived_task_service::get_all_by_day(&mut *con, user_id, &day)
+        .await
+        .map_err(report_postgres_err)?
+        .into_iter()
+        .map(archived_task_response)
+        .collect::<Result<Vec<_>, AppError>>()?;
+    Ok(web::Json(tasks))
+}
+
+fn archived_task_response(x: db_types::ArchivedTask) -> Result<FinishedTask, AppError> {
+    Ok(FinishedTask {
+        id: x.task_id,
+        value: x.value,
+        status: serde_json::from_str(&x.status_json).map_err(report_internal_serde_error)?,
+    })
+}
+
+/// How many audit events are sent at once if the client doesn't say.
+const DEFAULT_AUDIT_PAGE: i64 = 50;
+/// The most audit events sent at once.
+const MAX_AUDIT_PAGE: i64 = 500;
+
+// a page of the user's audit log, newest first
+#[tracing::instrument(skip_all)]
+pub async fn audit_view(
+    req: web::Json<protocol::request::AuditView>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::AuditView {
+        api_key,
+        before_id,
+        kind,
+        since_time,
+        until_time,
+        limit,
+    } = req.into_inner();
+    let user_id = get_user_id_if_api_key_valid(&data, api_key).await?;
+    let limit = limit.unwrap_or(DEFAULT_AUDIT_PAGE).clamp(1, MAX_AUDIT_PAGE);
+
+    let con: &mut tokio_postgres::Client =
+        &mut *read_pool(&data).get().await.map_err(report_pool_err)?;
+    // one more than asked for, to know if there are more
+    let mut events = audit_event_service::get_page(
+        &mut *con,
+        user_id,
+        before_id,
+        kind.as_deref(),
+        since_time,
+        until_time,
+        limit + 1,
+    )
+    .await
+    .map_err(report_postgres_err)?;
+    let more = events.len() as i64 > limit;
+    events.truncate(limit as usize);
+
+    Ok(web::Json(protocol::response::AuditPage {
+        events: events
+            .into_iter()
+            .map(|x| protocol::response::AuditEvent {
+                audit_event_id: x.audit_event_id,
+                creation_time: x.creation_time,
+                kind: x.kind,
+                detail: x.detail,
+                session_id: x.session_id,
+                source_ip: x.source_ip,
+            })
+            .collect(),
+        more,
+    }))
+}
+
+// what the user has used of their quotas today
+#[tracing::instrument(skip_all)]
+pub async fn quota_view(
+    req: web::Json<protocol::request::QuotaView>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let user_id = get_user_id_if_api_key_valid(&data, req.into_inner().api_key).await?;
+    let settings = data.settings.get();
+
+    // task values are only counted in the worker's snapshot
+    let (worker, subscription) = user_worker::connect(&data, user_id).await?;
+    let _registration = Registration(worker);
+    let snapshot = match subscription.initial_op.kind {
+        OpKind::Base(WebsocketOpKind::OverwriteState(snapshot)) => snapshot,
+        _ => return Err(AppError::InternalServerError),
+    };
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let day = quota::today();
+    let ops = op_usage_service::get_by_user_id_and_day(&mut *con, user_id, &day)
+        .await
+        .map_err(report_postgres_err)?
+        .map_or(0, |x| x.ops);
+    let storage_bytes = quota::storage_bytes(&mut *con, user_id, &snapshot)
+        .await
+        .map_err(report_postgres_err)?;
+
+    Ok(web::Json(protocol::response::QuotaUsage {
+        day,
+        ops,
+        max_ops_per_day: Some(settings.max_ops_per_day).filter(|x| *x > 0),
+        storage_bytes,
+        max_storage_bytes: Some(settings.max_storage_bytes).filter(|x| *x > 0),
+    }))
+}
+
+// list users with open connections
+#[tracing::instrument(skip_all)]
+pub async fn admin_users_view(
+    req: web::Json<protocol::request::AdminUsersView>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let scope = get_admin_scope_if_api_key_valid(&data, req.into_inner().api_key).await?;
+    let scope_users = admin_scope_users(&data, scope).await?;
+
+    // collect the handles first, so the map isn't locked while we wait on workers
+    let workers: Vec<_> = data
+        .user_worker_data
+        .iter()
+        .filter(|x| {
+            scope_users
+                .as_ref()
+                .map_or(true, |users| users.contains(x.key()))
+        })
+        .map(|x| x.value().clone())
+        .collect();
+
+    let mut users = vec![];
+    for worker in workers {
+        // the worker may have shut down in the meantime
+        if let Ok(stats) = worker.stats().await {
+            users.push(stats);
+        }
+    }
+    users.sort_by_key(|x| x.user_id);
+    Ok(web::Json(users))
+}
+
+// list open websockets
+#[tracing::instrument(skip_all)]
+pub async fn admin_sessions_view(
+    req: web::Json<protocol::request::AdminSessionsView>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let scope = get_admin_scope_if_api_key_valid(&data, req.into_inner().api_key).await?;
+    let scope_users = admin_scope_users(&data, scope).await?;
+
+    let mut sessions: Vec<_> = data
+        .sessions
+        .iter()
+        .filter(|x| {
+            scope_users
+                .as_ref()
+                .map_or(true, |users| users.contains(&x.user_id))
+        })
+        .map(|x| protocol::response::AdminSession {
+            connection_id: x.key().clone(),
+            user_id: x.user_id,
+            connected_time: x.connected_time,
+            last_activity_time: x.last_activity_time.load(Ordering::Relaxed),
+            protocol_version: x.protocol_version,
+            rtt_ms: Some(x.rtt_ms.load(Ordering::Relaxed)).filter(|x| *x >= 0),
+        })
+        .collect();
+    sessions.sort_by_key(|x| x.connected_time);
+    Ok(web::Json(sessions))
+}
+
+// forget cached api keys on this instance, e.g. after revoking a key with the auth service
+#[tracing::instrument(skip_all)]
+pub async fn admin_auth_cache_clear(
+    req: web::Json<protocol::request::AdminAuthCacheClear>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::AdminAuthCacheClear { api_key, user_id } = req.into_inner();
+    let scope = get_admin_scope_if_api_key_valid(&data, api_key).await?;
+    if let Some(user_id) = user_id {
+        check_admin_scope(&data, scope, user_id).await?;
+    }
+
+    let entries = match (&data.auth_cache, user_id) {
+        (Some(auth_cache), Some(user_id)) => auth_cache.invalidate_user(user_id),
+        (Some(auth_cache), None) => match admin_scope_users(&data, scope).await? {
+            Some(users) => users
+                .into_iter()
+                .map(|x| auth_cache.invalidate_user(x))
+                .sum(),
+            None => auth_cache.clear(),
+        },
+        (None, _) => 0,
+    };
+    log::info!("admin cleared {entries} cached api keys (user: {user_id:?})");
+    Ok(web::Json(protocol::response::AdminAuthCacheClear {
+        entries,
+    }))
+}
+
+// write a checkpoint for a connected user right away, optionally evicting them
+#[tracing::instrument(skip_all)]
+pub async fn admin_checkpoint(
+    req: web::Json<protocol::request::AdminCheckpoint>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let req = req.into_inner();
+    let scope = get_admin_scope_if_api_key_valid(&data, req.api_key).await?;
+    check_admin_scope(&data, scope, req.user_id).await?;
+
+    // users without a worker have nothing in memory to checkpoint
+    let worker = data
+        .user_worker_data
+        .get(&req.user_id)
+        .map(|x| x.value().clone())
+        .ok_or(AppError::NotFound)?;
+
+    let checkpoint_id = worker.checkpoint(req.evict).await?;
+    log::info!(
+        "admin wrote checkpoint {checkpoint_id} for user {} (evict: {})",
+        req.user_id,
+        req.evict
+    );
+    Ok(web::Json(protocol::response::AdminCheckpoint {
+        checkpoint_id,
+        evicted: req.evict,
+    }))
+}
+
+// show a notice to a connected user, or to everyone connected to this instance
+#[tracing::instrument(skip_all)]
+pub async fn admin_notice(
+    req: web::Json<protocol::request::AdminNotice>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::AdminNotice {
+        api_key,
+        user_id,
+        text,
+    } = req.into_inner();
+    let scope = get_admin_scope_if_api_key_valid(&data, api_key).await?;
+    let scope_users = match user_id {
+        Some(user_id) => {
+            check_admin_scope(&data, scope, user_id).await?;
+            None
+        }
+        None => admin_scope_users(&data, scope).await?,
+    };
+
+    let workers = data
+        .user_worker_data
+        .iter()
+        .filter(|x| user_id.map_or(true, |user_id| *x.key() == user_id))
+        .filter(|x| {
+            scope_users
+                .as_ref()
+                .map_or(true, |users| users.contains(x.key()))
+        })
+        .map(|x| x.value().clone())
+        .collect::<Vec<_>>();
+    for worker in &workers {
+        worker
+            .send_event(protocol::ServerEvent::AdminNotice { text: text.clone() })
+            .await;
+    }
+    Ok(web::Json(protocol::response::AdminNotice {
+        users: workers.len(),
+    }))
+}
+
+fn tenant_response(x: db_types::Tenant) -> protocol::response::AdminTenant {
+    protocol::response::AdminTenant {
+        tenant_id: x.tenant_id,
+        creation_time: x.creation_time,
+        name: x.name,
+        admin_user_id: x.admin_user_id,
+    }
+}
+
+// tenants are managed by the deployment's author, not by each other
+#[tracing::instrument(skip_all)]
+pub async fn admin_tenant_new(
+    req: web::Json<protocol::request::AdminTenantNew>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::AdminTenantNew {
+        api_key,
+        name,
+        admin_user_id,
+    } = req.into_inner();
+    get_author_id_if_api_key_valid(&data, api_key).await?;
+    if name.trim().is_empty() {
+        return Err(AppError::BadRequest);
+    }
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    // the name or admin is taken
+    let tenant = tenant_service::add(&mut *con, name.trim().to_owned(), admin_user_id)
+        .await
+        .map_err(report_postgres_err)?
+        .ok_or(AppError::BadRequest)?;
+    log::info!("admin made tenant {}", tenant.tenant_id);
+    Ok(web::Json(tenant_response(tenant)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn admin_tenant_view(
+    req: web::Json<protocol::request::AdminTenantView>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    get_author_id_if_api_key_valid(&data, req.into_inner().api_key).await?;
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let tenants = tenant_service::get_all(&mut *con)
+        .await
+        .map_err(report_postgres_err)?;
+    Ok(web::Json(
+        tenants.into_iter().map(tenant_response).collect::<Vec<_>>(),
+    ))
+}
+
+// the tenant's users go back to belonging to the deployment
+#[tracing::instrument(skip_all)]
+pub async fn admin_tenant_delete(
+    req: web::Json<protocol::request::AdminTenantDelete>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::AdminTenantDelete { api_key, tenant_id } = req.into_inner();
+    get_author_id_if_api_key_valid(&data, api_key).await?;
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let mut tx = con.transaction().await.map_err(report_postgres_err)?;
+    let users = tenant_user_service::remove_all_by_tenant_id(&mut tx, tenant_id)
+        .await
+        .map_err(report_postgres_err)?;
+    let removed = tenant_service::remove(&mut tx, tenant_id)
+        .await
+        .map_err(report_postgres_err)?;
+    if !removed {
+        return Err(AppError::NotFound);
+    }
+    tx.commit().await.map_err(report_postgres_err)?;
+    log::info!("admin deleted tenant {tenant_id}");
+    Ok(web::Json(protocol::response::AdminTenantDelete { users }))
+}
+
+// moves the user into the tenant, or back to the deployment if there's no tenant_id
+#[tracing::instrument(skip_all)]
+pub async fn admin_tenant_user_set(
+    req: web::Json<protocol::request::AdminTenantUserSet>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::AdminTenantUserSet {
+        api_key,
+        user_id,
+        tenant_id,
+    } = req.into_inner();
+    get_author_id_if_api_key_valid(&data, api_key).await?;
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    match tenant_id {
+        Some(tenant_id) => {
+            tenant_service::get_by_tenant_id(&mut *con, tenant_id)
+                .await
+                .map_err(report_postgres_err)?
+                .ok_or(AppError::NotFound)?;
+            tenant_user_service::set(&mut *con, user_id, tenant_id)
+                .await
+                .map_err(report_postgres_err)?;
+        }
+        None => {
+            tenant_user_service::remove_all_by_user_id(&mut *con, user_id)
+                .await
+                .map_err(report_postgres_err)?;
+        }
+    }
+    log::info!("admin moved user {user_id} to tenant {tenant_id:?}");
+    Ok(web::Json(()))
+}
+
+// close every websocket on this instance after the grace period, and turn away new ones
+#[tracing::instrument(skip_all)]
+pub async fn admin_drain(
+    req: web::Json<protocol::request::AdminDrain>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::AdminDrain { api_key } = req.into_inner();
+    get_author_id_if_api_key_valid(&data, api_key).await?;
+
+    let started = data.drain.start();
+    if started {
+        log::info!("admin started draining");
+    }
+    Ok(web::Json(protocol::response::AdminDrain {
+        started,
+        sessions: data.sessions.len(),
+    }))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn admin_settings_view(
+    req: web::Json<protocol::request::AdminSettingsView>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::AdminSettingsView { api_key } = req.into_inner();
+    get_author_id_if_api_key_valid(&data, api_key).await?;
+
+    Ok(web::Json(protocol::response::AdminSettings {
+        settings: (*data.settings.get()).clone(),
+    }))
+}
+
+// how well this instance's checkpoints compress
+#[tracing::instrument(skip_all)]
+pub async fn admin_metrics(
+    req: web::Json<protocol::request::AdminMetricsView>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::AdminMetricsView { api_key } = req.into_inner();
+    get_author_id_if_api_key_valid(&data, api_key).await?;
+
+    let stats = snapshot_codec::stats();
+    let pool = data.pool.stats();
+    let rtts = data
+        .sessions
+        .iter()
+        .map(|x| x.rtt_ms.load(Ordering::Relaxed))
+        .filter(|x| *x >= 0)
+        .collect::<Vec<_>>();
+    Ok(web::Json(protocol::response::AdminMetrics {
+        checkpoints_written: stats.snapshots,
+        checkpoint_json_bytes: stats.json_bytes,
+        checkpoint_stored_bytes: stats.stored_bytes,
+        checkpoint_compression_ratio: (stats.stored_bytes > 0)
+            .then(|| stats.json_bytes as f64 / stats.stored_bytes as f64),
+        pool: pool_metrics(pool),
+        read_pool: data.read_pool.as_ref().map(|x| pool_metrics(x.stats())),
+        jobs_dead_lettered: job_queue::jobs_dead_lettered(),
+        events_published: event_stream::events_published(),
+        events_dropped: event_stream::events_dropped(),
+        session_mean_rtt_ms: (!rtts.is_empty())
+            .then(|| rtts.iter().sum::<i64>() as f64 / rtts.len() as f64),
+        session_max_rtt_ms: rtts.iter().max().copied(),
+    }))
+}
+
+/// How many dead lettered jobs are returned if the request doesn't say.
+const DEFAULT_DEAD_JOBS_LIMIT: i64 = 100;
+
+#[tracing::instrument(skip_all)]
+pub async fn admin_dead_jobs_view(
+    req: web::Json<protocol::request::AdminDeadJobsView>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::AdminDeadJobsView { api_key, limit } = req.into_inner();
+    get_author_id_if_api_key_valid(&data, api_key).await?;
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let jobs = job_service::get_dead(&mut *con, limit.unwrap_or(DEFAULT_DEAD_JOBS_LIMIT).max(0))
+        .await
+        .map_err(report_postgres_err)?;
+    Ok(web::Json(
+        jobs.into_iter()
+            .map(|x| protocol::response::DeadJob {
+                job_id: x.job_id,
+                creation_time: x.creation_time,
+                job: serde_json::from_str(&x.jsonval)
+                    .unwrap_or(serde_json::Value::String(x.jsonval)),
+                attempts: x.attempts,
+                last_error: x.last_error,
+            })
+            .collect::<Vec<_>>(),
+    ))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn admin_dead_job_retry(
+    req: web::Json<protocol::request::AdminDeadJobRetry>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::AdminDeadJobRetry { api_key, job_id } = req.into_inner();
+    get_author_id_if_api_key_valid(&data, api_key).await?;
+
+    let con: &mut tokio_postgres::Client = &mut *data.pool.get().await.map_err(report_pool_err)?;
+    let revived = job_service::revive(&mut *con, job_id, utils::current_time_millis())
+        .await
+        .map_err(report_postgres_err)?;
+    if !revived {
+        return Err(AppError::NotFound);
+    }
+    log::info!("admin retried dead lettered job {job_id}");
+    Ok(web::Json(()))
+}
+
+fn pool_metrics(x: db_pool::PoolStats) -> protocol::response::PoolMetrics {
+    protocol::response::PoolMetrics {
+        max_size: x.status.max_size,
+        size: x.status.size,
+        available: x.status.available,
+        waiting: x.status.waiting,
+        gets: x.gets,
+        mean_wait_ms: (x.gets > 0).then(|| x.wait.as_secs_f64() * 1000.0 / x.gets as f64),
+        max_wait_ms: x.max_wait.as_secs_f64() * 1000.0,
+        timeouts: x.timeouts,
+    }
+}
+
+// only reloads this instance; the others need to be told too
+#[tracing::instrument(skip_all)]
+pub async fn admin_settings_reload(
+    req: web::Json<protocol::request::AdminSettingsReload>,
+    data: web::Data<AppData>,
+) -> Result<impl Responder, AppError> {
+    let protocol::request::AdminSettingsReload { api_key } = req.into_inner();
+    get_author_id_if_api_key_valid(&data, api_key).await?;
+
+    let settings = data.settings.reload().map_err(|e| {
+        log::warn!("couldn't reload settings: {e}");
+        AppError::BadRequest
+    })?;
+    log::info!("admin reloaded settings");
+    Ok(web::Json(protocol::response::AdminSettings {
+        settings: (*settings).clone(),
+    }))
+}
+
+// the process is up and serving requests
+pub async fn healthz() -> impl Responder {
+    web::Json(protocol::response::Health { alive: true })
+}
+
+// we can reach everything we need to handle requests
+#[tracing::instrument(skip_all)]
+pub async fn readyz(data: web::Data<AppData>) -> impl Responder {
+    let database = match data.pool.get().await {
+        Ok(con) => con.query_one("SELECT 1", &[]).await.is_ok(),
+        Err(_) => false,
+    };
+    let auth_service = data.auth.info().await.is_ok();
+    let draining = data.drain.is_draining();
+    let ready = database && auth_service && !draining;
+
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    HttpResponse::build(status).json(protocol::response::Readiness {
+        ready,
+        database,
+        auth_service,
+        draining,
+    })
+}
+
+// start websocket connection
+pub async fn ws_task_updates(
+    data: web::Data<AppData>,
+    req: HttpRequest,
+    stream: web::Payload,
+    query: web::Query<protocol::request::WebsocketInit>,
+) -> Result<impl Responder, Error> {
+    // the client should reconnect to an instance that isn't going away
+    if data.drain.is_draining() {
+        return Ok(HttpResponse::ServiceUnavailable()
+            .insert_header((RETRY_AFTER, data.drain.retry_after().as_secs()))
+            .finish());
+    }
+    let (res, session, msg_stream) = actix_ws::handle(&req, stream)?;
+    // the connection outlives the request, so it keeps the request's id
+    let connection_id = req
+        .extensions()
+        .get::<RequestId>()
+        .map(|x| x.0.clone())
+        .unwrap_or_default();
+    // every log line for this connection carries its id, and the user id once we know it
+    let span = tracing::info_span!(
+        "ws",
+        connection_id = %connection_id,
+        user_id = tracing::field::Empty,
+        tenant_id = tracing::field::Empty
+    );
+    // spawn websocket handler (and don't await it) so that the response is returned immediately
+    // its ops come from the same place the request did
+    rt::spawn(
+        audit::scope(
+            audit::current(),
+            task_updates::manage_updates_ws(
+                data,
+                query.into_inner(),
+                connection_id,
+                session,
+                msg_stream,
+            ),
+        )
+        .instrument(span),
+    );
     Ok(res)
 }
@@ -0,0 +1,96 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for IdempotencyKey {
+    // select * from idempotency_key order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> IdempotencyKey {
+        IdempotencyKey {
+            idempotency_key_id: row.get("idempotency_key_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            key: row.get("key"),
+        }
+    }
+}
+
+// returns None if the user has already used this key
+#[tracing::instrument(skip(con))]
+pub async fn add(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    key: String,
+) -> Result<Option<IdempotencyKey>, tokio_postgres::Error> {
+    let row = con
+        .query_opt(
+            "INSERT INTO
+             idempotency_key(
+                 creator_user_id,
+                 key
+             )
+             VALUES($1, $2)
+             ON CONFLICT DO NOTHING
+             RETURNING idempotency_key_id, creation_time
+            ",
+            &[&creator_user_id, &key],
+        )
+        .await?;
+
+    // return idempotency key
+    Ok(row.map(|row| IdempotencyKey {
+        idempotency_key_id: row.get(0),
+        creation_time: row.get(1),
+        creator_user_id,
+        key,
+    }))
+}
+
+// most recently used first
+#[tracing::instrument(skip(con))]
+pub async fn get_recent_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    limit: i64,
+) -> Result<Vec<IdempotencyKey>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT *
+             FROM idempotency_key
+             WHERE creator_user_id = $1
+             ORDER BY idempotency_key_id DESC
+             LIMIT $2
+            ",
+            &[&creator_user_id, &limit],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+
+    Ok(result)
+}
+
+// returns the number of keys purged
+#[tracing::instrument(skip(con))]
+pub async fn purge_created_before(
+    con: &mut impl GenericClient,
+    creation_time: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM idempotency_key WHERE creation_time < $1",
+        &[&creation_time],
+    )
+    .await
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM idempotency_key WHERE creator_user_id = $1",
+        &[&creator_user_id],
+    )
+    .await
+}
@@ -0,0 +1,228 @@
+// a small database backed job queue, for work that should happen off the hot path and be retried
+// until it succeeds. every instance runs jobs, and each job is claimed by one instance at a time.
+// jobs that keep failing are dead lettered: left in the table, marked dead, with their last error,
+// until the deployment's author looks at them and retries them
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use todoproxy_api::TaskStatus;
+use tokio_postgres::GenericClient;
+
+use crate::handlers::{self, AppError};
+use crate::user_worker::{self, Registration};
+use crate::{
+    github, github_link_service, job_service, push, push_subscription_service, utils, AppData,
+};
+
+/// How often to look for due jobs when the queue is empty.
+const JOB_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a claimed job is hidden from other instances; if it hasn't finished by then, it's retried.
+const JOB_LEASE: Duration = Duration::from_secs(5 * 60);
+
+/// The delay before the first retry; it doubles with each attempt after that.
+const JOB_RETRY_BASE: Duration = Duration::from_secs(10);
+
+/// The longest delay between retries.
+const JOB_RETRY_MAX: Duration = Duration::from_secs(60 * 60);
+
+/// A job that has failed this many times is dead lettered.
+const MAX_JOB_ATTEMPTS: i64 = 10;
+
+// since this instance started
+static JOBS_DEAD_LETTERED: AtomicU64 = AtomicU64::new(0);
+
+pub fn jobs_dead_lettered() -> u64 {
+    JOBS_DEAD_LETTERED.load(Ordering::Relaxed)
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Job {
+    // write a checkpoint for a user, for when their worker couldn't
+    Checkpoint {
+        user_id: i64,
+    },
+    // deliver a web push notification
+    WebPush {
+        push_subscription_id: i64,
+        payload: String,
+    },
+    // remove an attachment's object from the bucket, once nothing refers to it
+    DeleteObject {
+        object_key: String,
+    },
+    // close or comment on the issue a task that was finished stood for
+    FinishGithubIssue {
+        user_id: i64,
+        external_id: String,
+        status: TaskStatus,
+    },
+}
+
+// schedules the job to run as soon as an instance gets to it
+pub async fn enqueue(con: &mut impl GenericClient, job: &Job) -> Result<(), tokio_postgres::Error> {
+    enqueue_at(con, job, utils::current_time_millis()).await
+}
+
+// schedules the job to run once it's run_after, as soon as an instance gets to it
+pub async fn enqueue_at(
+    con: &mut impl GenericClient,
+    job: &Job,
+    run_after: i64,
+) -> Result<(), tokio_postgres::Error> {
+    let jsonval = serde_json::to_string(job).unwrap();
+    job_service::add(con, jsonval, run_after).await?;
+    Ok(())
+}
+
+// runs due jobs for as long as the server does
+pub async fn run(data: AppData) {
+    let mut interval = tokio::time::interval(JOB_POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        // work through everything that's due before waiting again
+        loop {
+            match run_next(&data).await {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    log::error!("couldn't run jobs: {e}");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn retry_delay(attempts: i64) -> Duration {
+    let exponent = attempts.saturating_sub(1).clamp(0, 16) as u32;
+    std::cmp::min(JOB_RETRY_BASE * 2u32.pow(exponent), JOB_RETRY_MAX)
+}
+
+// returns whether there was a job to run
+async fn run_next(data: &AppData) -> Result<bool, AppError> {
+    let now = utils::current_time_millis();
+    let job = {
+        let con: &mut tokio_postgres::Client =
+            &mut *data.pool.get().await.map_err(handlers::report_pool_err)?;
+        job_service::claim(&mut *con, now, now + JOB_LEASE.as_millis() as i64)
+            .await
+            .map_err(handlers::report_postgres_err)?
+    };
+    let job = match job {
+        Some(job) => job,
+        None => return Ok(false),
+    };
+
+    let result = match serde_json::from_str::<Job>(&job.jsonval) {
+        Ok(x) => perform(data, x).await,
+        Err(e) => Err(format!("malformed job: {e}")),
+    };
+
+    let con: &mut tokio_postgres::Client =
+        &mut *data.pool.get().await.map_err(handlers::report_pool_err)?;
+    match result {
+        Ok(()) => job_service::remove(&mut *con, job.job_id).await,
+        Err(e) => {
+            let retry_after = (job.attempts < MAX_JOB_ATTEMPTS).then(|| {
+                utils::current_time_millis() + retry_delay(job.attempts).as_millis() as i64
+            });
+            match retry_after {
+                Some(_) => log::warn!("job {} failed on attempt {}: {e}", job.job_id, job.attempts),
+                None => {
+                    log::error!(
+                        "dead lettering job {} after {} attempts: {e}",
+                        job.job_id,
+                        job.attempts
+                    );
+                    JOBS_DEAD_LETTERED.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            job_service::fail(&mut *con, job.job_id, e, retry_after).await
+        }
+    }
+    .map_err(handlers::report_postgres_err)?;
+    Ok(true)
+}
+
+// errors are the underlying cause, which is kept with the job if it ends up dead lettered
+async fn perform(data: &AppData, job: Job) -> Result<(), String> {
+    match job {
+        // going through the worker keeps the checkpoint consistent with any session that's open
+        Job::Checkpoint { user_id } => {
+            let (worker, _) = user_worker::connect(data, user_id)
+                .await
+                .map_err(|e| e.to_string())?;
+            let _registration = Registration(worker.clone());
+            let checkpoint_id = worker.checkpoint(false).await.map_err(|e| e.to_string())?;
+            log::info!("wrote checkpoint {checkpoint_id} for user {user_id}");
+        }
+        Job::WebPush {
+            push_subscription_id,
+            payload,
+        } => {
+            let pusher = data.push.as_ref().ok_or("push isn't configured")?;
+            let con: &mut tokio_postgres::Client =
+                &mut *data.pool.get().await.map_err(|e| e.to_string())?;
+            let subscription = push_subscription_service::get_by_push_subscription_id(
+                &mut *con,
+                push_subscription_id,
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+            // the browser unsubscribed since the push was queued
+            let subscription = match subscription {
+                Some(x) => x,
+                None => return Ok(()),
+            };
+            match pusher.send(&subscription, payload.as_bytes()).await {
+                Ok(()) => {}
+                Err(e) if push::is_gone(&e) => {
+                    log::info!("removing expired push subscription {push_subscription_id}");
+                    push_subscription_service::remove(&mut *con, push_subscription_id)
+                        .await
+                        .map_err(|e| e.to_string())?;
+                }
+                Err(e) => {
+                    return Err(format!(
+                        "couldn't deliver push to subscription {push_subscription_id}: {e}"
+                    ));
+                }
+            }
+        }
+        Job::DeleteObject { object_key } => {
+            let storage = data.storage.as_ref().ok_or("storage isn't configured")?;
+            storage
+                .delete(&object_key)
+                .await
+                .map_err(|e| format!("couldn't delete object {object_key}: {e}"))?;
+        }
+        Job::FinishGithubIssue {
+            user_id,
+            external_id,
+            status,
+        } => {
+            let Some((repo, number)) = github::parse_external_id(&external_id) else {
+                log::error!("malformed github issue id {external_id}");
+                return Ok(());
+            };
+            let con: &mut tokio_postgres::Client =
+                &mut *data.pool.get().await.map_err(|e| e.to_string())?;
+            let link = github_link_service::get_by_repo(&mut *con, user_id, repo)
+                .await
+                .map_err(|e| e.to_string())?;
+            // the user unlinked the repo since the task was finished
+            let link = match link {
+                Some(x) => x,
+                None => return Ok(()),
+            };
+            let token = data.github.token(&link).map_err(|e| e.to_string())?;
+            data.github
+                .finish_issue(&link, &token, number, &status)
+                .await
+                .map_err(|e| format!("couldn't finish github issue {external_id}: {e}"))?;
+        }
+    }
+    Ok(())
+}
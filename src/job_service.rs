@@ -0,0 +1,143 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for Job {
+    // select * from job order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> Job {
+        Job {
+            job_id: row.get("job_id"),
+            creation_time: row.get("creation_time"),
+            jsonval: row.get("jsonval"),
+            attempts: row.get("attempts"),
+            run_after: row.get("run_after"),
+            last_error: row.get("last_error"),
+            dead: row.get("dead"),
+        }
+    }
+}
+
+#[tracing::instrument(skip(con, jsonval))]
+pub async fn add(
+    con: &mut impl GenericClient,
+    jsonval: String,
+    run_after: i64,
+) -> Result<Job, tokio_postgres::Error> {
+    let row = con
+        .query_one(
+            "INSERT INTO
+             job(
+                 jsonval,
+                 run_after
+             )
+             VALUES($1, $2)
+             RETURNING job_id, creation_time
+            ",
+            &[&jsonval, &run_after],
+        )
+        .await?;
+
+    // return job
+    Ok(Job {
+        job_id: row.get(0),
+        creation_time: row.get(1),
+        jsonval,
+        attempts: 0,
+        run_after,
+        last_error: None,
+        dead: false,
+    })
+}
+
+// takes the oldest due job, counting the attempt and hiding it from other claims until lease_until
+// skip locked means instances claiming at once get different jobs instead of waiting on each other
+#[tracing::instrument(skip(con))]
+pub async fn claim(
+    con: &mut impl GenericClient,
+    now: i64,
+    lease_until: i64,
+) -> Result<Option<Job>, tokio_postgres::Error> {
+    let result = con
+        .query_opt(
+            "UPDATE job
+             SET attempts = attempts + 1, run_after = $2
+             WHERE job_id = (
+                 SELECT job_id FROM job
+                 WHERE NOT dead AND run_after <= $1
+                 ORDER BY run_after
+                 LIMIT 1
+                 FOR UPDATE SKIP LOCKED
+             )
+             RETURNING *
+            ",
+            &[&now, &lease_until],
+        )
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn remove(
+    con: &mut impl GenericClient,
+    job_id: i64,
+) -> Result<(), tokio_postgres::Error> {
+    con.execute("DELETE FROM job WHERE job_id = $1", &[&job_id])
+        .await?;
+    Ok(())
+}
+
+// dead lettered jobs, most recently queued first
+#[tracing::instrument(skip(con))]
+pub async fn get_dead(
+    con: &mut impl GenericClient,
+    limit: i64,
+) -> Result<Vec<Job>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT * FROM job WHERE dead ORDER BY job_id DESC LIMIT $1",
+            &[&limit],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// gives a dead lettered job a fresh set of attempts, starting at run_after
+// returns false if there's no dead job with that id
+#[tracing::instrument(skip(con))]
+pub async fn revive(
+    con: &mut impl GenericClient,
+    job_id: i64,
+    run_after: i64,
+) -> Result<bool, tokio_postgres::Error> {
+    let count = con
+        .execute(
+            "UPDATE job SET dead = FALSE, attempts = 0, run_after = $2
+             WHERE job_id = $1 AND dead
+            ",
+            &[&job_id, &run_after],
+        )
+        .await?;
+    Ok(count > 0)
+}
+
+// schedules another attempt, or dead letters the job if retry_after is None
+#[tracing::instrument(skip(con, last_error))]
+pub async fn fail(
+    con: &mut impl GenericClient,
+    job_id: i64,
+    last_error: String,
+    retry_after: Option<i64>,
+) -> Result<(), tokio_postgres::Error> {
+    con.execute(
+        "UPDATE job
+         SET last_error = $2, run_after = COALESCE($3, run_after), dead = $3 IS NULL
+         WHERE job_id = $1
+        ",
+        &[&job_id, &last_error, &retry_after],
+    )
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,371 @@
+// background work that runs for as long as the server does
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use dashmap::DashMap;
+
+use crate::db_pool;
+use crate::db_types::DeletedTask;
+use crate::handlers::{self, AppError};
+use crate::job_queue::{self, Job};
+use crate::protocol::{ExtOpKind, Op, OpKind, OpRejectReason, Preferences, DAY_FORMAT};
+use crate::rate_limit::RateLimiter;
+use crate::settings::RuntimeSettings;
+use crate::task_updates::{ResumeToken, RESUME_TOKEN_TTL};
+use crate::user_worker::{self, ApplyOutcome, LoadedState, Registration};
+use crate::{
+    attachment_service, audit_event_service, checkpoint_service, deleted_task_service,
+    idempotency_key_service, op_usage_service, operation_service, preferences, rollover_service,
+    snapshot_codec, utils, AppData,
+};
+
+/// How often to look for deleted tasks, idempotency keys, audit events and op usage past their retention period.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often to check whether any user's day has ended.
+const ROLLOVER_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// How many checkpoints from before compression are compressed at a time.
+const COMPRESS_BATCH_SIZE: i64 = 100;
+
+/// How many operation_ids' worth of ops are moved to jsonb at a time.
+const JSONB_BATCH_SIZE: i64 = 1000;
+
+/// How long a client may keep retrying an op before its idempotency key is forgotten.
+const IDEMPOTENCY_KEY_RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+
+// permanently removes soft deleted tasks once they're older than retention, and their attachments
+pub async fn purge_deleted_tasks(pool: db_pool::Pool, retention: Duration) {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let cutoff = utils::current_time_millis() - retention.as_millis() as i64;
+        let result: Result<usize, AppError> = try {
+            let con: &mut tokio_postgres::Client =
+                &mut *pool.get().await.map_err(handlers::report_pool_err)?;
+            let mut tx = con
+                .transaction()
+                .await
+                .map_err(handlers::report_postgres_err)?;
+            let purged = deleted_task_service::purge_created_before(&mut tx, cutoff)
+                .await
+                .map_err(handlers::report_postgres_err)?;
+            remove_orphaned_attachments(&pool, &mut tx, purged.iter()).await?;
+            tx.commit().await.map_err(handlers::report_postgres_err)?;
+            purged.len()
+        };
+        match result {
+            Ok(0) => {}
+            Ok(n) => log::info!("purged {n} deleted tasks"),
+            // we'll get them next time
+            Err(e) => log::error!("couldn't purge deleted tasks: {e}"),
+        }
+    }
+}
+
+// removes the attachments of purged tasks, unless the task was restored or is in the trash again
+// their objects are deleted by the job queue
+async fn remove_orphaned_attachments(
+    pool: &db_pool::Pool,
+    tx: &mut tokio_postgres::Transaction<'_>,
+    purged: impl Iterator<Item = &DeletedTask>,
+) -> Result<(), AppError> {
+    let mut purged_by_user = HashMap::<i64, HashSet<&str>>::new();
+    for x in purged {
+        purged_by_user
+            .entry(x.creator_user_id)
+            .or_default()
+            .insert(&x.task_id);
+    }
+    for (user_id, task_ids) in purged_by_user {
+        let con: &mut deadpool_postgres::Client =
+            &mut pool.get().await.map_err(handlers::report_pool_err)?;
+        let LoadedState { snapshot, .. } = user_worker::load_state(con, user_id).await?;
+        for task_id in task_ids {
+            let in_trash = deleted_task_service::get_recent_by_task_id(&mut *tx, user_id, task_id)
+                .await
+                .map_err(handlers::report_postgres_err)?
+                .is_some();
+            if in_trash
+                || snapshot.live.iter().any(|x| x.id == task_id)
+                || snapshot.finished.iter().any(|x| x.id == task_id)
+            {
+                continue;
+            }
+            let attachments = attachment_service::remove_by_task_id(&mut *tx, user_id, task_id)
+                .await
+                .map_err(handlers::report_postgres_err)?;
+            for x in attachments {
+                let job = Job::DeleteObject {
+                    object_key: x.object_key,
+                };
+                job_queue::enqueue(&mut *tx, &job)
+                    .await
+                    .map_err(handlers::report_postgres_err)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// forgets idempotency keys once clients have long stopped retrying the ops they were sent with
+pub async fn purge_idempotency_keys(pool: db_pool::Pool) {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let cutoff = utils::current_time_millis() - IDEMPOTENCY_KEY_RETENTION.as_millis() as i64;
+        let result: Result<u64, AppError> = try {
+            let con: &mut tokio_postgres::Client =
+                &mut *pool.get().await.map_err(handlers::report_pool_err)?;
+            idempotency_key_service::purge_created_before(&mut *con, cutoff)
+                .await
+                .map_err(handlers::report_postgres_err)?
+        };
+        match result {
+            Ok(0) => {}
+            Ok(n) => log::info!("purged {n} idempotency keys"),
+            // we'll get them next time
+            Err(e) => log::error!("couldn't purge idempotency keys: {e}"),
+        }
+    }
+}
+
+// removes audit log entries once they're older than retention
+pub async fn purge_audit_events(pool: db_pool::Pool, retention: Duration) {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let cutoff = utils::current_time_millis() - retention.as_millis() as i64;
+        let result: Result<u64, AppError> = try {
+            let con: &mut tokio_postgres::Client =
+                &mut *pool.get().await.map_err(handlers::report_pool_err)?;
+            audit_event_service::purge_created_before(&mut *con, cutoff)
+                .await
+                .map_err(handlers::report_postgres_err)?
+        };
+        match result {
+            Ok(0) => {}
+            Ok(n) => log::info!("purged {n} audit events"),
+            // we'll get them next time
+            Err(e) => log::error!("couldn't purge audit events: {e}"),
+        }
+    }
+}
+
+// removes op counts from before yesterday; only today's count towards a quota, but yesterday's is
+// kept for instances whose clocks haven't passed midnight yet
+pub async fn purge_op_usage(pool: db_pool::Pool) {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let yesterday = (Utc::now() - chrono::Duration::days(1))
+            .format(DAY_FORMAT)
+            .to_string();
+        let result: Result<u64, AppError> = try {
+            let con: &mut tokio_postgres::Client =
+                &mut *pool.get().await.map_err(handlers::report_pool_err)?;
+            op_usage_service::purge_before_day(&mut *con, &yesterday)
+                .await
+                .map_err(handlers::report_postgres_err)?
+        };
+        match result {
+            Ok(0) => {}
+            Ok(n) => log::info!("purged {n} days of op usage"),
+            // we'll get them next time
+            Err(e) => log::error!("couldn't purge op usage: {e}"),
+        }
+    }
+}
+
+// compresses the checkpoints stored before checkpoints were compressed, then stops
+// they can be read either way, so it doesn't matter how long it takes
+pub async fn compress_checkpoints(pool: db_pool::Pool) {
+    let mut compressed = 0;
+    loop {
+        let result: Result<usize, AppError> = try {
+            let con: &mut deadpool_postgres::Client =
+                &mut pool.get().await.map_err(handlers::report_pool_err)?;
+            let checkpoints = checkpoint_service::get_uncompressed(&mut *con, COMPRESS_BATCH_SIZE)
+                .await
+                .map_err(handlers::report_postgres_err)?;
+            for x in &checkpoints {
+                let snapshot = snapshot_codec::checkpoint_snapshot(x)?;
+                checkpoint_service::update_snapshot(&mut *con, x.checkpoint_id, &snapshot)
+                    .await
+                    .map_err(handlers::report_postgres_err)?;
+            }
+            checkpoints.len()
+        };
+        match result {
+            Ok(0) => {
+                if compressed > 0 {
+                    log::info!("compressed {compressed} checkpoints");
+                }
+                return;
+            }
+            Ok(n) => compressed += n,
+            // try again later
+            Err(e) => {
+                log::error!("couldn't compress checkpoints: {e}");
+                tokio::time::sleep(PURGE_INTERVAL).await;
+            }
+        }
+    }
+}
+
+// moves ops stored before ops were stored as jsonb over, then stops
+// queries read either column, so it doesn't matter how long it takes
+pub async fn move_operations_to_jsonb(pool: db_pool::Pool) {
+    // ops stored since the migration are already jsonb, so the last one to move is known up front
+    let mut last_id = None;
+    let mut after_id = 0;
+    let mut moved = 0;
+    loop {
+        let result: Result<bool, AppError> = try {
+            let con: &mut deadpool_postgres::Client =
+                &mut pool.get().await.map_err(handlers::report_pool_err)?;
+            let last_id = match last_id {
+                Some(x) => x,
+                None => *last_id.insert(
+                    operation_service::get_last_unmoved_operation_id(&mut *con)
+                        .await
+                        .map_err(handlers::report_postgres_err)?
+                        .unwrap_or(0),
+                ),
+            };
+            if after_id >= last_id {
+                true
+            } else {
+                let until_id = (after_id + JSONB_BATCH_SIZE).min(last_id);
+                moved += operation_service::move_to_jsonb(&mut *con, after_id, until_id)
+                    .await
+                    .map_err(handlers::report_postgres_err)?;
+                after_id = until_id;
+                false
+            }
+        };
+        match result {
+            Ok(false) => {}
+            Ok(true) => {
+                if moved > 0 {
+                    log::info!("moved {moved} ops to jsonb");
+                }
+                return;
+            }
+            // try again later
+            Err(e) => {
+                log::error!("couldn't move ops to jsonb: {e}");
+                tokio::time::sleep(PURGE_INTERVAL).await;
+            }
+        }
+    }
+}
+
+// forgets resume tokens that weren't used in time; they're only ever kept in memory
+pub async fn purge_resume_tokens(resume_tokens: Arc<DashMap<String, ResumeToken>>) {
+    let mut interval = tokio::time::interval(RESUME_TOKEN_TTL);
+    loop {
+        interval.tick().await;
+        let now = utils::current_time_millis();
+        resume_tokens.retain(|_, x| x.expiry_time.map_or(true, |expiry_time| expiry_time > now));
+    }
+}
+
+// forgets rate limit windows that are over; they're only ever kept in memory
+pub async fn purge_rate_limits(rate_limiter: Arc<RateLimiter>, settings: Arc<RuntimeSettings>) {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        rate_limiter.purge(settings.get().rate_limit_window());
+    }
+}
+
+// rolls over the finished lists of users who turned it on, once their day has ended
+// the ops go through each user's worker, so connected sessions are sent them
+pub async fn roll_over_days(data: AppData) {
+    let mut interval = tokio::time::interval(ROLLOVER_INTERVAL);
+    loop {
+        interval.tick().await;
+        let result: Result<Vec<(i64, Preferences)>, AppError> = try {
+            let con: &mut tokio_postgres::Client =
+                &mut *data.pool.get().await.map_err(handlers::report_pool_err)?;
+            let mut users = preferences::get_all(&mut *con).await?;
+            users.retain(|(_, preferences)| preferences.rollover.enabled);
+            users
+        };
+        let users = match result {
+            Ok(users) => users,
+            // we'll get them next time
+            Err(e) => {
+                log::error!("couldn't find users to roll over: {e}");
+                continue;
+            }
+        };
+        let mut rolled_over = 0;
+        for (user_id, preferences) in users {
+            match roll_over_day(&data, user_id, &preferences).await {
+                Ok(true) => rolled_over += 1,
+                Ok(false) => {}
+                Err(e) => log::error!("couldn't roll over user {user_id}: {e}"),
+            }
+        }
+        if rolled_over > 0 {
+            log::info!("rolled over {rolled_over} users");
+        }
+    }
+}
+
+// returns whether the user's day ended since they were last rolled over
+async fn roll_over_day(
+    data: &AppData,
+    user_id: i64,
+    preferences: &Preferences,
+) -> Result<bool, AppError> {
+    let today = preferences::day_of(preferences, Utc::now())
+        .format(DAY_FORMAT)
+        .to_string();
+    {
+        let con: &mut tokio_postgres::Client =
+            &mut *data.pool.get().await.map_err(handlers::report_pool_err)?;
+        let last = rollover_service::get_by_user_id(&mut *con, user_id)
+            .await
+            .map_err(handlers::report_postgres_err)?;
+        match last {
+            // the first day starts when rollover is turned on, rather than ending right away
+            None => {
+                rollover_service::advance(&mut *con, user_id, &today)
+                    .await
+                    .map_err(handlers::report_postgres_err)?;
+                return Ok(false);
+            }
+            Some(last) if last.day >= today => return Ok(false),
+            Some(_) => {}
+        }
+    }
+
+    let (worker, _subscription) = user_worker::connect(data, user_id).await?;
+    let _registration = Registration(worker.clone());
+    let op = Op {
+        alleged_time: utils::current_time_millis(),
+        kind: OpKind::Ext(ExtOpKind::RolloverDay {
+            day: today,
+            requeue_failed: preferences.rollover.requeue_failed,
+        }),
+        seq: None,
+        checksum: None,
+        idempotency_key: None,
+        value_encoding: None,
+    };
+    match worker.apply_op(op).await? {
+        ApplyOutcome::Applied(_) => Ok(true),
+        // another instance, or one of the user's clients, got there first
+        ApplyOutcome::Rejected(OpRejectReason::AlreadyRolledOver) => Ok(false),
+        ApplyOutcome::Rejected(reason) => {
+            log::error!("rollover of user {user_id} was rejected: {reason:?}");
+            Ok(false)
+        }
+        ApplyOutcome::Duplicate => Ok(false),
+    }
+}
@@ -0,0 +1,109 @@
+// validates api keys that are signed JWTs, against the issuer's published keys
+// so connecting doesn't need a round trip to the auth service
+use std::time::{Duration, Instant};
+
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{DecodingKey, Validation};
+use tokio::sync::RwLock;
+
+use crate::handlers::AppError;
+
+/// Tokens signed with a key we don't know trigger a refetch of the JWKS, at most this often.
+const JWKS_REFETCH_INTERVAL: Duration = Duration::from_secs(60);
+
+pub struct JwtValidator {
+    jwks_url: String,
+    issuer: String,
+    audience: Option<String>,
+    // the claim holding the user id, as a number or a numeric string
+    user_id_claim: String,
+    client: reqwest::Client,
+    // keys, and when they were fetched
+    jwks: RwLock<(JwkSet, Instant)>,
+}
+
+impl JwtValidator {
+    // fetches the JWKS once, so that misconfiguration shows up at startup
+    pub async fn new(
+        jwks_url: String,
+        issuer: String,
+        audience: Option<String>,
+        user_id_claim: String,
+    ) -> Result<JwtValidator, reqwest::Error> {
+        let client = reqwest::Client::new();
+        let jwks = fetch_jwks(&client, &jwks_url).await?;
+        Ok(JwtValidator {
+            jwks_url,
+            issuer,
+            audience,
+            user_id_claim,
+            client,
+            jwks: RwLock::new((jwks, Instant::now())),
+        })
+    }
+
+    pub async fn get_user_id(&self, token: &str) -> Result<i64, AppError> {
+        let header = jsonwebtoken::decode_header(token).map_err(|_| AppError::Unauthorized)?;
+        let kid = header.kid.ok_or(AppError::Unauthorized)?;
+        let key = self.decoding_key(&kid).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.issuer]);
+        match self.audience {
+            Some(ref audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        let claims = jsonwebtoken::decode::<serde_json::Map<String, serde_json::Value>>(
+            token,
+            &key,
+            &validation,
+        )
+        .map_err(|_| AppError::Unauthorized)?
+        .claims;
+
+        match claims.get(&self.user_id_claim) {
+            Some(serde_json::Value::Number(n)) => n.as_i64(),
+            Some(serde_json::Value::String(s)) => s.parse().ok(),
+            _ => None,
+        }
+        .ok_or(AppError::Unauthorized)
+    }
+
+    async fn decoding_key(&self, kid: &str) -> Result<DecodingKey, AppError> {
+        {
+            let jwks = self.jwks.read().await;
+            if let Some(jwk) = jwks.0.find(kid) {
+                return DecodingKey::from_jwk(jwk).map_err(|_| AppError::Unauthorized);
+            }
+            // don't let a stream of bogus tokens hammer the issuer
+            if jwks.1.elapsed() < JWKS_REFETCH_INTERVAL {
+                return Err(AppError::Unauthorized);
+            }
+        }
+
+        // the issuer may have rotated its keys
+        let mut jwks = self.jwks.write().await;
+        if jwks.1.elapsed() >= JWKS_REFETCH_INTERVAL {
+            let fetched = fetch_jwks(&self.client, &self.jwks_url)
+                .await
+                .map_err(|e| {
+                    log::error!("couldn't fetch jwks: {e}");
+                    AppError::InternalServerError
+                })?;
+            *jwks = (fetched, Instant::now());
+        }
+        let jwk = jwks.0.find(kid).ok_or(AppError::Unauthorized)?;
+        DecodingKey::from_jwk(jwk).map_err(|_| AppError::Unauthorized)
+    }
+}
+
+async fn fetch_jwks(client: &reqwest::Client, jwks_url: &str) -> Result<JwkSet, reqwest::Error> {
+    client
+        .get(jwks_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+}
@@ -0,0 +1,125 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for KnownDevice {
+    // select * from known_device order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> KnownDevice {
+        KnownDevice {
+            known_device_id: row.get("known_device_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            device_id: row.get("device_id"),
+            label: row.get("label"),
+            platform: row.get("platform"),
+            last_seen_time: row.get("last_seen_time"),
+            revoked: row.get("revoked"),
+        }
+    }
+}
+
+// records that the device opened a websocket, adding it if it's new
+// a label or platform left out keeps the one it had; revoked devices are returned unchanged
+#[tracing::instrument(skip(con))]
+pub async fn seen(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    device_id: &str,
+    label: Option<&str>,
+    platform: Option<&str>,
+    time: i64,
+) -> Result<KnownDevice, tokio_postgres::Error> {
+    con.execute(
+        "INSERT INTO
+         known_device(
+             creator_user_id,
+             device_id,
+             label,
+             platform,
+             last_seen_time
+         )
+         VALUES($1, $2, $3, $4, $5)
+         ON CONFLICT (creator_user_id, device_id) DO UPDATE
+         SET label = coalesce($3, known_device.label),
+             platform = coalesce($4, known_device.platform),
+             last_seen_time = $5
+         WHERE NOT known_device.revoked
+        ",
+        &[&creator_user_id, &device_id, &label, &platform, &time],
+    )
+    .await?;
+    let row = con
+        .query_one(
+            "SELECT * FROM known_device WHERE creator_user_id=$1 AND device_id=$2",
+            &[&creator_user_id, &device_id],
+        )
+        .await?;
+    Ok(row.into())
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn get_by_device_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    device_id: &str,
+) -> Result<Option<KnownDevice>, tokio_postgres::Error> {
+    let result = con
+        .query_opt(
+            "SELECT * FROM known_device WHERE creator_user_id=$1 AND device_id=$2",
+            &[&creator_user_id, &device_id],
+        )
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+// the devices that weren't revoked, most recently seen first
+#[tracing::instrument(skip(con))]
+pub async fn get_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<Vec<KnownDevice>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT * FROM known_device
+             WHERE creator_user_id=$1 AND NOT revoked
+             ORDER BY last_seen_time DESC
+            ",
+            &[&creator_user_id],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// returns whether the user had a device with this id that wasn't already revoked
+#[tracing::instrument(skip(con))]
+pub async fn revoke(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    device_id: &str,
+) -> Result<bool, tokio_postgres::Error> {
+    let revoked = con
+        .execute(
+            "UPDATE known_device SET revoked = true
+             WHERE creator_user_id = $1 AND device_id = $2 AND NOT revoked
+            ",
+            &[&creator_user_id, &device_id],
+        )
+        .await?;
+    Ok(revoked > 0)
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM known_device WHERE creator_user_id = $1",
+        &[&creator_user_id],
+    )
+    .await
+}
@@ -1,24 +1,111 @@
 #![feature(try_blocks)]
-use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::{net::Ipv4Addr, sync::Arc};
+use std::sync::Arc;
+use std::time::Duration;
 
+use actix_cors::Cors;
 use actix_web::{middleware, web, App, HttpServer};
-use auth_service_api::response::User;
-use clap::Parser;
+use clap::{ArgGroup, Parser};
+use dashmap::DashMap;
+use socket2::{Domain, Socket, Type};
+use task_updates::{ResumeToken, SessionInfo, SlowConsumerPolicy, TaskLimits};
+use telemetry::LogFormat;
 
+use auth::{AuthBackend, AuthCache};
 use auth_service_api::client::AuthService;
-use todoproxy_api::{StateSnapshot, WebsocketOp};
-use tokio::sync::broadcast;
-use tokio::sync::Mutex;
+use circuit_breaker::CircuitBreaker;
+use commands::Command;
+use credentials::CredentialKey;
+use drain::Drain;
+use email::EmailGateway;
+use event_stream::{EventStream, EventStreamKind, KafkaPublisher, NatsPublisher};
+use fanout::{BroadcastBackend, BroadcastBackendKind, LocalBackend, PostgresBackend, RedisBackend};
+use github::Github;
+use handlers::WebhookUsage;
+use jwt::JwtValidator;
+use push::Pusher;
+use rate_limit::RateLimiter;
+use settings::{RuntimeSettings, Settings};
+use snapshot_cache::SnapshotCache;
+use storage::ObjectStore;
+use user_worker::UserWorkerHandle;
 
+mod activity;
+mod audit;
+mod auth;
+mod backup;
+mod circuit_breaker;
+mod commands;
+mod credentials;
+mod db_pool;
 mod db_types;
+mod drain;
+mod email;
+mod event_stream;
+mod fanout;
+mod github;
+mod graphql;
+mod grpc;
 mod handlers;
+mod job_queue;
+mod jobs;
+mod jwt;
+mod migrations;
+mod preferences;
+mod protocol;
+mod push;
+mod quick_add;
+mod quota;
+mod rate_limit;
+mod request_id;
+mod settings;
+mod snapshot_cache;
+mod snapshot_codec;
+mod sse;
+mod state_machine;
+mod stats;
+mod storage;
 mod task_updates;
+mod telemetry;
+mod user_worker;
 mod utils;
 
+mod api_token_service;
+mod archived_task_service;
+mod attachment_service;
+mod audit_event_service;
 mod checkpoint_service;
+mod deleted_task_service;
+mod email_alias_service;
+mod external_task_service;
+mod github_link_service;
+mod goal_service;
+mod habit_check_in_service;
+mod habit_definition_service;
+mod idempotency_key_service;
+mod job_service;
+mod known_device_service;
+mod notification_digest_service;
+mod op_usage_service;
 mod operation_service;
+mod preference_service;
+mod push_subscription_service;
+mod read_only_api_key_service;
+mod reminder_service;
+mod rollover_service;
+mod task_comment_service;
+mod task_estimate_service;
+mod task_focus_service;
+mod task_goal_service;
+mod task_lane_service;
+mod template_service;
+mod tenant_service;
+mod tenant_user_service;
+mod webhook_service;
 
 static SERVICE: &'static str = "todoproxy";
 static VERSION_MAJOR: i64 = 0;
@@ -27,99 +114,1170 @@ static VERSION_REV: i64 = 1;
 
 #[derive(Parser, Debug, Clone)]
 #[clap(about, version, author)]
+// subcommands don't start the server, so they don't need its arguments
+#[clap(subcommand_negates_reqs = true)]
+#[clap(group(ArgGroup::new("listen").required(true).multiple(true).args(["port", "unix_socket"])))]
 struct Opts {
     #[clap(long)]
-    port: u16,
+    port: Option<u16>,
+    /// also serve the grpc api on this port, on every bind_addr
     #[clap(long)]
-    database_url: String,
+    grpc_port: Option<u16>,
+    /// address to listen on; may be given more than once (e.g. 0.0.0.0 and :: for dual stack)
+    #[clap(long, default_values_t = [IpAddr::V4(Ipv4Addr::LOCALHOST)])]
+    bind_addr: Vec<IpAddr>,
+    /// also listen on this unix domain socket (e.g. for nginx on the same host)
     #[clap(long)]
-    auth_service_url: String,
+    unix_socket: Option<PathBuf>,
+    /// permissions of the unix domain socket, in octal
+    #[clap(long, default_value = "660", value_parser = parse_octal)]
+    unix_socket_mode: u32,
+    /// number of worker threads handling connections, and running background jobs (defaults to the number of cores)
     #[clap(long)]
-    app_pub_origin: String,
-}
-
-pub struct PerUserWorkerData {
-    // user
-    pub user: User,
-    // websockets send to this channel when they receive an event
-    pub updates_tx: broadcast::Sender<WebsocketOp>,
-    // snapshot at the current state of the channel
-    pub snapshot: StateSnapshot,
-    // id of checkpoint
-    pub checkpoint_id: i64,
+    workers: Option<usize>,
+    /// how many ops may be queued for a websocket before the slow consumer policy applies (less than the broadcast capacity)
+    #[clap(long, default_value_t = 500)]
+    outbound_high_water_mark: usize,
+    /// what to do with sessions that can't keep up: resync them, or disconnect them
+    #[clap(long, value_enum, default_value_t = SlowConsumerPolicy::Resync)]
+    slow_consumer_policy: SlowConsumerPolicy,
+    /// how many ops each user's worker buffers for its sessions; ones that fall further behind miss ops
+    #[clap(long, default_value_t = 1000)]
+    broadcast_capacity: usize,
+    /// how often websockets are pinged; should be half (or less) of the client timeout
+    #[clap(long, default_value_t = 5)]
+    heartbeat_interval_secs: u64,
+    /// how long a websocket may go without answering a ping before it's closed
+    #[clap(long, default_value_t = 30)]
+    client_timeout_secs: u64,
+    /// how often websockets check their api key wasn't revoked, closing the session if it was
+    #[clap(long, default_value_t = 300)]
+    api_key_revalidate_interval_secs: u64,
+    /// how long websockets are kept open after the server starts draining (on SIGUSR1 or /admin/drain)
+    #[clap(long, default_value_t = 30)]
+    drain_grace_period_secs: u64,
+    /// how long new websockets turned away while draining are told to wait before reconnecting
+    #[clap(long, default_value_t = 5)]
+    drain_retry_after_secs: u64,
+    /// the longest task value ops may set, in bytes
+    #[clap(long, default_value_t = 65536)]
+    max_value_len: usize,
+    /// the most live tasks a user may have (unlimited if unset)
+    #[clap(long)]
+    max_live_tasks: Option<usize>,
+    /// the most finished tasks a user may have (unlimited if unset)
+    #[clap(long)]
+    max_finished_tasks: Option<usize>,
+    #[clap(long, required = true)]
+    database_url: Option<String>,
+    /// a read replica of the database, for reads that can be a little behind (state, history, calendar, backups)
+    #[clap(long)]
+    read_database_url: Option<String>,
+    /// the most database connections to keep open (to each database)
+    #[clap(long, default_value_t = 16)]
+    db_pool_max_size: usize,
+    /// how long a request waits for a database connection before giving up (0 to wait forever)
+    #[clap(long, default_value_t = 10)]
+    db_acquire_timeout_secs: u64,
+    /// how long a query may run before postgres cancels it (0 for no limit)
+    #[clap(long, default_value_t = 30)]
+    db_statement_timeout_secs: u64,
+    #[clap(long, required_unless_present_any = ["standalone", "jwt_jwks_url"])]
+    auth_service_url: Option<String>,
+    /// check api keys against tokens issued with `token create`, instead of the auth service
+    #[clap(long)]
+    standalone: bool,
+    /// treat api keys as JWTs, checked against the keys published here instead of by the auth service
+    #[clap(long, requires = "jwt_issuer", conflicts_with = "standalone")]
+    jwt_jwks_url: Option<String>,
+    /// the iss claim JWTs must have
+    #[clap(long)]
+    jwt_issuer: Option<String>,
+    /// the aud claim JWTs must have, if any
+    #[clap(long)]
+    jwt_audience: Option<String>,
+    /// the claim holding the user id
+    #[clap(long, default_value = "sub")]
+    jwt_user_id_claim: String,
+    /// how long to remember which user a valid api key belongs to (0 to always ask the auth backend)
+    #[clap(long, default_value_t = 60)]
+    auth_cache_ttl_secs: u64,
+    /// the most api keys to remember at once
+    #[clap(long, default_value_t = 10000)]
+    auth_cache_max_entries: usize,
+    /// how long past that api keys are still accepted while the auth backend is unavailable (0 never)
+    #[clap(long, default_value_t = 600)]
+    auth_cache_stale_secs: u64,
+    /// how long a call to the auth service may take before it's given up on
+    #[clap(long, default_value_t = 5)]
+    auth_service_timeout_secs: u64,
+    /// failed calls to the auth service in a row after which it isn't called for a while (0 to always call it)
+    #[clap(long, default_value_t = 5)]
+    auth_service_failure_threshold: u32,
+    /// how long the auth service isn't called for after that
+    #[clap(long, default_value_t = 30)]
+    auth_service_retry_secs: u64,
+    #[clap(long, required = true)]
+    app_pub_origin: Option<String>,
+    /// user id of the deployment's author, who may use the admin api
+    #[clap(long)]
+    author_id: Option<i64>,
+    /// apply pending database migrations before starting the server
+    #[clap(long)]
+    migrate: bool,
+    /// how long to keep retrying the database and auth service at startup
+    #[clap(long, default_value_t = 60)]
+    startup_timeout_secs: u64,
+    /// how instances sharing a database tell each other about stored ops; local means there's only one
+    #[clap(long, value_enum, default_value_t = BroadcastBackendKind::Local)]
+    broadcast_backend: BroadcastBackendKind,
+    /// redis server for --broadcast-backend redis
+    #[clap(long, required_if_eq("broadcast_backend", "redis"))]
+    redis_url: Option<String>,
+    /// cache users' materialized state in this redis server, to speed up loading long histories
+    #[clap(long)]
+    snapshot_cache_url: Option<String>,
+    /// also publish every stored op to kafka or nats
+    #[clap(long, value_enum, requires = "event_stream_url")]
+    event_stream: Option<EventStreamKind>,
+    /// kafka brokers (comma separated host:port) or nats server url for --event-stream
+    #[clap(long, requires = "event_stream")]
+    event_stream_url: Option<String>,
+    /// kafka topic events are published to; for nats, the subject they're published under, followed by .<user_id>
+    #[clap(long, default_value = "todoproxy.ops")]
+    event_stream_topic: String,
+    /// pem file with the VAPID private key web push notifications are signed with
+    #[clap(long, requires = "vapid_public_key")]
+    vapid_private_key_file: Option<PathBuf>,
+    /// the matching public key, base64url encoded, which browsers subscribe with
+    #[clap(long, requires = "vapid_private_key_file")]
+    vapid_public_key: Option<String>,
+    /// s3 compatible endpoint attachments are stored at, e.g. https://s3.us-east-1.amazonaws.com
+    #[clap(long, requires_all = ["s3_bucket", "s3_access_key_id", "s3_secret_access_key_file"])]
+    s3_endpoint: Option<String>,
+    /// bucket attachments are stored in
+    #[clap(long, requires = "s3_endpoint")]
+    s3_bucket: Option<String>,
+    /// region the bucket is in
+    #[clap(long, default_value = "us-east-1")]
+    s3_region: String,
+    #[clap(long, requires = "s3_endpoint")]
+    s3_access_key_id: Option<String>,
+    /// file containing the secret access key for --s3-access-key-id
+    #[clap(long, requires = "s3_endpoint")]
+    s3_secret_access_key_file: Option<PathBuf>,
+    /// the largest file that can be attached to a task, in bytes
+    #[clap(long, default_value_t = 25 * 1024 * 1024)]
+    max_attachment_bytes: i64,
+    /// imap server to fetch emails to users' aliases from, over tls
+    #[clap(long, requires_all = ["imap_username", "imap_password_file", "imap_address"])]
+    imap_host: Option<String>,
+    #[clap(long, default_value_t = 993)]
+    imap_port: u16,
+    #[clap(long, requires = "imap_host")]
+    imap_username: Option<String>,
+    /// file containing the password for --imap-username
+    #[clap(long, requires = "imap_host")]
+    imap_password_file: Option<PathBuf>,
+    /// folder to look for new mail in
+    #[clap(long, default_value = "INBOX")]
+    imap_folder: String,
+    /// address of the mailbox; aliases are plus addresses of it, e.g. tasks+abc123@example.com
+    #[clap(long, requires = "imap_host")]
+    imap_address: Option<String>,
+    /// how often to check for new mail
+    #[clap(long, default_value_t = 60)]
+    imap_poll_interval_secs: u64,
+    /// github api that linked repos are synced with (e.g. a github enterprise server's /api/v3)
+    #[clap(long, default_value = "https://api.github.com")]
+    github_api_url: String,
+    /// file holding the key that github tokens are sealed with, as 64 hex characters (repos can't be linked without one)
+    #[clap(long)]
+    credential_key_file: Option<PathBuf>,
+    /// how long soft deleted tasks can be restored before they're purged
+    #[clap(long, default_value_t = 30)]
+    trash_retention_days: u64,
+    /// how long entries in users' audit logs are kept
+    #[clap(long, default_value_t = 365)]
+    audit_retention_days: u64,
+    /// how many http requests an api key may make per rate limit window, on each instance (0 for unlimited)
+    #[clap(long, default_value_t = 600)]
+    rate_limit_requests: u32,
+    /// how long rate limit windows are
+    #[clap(long, default_value_t = 60)]
+    rate_limit_window_secs: u64,
+    /// how many ops each user may apply per (utc) day (0 for unlimited)
+    #[clap(long, default_value_t = 0)]
+    max_ops_per_day: u64,
+    /// how many bytes of task values and attachments each user may store (0 for unlimited)
+    #[clap(long, default_value_t = 0)]
+    max_storage_bytes: u64,
+    /// how many websockets each user may have open at once, on each instance (0 for unlimited)
+    #[clap(long, default_value_t = 0)]
+    max_devices_per_user: usize,
+    /// json emits one object per event, including span fields like user_id and connection_id
+    #[clap(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    /// export tracing spans to this OTLP (grpc) collector
+    #[clap(long)]
+    otlp_endpoint: Option<String>,
+    /// other origins browsers may call us from (comma separated, or * for any); app_pub_origin is always allowed
+    #[clap(long, value_delimiter = ',')]
+    allowed_origins: Vec<String>,
+    /// json file overriding settings that can be reloaded without restarting (on SIGHUP or /admin/settings/reload)
+    #[clap(long)]
+    settings_file: Option<PathBuf>,
+    #[clap(subcommand)]
+    command: Option<Command>,
 }
 
+// shared between every worker thread
 #[derive(Clone)]
 pub struct AppData {
-    pub user_worker_data: Arc<Mutex<HashMap<i64, Arc<Mutex<PerUserWorkerData>>>>>,
-    pub auth_service: AuthService,
+    // handles to the workers of connected users
+    pub user_worker_data: Arc<DashMap<i64, UserWorkerHandle>>,
+    // open websockets, by connection id
+    pub sessions: Arc<DashMap<String, SessionInfo>>,
+    // resume tokens issued to websockets, which outlive them for a little while
+    pub resume_tokens: Arc<DashMap<String, ResumeToken>>,
+    pub auth: AuthBackend,
+    // which users recently checked api keys belong to, unless caching is off
+    pub auth_cache: Option<Arc<AuthCache>>,
     pub app_pub_origin: String,
-    pub pool: deadpool_postgres::Pool,
+    pub author_id: Option<i64>,
+    pub pool: db_pool::Pool,
+    // a replica for reads that can be a little behind, if there is one; see handlers::read_pool
+    pub read_pool: Option<db_pool::Pool>,
+    // settings that can be reloaded without restarting
+    pub settings: Arc<RuntimeSettings>,
+    // whether the instance is draining, and how
+    pub drain: Arc<Drain>,
+    pub task_limits: TaskLimits,
+    // tells other instances about stored ops
+    pub broadcast: Arc<dyn BroadcastBackend>,
+    pub snapshot_cache: Option<SnapshotCache>,
+    // publishes stored ops, if an event stream was given
+    pub events: Option<EventStream>,
+    // sends web push notifications, if a vapid key was given
+    pub push: Option<Arc<Pusher>>,
+    // where attachments are uploaded to, if a bucket was given
+    pub storage: Option<Arc<ObjectStore>>,
+    pub max_attachment_bytes: i64,
+    // how much each webhook was used in its current window, on this instance
+    pub webhook_usage: Arc<DashMap<i64, WebhookUsage>>,
+    // where emails to users' aliases are fetched from, if an imap server was given
+    pub email: Option<Arc<EmailGateway>>,
+    // syncs repos users link with their tasks
+    pub github: Arc<Github>,
+    // counts requests against each api key's limit
+    pub rate_limiter: Arc<RateLimiter>,
+}
+
+// fails to compile if something that can't be shared between workers is added to AppData
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<AppData>();
+};
+
+// the url's connection config, with queries held to the statement timeout
+fn connection_config(
+    url: &str,
+    statement_timeout_secs: u64,
+) -> Result<tokio_postgres::Config, tokio_postgres::Error> {
+    let mut config = tokio_postgres::Config::from_str(url)?;
+    if statement_timeout_secs > 0 {
+        // keep any options the url already sets
+        let options = format!(
+            "{} -c statement_timeout={}",
+            config.get_options().unwrap_or_default(),
+            statement_timeout_secs * 1000
+        );
+        config.options(options.trim_start());
+    }
+    Ok(config)
 }
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
-    env_logger::init();
+fn build_pool(
+    config: tokio_postgres::Config,
+    max_size: usize,
+    acquire_timeout: Option<Duration>,
+) -> Result<db_pool::Pool, deadpool_postgres::BuildError> {
+    let mgr = deadpool_postgres::Manager::from_config(
+        config,
+        tokio_postgres::NoTls,
+        deadpool_postgres::ManagerConfig {
+            recycling_method: deadpool_postgres::RecyclingMethod::Fast,
+        },
+    );
+    let pool = deadpool_postgres::Pool::builder(mgr)
+        .max_size(max_size)
+        .wait_timeout(acquire_timeout)
+        .create_timeout(acquire_timeout)
+        .runtime(deadpool_postgres::Runtime::Tokio1)
+        .build()?;
+    Ok(db_pool::Pool::new(pool))
+}
 
+// ipv6 sockets are made v6-only, so that :: and 0.0.0.0 can be bound at the same time
+fn listener(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(socket.into())
+}
+
+// a socket file left behind by a previous run is removed, but one that's still being served is not
+fn unix_listener(path: &Path, mode: u32) -> std::io::Result<UnixListener> {
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "path exists and is not a socket",
+            ));
+        }
+        if UnixStream::connect(path).is_ok() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AddrInUse,
+                "another process is listening on this socket",
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    Ok(listener)
+}
+
+fn parse_octal(s: &str) -> Result<u32, std::num::ParseIntError> {
+    u32::from_str_radix(s, 8)
+}
+
+/// Where dashboards fetch the widget from, which any origin may do.
+const WIDGET_PATH: &str = "/public/widget";
+
+fn cors(app_pub_origin: &str, allowed_origins: &[String]) -> Cors {
+    let mut cors = Cors::default()
+        .allowed_origin(app_pub_origin)
+        .allow_any_method()
+        .allow_any_header()
+        .expose_headers(["x-request-id"])
+        .max_age(3600)
+        // the widget is meant to be embedded anywhere
+        .allowed_origin_fn(|_, req| req.uri.path() == WIDGET_PATH);
+    for origin in allowed_origins {
+        cors = if origin == "*" {
+            cors.allow_any_origin()
+        } else {
+            cors.allowed_origin(origin)
+        };
+    }
+    cors
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
+    let opts = Opts::parse();
+
+    // background jobs (purges, rollover, fanout, the job queue...) get the same number of threads,
+    // so that one busy user doesn't stall everyone else
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    if let Some(workers) = opts.workers {
+        runtime.worker_threads(workers);
+    }
+    runtime.enable_all().build()?.block_on(run(opts))
+}
+
+async fn run(opts: Opts) -> Result<(), Box<dyn std::error::Error + 'static>> {
     let Opts {
         auth_service_url,
+        standalone,
+        jwt_jwks_url,
+        jwt_issuer,
+        jwt_audience,
+        jwt_user_id_claim,
+        auth_cache_ttl_secs,
+        auth_cache_max_entries,
+        auth_cache_stale_secs,
+        auth_service_timeout_secs,
+        auth_service_failure_threshold,
+        auth_service_retry_secs,
         app_pub_origin,
+        author_id,
         port,
+        grpc_port,
+        bind_addr,
+        unix_socket,
+        unix_socket_mode,
+        workers,
+        outbound_high_water_mark,
+        slow_consumer_policy,
+        broadcast_capacity,
+        heartbeat_interval_secs,
+        client_timeout_secs,
+        api_key_revalidate_interval_secs,
+        drain_grace_period_secs,
+        drain_retry_after_secs,
+        max_value_len,
+        max_live_tasks,
+        max_finished_tasks,
         database_url,
-    } = Opts::parse();
+        read_database_url,
+        db_pool_max_size,
+        db_acquire_timeout_secs,
+        db_statement_timeout_secs,
+        migrate,
+        startup_timeout_secs,
+        broadcast_backend,
+        redis_url,
+        snapshot_cache_url,
+        event_stream,
+        event_stream_url,
+        event_stream_topic,
+        vapid_private_key_file,
+        vapid_public_key,
+        s3_endpoint,
+        s3_bucket,
+        s3_region,
+        s3_access_key_id,
+        s3_secret_access_key_file,
+        max_attachment_bytes,
+        imap_host,
+        imap_port,
+        imap_username,
+        imap_password_file,
+        imap_folder,
+        imap_address,
+        imap_poll_interval_secs,
+        github_api_url,
+        credential_key_file,
+        trash_retention_days,
+        audit_retention_days,
+        rate_limit_requests,
+        rate_limit_window_secs,
+        max_ops_per_day,
+        max_storage_bytes,
+        max_devices_per_user,
+        log_format,
+        otlp_endpoint,
+        allowed_origins,
+        settings_file,
+        command,
+    } = opts;
 
-    // connect to postgres
-    let postgres_config = tokio_postgres::Config::from_str(&database_url).map_err(|e| {
-        log::error!(target:"todoproxy::deadpool", "couldn't parse database_url: {}", e);
-        e
-    })?;
-    log::info!("parsed database url");
+    let log_filter = telemetry::init(log_format, otlp_endpoint)?;
 
-    let mgr = deadpool_postgres::Manager::from_config(
-        postgres_config,
-        tokio_postgres::NoTls,
-        deadpool_postgres::ManagerConfig {
-            recycling_method: deadpool_postgres::RecyclingMethod::Fast,
+    let startup_timeout = Duration::from_secs(startup_timeout_secs);
+
+    // the settings file may override any of these
+    let settings = Arc::new(RuntimeSettings::new(
+        Settings {
+            log_filter: None,
+            rate_limit_requests,
+            rate_limit_window_secs,
+            heartbeat_interval_secs,
+            client_timeout_secs,
+            api_key_revalidate_interval_secs,
+            outbound_high_water_mark,
+            slow_consumer_policy,
+            broadcast_capacity,
+            max_ops_per_day,
+            max_storage_bytes,
+            max_devices_per_user,
         },
-    );
+        settings_file,
+        log_filter,
+    )?);
+    let auth_cache = match (auth_cache_ttl_secs, auth_cache_max_entries) {
+        (0, _) | (_, 0) => None,
+        (ttl_secs, max_entries) => Some(Arc::new(AuthCache::new(
+            Duration::from_secs(ttl_secs),
+            Duration::from_secs(auth_cache_stale_secs),
+            max_entries,
+        ))),
+    };
 
-    let pool = deadpool_postgres::Pool::builder(mgr)
-        .max_size(16)
-        .build()
+    // subcommands need it too, but clap only enforces it when serving
+    let database_url = database_url.ok_or("--database-url is required")?;
+
+    // connect to postgres
+    let postgres_config =
+        connection_config(&database_url, db_statement_timeout_secs).map_err(|e| {
+            log::error!(target:"todoproxy::deadpool", "couldn't parse database_url: {}", e);
+            e
+        })?;
+    log::info!("parsed database url");
+
+    let acquire_timeout =
+        (db_acquire_timeout_secs > 0).then(|| Duration::from_secs(db_acquire_timeout_secs));
+    let pool = build_pool(postgres_config.clone(), db_pool_max_size, acquire_timeout)
         .map_err(|e| { log::error!(target:"todoproxy::deadpool", "couldn't build database connection pool: {}", e); e })?;
 
     log::info!(target:"todoproxy::deadpool", "built database connection pool");
 
-    // open connection to auth service
-    let auth_service = AuthService::new(&auth_service_url);
-    log::info!(target:"todoproxy::deadpool", "connected to auth service");
+    // the replica is connected to lazily, so an unreachable one only fails the reads sent to it
+    let read_pool = match read_database_url {
+        Some(read_database_url) => {
+            let config = connection_config(&read_database_url, db_statement_timeout_secs).map_err(|e| {
+                log::error!(target:"todoproxy::deadpool", "couldn't parse read_database_url: {}", e);
+                e
+            })?;
+            let read_pool = build_pool(config, db_pool_max_size, acquire_timeout)
+                .map_err(|e| { log::error!(target:"todoproxy::deadpool", "couldn't build read replica connection pool: {}", e); e })?;
+            log::info!(target:"todoproxy::deadpool", "built read replica connection pool");
+            Some(read_pool)
+        }
+        None => None,
+    };
+
+    // wait for postgres to accept connections
+    let mut con = utils::retry_with_backoff("database", startup_timeout, || pool.get())
+        .await
+        .map_err(|e| {
+            log::error!(target:"todoproxy::startup", "couldn't connect to database: {}", e);
+            e
+        })?;
+    log::info!(target:"todoproxy::startup", "database is reachable");
+
+    if migrate {
+        migrations::migrate(&mut con).await.map_err(|e| {
+            log::error!(target:"todoproxy::migrations", "couldn't apply migrations: {}", e);
+            e
+        })?;
+        log::info!(target:"todoproxy::migrations", "database schema is up to date");
+    }
+    drop(con);
+
+    if let Some(command) = command {
+        return commands::run(command, &pool)
+            .await
+            .map_err(|e| e.to_string().into());
+    }
+
+    // clap requires these when there's no subcommand
+    let app_pub_origin = app_pub_origin.unwrap();
+
+    let auth = match (auth_service_url, jwt_jwks_url) {
+        (_, Some(jwt_jwks_url)) => {
+            // clap requires an issuer alongside the jwks url
+            let jwt_issuer = jwt_issuer.unwrap();
+            let validator = utils::retry_with_backoff("jwks", startup_timeout, || {
+                JwtValidator::new(
+                    jwt_jwks_url.clone(),
+                    jwt_issuer.clone(),
+                    jwt_audience.clone(),
+                    jwt_user_id_claim.clone(),
+                )
+            })
+            .await
+            .map_err(|e| {
+                log::error!(target:"todoproxy::startup", "couldn't fetch jwks: {}", e);
+                e
+            })?;
+            log::info!(target:"todoproxy::startup", "validating api keys as JWTs");
+            AuthBackend::Jwt(Arc::new(validator))
+        }
+        (Some(auth_service_url), None) if !standalone => {
+            // open connection to auth service
+            let auth_service = AuthService::new(&auth_service_url);
+            utils::retry_with_backoff("auth service", startup_timeout, || auth_service.info())
+                .await
+                .map_err(|e| {
+                    log::error!(target:"todoproxy::startup", "couldn't reach auth service: {}", e);
+                    e.to_string()
+                })?;
+            log::info!(target:"todoproxy::deadpool", "connected to auth service");
+            AuthBackend::Service {
+                client: auth_service,
+                breaker: Arc::new(CircuitBreaker::new(
+                    "auth service",
+                    Duration::from_secs(auth_service_timeout_secs),
+                    auth_service_failure_threshold,
+                    Duration::from_secs(auth_service_retry_secs),
+                )),
+            }
+        }
+        _ => {
+            log::info!(target:"todoproxy::startup", "running standalone, without an auth service");
+            AuthBackend::Standalone
+        }
+    };
+
+    log::info!(target:"todoproxy::startup", "dependencies ready, starting server");
+
+    tokio::spawn(jobs::purge_deleted_tasks(
+        pool.clone(),
+        Duration::from_secs(trash_retention_days * 24 * 60 * 60),
+    ));
+    tokio::spawn(jobs::purge_idempotency_keys(pool.clone()));
+    tokio::spawn(jobs::purge_audit_events(
+        pool.clone(),
+        Duration::from_secs(audit_retention_days * 24 * 60 * 60),
+    ));
+    tokio::spawn(jobs::purge_op_usage(pool.clone()));
+    tokio::spawn(jobs::compress_checkpoints(pool.clone()));
+    tokio::spawn(jobs::move_operations_to_jsonb(pool.clone()));
+
+    let user_worker_data = Arc::new(DashMap::new());
+
+    let broadcast: Arc<dyn BroadcastBackend> = match broadcast_backend {
+        BroadcastBackendKind::Local => Arc::new(LocalBackend),
+        BroadcastBackendKind::Postgres => Arc::new(PostgresBackend {
+            pool: pool.clone(),
+            config: postgres_config,
+        }),
+        BroadcastBackendKind::Redis => {
+            let client = redis::Client::open(redis_url.unwrap_or_default()).map_err(|e| {
+                log::error!(target:"todoproxy::startup", "couldn't parse redis_url: {}", e);
+                e.to_string()
+            })?;
+            let con = utils::retry_with_backoff("redis", startup_timeout, || {
+                client.get_multiplexed_async_connection()
+            })
+            .await
+            .map_err(|e| {
+                log::error!(target:"todoproxy::startup", "couldn't connect to redis: {}", e);
+                e.to_string()
+            })?;
+            log::info!(target:"todoproxy::startup", "connected to redis");
+            Arc::new(RedisBackend { client, con })
+        }
+    };
+    tokio::spawn(broadcast.clone().listen(user_worker_data.clone()));
+
+    let snapshot_cache = match snapshot_cache_url {
+        Some(snapshot_cache_url) => {
+            let client = redis::Client::open(snapshot_cache_url).map_err(|e| {
+                log::error!(target:"todoproxy::startup", "couldn't parse snapshot_cache_url: {}", e);
+                e.to_string()
+            })?;
+            let con = utils::retry_with_backoff("snapshot cache", startup_timeout, || {
+                client.get_multiplexed_async_connection()
+            })
+            .await
+            .map_err(|e| {
+                log::error!(target:"todoproxy::startup", "couldn't connect to snapshot cache: {}", e);
+                e.to_string()
+            })?;
+            log::info!(target:"todoproxy::startup", "connected to snapshot cache");
+            Some(SnapshotCache { con })
+        }
+        None => None,
+    };
+
+    let events = match (event_stream, event_stream_url) {
+        (Some(EventStreamKind::Kafka), Some(url)) => {
+            let publisher = KafkaPublisher::new(&url, event_stream_topic).map_err(|e| {
+                log::error!(target:"todoproxy::startup", "couldn't set up the kafka producer: {}", e);
+                e.to_string()
+            })?;
+            log::info!(target:"todoproxy::startup", "publishing ops to kafka");
+            Some(EventStream::new(Arc::new(publisher)))
+        }
+        (Some(EventStreamKind::Nats), Some(url)) => {
+            let client = utils::retry_with_backoff("nats", startup_timeout, || {
+                async_nats::connect(url.as_str())
+            })
+            .await
+            .map_err(|e| {
+                log::error!(target:"todoproxy::startup", "couldn't connect to nats: {}", e);
+                e.to_string()
+            })?;
+            log::info!(target:"todoproxy::startup", "publishing ops to nats");
+            Some(EventStream::new(Arc::new(NatsPublisher {
+                client,
+                subject: event_stream_topic,
+            })))
+        }
+        _ => None,
+    };
+
+    let push = match (vapid_private_key_file, vapid_public_key) {
+        (Some(vapid_private_key_file), Some(vapid_public_key)) => {
+            let vapid_private_key = std::fs::read(&vapid_private_key_file).map_err(|e| {
+                log::error!(target:"todoproxy::startup", "couldn't read {}: {}", vapid_private_key_file.display(), e);
+                e
+            })?;
+            let pusher = Pusher::new(vapid_private_key, vapid_public_key).map_err(|e| {
+                log::error!(target:"todoproxy::startup", "couldn't set up web push: {}", e);
+                e.to_string()
+            })?;
+            log::info!(target:"todoproxy::startup", "sending web push notifications");
+            Some(Arc::new(pusher))
+        }
+        _ => None,
+    };
+
+    let storage = match (
+        s3_endpoint,
+        s3_bucket,
+        s3_access_key_id,
+        s3_secret_access_key_file,
+    ) {
+        (Some(endpoint), Some(bucket), Some(access_key_id), Some(secret_access_key_file)) => {
+            let secret_access_key = std::fs::read_to_string(&secret_access_key_file).map_err(|e| {
+                log::error!(target:"todoproxy::startup", "couldn't read {}: {}", secret_access_key_file.display(), e);
+                e
+            })?;
+            let storage = ObjectStore::new(
+                endpoint,
+                bucket,
+                s3_region,
+                access_key_id,
+                secret_access_key.trim().to_owned(),
+            )
+            .map_err(|e| {
+                log::error!(target:"todoproxy::startup", "couldn't set up attachment storage: {}", e);
+                e
+            })?;
+            log::info!(target:"todoproxy::startup", "storing attachments");
+            Some(Arc::new(storage))
+        }
+        _ => None,
+    };
+
+    let email = match (imap_host, imap_username, imap_password_file, imap_address) {
+        (Some(host), Some(username), Some(password_file), Some(address)) => {
+            if imap_poll_interval_secs == 0 {
+                return Err("imap poll interval must be at least one second".into());
+            }
+            let password = std::fs::read_to_string(&password_file).map_err(|e| {
+                log::error!(target:"todoproxy::startup", "couldn't read {}: {}", password_file.display(), e);
+                e
+            })?;
+            let email = EmailGateway::new(
+                host,
+                imap_port,
+                username,
+                password.trim().to_owned(),
+                imap_folder,
+                address,
+                Duration::from_secs(imap_poll_interval_secs),
+            )
+            .map_err(|e| {
+                log::error!(target:"todoproxy::startup", "couldn't set up the email gateway: {}", e);
+                e
+            })?;
+            log::info!(target:"todoproxy::startup", "making tasks from email");
+            Some(Arc::new(email))
+        }
+        _ => None,
+    };
 
-    let user_worker_data = Arc::new(Mutex::new(HashMap::new()));
+    let credential_key = match credential_key_file {
+        Some(credential_key_file) => {
+            let key = std::fs::read_to_string(&credential_key_file).map_err(|e| {
+                log::error!(target:"todoproxy::startup", "couldn't read {}: {}", credential_key_file.display(), e);
+                e
+            })?;
+            let key = CredentialKey::from_hex(&key).ok_or_else(|| {
+                log::error!(target:"todoproxy::startup", "{} isn't 32 bytes in hex", credential_key_file.display());
+                "invalid credential key"
+            })?;
+            // links made before tokens were sealed
+            let con: &mut tokio_postgres::Client = &mut *pool.get().await?;
+            let sealed = github::seal_plaintext_tokens(&mut *con, &key)
+                .await
+                .map_err(|e| {
+                    log::error!(target:"todoproxy::startup", "couldn't seal github tokens: {}", e);
+                    e
+                })?;
+            if sealed > 0 {
+                log::info!(target:"todoproxy::startup", "sealed {} github tokens", sealed);
+            }
+            log::info!(target:"todoproxy::startup", "syncing linked github repos");
+            Some(key)
+        }
+        None => None,
+    };
 
     // start server
     let data = AppData {
         user_worker_data,
-        auth_service,
+        sessions: Arc::new(DashMap::new()),
+        resume_tokens: Arc::new(DashMap::new()),
+        auth,
+        auth_cache,
         app_pub_origin,
+        author_id,
         pool,
+        read_pool,
+        settings,
+        drain: Arc::new(Drain::new(
+            Duration::from_secs(drain_grace_period_secs),
+            Duration::from_secs(drain_retry_after_secs),
+        )),
+        task_limits: TaskLimits {
+            max_value_len,
+            max_live_tasks,
+            max_finished_tasks,
+        },
+        broadcast,
+        snapshot_cache,
+        events,
+        push,
+        storage,
+        max_attachment_bytes,
+        webhook_usage: Arc::new(DashMap::new()),
+        email,
+        github: Arc::new(Github::new(github_api_url, credential_key)),
+        rate_limiter: Arc::new(RateLimiter::new()),
     };
 
-    HttpServer::new(move || {
+    tokio::spawn(job_queue::run(data.clone()));
+    tokio::spawn(push::dispatch_reminders(data.clone()));
+    if data.push.is_some() {
+        tokio::spawn(push::send_digests(data.clone()));
+    }
+    tokio::spawn(jobs::purge_resume_tokens(data.resume_tokens.clone()));
+    tokio::spawn(jobs::purge_rate_limits(
+        data.rate_limiter.clone(),
+        data.settings.clone(),
+    ));
+    tokio::spawn(jobs::roll_over_days(data.clone()));
+    if data.email.is_some() {
+        tokio::spawn(email::poll(data.clone()));
+    }
+    if data.github.enabled() {
+        tokio::spawn(github::sync(data.clone()));
+    }
+    tokio::spawn(drain::on_signal(data.drain.clone()));
+    tokio::spawn(settings::on_signal(data.settings.clone()));
+
+    let schema = graphql::schema(data.clone());
+
+    if let Some(grpc_port) = grpc_port {
+        for addr in &bind_addr {
+            let addr = SocketAddr::new(*addr, grpc_port);
+            let std_listener = listener(addr).map_err(|e| {
+                log::error!(target:"todoproxy::startup", "couldn't bind {}: {}", addr, e);
+                e
+            })?;
+            std_listener.set_nonblocking(true)?;
+            let incoming = tonic::transport::server::TcpIncoming::from_listener(
+                tokio::net::TcpListener::from_std(std_listener)?,
+                true,
+                None,
+            )
+            .map_err(|e| e.to_string())?;
+            let router =
+                tonic::transport::Server::builder().add_service(grpc::service(data.clone()));
+            tokio::spawn(async move {
+                if let Err(e) = router.serve_with_incoming(incoming).await {
+                    log::error!(target:"todoproxy::grpc", "grpc server on {} failed: {}", addr, e);
+                }
+            });
+            log::info!(target:"todoproxy::startup", "serving grpc on {}", addr);
+        }
+    }
+
+    let mut server = HttpServer::new(move || {
         App::new()
+            // report rate limits in headers
+            .wrap_fn(rate_limit::middleware)
+            // remember where changes come from
+            .wrap_fn(audit::middleware)
+            // tag every request with an id
+            .wrap_fn(request_id::middleware)
+            // answer preflight requests and reject disallowed origins
+            .wrap(cors(&data.app_pub_origin, &allowed_origins))
             // enable logger
-            .wrap(middleware::Logger::default())
+            .wrap(middleware::Logger::new(
+                "%a \"%r\" %s %b \"%{Referer}i\" \"%{User-Agent}i\" %T %{x-request-id}o",
+            ))
             // add data
             .app_data(actix_web::web::Data::new(data.clone()))
+            .app_data(actix_web::web::Data::new(schema.clone()))
             // handle info query
             .service(web::resource("/public/info").route(web::route().to(handlers::info)))
+            // handle health checks
+            .service(web::resource("/public/healthz").route(web::route().to(handlers::healthz)))
+            .service(web::resource("/public/readyz").route(web::route().to(handlers::readyz)))
+            // handle api key restriction
+            .service(
+                web::resource("/public/api_key/make_read_only")
+                    .route(web::post().to(handlers::api_key_make_read_only)),
+            )
+            // handle graphql
+            .service(web::resource("/public/graphql").route(web::post().to(graphql::graphql)))
+            .service(web::resource("/public/graphql/ws").route(web::get().to(graphql::graphql_ws)))
+            // handle ops sent without a websocket
+            .service(web::resource("/public/ops/apply").route(web::post().to(handlers::apply_op)))
+            // handle account deletion
+            .service(
+                web::resource("/public/account/delete")
+                    .route(web::post().to(handlers::account_delete)),
+            )
+            // handle activity feed
+            .service(web::resource("/public/activity").route(web::post().to(handlers::activity)))
+            // handle dashboard widgets
+            .service(web::resource(WIDGET_PATH).route(web::get().to(handlers::widget)))
+            // handle state query
+            .service(web::resource("/public/state").route(web::get().to(handlers::state)))
+            .service(web::resource("/public/state/at").route(web::get().to(handlers::state_at)))
+            // handle web push subscriptions and reminders
+            .service(
+                web::resource("/public/push/subscribe")
+                    .route(web::post().to(handlers::push_subscribe)),
+            )
+            .service(
+                web::resource("/public/push/unsubscribe")
+                    .route(web::post().to(handlers::push_unsubscribe)),
+            )
+            .service(
+                web::resource("/public/reminder/set").route(web::post().to(handlers::reminder_set)),
+            )
+            .service(
+                web::resource("/public/reminder/clear")
+                    .route(web::post().to(handlers::reminder_clear)),
+            )
+            .service(
+                web::resource("/public/reminder/view")
+                    .route(web::post().to(handlers::reminder_view)),
+            )
+            // handle quick add
+            .service(
+                web::resource("/public/task/quick_add").route(web::post().to(handlers::quick_add)),
+            )
+            .service(
+                web::resource("/public/task/calendar")
+                    .route(web::get().to(handlers::task_calendar)),
+            )
+            // handle focus
+            .service(
+                web::resource("/public/focus/view").route(web::post().to(handlers::focus_view)),
+            )
+            // handle stats
+            .service(
+                web::resource("/public/stats/burndown").route(web::get().to(handlers::burndown)),
+            )
+            .service(web::resource("/public/review").route(web::get().to(handlers::review)))
+            // handle preferences
+            .service(
+                web::resource("/public/preferences")
+                    .route(web::get().to(handlers::preferences_view))
+                    .route(web::post().to(handlers::preferences_set)),
+            )
+            .service(
+                web::resource("/public/preferences/notifications")
+                    .route(web::get().to(handlers::notification_preferences_view))
+                    .route(web::post().to(handlers::notification_preferences_set)),
+            )
+            // handle attachments
+            .service(
+                web::resource("/public/attachment/new")
+                    .route(web::post().to(handlers::attachment_new)),
+            )
+            .service(
+                web::resource("/public/attachment/view")
+                    .route(web::post().to(handlers::attachment_view)),
+            )
+            .service(
+                web::resource("/public/attachment/delete")
+                    .route(web::post().to(handlers::attachment_delete)),
+            )
+            // handle comments, which are added and removed with ops
+            .service(
+                web::resource("/public/comment/view").route(web::post().to(handlers::comment_view)),
+            )
+            // handle a task's op log query
+            .service(
+                web::resource("/public/task/ops/view")
+                    .route(web::post().to(handlers::task_ops_view)),
+            )
+            // handle templates
+            .service(
+                web::resource("/public/template/new").route(web::post().to(handlers::template_new)),
+            )
+            .service(
+                web::resource("/public/template/edit")
+                    .route(web::post().to(handlers::template_edit)),
+            )
+            .service(
+                web::resource("/public/template/view")
+                    .route(web::post().to(handlers::template_view)),
+            )
+            .service(
+                web::resource("/public/template/delete")
+                    .route(web::post().to(handlers::template_delete)),
+            )
+            // handle habits
+            .service(
+                web::resource("/public/habit/view").route(web::post().to(handlers::habit_view)),
+            )
+            // handle devices
+            .service(
+                web::resource("/public/device/view").route(web::post().to(handlers::device_view)),
+            )
+            .service(
+                web::resource("/public/device/revoke")
+                    .route(web::post().to(handlers::device_revoke)),
+            )
+            // handle goals
+            .service(web::resource("/public/goal/new").route(web::post().to(handlers::goal_new)))
+            .service(web::resource("/public/goal/edit").route(web::post().to(handlers::goal_edit)))
+            .service(web::resource("/public/goal/view").route(web::post().to(handlers::goal_view)))
+            .service(
+                web::resource("/public/goal/delete").route(web::post().to(handlers::goal_delete)),
+            )
+            // handle webhooks
+            .service(
+                web::resource("/public/webhook/new").route(web::post().to(handlers::webhook_new)),
+            )
+            .service(
+                web::resource("/public/webhook/view").route(web::post().to(handlers::webhook_view)),
+            )
+            .service(
+                web::resource("/public/webhook/delete")
+                    .route(web::post().to(handlers::webhook_delete)),
+            )
+            .service(web::resource("/public/hook/{token}").route(web::post().to(handlers::hook)))
+            // handle github links
+            .service(
+                web::resource("/public/github_link/new")
+                    .route(web::post().to(handlers::github_link_new)),
+            )
+            .service(
+                web::resource("/public/github_link/view")
+                    .route(web::post().to(handlers::github_link_view)),
+            )
+            .service(
+                web::resource("/public/github_link/delete")
+                    .route(web::post().to(handlers::github_link_delete)),
+            )
+            // handle email aliases
+            .service(
+                web::resource("/public/email_alias/new")
+                    .route(web::post().to(handlers::email_alias_new)),
+            )
+            .service(
+                web::resource("/public/email_alias/view")
+                    .route(web::post().to(handlers::email_alias_view)),
+            )
+            .service(
+                web::resource("/public/email_alias/delete")
+                    .route(web::post().to(handlers::email_alias_delete)),
+            )
+            // handle audit log query
+            .service(
+                web::resource("/public/audit/view").route(web::post().to(handlers::audit_view)),
+            )
+            // handle quota usage query
+            .service(
+                web::resource("/public/quota/view").route(web::post().to(handlers::quota_view)),
+            )
+            // handle trash query
+            .service(
+                web::resource("/public/trash/view").route(web::post().to(handlers::trash_view)),
+            )
+            // handle history query
+            .service(
+                web::resource("/public/history/view").route(web::post().to(handlers::history_view)),
+            )
+            // handle admin api
+            .service(
+                web::resource("/admin/users/view")
+                    .route(web::post().to(handlers::admin_users_view)),
+            )
+            .service(
+                web::resource("/admin/sessions/view")
+                    .route(web::post().to(handlers::admin_sessions_view)),
+            )
+            .service(
+                web::resource("/admin/auth_cache/clear")
+                    .route(web::post().to(handlers::admin_auth_cache_clear)),
+            )
+            .service(
+                web::resource("/admin/users/checkpoint")
+                    .route(web::post().to(handlers::admin_checkpoint)),
+            )
+            .service(web::resource("/admin/notice").route(web::post().to(handlers::admin_notice)))
+            .service(web::resource("/admin/drain").route(web::post().to(handlers::admin_drain)))
+            .service(
+                web::resource("/admin/tenant/new")
+                    .route(web::post().to(handlers::admin_tenant_new)),
+            )
+            .service(
+                web::resource("/admin/tenant/view")
+                    .route(web::post().to(handlers::admin_tenant_view)),
+            )
+            .service(
+                web::resource("/admin/tenant/delete")
+                    .route(web::post().to(handlers::admin_tenant_delete)),
+            )
+            .service(
+                web::resource("/admin/tenant/user/set")
+                    .route(web::post().to(handlers::admin_tenant_user_set)),
+            )
+            .service(
+                web::resource("/admin/settings/view")
+                    .route(web::post().to(handlers::admin_settings_view)),
+            )
+            .service(
+                web::resource("/admin/settings/reload")
+                    .route(web::post().to(handlers::admin_settings_reload)),
+            )
+            .service(web::resource("/admin/metrics").route(web::post().to(handlers::admin_metrics)))
+            .service(
+                web::resource("/admin/jobs/dead/view")
+                    .route(web::post().to(handlers::admin_dead_jobs_view)),
+            )
+            .service(
+                web::resource("/admin/jobs/dead/retry")
+                    .route(web::post().to(handlers::admin_dead_job_retry)),
+            )
+            .service(web::resource("/admin/backup").route(web::post().to(backup::admin_backup)))
+            .service(web::resource("/admin/restore").route(web::post().to(backup::admin_restore)))
+            // handle long polling, for clients that can't use websockets or event streams
+            .service(
+                web::resource("/public/poll/task_updates")
+                    .route(web::get().to(handlers::poll_task_updates)),
+            )
+            // handle event stream, for clients that can't use websockets
+            .service(
+                web::resource("/public/sse/task_updates")
+                    .route(web::get().to(sse::sse_task_updates)),
+            )
             // handle ws connection
             .service(
-                web::resource("/public/ws/task_updates").route(web::get().to(handlers::ws_task_updates)),
+                web::resource("/public/ws/task_updates")
+                    .route(web::get().to(handlers::ws_task_updates)),
             )
-    })
-    .bind((Ipv4Addr::LOCALHOST, port))?
-    .run()
-    .await?;
+    });
+
+    // each worker runs its own single threaded runtime, and connections are spread between them
+    if let Some(workers) = workers {
+        server = server.workers(workers);
+    }
+
+    if let Some(port) = port {
+        for addr in bind_addr {
+            let addr = SocketAddr::new(addr, port);
+            server = server.listen(listener(addr).map_err(|e| {
+                log::error!(target:"todoproxy::startup", "couldn't bind {}: {}", addr, e);
+                e
+            })?)?;
+            log::info!(target:"todoproxy::startup", "listening on {}", addr);
+        }
+    }
+
+    if let Some(ref path) = unix_socket {
+        server = server.listen_uds(unix_listener(path, unix_socket_mode).map_err(|e| {
+            log::error!(target:"todoproxy::startup", "couldn't bind {}: {}", path.display(), e);
+            e
+        })?)?;
+        log::info!(target:"todoproxy::startup", "listening on {}", path.display());
+    }
+
+    server.run().await?;
+
+    if let Some(path) = unix_socket {
+        let _ = std::fs::remove_file(path);
+    }
+
+    telemetry::shutdown();
 
     Ok(())
 }
@@ -0,0 +1,85 @@
+// the schema is embedded in the binary and applied in order when started with --migrate
+
+// (version, sql); never edit an entry once released, only append new ones
+static MIGRATIONS: &[(i64, &str)] = &[
+    (1, include_str!("../sql/migrations/1-init.sql")),
+    (2, include_str!("../sql/migrations/2-deleted-task.sql")),
+    (3, include_str!("../sql/migrations/3-idempotency-key.sql")),
+    (4, include_str!("../sql/migrations/4-read-only-api-key.sql")),
+    (5, include_str!("../sql/migrations/5-api-token.sql")),
+    (6, include_str!("../sql/migrations/6-job.sql")),
+    (7, include_str!("../sql/migrations/7-push.sql")),
+    (8, include_str!("../sql/migrations/8-template.sql")),
+    (9, include_str!("../sql/migrations/9-task-comment.sql")),
+    (10, include_str!("../sql/migrations/10-attachment.sql")),
+    (11, include_str!("../sql/migrations/11-preference.sql")),
+    (12, include_str!("../sql/migrations/12-rollover.sql")),
+    (13, include_str!("../sql/migrations/13-task-lane.sql")),
+    (14, include_str!("../sql/migrations/14-webhook.sql")),
+    (15, include_str!("../sql/migrations/15-email-alias.sql")),
+    (16, include_str!("../sql/migrations/16-github.sql")),
+    (17, include_str!("../sql/migrations/17-tenant.sql")),
+    (18, include_str!("../sql/migrations/18-audit-event.sql")),
+    (19, include_str!("../sql/migrations/19-op-usage.sql")),
+    (
+        20,
+        include_str!("../sql/migrations/20-checkpoint-snapshot.sql"),
+    ),
+    (21, include_str!("../sql/migrations/21-operation-jsonb.sql")),
+    (22, include_str!("../sql/migrations/22-task-estimate.sql")),
+    (23, include_str!("../sql/migrations/23-task-focus.sql")),
+    (24, include_str!("../sql/migrations/24-goal.sql")),
+    (25, include_str!("../sql/migrations/25-habit.sql")),
+    (
+        26,
+        include_str!("../sql/migrations/26-notification-digest.sql"),
+    ),
+    (27, include_str!("../sql/migrations/27-known-device.sql")),
+    (
+        28,
+        include_str!("../sql/migrations/28-github-token-sealed.sql"),
+    ),
+];
+
+// the schema version a fully migrated database has
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map(|(version, _)| *version).unwrap_or(0)
+}
+
+pub async fn migrate(con: &mut tokio_postgres::Client) -> Result<(), tokio_postgres::Error> {
+    con.batch_execute(
+        "CREATE TABLE IF NOT EXISTS schema_migration(
+             version bigint primary key,
+             creation_time bigint not null default extract(epoch from now()) * 1000
+         )",
+    )
+    .await?;
+
+    for (version, sql) in MIGRATIONS {
+        let tx = con.transaction().await?;
+        // lock the table so that two instances starting at once don't both apply a migration
+        tx.batch_execute("LOCK TABLE schema_migration IN EXCLUSIVE MODE")
+            .await?;
+        // migrations can take longer than queries are usually allowed to
+        tx.batch_execute("SET LOCAL statement_timeout = 0").await?;
+        let applied = tx
+            .query_opt(
+                "SELECT version FROM schema_migration WHERE version=$1",
+                &[version],
+            )
+            .await?
+            .is_some();
+        if !applied {
+            tx.batch_execute(sql).await?;
+            tx.execute(
+                "INSERT INTO schema_migration(version) VALUES($1)",
+                &[version],
+            )
+            .await?;
+            log::info!(target:"todoproxy::migrations", "applied migration {}", version);
+        }
+        tx.commit().await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,82 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for OpUsage {
+    // select * from op_usage order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> OpUsage {
+        OpUsage {
+            op_usage_id: row.get("op_usage_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            day: row.get("day"),
+            ops: row.get("ops"),
+        }
+    }
+}
+
+// counts one more op on the day, returning how many there have been
+#[tracing::instrument(skip(con))]
+pub async fn increment(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    day: &str,
+) -> Result<i64, tokio_postgres::Error> {
+    let row = con
+        .query_one(
+            "INSERT INTO
+             op_usage(
+                 creator_user_id,
+                 day,
+                 ops
+             )
+             VALUES($1, $2, 1)
+             ON CONFLICT (creator_user_id, day) DO UPDATE
+             SET ops = op_usage.ops + 1
+             RETURNING ops
+            ",
+            &[&creator_user_id, &day],
+        )
+        .await?;
+
+    // return ops
+    Ok(row.get(0))
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn get_by_user_id_and_day(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    day: &str,
+) -> Result<Option<OpUsage>, tokio_postgres::Error> {
+    let result = con
+        .query_opt(
+            "SELECT * FROM op_usage WHERE creator_user_id=$1 AND day=$2",
+            &[&creator_user_id, &day],
+        )
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+// days are yyyy-mm-dd, so they sort as text
+#[tracing::instrument(skip(con))]
+pub async fn purge_before_day(
+    con: &mut impl GenericClient,
+    day: &str,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute("DELETE FROM op_usage WHERE day < $1", &[&day])
+        .await
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM op_usage WHERE creator_user_id = $1",
+        &[&creator_user_id],
+    )
+    .await
+}
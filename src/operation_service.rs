@@ -1,6 +1,7 @@
 use super::db_types::*;
-use todoproxy_api::WebsocketOp;
-use tokio_postgres::GenericClient;
+use super::protocol::Op;
+use deadpool_postgres::{GenericClient, Transaction};
+use tokio_postgres::Portal;
 
 impl From<tokio_postgres::row::Row> for Operation {
     // select * from operation order only, otherwise it will fail
@@ -9,66 +10,328 @@ impl From<tokio_postgres::row::Row> for Operation {
             operation_id: row.get("operation_id"),
             creation_time: row.get("creation_time"),
             checkpoint_id: row.get("checkpoint_id"),
-            jsonval: row.get("jsonval"),
+            // ops that haven't been moved to jsonbval yet are still in jsonval
+            jsonval: match row.get::<_, Option<serde_json::Value>>("jsonbval") {
+                Some(x) => x.to_string(),
+                None => row.get("jsonval"),
+            },
         }
     }
 }
 
+#[tracing::instrument(skip(con, op))]
 pub async fn add(
     con: &mut impl GenericClient,
     checkpoint_id: i64,
-    op: WebsocketOp,
+    op: Op,
 ) -> Result<Operation, tokio_postgres::Error> {
-    let jsonval = serde_json::to_string(&op).unwrap();
-    let row = con
-        .query_one(
+    let jsonbval = serde_json::to_value(&op).unwrap();
+    let statement = con
+        .prepare_cached(
             "INSERT INTO
              operation(
                  checkpoint_id,
-                 jsonval
+                 jsonbval
              )
              VALUES($1, $2)
              RETURNING operation_id, creation_time
             ",
-            &[&checkpoint_id, &jsonval],
         )
         .await?;
+    let row = con
+        .query_one(&statement, &[&checkpoint_id, &jsonbval])
+        .await?;
 
     // return operation
     Ok(Operation {
         operation_id: row.get(0),
         creation_time: row.get(1),
         checkpoint_id,
-        jsonval,
+        jsonval: jsonbval.to_string(),
     })
 }
 
+#[tracing::instrument(skip(con))]
 pub async fn get_by_operation_id(
     con: &mut impl GenericClient,
     operation_id: i64,
 ) -> Result<Option<Operation>, tokio_postgres::Error> {
+    let statement = con
+        .prepare_cached("SELECT * FROM operation WHERE operation_id=$1")
+        .await?;
     let result = con
-        .query_opt(
-            "SELECT * FROM operation WHERE operation_id=$1",
-            &[&operation_id],
-        )
+        .query_opt(&statement, &[&operation_id])
         .await?
         .map(|x| x.into());
     Ok(result)
 }
 
-pub async fn get_operations_since(
+// a portal over the operations made on a checkpoint, oldest first, to fetch a batch at a time
+// portals only last as long as the transaction they were bound in
+#[tracing::instrument(skip(tx))]
+pub async fn bind_operations_since(
+    tx: &Transaction<'_>,
+    checkpoint_id: i64,
+) -> Result<Portal, tokio_postgres::Error> {
+    let statement = tx
+        .prepare_cached(
+            "SELECT *
+             FROM operation
+             WHERE checkpoint_id = $1
+             ORDER BY operation_id
+            ",
+        )
+        .await?;
+    tx.bind(&statement, &[&checkpoint_id]).await
+}
+
+// the portal's next max_rows operations; fewer means there are no more
+#[tracing::instrument(skip(tx, portal))]
+pub async fn fetch_operations(
+    tx: &Transaction<'_>,
+    portal: &Portal,
+    max_rows: i32,
+) -> Result<Vec<Operation>, tokio_postgres::Error> {
+    let result = tx
+        .query_portal(portal, max_rows)
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+
+    Ok(result)
+}
+
+// the operations made on a checkpoint up to the given time
+#[tracing::instrument(skip(con))]
+pub async fn get_operations_since_until(
     con: &mut impl GenericClient,
     checkpoint_id: i64,
+    time: i64,
 ) -> Result<Vec<Operation>, tokio_postgres::Error> {
+    let statement = con
+        .prepare_cached(
+            "SELECT *
+             FROM operation
+             WHERE checkpoint_id = $1 AND creation_time <= $2
+             ORDER BY operation_id
+            ",
+        )
+        .await?;
     let result = con
-        .query(
+        .query(&statement, &[&checkpoint_id, &time])
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+
+    Ok(result)
+}
+
+// every op the user has applied after operation_id, across checkpoints, oldest first
+#[tracing::instrument(skip(con))]
+pub async fn get_operations_by_user_id_after(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    operation_id: i64,
+    limit: i64,
+) -> Result<Vec<Operation>, tokio_postgres::Error> {
+    let statement = con
+        .prepare_cached(
+            "SELECT o.*
+             FROM operation o
+             INNER JOIN checkpoint c ON o.checkpoint_id = c.checkpoint_id
+             WHERE c.creator_user_id = $1 AND o.operation_id > $2
+             ORDER BY o.operation_id
+             LIMIT $3
+            ",
+        )
+        .await?;
+    let result = con
+        .query(&statement, &[&creator_user_id, &operation_id, &limit])
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+
+    Ok(result)
+}
+
+// every op the user has applied before operation_id, across checkpoints, newest first
+#[tracing::instrument(skip(con))]
+pub async fn get_operations_by_user_id_before(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    operation_id: i64,
+    limit: i64,
+) -> Result<Vec<Operation>, tokio_postgres::Error> {
+    let statement = con
+        .prepare_cached(
+            "SELECT o.*
+             FROM operation o
+             INNER JOIN checkpoint c ON o.checkpoint_id = c.checkpoint_id
+             WHERE c.creator_user_id = $1 AND o.operation_id < $2
+             ORDER BY o.operation_id DESC
+             LIMIT $3
+            ",
+        )
+        .await?;
+    let result = con
+        .query(&statement, &[&creator_user_id, &operation_id, &limit])
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+
+    Ok(result)
+}
+
+// the operations made on a checkpoint before operation_id, oldest first
+#[tracing::instrument(skip(con))]
+pub async fn get_operations_by_checkpoint_id_before(
+    con: &mut impl GenericClient,
+    checkpoint_id: i64,
+    operation_id: i64,
+) -> Result<Vec<Operation>, tokio_postgres::Error> {
+    let statement = con
+        .prepare_cached(
             "SELECT *
              FROM operation
-             WHERE checkpoint_id = $1
+             WHERE checkpoint_id = $1 AND operation_id < $2
              ORDER BY operation_id
             ",
-            &[&checkpoint_id],
+        )
+        .await?;
+    let result = con
+        .query(&statement, &[&checkpoint_id, &operation_id])
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+
+    Ok(result)
+}
+
+// the ops that finished tasks for the user in [from_time, to_time), across checkpoints, oldest first
+#[tracing::instrument(skip(con))]
+pub async fn get_finish_operations_by_user_id_between(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    from_time: i64,
+    to_time: i64,
+) -> Result<Vec<Operation>, tokio_postgres::Error> {
+    let statement = con
+        .prepare_cached(
+            "SELECT o.*
+             FROM operation o
+             INNER JOIN checkpoint c ON o.checkpoint_id = c.checkpoint_id
+             WHERE c.creator_user_id = $1 AND o.creation_time >= $2 AND o.creation_time < $3
+             AND coalesce(o.jsonbval, o.jsonval::jsonb) -> 'kind'
+                 ?| array['FinishLiveTask', 'FinishManyLiveTasks']
+             ORDER BY o.operation_id
+            ",
+        )
+        .await?;
+    let result = con
+        .query(&statement, &[&creator_user_id, &from_time, &to_time])
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+
+    Ok(result)
+}
+
+// every op the user applied in [from_time, to_time), across checkpoints, oldest first
+#[tracing::instrument(skip(con))]
+pub async fn get_operations_by_user_id_between(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    from_time: i64,
+    to_time: i64,
+) -> Result<Vec<Operation>, tokio_postgres::Error> {
+    let statement = con
+        .prepare_cached(
+            "SELECT o.*
+             FROM operation o
+             INNER JOIN checkpoint c ON o.checkpoint_id = c.checkpoint_id
+             WHERE c.creator_user_id = $1 AND o.creation_time >= $2 AND o.creation_time < $3
+             ORDER BY o.operation_id
+            ",
+        )
+        .await?;
+    let result = con
+        .query(&statement, &[&creator_user_id, &from_time, &to_time])
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+
+    Ok(result)
+}
+
+// the ops that set the user's task estimates before time, across checkpoints, oldest first
+#[tracing::instrument(skip(con))]
+pub async fn get_estimate_operations_by_user_id_before(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    time: i64,
+) -> Result<Vec<Operation>, tokio_postgres::Error> {
+    let statement = con
+        .prepare_cached(
+            "SELECT o.*
+             FROM operation o
+             INNER JOIN checkpoint c ON o.checkpoint_id = c.checkpoint_id
+             WHERE c.creator_user_id = $1 AND o.creation_time < $2
+             AND coalesce(o.jsonbval, o.jsonval::jsonb) -> 'kind' ? 'SetTaskEstimate'
+             ORDER BY o.operation_id
+            ",
+        )
+        .await?;
+    let result = con
+        .query(&statement, &[&creator_user_id, &time])
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+
+    Ok(result)
+}
+
+// the user's ops after operation_id that name the task, across checkpoints, oldest first
+// OverwriteStates aren't included, since they name every task
+#[tracing::instrument(skip(con))]
+pub async fn get_operations_by_task_id_after(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    task_id: &str,
+    operation_id: i64,
+    limit: i64,
+) -> Result<Vec<Operation>, tokio_postgres::Error> {
+    let statement = con
+        .prepare_cached(
+            "SELECT o.*
+             FROM operation o
+             INNER JOIN checkpoint c ON o.checkpoint_id = c.checkpoint_id
+             WHERE c.creator_user_id = $1 AND o.operation_id > $3
+             AND jsonb_path_exists(
+                 coalesce(o.jsonbval, o.jsonval::jsonb),
+                 '$.kind.* ? (
+                     @.id == $id || @.id1 == $id || @.id2 == $id || @.id_ins == $id
+                     || @.id_del == $id || @.id_first == $id || @.id_last == $id
+                     || @.task_id == $id || @.ids[*] == $id || @.tasks[*].id == $id
+                 )',
+                 jsonb_build_object('id', $2::text)
+             )
+             ORDER BY o.operation_id
+             LIMIT $4
+            ",
+        )
+        .await?;
+    let result = con
+        .query(
+            &statement,
+            &[&creator_user_id, &task_id, &operation_id, &limit],
         )
         .await?
         .into_iter()
@@ -77,3 +340,69 @@ pub async fn get_operations_since(
 
     Ok(result)
 }
+
+// the last op that's still only in jsonval
+#[tracing::instrument(skip(con))]
+pub async fn get_last_unmoved_operation_id(
+    con: &mut impl GenericClient,
+) -> Result<Option<i64>, tokio_postgres::Error> {
+    let statement = con
+        .prepare_cached("SELECT MAX(operation_id) FROM operation WHERE jsonbval IS NULL")
+        .await?;
+    let row = con.query_one(&statement, &[]).await?;
+    Ok(row.get(0))
+}
+
+// moves the ops in (after_id, until_id] from jsonval to jsonbval, returning how many there were
+#[tracing::instrument(skip(con))]
+pub async fn move_to_jsonb(
+    con: &mut impl GenericClient,
+    after_id: i64,
+    until_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    let statement = con
+        .prepare_cached(
+            "UPDATE operation
+         SET jsonbval = jsonval::jsonb, jsonval = NULL
+         WHERE operation_id > $1 AND operation_id <= $2 AND jsonbval IS NULL
+        ",
+        )
+        .await?;
+    con.execute(&statement, &[&after_id, &until_id]).await
+}
+
+// returns the number of operations removed; do this before removing the user's checkpoints
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    let statement = con
+        .prepare_cached(
+            "DELETE FROM operation o
+         USING checkpoint c
+         WHERE o.checkpoint_id = c.checkpoint_id AND c.creator_user_id = $1
+        ",
+        )
+        .await?;
+    con.execute(&statement, &[&creator_user_id]).await
+}
+
+// the operation_id of the user's most recent op, across checkpoints
+#[tracing::instrument(skip(con))]
+pub async fn get_last_operation_id_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<Option<i64>, tokio_postgres::Error> {
+    let statement = con
+        .prepare_cached(
+            "SELECT MAX(o.operation_id)
+             FROM operation o
+             INNER JOIN checkpoint c ON o.checkpoint_id = c.checkpoint_id
+             WHERE c.creator_user_id = $1
+            ",
+        )
+        .await?;
+    let row = con.query_one(&statement, &[&creator_user_id]).await?;
+    Ok(row.get(0))
+}
@@ -0,0 +1,91 @@
+use super::db_types::*;
+use super::protocol::Preferences;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for Preference {
+    // select * from preference order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> Preference {
+        Preference {
+            preference_id: row.get("preference_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            jsonval: row.get("jsonval"),
+        }
+    }
+}
+
+// replaces whatever the user had before
+#[tracing::instrument(skip(con, preferences))]
+pub async fn set(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    preferences: &Preferences,
+) -> Result<Preference, tokio_postgres::Error> {
+    let jsonval = serde_json::to_string(preferences).unwrap();
+
+    let row = con
+        .query_one(
+            "INSERT INTO
+             preference(
+                 creator_user_id,
+                 jsonval
+             )
+             VALUES($1, $2)
+             ON CONFLICT (creator_user_id) DO UPDATE
+             SET jsonval = $2
+             RETURNING preference_id, creation_time
+            ",
+            &[&creator_user_id, &jsonval],
+        )
+        .await?;
+
+    // return preference
+    Ok(Preference {
+        preference_id: row.get(0),
+        creation_time: row.get(1),
+        creator_user_id,
+        jsonval,
+    })
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn get_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<Option<Preference>, tokio_postgres::Error> {
+    let result = con
+        .query_opt(
+            "SELECT * FROM preference WHERE creator_user_id=$1",
+            &[&creator_user_id],
+        )
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+// every user who has set their preferences
+#[tracing::instrument(skip(con))]
+pub async fn get_all(
+    con: &mut impl GenericClient,
+) -> Result<Vec<Preference>, tokio_postgres::Error> {
+    let result = con
+        .query("SELECT * FROM preference ORDER BY preference_id", &[])
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM preference WHERE creator_user_id = $1",
+        &[&creator_user_id],
+    )
+    .await
+}
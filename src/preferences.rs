@@ -0,0 +1,114 @@
+// what a user's preferences mean for the features that depend on them
+// users who never set any get the defaults, so the day starts at midnight utc
+use std::str::FromStr;
+
+use chrono::{DateTime, Days, NaiveDate, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use tokio_postgres::GenericClient;
+
+use crate::handlers::{self, AppError};
+use crate::preference_service;
+use crate::protocol::{NotificationPreferences, Preferences};
+
+pub async fn get(con: &mut impl GenericClient, user_id: i64) -> Result<Preferences, AppError> {
+    match preference_service::get_by_user_id(con, user_id)
+        .await
+        .map_err(handlers::report_postgres_err)?
+    {
+        Some(x) => serde_json::from_str(&x.jsonval).map_err(handlers::report_internal_serde_error),
+        None => Ok(Preferences::default()),
+    }
+}
+
+// every user who set preferences; ones that can't be read are logged and left out
+pub async fn get_all(con: &mut impl GenericClient) -> Result<Vec<(i64, Preferences)>, AppError> {
+    Ok(preference_service::get_all(con)
+        .await
+        .map_err(handlers::report_postgres_err)?
+        .into_iter()
+        .filter_map(|x| match serde_json::from_str::<Preferences>(&x.jsonval) {
+            Ok(preferences) => Some((x.creator_user_id, preferences)),
+            Err(e) => {
+                log::error!(
+                    "couldn't read preferences of user {}: {e}",
+                    x.creator_user_id
+                );
+                None
+            }
+        })
+        .collect())
+}
+
+// rejects preferences that couldn't be acted on
+pub fn check(preferences: &Preferences) -> Result<(), AppError> {
+    if Tz::from_str(&preferences.timezone).is_err() || preferences.day_start_hour > 23 {
+        return Err(AppError::BadRequest);
+    }
+    check_notifications(&preferences.notifications)
+}
+
+pub fn check_notifications(notifications: &NotificationPreferences) -> Result<(), AppError> {
+    if notifications.digest_hour > 23 {
+        return Err(AppError::BadRequest);
+    }
+    if let Some(x) = notifications.quiet_hours {
+        if x.start_hour > 23 || x.end_hour > 23 || x.start_hour == x.end_hour {
+            return Err(AppError::BadRequest);
+        }
+    }
+    Ok(())
+}
+
+// the user's date at the given time, which only changes at day_start_hour
+pub fn day_of(preferences: &Preferences, time: DateTime<Utc>) -> NaiveDate {
+    let local = time.with_timezone(&timezone(preferences));
+    (local - chrono::Duration::hours(preferences.day_start_hour as i64)).date_naive()
+}
+
+// when the user's day starts on the given date
+pub fn day_start(preferences: &Preferences, day: NaiveDate) -> DateTime<Utc> {
+    at_hour(preferences, day, preferences.day_start_hour)
+}
+
+// when the user's clock reads hour on the given date
+pub fn at_hour(preferences: &Preferences, day: NaiveDate, hour: u32) -> DateTime<Utc> {
+    let timezone = timezone(preferences);
+    let local = day.and_hms_opt(hour.min(23), 0, 0).unwrap();
+    // if the clocks skip that hour, it's when they land
+    timezone
+        .from_local_datetime(&local)
+        .earliest()
+        .or_else(|| {
+            timezone
+                .from_local_datetime(&(local + chrono::Duration::hours(1)))
+                .earliest()
+        })
+        .map(|x| x.with_timezone(&Utc))
+        .unwrap_or_else(|| local.and_utc())
+}
+
+// when quiet hours that are in effect at time end, or None if they aren't
+pub fn quiet_until(preferences: &Preferences, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let quiet_hours = preferences.notifications.quiet_hours?;
+    let (start, end) = (quiet_hours.start_hour, quiet_hours.end_hour);
+    let local = time.with_timezone(&timezone(preferences)).naive_local();
+    let hour = local.hour();
+    let quiet = match start < end {
+        true => start <= hour && hour < end,
+        false => start <= hour || hour < end,
+    };
+    if !quiet {
+        return None;
+    }
+    // the next time the clock reads end_hour
+    let day = match hour < end {
+        true => local.date(),
+        false => local.date().checked_add_days(Days::new(1))?,
+    };
+    Some(at_hour(preferences, day, end))
+}
+
+// UTC if the time zone isn't one we know, e.g. one set before it was dropped from the tz database
+pub fn timezone(preferences: &Preferences) -> Tz {
+    Tz::from_str(&preferences.timezone).unwrap_or(Tz::UTC)
+}
@@ -0,0 +1,1477 @@
+// types this server exchanges with clients that aren't part of todoproxy_api
+// base ops are passed through untouched, so old clients and old rows in the operation table keep working
+use serde::{Deserialize, Serialize};
+use todoproxy_api::FinishedTask;
+// ops are in todoproxy_state, next to what they do
+pub use todoproxy_state::{ExtOpKind, OpKind};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Op {
+    pub alleged_time: i64,
+    pub kind: OpKind,
+    // set by the server on broadcast: the operation_id this op was stored under
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<i64>,
+    // set by the server on broadcast: checksum of the snapshot after this op was applied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    // set by the client: if an op with the same key was already applied, this one is dropped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+    // set by the client: how the task values in this op are encoded, plain if absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value_encoding: Option<ValueEncoding>,
+}
+
+/// The oldest websocket protocol version the server still speaks; older clients are turned away.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// The newest websocket protocol version the server speaks; newer clients are downshifted to it.
+/// Clients that don't say which version they speak are taken to speak version 1.
+/// Version 2 sessions are issued resume tokens.
+/// Version 3 sessions are sent the live tasks' lanes.
+/// Version 4 sessions are sent the focused task.
+pub const PROTOCOL_VERSION: u32 = 4;
+
+/// How days are written in RolloverDay ops, the history and the calendar, e.g. 2024-05-31.
+pub const DAY_FORMAT: &str = "%Y-%m-%d";
+// iso weeks, which start on monday, e.g. 2026-W42
+pub const WEEK_FORMAT: &str = "%G-W%V";
+
+/// Encrypted task values start with this, so they can be told apart in snapshots too.
+pub const ENCRYPTED_VALUE_PREFIX: &str = "e2e1:";
+
+// clients that encrypt task values end to end mark their ops as Encrypted
+// an encrypted value is ENCRYPTED_VALUE_PREFIX followed by the base64 of the ciphertext,
+// which the server stores and forwards without looking inside
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ValueEncoding {
+    Plain,
+    Encrypted,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Priority {
+    Low,
+    Medium,
+    High,
+}
+
+// tasks are plain values, so a template's tags and priority are written into the value as
+// `#tag` and `!priority`, the way people type them
+// each checklist item becomes a task of its own, after the template's value
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TaskTemplate {
+    pub value: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub priority: Option<Priority>,
+    #[serde(default)]
+    pub checklist: Vec<String>,
+}
+
+// a user's settings; any left out take their default
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preferences {
+    // an IANA time zone name, like America/Los_Angeles
+    pub timezone: String,
+    // the hour (0-23) the user's day starts at, for people who stay up past midnight
+    pub day_start_hour: u32,
+    pub notifications: NotificationPreferences,
+    pub rollover: RolloverPreferences,
+    // the most live tasks each kanban lane may hold, by lane name; lanes left out have no limit
+    // only moves into a lane are held to it, so tasks restored to a full lane still go back
+    pub lane_limits: std::collections::HashMap<String, usize>,
+}
+
+impl Default for Preferences {
+    fn default() -> Preferences {
+        Preferences {
+            timezone: String::from("UTC"),
+            day_start_hour: 0,
+            notifications: NotificationPreferences::default(),
+            rollover: RolloverPreferences::default(),
+            lane_limits: Default::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationPreferences {
+    // push notifications for due reminders; open sessions are sent them regardless
+    pub reminder_push: bool,
+    // push notifications for tasks made from emails sent to the user's alias
+    pub email_push: bool,
+    // a push notification each day at digest_hour with how many live tasks and reminders there are
+    pub digest_push: bool,
+    pub digest_hour: u32,
+    // push notifications that would be sent in these hours are held until they're over
+    pub quiet_hours: Option<QuietHours>,
+}
+
+impl Default for NotificationPreferences {
+    fn default() -> NotificationPreferences {
+        NotificationPreferences {
+            reminder_push: true,
+            email_push: false,
+            digest_push: false,
+            digest_hour: 8,
+            quiet_hours: None,
+        }
+    }
+}
+
+// hours (0-23) on the user's clock; if end_hour is before start_hour they run past midnight
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct QuietHours {
+    pub start_hour: u32,
+    pub end_hour: u32,
+}
+
+// what happens to the finished list when the user's day ends
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RolloverPreferences {
+    // move the finished list into the user's history at the start of each day
+    pub enabled: bool,
+    // put failed tasks back on the live list rather than in the history
+    pub requeue_failed: bool,
+}
+
+// requests from the client that don't change state
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ClientRequest {
+    // the client thinks it has drifted, and wants the current state resent
+    Resync,
+    // the client wants up to limit more finished tasks, older than after_id (the oldest it has),
+    // or from the most recent if it has none
+    RequestMoreFinished {
+        after_id: Option<String>,
+        limit: usize,
+    },
+    // the client started editing task_id, or stopped editing if it's unset
+    // relayed to the user's other sessions, but not stored
+    Editing {
+        task_id: Option<String>,
+    },
+    // the client has a fresh api key for the same user, e.g. because the old one is about to
+    // expire; the session carries on with it instead of reconnecting
+    Reauth {
+        api_key: String,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ClientFrame {
+    Op(Op),
+    Request(ClientRequest),
+}
+
+// frames from the server that aren't ops
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ServerNotice {
+    // checksum of the state the client should have after applying every op up to seq
+    StateChecksum {
+        seq: i64,
+        checksum: String,
+    },
+    // sent to the client whose InsLiveTaskNew was applied, with the id the server picked
+    OpAck {
+        seq: i64,
+        id: String,
+    },
+    // the client's op wasn't applied, and won't be broadcast
+    OpRejected {
+        reason: OpRejectReason,
+    },
+    // the protocol version the session will use, sent first to clients that asked for one
+    ProtocolVersion {
+        version: u32,
+    },
+    // the finished tasks after after_id, oldest last; more is set if there are older ones still
+    FinishedChunk {
+        after_id: Option<String>,
+        tasks: Vec<FinishedTask>,
+        more: bool,
+    },
+    // lets the client reconnect without the api key for valid_for_ms after this session ends,
+    // and be sent only the ops it missed
+    ResumeToken {
+        token: String,
+        valid_for_ms: u64,
+    },
+    // the lane of each live task that isn't in the default lane, by task id
+    // sent with the state, after which MvLiveTaskToLane ops keep it current
+    Lanes {
+        lanes: std::collections::HashMap<String, String>,
+    },
+    // the live task the user is focused on, if any, and since when (in milliseconds since the epoch)
+    // sent with the state, after which SetFocusedTask ops keep it current
+    Focus {
+        live_task_id: Option<String>,
+        since: Option<i64>,
+    },
+    // the session now uses the api key the client sent in its Reauth
+    Reauthed {
+        read_only: bool,
+    },
+    // the api key the client sent in its Reauth isn't valid for this user; the session keeps
+    // using the old one
+    ReauthRejected,
+    // a frame from the client couldn't be handled; the session stays open unless too many in a
+    // row can't be
+    Error {
+        code: FrameErrorCode,
+        detail: String,
+    },
+}
+
+// things the server tells a user's sessions about that aren't changes to their tasks
+// tagged with a type, unlike ops and notices, so clients can skip the types they don't know
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerEvent {
+    // a reminder set on a live task is due
+    Reminder {
+        task_id: String,
+        value: String,
+    },
+    // an op changed how many of the tasks linked to the goal there are, or are completed
+    GoalProgress {
+        goal_id: i64,
+        completed: i64,
+        total: i64,
+    },
+    // checking in on the habit took its streak to this many days, one of the milestones
+    StreakMilestone {
+        habit_id: String,
+        days: u64,
+    },
+    // a message from the deployment's author, e.g. about upcoming maintenance
+    AdminNotice {
+        text: String,
+    },
+    // another of the user's devices opened a websocket; sessions aren't told about themselves
+    // device_id and platform are set if the device sent them in its init message
+    DeviceConnected {
+        session_id: String,
+        label: Option<String>,
+        device_id: Option<String>,
+        platform: Option<String>,
+    },
+    // a device that was connected closed its websocket
+    // any Editing it sent no longer applies
+    DeviceDisconnected {
+        session_id: String,
+    },
+    // the user revoked one of their devices, whose sessions are being closed
+    DeviceRevoked {
+        device_id: String,
+    },
+    // someone is editing task_id, or stopped editing if it's unset
+    Editing {
+        session_id: String,
+        user_id: i64,
+        task_id: Option<String>,
+    },
+    // the server is going away, and will close the session in grace_ms
+    // the client should reconnect, and will be sent to another instance
+    ServerRestarting {
+        grace_ms: u64,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum OpRejectReason {
+    // the op would insert a task with an id that's already live or finished
+    DuplicateId,
+    // there's no deleted task with that id to restore
+    NotInTrash,
+    // the connection's api key may only be used to view state
+    ReadOnly,
+    // a value in an Encrypted op isn't prefixed base64, or a value in a plain op looks encrypted
+    InvalidValueEncoding,
+    // the user has no template with that id
+    NoSuchTemplate,
+    // there's no live or finished task with that id to comment on
+    NoSuchTask,
+    // the user has no comment with that id
+    NoSuchComment,
+    // the user has no goal with that id
+    NoSuchGoal,
+    // the user has no habit with that id
+    NoSuchHabit,
+    // a RolloverDay's or HabitCheckIn's day isn't a yyyy-mm-dd date
+    InvalidDay,
+    // a lane name is empty, too long, or has control characters in it
+    InvalidLane,
+    // the lane already holds as many live tasks as the user allows
+    LaneFull { lane: String, max: usize },
+    // the day, or a later one, was already rolled over
+    AlreadyRolledOver,
+    // a task value is longer than the server allows, in bytes
+    ValueTooLong { max_len: usize },
+    // the op would put more tasks in the live list than the server allows
+    TooManyLiveTasks { max: usize },
+    // the op would put more tasks in the finished list than the server allows
+    TooManyFinishedTasks { max: usize },
+    // the user has applied as many ops today, or stores as many bytes, as the server allows
+    QuotaExceeded { quota: Quota, max: u64 },
+}
+
+// why the server closed a websocket
+// the close frame's reason is a SessionClose as json, so clients can branch on the code
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum SessionCloseCode {
+    // the client fell too far behind under SlowConsumerPolicy::Disconnect
+    SlowConsumer,
+    // the user's account was deleted; clients shouldn't reconnect
+    AccountDeleted,
+    // the client only speaks protocol versions older than MIN_PROTOCOL_VERSION
+    UnsupportedProtocol,
+    // the session's api key stopped being valid; clients should ask the user to log in again
+    ApiKeyRevoked,
+    // the api key or resume token the session was opened with isn't valid
+    AuthFailed,
+    // the user is over their quota or rate limit
+    QuotaExceeded,
+    // the user already has as many sessions open as the server allows
+    TooManyDevices,
+    // the client sent frames the server couldn't make sense of
+    ProtocolError,
+    // the user revoked the device the session was opened from; clients shouldn't reconnect
+    DeviceRevoked,
+    // the server is going away; the client should reconnect, and will be sent to another instance
+    Draining,
+    // the user's state is being reloaded; the client should reconnect
+    Reloading,
+    // the database is too busy; the client should reconnect later
+    Unavailable,
+    ServerError,
+}
+
+impl SessionCloseCode {
+    // the websocket close code sent with it
+    pub fn close_code(self) -> u16 {
+        match self {
+            SessionCloseCode::SlowConsumer => 4000,
+            SessionCloseCode::AccountDeleted => 4001,
+            SessionCloseCode::UnsupportedProtocol => 4002,
+            SessionCloseCode::ApiKeyRevoked => 4003,
+            SessionCloseCode::AuthFailed => 4004,
+            SessionCloseCode::QuotaExceeded => 4005,
+            SessionCloseCode::TooManyDevices => 4006,
+            SessionCloseCode::ProtocolError => 4007,
+            SessionCloseCode::DeviceRevoked => 4008,
+            // the standard codes, which clients that don't know these already handle
+            SessionCloseCode::Draining | SessionCloseCode::Reloading => 1012,
+            SessionCloseCode::Unavailable => 1013,
+            SessionCloseCode::ServerError => 1011,
+        }
+    }
+}
+
+// close reasons are limited to 123 bytes, so detail is kept short
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionClose {
+    pub code: SessionCloseCode,
+    pub detail: String,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum FrameErrorCode {
+    // the frame isn't a text frame, or isn't json for a ClientFrame the server knows
+    InvalidFrame,
+    // the frame is one the server knows, but can't act on as sent
+    BadRequest,
+    NotFound,
+    TooManyRequests,
+    QuotaExceeded,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum Quota {
+    // ops applied each day, reset at midnight utc
+    OpsPerDay,
+    // bytes of task values and attachments
+    StorageBytes,
+}
+
+pub mod request {
+    use serde::{Deserialize, Serialize};
+
+    // restricts the given api key to viewing state, permanently
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct ApiKeyMakeReadOnly {
+        pub api_key: String,
+    }
+
+    // the query string of a websocket connection
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct WebsocketInit {
+        // may be left out when resuming
+        #[serde(default)]
+        pub api_key: String,
+        // only send this many of the most recent finished tasks up front, and the rest on request
+        // checksums cover the whole finished list, so sessions that set this aren't sent them
+        pub finished_limit: Option<usize>,
+        // the newest protocol version the client speaks; 1 if absent
+        pub protocol_version: Option<u32>,
+        // the token from a previous session's ResumeToken notice
+        // if it's expired or unknown the api key is used instead
+        pub resume_token: Option<String>,
+        // the seq of the last op the client applied, so it's only sent the ops after it
+        pub resume_seq: Option<i64>,
+        // shown to the user's other devices, e.g. "laptop"
+        pub device_label: Option<String>,
+        // an id the client picks once and keeps, so its sessions are remembered as one device
+        // the user can see and revoke the devices that sent one
+        pub device_id: Option<String>,
+        // what the device runs on, e.g. "android" or "firefox"
+        pub device_platform: Option<String>,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct ApplyOp {
+        pub api_key: String,
+        pub op: crate::protocol::Op,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct PollTaskUpdates {
+        pub api_key: String,
+        // the seq of the last op the client has; 0 if it has nothing yet
+        pub since_seq: i64,
+        // how long to wait for a new op, if there isn't one already
+        pub timeout_secs: Option<u64>,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminBackup {
+        pub api_key: String,
+    }
+
+    // the backup itself is the request body
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminRestore {
+        pub api_key: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AccountDelete {
+        pub api_key: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct State {
+        pub api_key: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct StateAt {
+        pub api_key: String,
+        // milliseconds since the epoch
+        pub timestamp: i64,
+    }
+
+    // the fields of a browser's PushSubscription, as given by its toJSON()
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct PushSubscribe {
+        pub api_key: String,
+        pub endpoint: String,
+        pub p256dh: String,
+        pub auth: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct PushUnsubscribe {
+        pub api_key: String,
+        pub endpoint: String,
+    }
+
+    // setting a task's reminder again replaces it, which is how reminders are snoozed
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct ReminderSet {
+        pub api_key: String,
+        pub task_id: String,
+        // milliseconds since the epoch
+        pub remind_time: i64,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct ReminderClear {
+        pub api_key: String,
+        pub task_id: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct ReminderView {
+        pub api_key: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct QuickAdd {
+        pub api_key: String,
+        // e.g. "pay rent tomorrow 5pm #finance !high"
+        pub text: String,
+        // the user's offset from utc, for reading dates and times
+        // if absent, the time zone in the user's preferences is used
+        pub utc_offset_mins: Option<i32>,
+        pub idempotency_key: Option<String>,
+    }
+
+    // a token other services can post to, to create live tasks
+    // source is put in front of the tasks it creates; max_per_hour defaults to 60
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct WebhookNew {
+        pub api_key: String,
+        pub source: Option<String>,
+        pub max_per_hour: Option<i64>,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct WebhookView {
+        pub api_key: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct WebhookDelete {
+        pub api_key: String,
+        pub webhook_id: i64,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct EmailAliasNew {
+        pub api_key: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct EmailAliasView {
+        pub api_key: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct EmailAliasDelete {
+        pub api_key: String,
+        pub email_alias_id: i64,
+    }
+
+    // links the repo, or changes the token and settings of a repo that's already linked
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct GithubLinkNew {
+        pub api_key: String,
+        // owner/name
+        pub repo: String,
+        // a personal access token that can read and write the repo's issues
+        pub token: String,
+        // whether finishing a task closes its issue, rather than commenting on it; defaults to true
+        pub close_issues: Option<bool>,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct GithubLinkView {
+        pub api_key: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct GithubLinkDelete {
+        pub api_key: String,
+        pub github_link_id: i64,
+    }
+
+    // what's posted to a webhook, as json or a form
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct HookPost {
+        pub value: String,
+        pub idempotency_key: Option<String>,
+    }
+
+    // tasks between two of the user's days, inclusive (yyyy-mm-dd)
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct TaskCalendar {
+        pub api_key: String,
+        pub from: String,
+        pub to: String,
+    }
+
+    // remaining estimated effort at the end of each of the user's days, inclusive (yyyy-mm-dd)
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct Burndown {
+        pub api_key: String,
+        pub from: String,
+        pub to: String,
+    }
+
+    // the user's week in review; week is like 2026-W42
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct Review {
+        pub api_key: String,
+        pub week: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct TemplateNew {
+        pub api_key: String,
+        pub name: String,
+        pub template: crate::protocol::TaskTemplate,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct TemplateEdit {
+        pub api_key: String,
+        pub template_id: i64,
+        pub name: String,
+        pub template: crate::protocol::TaskTemplate,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct TemplateView {
+        pub api_key: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct TemplateDelete {
+        pub api_key: String,
+        pub template_id: i64,
+    }
+
+    // reserves space for a file on a live or finished task, to be uploaded to the returned url
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AttachmentNew {
+        pub api_key: String,
+        pub task_id: String,
+        pub filename: String,
+        pub content_type: String,
+        // in bytes
+        pub size: i64,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AttachmentView {
+        pub api_key: String,
+        pub task_id: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AttachmentDelete {
+        pub api_key: String,
+        pub attachment_id: i64,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct PreferencesView {
+        pub api_key: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct PreferencesSet {
+        pub api_key: String,
+        pub preferences: crate::protocol::Preferences,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct NotificationPreferencesView {
+        pub api_key: String,
+    }
+
+    // replaces only the user's notification preferences
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct NotificationPreferencesSet {
+        pub api_key: String,
+        pub notifications: crate::protocol::NotificationPreferences,
+    }
+
+    // a page of a task's comments, oldest first
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct CommentView {
+        pub api_key: String,
+        pub task_id: String,
+        // the id of the last comment on the previous page
+        pub after_id: Option<String>,
+        pub limit: Option<i64>,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct TaskOpsView {
+        pub api_key: String,
+        pub task_id: String,
+        // the seq of the last op on the previous page
+        pub after_seq: Option<i64>,
+        pub limit: Option<i64>,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct GoalNew {
+        pub api_key: String,
+        pub name: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct GoalEdit {
+        pub api_key: String,
+        pub goal_id: i64,
+        pub name: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct GoalView {
+        pub api_key: String,
+    }
+
+    // the tasks linked to the goal are unlinked
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct GoalDelete {
+        pub api_key: String,
+        pub goal_id: i64,
+    }
+
+    // the user's habits, with the days they were done on from since (yyyy-mm-dd) on, or the last
+    // few weeks if since is unset
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct HabitView {
+        pub api_key: String,
+        pub since: Option<String>,
+    }
+
+    // what a dashboard widget shows, with the first limit live tasks
+    // the api key has to be read only
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct Widget {
+        pub api_key: String,
+        pub limit: Option<usize>,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct DeviceView {
+        pub api_key: String,
+    }
+
+    // closes the device's sessions, and keeps it from connecting again
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct DeviceRevoke {
+        pub api_key: String,
+        pub device_id: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct FocusView {
+        pub api_key: String,
+    }
+
+    // a page of the user's activity feed, newest first
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct ActivityView {
+        pub api_key: String,
+        // the first_seq of the last entry on the previous page
+        pub before_seq: Option<i64>,
+        pub limit: Option<i64>,
+    }
+
+    // a page of the user's audit log, newest first; filters that are left out match everything
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AuditView {
+        pub api_key: String,
+        // the id of the last event on the previous page
+        pub before_id: Option<i64>,
+        pub kind: Option<String>,
+        pub since_time: Option<i64>,
+        pub until_time: Option<i64>,
+        pub limit: Option<i64>,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct QuotaView {
+        pub api_key: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct TrashView {
+        pub api_key: String,
+    }
+
+    // the tasks that were rolled over at the end of day (yyyy-mm-dd)
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct HistoryView {
+        pub api_key: String,
+        pub day: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminUsersView {
+        pub api_key: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminSessionsView {
+        pub api_key: String,
+    }
+
+    // forgets only the user's keys if there's a user_id, otherwise every key
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminAuthCacheClear {
+        pub api_key: String,
+        pub user_id: Option<i64>,
+    }
+
+    // shown to the user's sessions, or every session on this instance if there's no user_id
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminNotice {
+        pub api_key: String,
+        pub user_id: Option<i64>,
+        pub text: String,
+    }
+
+    // starts draining this instance
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminDrain {
+        pub api_key: String,
+    }
+
+    // admin_user_id may use the admin api for the tenant's users
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminTenantNew {
+        pub api_key: String,
+        pub name: String,
+        pub admin_user_id: i64,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminTenantView {
+        pub api_key: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminTenantDelete {
+        pub api_key: String,
+        pub tenant_id: i64,
+    }
+
+    // no tenant_id moves the user back to the deployment itself
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminTenantUserSet {
+        pub api_key: String,
+        pub user_id: i64,
+        pub tenant_id: Option<i64>,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminSettingsView {
+        pub api_key: String,
+    }
+
+    // rereads this instance's settings file
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminSettingsReload {
+        pub api_key: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminMetricsView {
+        pub api_key: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminCheckpoint {
+        pub api_key: String,
+        pub user_id: i64,
+        // also shut down the user's worker, disconnecting all their sessions
+        #[serde(default)]
+        pub evict: bool,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminDeadJobsView {
+        pub api_key: String,
+        // the most jobs to return, most recently queued first
+        pub limit: Option<i64>,
+    }
+
+    // queues a dead lettered job to run again, with a fresh set of attempts
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminDeadJobRetry {
+        pub api_key: String,
+        pub job_id: i64,
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct Info {
+        #[serde(flatten)]
+        pub base: todoproxy_api::response::Info,
+        // the server pings this often, and expects a pong within client_timeout_ms
+        pub heartbeat_interval_ms: u64,
+        pub client_timeout_ms: u64,
+        // task values starting with this are end to end encrypted; see ValueEncoding
+        pub encrypted_value_prefix: String,
+        // websocket protocol versions the server speaks, oldest first
+        pub protocol_versions: Vec<u32>,
+        // browsers subscribe to web push with this; unset if the server doesn't send notifications
+        pub vapid_public_key: Option<String>,
+        // the largest file that can be attached to a task; unset if attachments aren't available
+        pub max_attachment_bytes: Option<i64>,
+        // whether users can email tasks to aliases
+        pub email_aliases: bool,
+        // whether users can link github repos
+        pub github_links: bool,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct Health {
+        pub alive: bool,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct Readiness {
+        pub ready: bool,
+        pub database: bool,
+        pub auth_service: bool,
+        // a draining instance isn't ready for new clients, even if it can reach everything
+        pub draining: bool,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct ApplyOp {
+        // false if the op was rejected, or was a duplicate
+        pub applied: bool,
+        // set if the op was applied
+        pub seq: Option<i64>,
+        // the id of the inserted task, if the op inserted one
+        pub id: Option<String>,
+        // set if the op was rejected
+        pub reject_reason: Option<crate::protocol::OpRejectReason>,
+    }
+
+    // how the text was read, and what became of the task it was turned into
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct QuickAdd {
+        #[serde(flatten)]
+        pub op: ApplyOp,
+        pub value: String,
+        pub tags: Vec<String>,
+        pub priority: Option<crate::protocol::Priority>,
+        // the task's reminder is set to this
+        pub deadline: Option<i64>,
+    }
+
+    // the next since_seq is the seq of the last op; no ops means the wait timed out
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct PollTaskUpdates {
+        pub ops: Vec<crate::protocol::Op>,
+    }
+
+    // seq and checksum are the same as a websocket client would get with this state
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct State {
+        pub snapshot: todoproxy_api::StateSnapshot,
+        pub seq: i64,
+        pub checksum: String,
+        // by task id; tasks without comments are left out
+        pub comment_counts: std::collections::HashMap<String, i64>,
+        // by task id; tasks without attachments are left out
+        pub attachments: std::collections::HashMap<String, Vec<Attachment>>,
+        // by task id; live tasks in the default lane are left out
+        pub lanes: std::collections::HashMap<String, String>,
+        // by task id; live tasks without an estimate are left out
+        pub estimates: std::collections::HashMap<String, i64>,
+        // the live task the user is focused on, if any
+        pub focused_task: Option<String>,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct Focus {
+        // the live task the user is focused on, if any, and since when
+        pub live_task_id: Option<String>,
+        pub since: Option<i64>,
+        // milliseconds each task was focused for, by task id, not counting the time since the
+        // focused task was focused; tasks that never were are left out
+        pub totals: std::collections::HashMap<String, i64>,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct Attachment {
+        pub attachment_id: i64,
+        pub task_id: String,
+        pub creation_time: i64,
+        pub filename: String,
+        pub content_type: String,
+        pub size: i64,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AttachmentNew {
+        pub attachment: Attachment,
+        // PUT the file here, with a Content-Length of exactly attachment.size
+        pub upload_url: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AttachmentDownload {
+        pub attachment: Attachment,
+        // expires after a few minutes
+        pub download_url: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct Comment {
+        pub id: String,
+        pub task_id: String,
+        pub creation_time: i64,
+        pub value: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct CommentPage {
+        pub comments: Vec<Comment>,
+        // set if there are newer comments after these
+        pub more: bool,
+    }
+
+    // ops that touched a task, with their seqs
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct TaskOps {
+        pub ops: Vec<crate::protocol::Op>,
+        // set if there are later ops after these
+        pub more: bool,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct ActivityPage {
+        pub entries: Vec<ActivityEntry>,
+        // set if there are earlier entries before these
+        pub more: bool,
+    }
+
+    // something the user did, for people to read; a run of edits to the same task is one entry
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct ActivityEntry {
+        // the seqs of the first and last ops it's made of
+        pub first_seq: i64,
+        pub seq: i64,
+        // when the last op was stored
+        pub time: i64,
+        pub kind: ActivityKind,
+        // the task, for entries about just one
+        pub task_id: Option<String>,
+        // its value at the time, or the value it was edited to; unset if it was already gone
+        pub value: Option<String>,
+        // how many tasks the entry is about
+        pub tasks: usize,
+        // how many ops it's made of
+        pub ops: usize,
+        // e.g. "completed buy milk"
+        pub text: String,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum ActivityKind {
+        Added,
+        Edited,
+        Completed,
+        Failed,
+        Obsoleted,
+        Restored,
+        Deleted,
+        Commented,
+        ClearedFinished,
+        RolledOver,
+        Replaced,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AuditEvent {
+        pub audit_event_id: i64,
+        pub creation_time: i64,
+        // the op's type, e.g. InsLiveTask, or the change, e.g. webhook_new
+        pub kind: String,
+        // the op's seq, or the id (or repo) of what was changed
+        pub detail: Option<String>,
+        // the websocket connection or request it came from; unset for changes the server made
+        pub session_id: Option<String>,
+        pub source_ip: Option<String>,
+    }
+
+    // what the user has used of their quotas; unset maximums are unlimited
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct QuotaUsage {
+        // the utc date the ops were applied on, yyyy-mm-dd
+        pub day: String,
+        pub ops: i64,
+        pub max_ops_per_day: Option<u64>,
+        pub storage_bytes: i64,
+        pub max_storage_bytes: Option<u64>,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AuditPage {
+        pub events: Vec<AuditEvent>,
+        // set if there are older events before these
+        pub more: bool,
+    }
+
+    // reminders that haven't been sent yet
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct Reminder {
+        pub task_id: String,
+        pub remind_time: i64,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct Template {
+        pub template_id: i64,
+        pub creation_time: i64,
+        pub name: String,
+        pub template: crate::protocol::TaskTemplate,
+    }
+
+    // streaks are days in a row the habit was done on; if it hasn't been done today, the streak up to
+    // yesterday counts, since there's still time to keep it going
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct Habit {
+        pub id: String,
+        pub creation_time: i64,
+        pub value: String,
+        // yyyy-mm-dd, oldest first
+        pub check_ins: Vec<String>,
+        pub streak: u64,
+        pub longest_streak: u64,
+    }
+
+    // tasks linked to the goal, including finished ones that were rolled over since
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct Goal {
+        pub goal_id: i64,
+        pub creation_time: i64,
+        pub name: String,
+        pub completed: i64,
+        pub total: i64,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct WidgetTask {
+        pub id: String,
+        pub value: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct Widget {
+        // the user's day, yyyy-mm-dd
+        pub day: String,
+        // the first few live tasks, in order
+        pub live: Vec<WidgetTask>,
+        // how many live tasks there are in all
+        pub live_count: usize,
+        // tasks that succeeded today and are still in the finished list, oldest first
+        pub completed_today: Vec<WidgetTask>,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct Device {
+        pub device_id: String,
+        // when it first connected
+        pub creation_time: i64,
+        pub label: Option<String>,
+        pub platform: Option<String>,
+        // when it last opened a websocket
+        pub last_seen_time: i64,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct DeletedTask {
+        pub id: String,
+        pub value: String,
+        pub deletion_time: i64,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct Webhook {
+        pub webhook_id: i64,
+        pub creation_time: i64,
+        pub source: Option<String>,
+        pub max_per_hour: i64,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct WebhookNew {
+        pub webhook: Webhook,
+        // post to /public/hook/{token}; it can't be shown again
+        pub token: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct EmailAlias {
+        pub email_alias_id: i64,
+        pub creation_time: i64,
+        // mail sent here becomes a live task, with the subject as its value and the body as a comment
+        pub address: String,
+    }
+
+    // the token isn't sent back
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct GithubLink {
+        pub github_link_id: i64,
+        pub creation_time: i64,
+        pub repo: String,
+        // the github user whose assigned issues are synced
+        pub login: String,
+        pub close_issues: bool,
+    }
+
+    // one of the user's days that has tasks due or finished on it; days without any are left out
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct CalendarDay {
+        // yyyy-mm-dd
+        pub day: String,
+        // live tasks with a reminder that day, soonest first
+        pub live: Vec<CalendarLiveTask>,
+        // tasks finished that day, earliest first
+        pub finished: Vec<CalendarFinishedTask>,
+    }
+
+    // one of the user's days, up to today; effort is in the unit the user estimates in
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct BurndownDay {
+        // yyyy-mm-dd
+        pub day: String,
+        // the estimates of the live tasks at the end of the day, or now for today
+        pub remaining: i64,
+        // the estimates of the tasks that succeeded that day
+        pub completed: i64,
+        // live tasks at the end of the day without an estimate, which remaining leaves out
+        pub unestimated: usize,
+    }
+
+    // what the user got done in a week of their days, up to now if it isn't over
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct Review {
+        pub week: String,
+        // yyyy-mm-dd, inclusive
+        pub from: String,
+        pub to: String,
+        // tasks that succeeded during the week, earliest first
+        pub completed: Vec<ReviewCompletedTask>,
+        // tasks still live at the end of the week, in order, which the next one starts with
+        pub carried_over: Vec<ReviewTask>,
+        pub streak: ReviewStreak,
+        // by tag, most completed first; tasks with more than one tag count toward each
+        pub tags: Vec<ReviewTag>,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct ReviewTask {
+        pub id: String,
+        pub value: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct ReviewCompletedTask {
+        pub id: String,
+        // its value when it was finished
+        pub value: String,
+        pub finish_time: i64,
+    }
+
+    // days in a row on which a task succeeded
+    // if the week isn't over and no task has succeeded today, the streak up to yesterday counts,
+    // since there's still time to keep it going
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct ReviewStreak {
+        // as the week started
+        pub before: u64,
+        // as it ended
+        pub after: u64,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct ReviewTag {
+        // unset for tasks without a tag
+        pub tag: Option<String>,
+        pub completed: usize,
+        pub carried_over: usize,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct CalendarLiveTask {
+        pub id: String,
+        pub value: String,
+        pub deadline: i64,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct CalendarFinishedTask {
+        pub id: String,
+        pub value: String,
+        pub status: todoproxy_api::TaskStatus,
+        pub finish_time: i64,
+    }
+
+    // a user with at least one open connection
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminUser {
+        pub user_id: i64,
+        pub connections: usize,
+        pub live_tasks: usize,
+        pub finished_tasks: usize,
+        // length of the snapshot's json serialization
+        pub snapshot_bytes: usize,
+        // when the user last connected, disconnected, or sent an op
+        pub last_activity_time: i64,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminSession {
+        pub connection_id: String,
+        pub user_id: i64,
+        pub connected_time: i64,
+        // when the client last sent us a message
+        pub last_activity_time: i64,
+        pub protocol_version: u32,
+        // how long the client took to answer the last heartbeat; unset until it answers one
+        pub rtt_ms: Option<i64>,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminRestore {
+        pub rows: u64,
+    }
+
+    // a receipt for everything that was removed
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AccountDelete {
+        pub user_id: i64,
+        pub deletion_time: i64,
+        pub checkpoints: u64,
+        pub operations: u64,
+        pub deleted_tasks: u64,
+        pub idempotency_keys: u64,
+        pub read_only_api_keys: u64,
+        pub api_tokens: u64,
+        pub push_subscriptions: u64,
+        pub reminders: u64,
+        pub templates: u64,
+        pub comments: u64,
+        pub attachments: u64,
+        pub preferences: u64,
+        pub archived_tasks: u64,
+        pub rollovers: u64,
+        pub task_lanes: u64,
+        pub webhooks: u64,
+        pub email_aliases: u64,
+        pub github_links: u64,
+        pub external_tasks: u64,
+        pub tenant_users: u64,
+        pub audit_events: u64,
+        pub op_usage: u64,
+        pub task_estimates: u64,
+        pub task_focus: u64,
+        pub goals: u64,
+        pub task_goals: u64,
+        pub habits: u64,
+        pub habit_check_ins: u64,
+        pub notification_digests: u64,
+        pub known_devices: u64,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminAuthCacheClear {
+        // how many api keys were forgotten, on this instance
+        pub entries: usize,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminNotice {
+        // users with a worker on this instance that were sent the notice
+        pub users: usize,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminTenant {
+        pub tenant_id: i64,
+        pub creation_time: i64,
+        pub name: String,
+        pub admin_user_id: i64,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminTenantDelete {
+        // users that went back to belonging to the deployment
+        pub users: u64,
+    }
+
+    // the settings this instance is using
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminSettings {
+        pub settings: crate::settings::Settings,
+    }
+
+    // counted by this instance since it started
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminMetrics {
+        pub checkpoints_written: u64,
+        // the checkpoints' json, before compression
+        pub checkpoint_json_bytes: u64,
+        pub checkpoint_stored_bytes: u64,
+        // json bytes per stored byte; unset until a checkpoint is written
+        pub checkpoint_compression_ratio: Option<f64>,
+        pub pool: PoolMetrics,
+        // unset without a read replica
+        pub read_pool: Option<PoolMetrics>,
+        // jobs that failed too many times to be retried again
+        pub jobs_dead_lettered: u64,
+        // ops sent to the event stream, and ones it couldn't be sent (always 0 without one)
+        pub events_published: u64,
+        pub events_dropped: u64,
+        // heartbeat round trip times of the websockets open on this instance, over those that
+        // have answered one; unset if none have
+        pub session_mean_rtt_ms: Option<f64>,
+        pub session_max_rtt_ms: Option<i64>,
+    }
+
+    // the database connection pool, since this instance started
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct PoolMetrics {
+        pub max_size: usize,
+        // connections open, and how many of them are idle
+        pub size: usize,
+        pub available: usize,
+        // requests waiting for a connection right now
+        pub waiting: usize,
+        pub gets: u64,
+        // how long getting a connection took; unset until one is gotten
+        pub mean_wait_ms: Option<f64>,
+        pub max_wait_ms: f64,
+        // requests that gave up waiting for a connection
+        pub timeouts: u64,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminDrain {
+        // false if the instance was already draining
+        pub started: bool,
+        // websockets open on this instance, which will be closed after the grace period
+        pub sessions: usize,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct AdminCheckpoint {
+        pub checkpoint_id: i64,
+        pub evicted: bool,
+    }
+
+    // a job that kept failing, e.g. a web push or github update that couldn't be delivered
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct DeadJob {
+        pub job_id: i64,
+        pub creation_time: i64,
+        // the job as it was queued, or the raw text if it isn't json
+        pub job: serde_json::Value,
+        pub attempts: i64,
+        pub last_error: Option<String>,
+    }
+}
@@ -0,0 +1,293 @@
+// web push notifications, signed with the deployment's VAPID key so browsers accept them
+// due reminders become one WebPush job per subscription, so failed deliveries are retried,
+// and are also sent as events to the user's sessions on this instance
+// pushes are held until the user's quiet hours are over, by queueing the jobs to run then
+use std::time::Duration;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio_postgres::GenericClient;
+use web_push::{
+    ContentEncoding, IsahcWebPushClient, SubscriptionInfo, VapidSignatureBuilder, WebPushClient,
+    WebPushError, WebPushMessageBuilder,
+};
+
+use crate::db_types::PushSubscription;
+use crate::handlers::{self, AppError};
+use crate::job_queue::{self, Job};
+use crate::protocol::{Preferences, ServerEvent, DAY_FORMAT};
+use crate::user_worker::{self, LoadedState};
+use crate::{
+    notification_digest_service, preferences, push_subscription_service, reminder_service, utils,
+    AppData,
+};
+
+/// How often to look for due reminders.
+const REMINDER_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often to check whether it's time for any user's digest.
+const DIGEST_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+pub struct Pusher {
+    // pem encoded, read once at startup
+    vapid_private_key: Vec<u8>,
+    // base64url encoded, for browsers to subscribe with
+    pub vapid_public_key: String,
+    client: IsahcWebPushClient,
+}
+
+// what the service worker gets, told apart by kind
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PushPayload {
+    Reminder {
+        task_id: String,
+        value: String,
+    },
+    // a task was made from an email sent to the user's alias
+    EmailTask {
+        task_id: String,
+        value: String,
+    },
+    // how many live tasks there are, and how many reminders are still to come today
+    Digest {
+        day: String,
+        live_tasks: usize,
+        reminders: usize,
+    },
+}
+
+impl Pusher {
+    pub fn new(
+        vapid_private_key: Vec<u8>,
+        vapid_public_key: String,
+    ) -> Result<Pusher, WebPushError> {
+        Ok(Pusher {
+            vapid_private_key,
+            vapid_public_key,
+            client: IsahcWebPushClient::new()?,
+        })
+    }
+
+    pub async fn send(
+        &self,
+        subscription: &PushSubscription,
+        payload: &[u8],
+    ) -> Result<(), WebPushError> {
+        let subscription_info = SubscriptionInfo::new(
+            &subscription.endpoint,
+            &subscription.p256dh,
+            &subscription.auth,
+        );
+        let signature =
+            VapidSignatureBuilder::from_pem(&self.vapid_private_key[..], &subscription_info)?
+                .build()?;
+        let mut builder = WebPushMessageBuilder::new(&subscription_info);
+        builder.set_payload(ContentEncoding::Aes128Gcm, payload);
+        builder.set_vapid_signature(signature);
+        self.client.send(builder.build()?).await
+    }
+}
+
+// the push service says the subscription is gone for good
+pub fn is_gone(e: &WebPushError) -> bool {
+    matches!(
+        e,
+        WebPushError::EndpointNotValid { .. } | WebPushError::EndpointNotFound { .. }
+    )
+}
+
+// queues the payload for each of the user's browsers
+pub async fn queue(
+    con: &mut impl GenericClient,
+    user_id: i64,
+    preferences: &Preferences,
+    payload: &PushPayload,
+) -> Result<(), tokio_postgres::Error> {
+    let now = Utc::now();
+    let run_after = preferences::quiet_until(preferences, now)
+        .unwrap_or(now)
+        .timestamp_millis();
+    let payload = serde_json::to_string(payload).unwrap();
+    let subscriptions = push_subscription_service::get_all_by_user_id(con, user_id).await?;
+    for subscription in subscriptions {
+        let job = Job::WebPush {
+            push_subscription_id: subscription.push_subscription_id,
+            payload: payload.clone(),
+        };
+        job_queue::enqueue_at(con, &job, run_after).await?;
+    }
+    Ok(())
+}
+
+// sends every due reminder, for as long as the server runs
+pub async fn dispatch_reminders(data: AppData) {
+    let mut interval = tokio::time::interval(REMINDER_POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        match dispatch_due(&data).await {
+            Ok(0) => {}
+            Ok(n) => log::info!("queued pushes for {n} reminders"),
+            // they're still in the table, so we'll get them next time
+            Err(e) => log::error!("couldn't dispatch reminders: {e}"),
+        }
+    }
+}
+
+// returns the number of reminders that were due
+async fn dispatch_due(data: &AppData) -> Result<usize, AppError> {
+    let con: &mut tokio_postgres::Client =
+        &mut *data.pool.get().await.map_err(handlers::report_pool_err)?;
+    // reminders are only removed if their pushes are queued
+    let mut tx = con
+        .transaction()
+        .await
+        .map_err(handlers::report_postgres_err)?;
+    let reminders = reminder_service::take_due(&mut tx, utils::current_time_millis())
+        .await
+        .map_err(handlers::report_postgres_err)?;
+
+    // the task's current value, from its own connection, since loading may write a first checkpoint
+    let state_con: &mut deadpool_postgres::Client =
+        &mut data.pool.get().await.map_err(handlers::report_pool_err)?;
+
+    let due = reminders.len();
+    let mut events = vec![];
+    for reminder in reminders {
+        let LoadedState { snapshot, .. } =
+            user_worker::load_state(state_con, reminder.creator_user_id).await?;
+        // finished and deleted tasks don't need reminding
+        let value = match snapshot.live.into_iter().find(|x| x.id == reminder.task_id) {
+            Some(task) => task.value,
+            None => continue,
+        };
+        events.push((
+            reminder.creator_user_id,
+            ServerEvent::Reminder {
+                task_id: reminder.task_id.clone(),
+                value: value.clone(),
+            },
+        ));
+
+        // there's no way to send them, so connected sessions are all that get the reminder
+        if data.push.is_none() {
+            continue;
+        }
+        let preferences = preferences::get(&mut tx, reminder.creator_user_id).await?;
+        if !preferences.notifications.reminder_push {
+            continue;
+        }
+        let payload = PushPayload::Reminder {
+            task_id: reminder.task_id,
+            value,
+        };
+        queue(&mut tx, reminder.creator_user_id, &preferences, &payload)
+            .await
+            .map_err(handlers::report_postgres_err)?;
+    }
+    tx.commit().await.map_err(handlers::report_postgres_err)?;
+
+    // only once they can't be taken again
+    for (user_id, event) in events {
+        let worker = data
+            .user_worker_data
+            .get(&user_id)
+            .map(|x| x.value().clone());
+        if let Some(worker) = worker {
+            worker.send_event(event).await;
+        }
+    }
+    Ok(due)
+}
+
+// pushes a digest to each user who turned them on, once a day at the hour they picked
+pub async fn send_digests(data: AppData) {
+    let mut interval = tokio::time::interval(DIGEST_INTERVAL);
+    loop {
+        interval.tick().await;
+        let result: Result<Vec<(i64, Preferences)>, AppError> = try {
+            let con: &mut tokio_postgres::Client =
+                &mut *data.pool.get().await.map_err(handlers::report_pool_err)?;
+            let mut users = preferences::get_all(&mut *con).await?;
+            users.retain(|(_, preferences)| preferences.notifications.digest_push);
+            users
+        };
+        let users = match result {
+            Ok(users) => users,
+            // we'll get them next time
+            Err(e) => {
+                log::error!("couldn't find users to send digests to: {e}");
+                continue;
+            }
+        };
+        let mut sent = 0;
+        for (user_id, preferences) in users {
+            match send_digest(&data, user_id, &preferences).await {
+                Ok(true) => sent += 1,
+                Ok(false) => {}
+                Err(e) => log::error!("couldn't send a digest to user {user_id}: {e}"),
+            }
+        }
+        if sent > 0 {
+            log::info!("queued digests for {sent} users");
+        }
+    }
+}
+
+// returns whether the digest was queued; it isn't if it's too early, or one was already sent today
+async fn send_digest(
+    data: &AppData,
+    user_id: i64,
+    preferences: &Preferences,
+) -> Result<bool, AppError> {
+    let now = Utc::now();
+    // the date on the user's clock, rather than their day, since digest_hour is on it too
+    let today = now
+        .with_timezone(&preferences::timezone(preferences))
+        .date_naive();
+    let digest_time =
+        preferences::at_hour(preferences, today, preferences.notifications.digest_hour);
+    if now < digest_time {
+        return Ok(false);
+    }
+    let end_time = preferences::at_hour(
+        preferences,
+        today.succ_opt().ok_or(AppError::InternalServerError)?,
+        0,
+    );
+
+    let con: &mut tokio_postgres::Client =
+        &mut *data.pool.get().await.map_err(handlers::report_pool_err)?;
+    // the digest is only recorded as sent if its pushes are queued
+    let mut tx = con
+        .transaction()
+        .await
+        .map_err(handlers::report_postgres_err)?;
+    let day = today.format(DAY_FORMAT).to_string();
+    let advanced = notification_digest_service::advance(&mut tx, user_id, &day)
+        .await
+        .map_err(handlers::report_postgres_err)?;
+    if advanced.is_none() {
+        return Ok(false);
+    }
+    let reminders = reminder_service::get_all_by_user_id(&mut tx, user_id)
+        .await
+        .map_err(handlers::report_postgres_err)?
+        .into_iter()
+        .filter(|x| x.remind_time < end_time.timestamp_millis())
+        .count();
+    // from its own connection, since loading may write a first checkpoint
+    let state_con: &mut deadpool_postgres::Client =
+        &mut data.pool.get().await.map_err(handlers::report_pool_err)?;
+    let LoadedState { snapshot, .. } = user_worker::load_state(state_con, user_id).await?;
+    let payload = PushPayload::Digest {
+        day,
+        live_tasks: snapshot.live.len(),
+        reminders,
+    };
+    queue(&mut tx, user_id, preferences, &payload)
+        .await
+        .map_err(handlers::report_postgres_err)?;
+    tx.commit().await.map_err(handlers::report_postgres_err)?;
+    Ok(true)
+}
@@ -0,0 +1,126 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for PushSubscription {
+    // select * from push_subscription order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> PushSubscription {
+        PushSubscription {
+            push_subscription_id: row.get("push_subscription_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            endpoint: row.get("endpoint"),
+            p256dh: row.get("p256dh"),
+            auth: row.get("auth"),
+        }
+    }
+}
+
+// a browser that subscribes again may have new keys, or belong to a different user now
+#[tracing::instrument(skip(con, p256dh, auth))]
+pub async fn add(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    endpoint: String,
+    p256dh: String,
+    auth: String,
+) -> Result<PushSubscription, tokio_postgres::Error> {
+    let row = con
+        .query_one(
+            "INSERT INTO
+             push_subscription(
+                 creator_user_id,
+                 endpoint,
+                 p256dh,
+                 auth
+             )
+             VALUES($1, $2, $3, $4)
+             ON CONFLICT (endpoint) DO UPDATE
+             SET creator_user_id = $1, p256dh = $3, auth = $4
+             RETURNING push_subscription_id, creation_time
+            ",
+            &[&creator_user_id, &endpoint, &p256dh, &auth],
+        )
+        .await?;
+
+    // return push subscription
+    Ok(PushSubscription {
+        push_subscription_id: row.get(0),
+        creation_time: row.get(1),
+        creator_user_id,
+        endpoint,
+        p256dh,
+        auth,
+    })
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn get_by_push_subscription_id(
+    con: &mut impl GenericClient,
+    push_subscription_id: i64,
+) -> Result<Option<PushSubscription>, tokio_postgres::Error> {
+    let result = con
+        .query_opt(
+            "SELECT * FROM push_subscription WHERE push_subscription_id=$1",
+            &[&push_subscription_id],
+        )
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn get_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<Vec<PushSubscription>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT * FROM push_subscription WHERE creator_user_id=$1",
+            &[&creator_user_id],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// returns the number of subscriptions removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_by_endpoint(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    endpoint: &str,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM push_subscription WHERE creator_user_id = $1 AND endpoint = $2",
+        &[&creator_user_id, &endpoint],
+    )
+    .await
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn remove(
+    con: &mut impl GenericClient,
+    push_subscription_id: i64,
+) -> Result<(), tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM push_subscription WHERE push_subscription_id = $1",
+        &[&push_subscription_id],
+    )
+    .await?;
+    Ok(())
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM push_subscription WHERE creator_user_id = $1",
+        &[&creator_user_id],
+    )
+    .await
+}
@@ -0,0 +1,303 @@
+// parses what people type into a quick add box, like "pay rent tomorrow 5pm #finance !high"
+// tags and priority stay in the value, written the same way templates write them, and the words
+// that made up the deadline are removed
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Weekday};
+
+use crate::protocol::Priority;
+
+/// The time of day a deadline with a date but no time falls on.
+const DEFAULT_DEADLINE_HOUR: u32 = 9;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParsedTask {
+    pub value: String,
+    pub tags: Vec<String>,
+    pub priority: Option<Priority>,
+    // milliseconds since the epoch
+    pub deadline: Option<i64>,
+}
+
+fn parse_priority(word: &str) -> Option<Priority> {
+    match word.to_ascii_lowercase().as_str() {
+        "low" => Some(Priority::Low),
+        "med" | "medium" => Some(Priority::Medium),
+        "high" => Some(Priority::High),
+        _ => None,
+    }
+}
+
+fn parse_weekday(word: &str) -> Option<Weekday> {
+    match word {
+        "mon" | "monday" => Some(Weekday::Mon),
+        "tue" | "tues" | "tuesday" => Some(Weekday::Tue),
+        "wed" | "wednesday" => Some(Weekday::Wed),
+        "thu" | "thurs" | "thursday" => Some(Weekday::Thu),
+        "fri" | "friday" => Some(Weekday::Fri),
+        "sat" | "saturday" => Some(Weekday::Sat),
+        "sun" | "sunday" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+// today, tomorrow, a weekday (the next one after today), or yyyy-mm-dd
+fn parse_date(word: &str, today: NaiveDate) -> Option<NaiveDate> {
+    match word {
+        "today" => return Some(today),
+        "tomorrow" | "tmrw" | "tmr" => return today.succ_opt(),
+        _ => {}
+    }
+    if let Some(weekday) = parse_weekday(word) {
+        let days =
+            (weekday.num_days_from_monday() + 7 - today.weekday().num_days_from_monday()) % 7;
+        let days = if days == 0 { 7 } else { days };
+        return today.checked_add_signed(Duration::days(days as i64));
+    }
+    NaiveDate::parse_from_str(word, "%Y-%m-%d").ok()
+}
+
+// 5pm, 5:30pm, 17:00, noon or midnight
+fn parse_time(word: &str) -> Option<NaiveTime> {
+    match word {
+        "noon" => return NaiveTime::from_hms_opt(12, 0, 0),
+        "midnight" => return NaiveTime::from_hms_opt(0, 0, 0),
+        _ => {}
+    }
+    let (clock, pm) = if let Some(clock) = word.strip_suffix("am") {
+        (clock, Some(false))
+    } else if let Some(clock) = word.strip_suffix("pm") {
+        (clock, Some(true))
+    } else {
+        (word, None)
+    };
+    let (hour, minute) = match clock.split_once(':') {
+        Some((hour, minute)) if minute.len() == 2 => (hour, minute.parse::<u32>().ok()?),
+        Some(_) => return None,
+        // a bare number is a quantity, not a time
+        None if pm.is_some() => (clock, 0),
+        None => return None,
+    };
+    if hour.is_empty() || hour.len() > 2 {
+        return None;
+    }
+    let hour = hour.parse::<u32>().ok()?;
+    let hour = match pm {
+        Some(_) if hour == 0 || hour > 12 => return None,
+        Some(false) => hour % 12,
+        Some(true) => hour % 12 + 12,
+        None => hour,
+    };
+    NaiveTime::from_hms_opt(hour, minute, 0)
+}
+
+// "in 3 days", "in 2 hours", "in 30 mins" or "in 1 week"; none if the count is too big to be a
+// duration
+fn parse_relative(count: &str, unit: &str) -> Option<Duration> {
+    let count = count.parse::<i64>().ok()?;
+    match unit {
+        "min" | "mins" | "minute" | "minutes" => Duration::try_minutes(count),
+        "h" | "hr" | "hrs" | "hour" | "hours" => Duration::try_hours(count),
+        "d" | "day" | "days" => Duration::try_days(count),
+        "w" | "wk" | "week" | "weeks" => Duration::try_weeks(count),
+        _ => None,
+    }
+}
+
+// only the first date, time and priority count; anything after them is left in the value
+pub fn parse<Tz: TimeZone>(text: &str, now: DateTime<Tz>) -> ParsedTask {
+    let words = text.split_whitespace().collect::<Vec<_>>();
+    let today = now.date_naive();
+
+    let mut kept = vec![];
+    let mut tags = vec![];
+    let mut priority = None;
+    let mut date = None;
+    let mut time = None;
+    let mut relative = None;
+
+    let mut i = 0;
+    while i < words.len() {
+        let word = words[i];
+        let lower = word.to_lowercase();
+        i += 1;
+
+        if let Some(tag) = word.strip_prefix('#').filter(|x| !x.is_empty()) {
+            tags.push(tag.to_owned());
+        } else if let Some(p) = word.strip_prefix('!').and_then(parse_priority) {
+            if priority.is_none() {
+                priority = Some(p);
+            }
+        } else if date.is_none() && relative.is_none() && parse_date(&lower, today).is_some() {
+            date = parse_date(&lower, today);
+            continue;
+        } else if time.is_none() && relative.is_none() && parse_time(&lower).is_some() {
+            time = parse_time(&lower);
+            continue;
+        } else if relative.is_none() && date.is_none() && time.is_none() && lower == "in" {
+            // a deadline past the end of time is left as text
+            let deadline = words
+                .get(i)
+                .zip(words.get(i + 1))
+                .and_then(|(count, unit)| parse_relative(count, &unit.to_lowercase()))
+                .and_then(|duration| now.clone().checked_add_signed(duration));
+            if deadline.is_some() {
+                relative = deadline;
+                i += 2;
+                continue;
+            }
+        } else if time.is_none() && lower == "at" {
+            // "at 5pm", but not "look at this"
+            if let Some(t) = words.get(i).and_then(|x| parse_time(&x.to_lowercase())) {
+                time = Some(t);
+                i += 1;
+                continue;
+            }
+        }
+        kept.push(word);
+    }
+
+    let deadline = match (relative, date, time) {
+        (Some(deadline), _, _) => Some(deadline),
+        (None, Some(date), time) => {
+            let time =
+                time.unwrap_or(NaiveTime::from_hms_opt(DEFAULT_DEADLINE_HOUR, 0, 0).unwrap());
+            now.timezone()
+                .from_local_datetime(&date.and_time(time))
+                .earliest()
+        }
+        // the next time it's that time
+        (None, None, Some(time)) => {
+            let deadline = now
+                .timezone()
+                .from_local_datetime(&today.and_time(time))
+                .earliest();
+            match deadline {
+                Some(deadline) if deadline <= now => today.succ_opt().and_then(|tomorrow| {
+                    now.timezone()
+                        .from_local_datetime(&tomorrow.and_time(time))
+                        .earliest()
+                }),
+                deadline => deadline,
+            }
+        }
+        (None, None, None) => None,
+    };
+
+    ParsedTask {
+        value: kept.join(" "),
+        tags,
+        priority,
+        deadline: deadline.map(|x| x.timestamp_millis()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use chrono_tz::America::New_York;
+
+    use super::*;
+
+    // a friday morning
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 1, 10, 0, 0).unwrap()
+    }
+
+    fn at<Tz: TimeZone>(tz: Tz, y: i32, m: u32, d: u32, h: u32, min: u32) -> Option<i64> {
+        Some(
+            tz.with_ymd_and_hms(y, m, d, h, min, 0)
+                .unwrap()
+                .timestamp_millis(),
+        )
+    }
+
+    #[test]
+    fn keeps_tags_and_priority_in_the_value() {
+        let parsed = parse("pay rent tomorrow 5pm #finance !high !low", now());
+        assert_eq!(
+            parsed,
+            ParsedTask {
+                value: "pay rent #finance !high !low".to_owned(),
+                tags: vec!["finance".to_owned()],
+                priority: Some(Priority::High),
+                deadline: at(Utc, 2024, 3, 2, 17, 0),
+            }
+        );
+    }
+
+    #[test]
+    fn weekday_is_the_next_one_after_today() {
+        assert_eq!(parse("x monday", now()).deadline, at(Utc, 2024, 3, 4, 9, 0));
+        assert_eq!(parse("x thu", now()).deadline, at(Utc, 2024, 3, 7, 9, 0));
+        // today is a friday, so friday is a week away
+        assert_eq!(parse("x friday", now()).deadline, at(Utc, 2024, 3, 8, 9, 0));
+    }
+
+    #[test]
+    fn twelve_am_is_midnight_and_twelve_pm_is_noon() {
+        assert_eq!(parse_time("12am"), NaiveTime::from_hms_opt(0, 0, 0));
+        assert_eq!(parse_time("12pm"), NaiveTime::from_hms_opt(12, 0, 0));
+        assert_eq!(parse_time("12:30am"), NaiveTime::from_hms_opt(0, 30, 0));
+        assert_eq!(parse_time("0am"), None);
+        assert_eq!(parse_time("13pm"), None);
+        assert_eq!(parse_time("12"), None);
+    }
+
+    #[test]
+    fn a_time_that_has_passed_today_is_tomorrow() {
+        assert_eq!(parse("x 9am", now()).deadline, at(Utc, 2024, 3, 2, 9, 0));
+        assert_eq!(parse("x 11am", now()).deadline, at(Utc, 2024, 3, 1, 11, 0));
+    }
+
+    #[test]
+    fn at_is_only_removed_before_a_time() {
+        let parsed = parse("meeting at 5pm", now());
+        assert_eq!(parsed.value, "meeting");
+        assert_eq!(parsed.deadline, at(Utc, 2024, 3, 1, 17, 0));
+
+        let parsed = parse("look at this", now());
+        assert_eq!(parsed.value, "look at this");
+        assert_eq!(parsed.deadline, None);
+    }
+
+    #[test]
+    fn in_is_relative_to_now() {
+        let parsed = parse("call mom in 3 days", now());
+        assert_eq!(parsed.value, "call mom");
+        assert_eq!(parsed.deadline, at(Utc, 2024, 3, 4, 10, 0));
+
+        let parsed = parse("stay in bed", now());
+        assert_eq!(parsed.value, "stay in bed");
+        assert_eq!(parsed.deadline, None);
+    }
+
+    #[test]
+    fn an_out_of_range_duration_is_left_as_text() {
+        // too big for a duration
+        let parsed = parse("call mom in 99999999999999 weeks", now());
+        assert_eq!(parsed.value, "call mom in 99999999999999 weeks");
+        assert_eq!(parsed.deadline, None);
+
+        // a duration, but past the last date chrono can represent
+        let parsed = parse("call mom in 100000000 weeks", now());
+        assert_eq!(parsed.value, "call mom in 100000000 weeks");
+        assert_eq!(parsed.deadline, None);
+    }
+
+    #[test]
+    fn dst_uses_the_earliest_local_time() {
+        let now = New_York.with_ymd_and_hms(2024, 3, 1, 10, 0, 0).unwrap();
+        // clocks go back, so 1:30am happens twice
+        assert_eq!(
+            parse("x 2024-11-03 1:30am", now).deadline,
+            at(Utc, 2024, 11, 3, 5, 30)
+        );
+        // clocks go forward, so 2:30am never happens
+        let parsed = parse("x 2024-03-10 2:30am", now);
+        assert_eq!(parsed.value, "x");
+        assert_eq!(parsed.deadline, None);
+        assert_eq!(
+            parse("x 2024-03-10 3:30am", now).deadline,
+            at(New_York, 2024, 3, 10, 3, 30)
+        );
+    }
+}
@@ -0,0 +1,77 @@
+// quotas keep one user's automation from swamping a shared deployment: each user may apply so many
+// ops a day, and store so many bytes of task values and attachments
+use chrono::Utc;
+use todoproxy_api::{StateSnapshot, WebsocketOpKind};
+
+use crate::protocol::{OpKind, OpRejectReason, Quota, DAY_FORMAT};
+use crate::settings::Settings;
+use crate::task_updates::op_values;
+use crate::{attachment_service, op_usage_service};
+
+// quotas reset at midnight utc, whatever the user's timezone
+pub fn today() -> String {
+    Utc::now().format(DAY_FORMAT).to_string()
+}
+
+// bytes of task values in the user's state
+pub fn state_bytes(snapshot: &StateSnapshot) -> i64 {
+    let live = snapshot.live.iter().map(|x| x.value.len());
+    let finished = snapshot.finished.iter().map(|x| x.value.len());
+    live.chain(finished).sum::<usize>() as i64
+}
+
+// bytes the user stores, in task values and attachments
+pub async fn storage_bytes(
+    con: &mut impl tokio_postgres::GenericClient,
+    user_id: i64,
+    snapshot: &StateSnapshot,
+) -> Result<i64, tokio_postgres::Error> {
+    let attachments = attachment_service::total_size_by_user_id(&mut *con, user_id).await?;
+    Ok(state_bytes(snapshot) + attachments)
+}
+
+// bytes of task values the op replaces
+fn replaced_bytes(snapshot: &StateSnapshot, op: &OpKind) -> i64 {
+    match op {
+        OpKind::Base(WebsocketOpKind::OverwriteState(_)) => state_bytes(snapshot),
+        OpKind::Base(WebsocketOpKind::EditLiveTask { id, .. }) => snapshot
+            .live
+            .iter()
+            .find(|x| &x.id == id)
+            .map_or(0, |x| x.value.len() as i64),
+        _ => 0,
+    }
+}
+
+// counts the op against today's usage, unless it would take the user over a quota
+// must run in the op's transaction, so ops that end up rejected aren't counted
+// like the task limits, storage that's already over quota may still shrink
+pub async fn check(
+    con: &mut impl tokio_postgres::GenericClient,
+    user_id: i64,
+    snapshot: &StateSnapshot,
+    op: &OpKind,
+    settings: &Settings,
+) -> Result<Result<(), OpRejectReason>, tokio_postgres::Error> {
+    // usage is counted even without a quota, so it can be viewed
+    let ops = op_usage_service::increment(&mut *con, user_id, &today()).await?;
+    if settings.max_ops_per_day > 0 && ops as u64 > settings.max_ops_per_day {
+        return Ok(Err(OpRejectReason::QuotaExceeded {
+            quota: Quota::OpsPerDay,
+            max: settings.max_ops_per_day,
+        }));
+    }
+
+    let added = op_values(op).into_iter().map(|x| x.len()).sum::<usize>() as i64;
+    if settings.max_storage_bytes > 0 && added > 0 {
+        let stored = storage_bytes(&mut *con, user_id, snapshot).await?;
+        let new_stored = stored - replaced_bytes(snapshot, op) + added;
+        if new_stored as u64 > settings.max_storage_bytes && new_stored > stored {
+            return Ok(Err(OpRejectReason::QuotaExceeded {
+                quota: Quota::StorageBytes,
+                max: settings.max_storage_bytes,
+            }));
+        }
+    }
+    Ok(Ok(()))
+}
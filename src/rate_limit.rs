@@ -0,0 +1,140 @@
+// requests over http are limited per api key, in fixed windows counted by each instance
+// handlers are checked when they look up the api key, and the middleware reports how much of the
+// window is left in RateLimit-* headers (and Retry-After, once it's used up)
+use std::cell::RefCell;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue, RETRY_AFTER};
+use actix_web::http::StatusCode;
+use actix_web::Error;
+use dashmap::DashMap;
+
+use crate::handlers::AppError;
+use crate::utils;
+
+tokio::task_local! {
+    static USAGE: RefCell<Option<Usage>>;
+}
+
+// the limit and window are passed in, since they can be reloaded
+pub struct RateLimiter {
+    // requests made in the current window, by api key hash
+    windows: DashMap<String, Window>,
+}
+
+struct Window {
+    start: Instant,
+    count: u32,
+}
+
+// what the headers of the response say
+#[derive(Clone, Debug)]
+struct Usage {
+    limit: u32,
+    remaining: u32,
+    reset: Duration,
+}
+
+impl RateLimiter {
+    pub fn new() -> RateLimiter {
+        RateLimiter {
+            windows: DashMap::new(),
+        }
+    }
+
+    // counts a request made with the api key, unless it already used up this window
+    // requests that aren't over http (grpc, background jobs) aren't counted
+    pub fn check(
+        &self,
+        api_key: &str,
+        max_requests: u32,
+        window_len: Duration,
+    ) -> Result<(), AppError> {
+        if USAGE.try_with(|_| ()).is_err() {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let mut window = self
+            .windows
+            .entry(utils::sha256_hex(api_key.as_bytes()))
+            .or_insert(Window {
+                start: now,
+                count: 0,
+            });
+        if now.duration_since(window.start) >= window_len {
+            *window = Window {
+                start: now,
+                count: 0,
+            };
+        }
+        let allowed = window.count < max_requests;
+        if allowed {
+            window.count += 1;
+        }
+        report(
+            max_requests,
+            max_requests.saturating_sub(window.count),
+            window_len.saturating_sub(now.duration_since(window.start)),
+        );
+        if allowed {
+            Ok(())
+        } else {
+            Err(AppError::TooManyRequests)
+        }
+    }
+
+    // forgets windows that are over, so keys that stopped being used don't take up memory
+    pub fn purge(&self, window_len: Duration) {
+        let now = Instant::now();
+        self.windows
+            .retain(|_, x| now.duration_since(x.start) < window_len);
+    }
+}
+
+// for limits counted elsewhere (like webhooks'), so the response has the same headers
+pub fn report(limit: u32, remaining: u32, reset: Duration) {
+    let usage = Usage {
+        limit,
+        remaining,
+        reset,
+    };
+    let _ = USAGE.try_with(|x| *x.borrow_mut() = Some(usage));
+}
+
+pub fn middleware<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    let fut = srv.call(req);
+    USAGE.scope(RefCell::new(None), async move {
+        let mut res = fut.await?;
+        let usage = USAGE.with(|x| x.borrow().clone());
+        if let Some(usage) = usage {
+            // rounded up, so clients don't come back a moment too early
+            let reset = usage.reset.as_secs() + u64::from(usage.reset.subsec_nanos() > 0);
+            let limited = res.status() == StatusCode::TOO_MANY_REQUESTS;
+            let headers = res.headers_mut();
+            headers.insert(
+                HeaderName::from_static("ratelimit-limit"),
+                HeaderValue::from(usage.limit),
+            );
+            headers.insert(
+                HeaderName::from_static("ratelimit-remaining"),
+                HeaderValue::from(usage.remaining),
+            );
+            headers.insert(
+                HeaderName::from_static("ratelimit-reset"),
+                HeaderValue::from(reset),
+            );
+            if limited {
+                headers.insert(RETRY_AFTER, HeaderValue::from(reset));
+            }
+        }
+        Ok(res)
+    })
+}
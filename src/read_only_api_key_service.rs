@@ -0,0 +1,64 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for ReadOnlyApiKey {
+    // select * from read_only_api_key order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> ReadOnlyApiKey {
+        ReadOnlyApiKey {
+            read_only_api_key_id: row.get("read_only_api_key_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            api_key_hash: row.get("api_key_hash"),
+        }
+    }
+}
+
+// restricting a key that's already restricted is a no-op
+#[tracing::instrument(skip(con, api_key_hash))]
+pub async fn add(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    api_key_hash: String,
+) -> Result<(), tokio_postgres::Error> {
+    con.execute(
+        "INSERT INTO
+         read_only_api_key(
+             creator_user_id,
+             api_key_hash
+         )
+         VALUES($1, $2)
+         ON CONFLICT DO NOTHING
+        ",
+        &[&creator_user_id, &api_key_hash],
+    )
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip(con, api_key_hash))]
+pub async fn get_by_api_key_hash(
+    con: &mut impl GenericClient,
+    api_key_hash: &str,
+) -> Result<Option<ReadOnlyApiKey>, tokio_postgres::Error> {
+    let result = con
+        .query_opt(
+            "SELECT * FROM read_only_api_key WHERE api_key_hash=$1",
+            &[&api_key_hash],
+        )
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM read_only_api_key WHERE creator_user_id = $1",
+        &[&creator_user_id],
+    )
+    .await
+}
@@ -0,0 +1,112 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for Reminder {
+    // select * from reminder order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> Reminder {
+        Reminder {
+            reminder_id: row.get("reminder_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            task_id: row.get("task_id"),
+            remind_time: row.get("remind_time"),
+        }
+    }
+}
+
+// replaces any reminder the task already has, so this is also how reminders are snoozed
+#[tracing::instrument(skip(con))]
+pub async fn set(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    task_id: String,
+    remind_time: i64,
+) -> Result<Reminder, tokio_postgres::Error> {
+    let row = con
+        .query_one(
+            "INSERT INTO
+             reminder(
+                 creator_user_id,
+                 task_id,
+                 remind_time
+             )
+             VALUES($1, $2, $3)
+             ON CONFLICT (creator_user_id, task_id) DO UPDATE
+             SET remind_time = $3
+             RETURNING reminder_id, creation_time
+            ",
+            &[&creator_user_id, &task_id, &remind_time],
+        )
+        .await?;
+
+    // return reminder
+    Ok(Reminder {
+        reminder_id: row.get(0),
+        creation_time: row.get(1),
+        creator_user_id,
+        task_id,
+        remind_time,
+    })
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn get_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<Vec<Reminder>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT * FROM reminder WHERE creator_user_id=$1 ORDER BY remind_time",
+            &[&creator_user_id],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// removes and returns reminders that are due, so each is only sent once
+#[tracing::instrument(skip(con))]
+pub async fn take_due(
+    con: &mut impl GenericClient,
+    now: i64,
+) -> Result<Vec<Reminder>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "DELETE FROM reminder WHERE remind_time <= $1 RETURNING *",
+            &[&now],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// returns the number of reminders removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_by_task_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    task_id: &str,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM reminder WHERE creator_user_id = $1 AND task_id = $2",
+        &[&creator_user_id, &task_id],
+    )
+    .await
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM reminder WHERE creator_user_id = $1",
+        &[&creator_user_id],
+    )
+    .await
+}
@@ -0,0 +1,50 @@
+// every request gets an id, which shows up in log lines, error responses, and websocket close reasons
+// so that a user can report an id and we can find the matching server logs
+use std::future::Future;
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{Error, HttpMessage};
+use tracing::Instrument;
+
+use crate::utils;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+// stored in the request extensions, for handlers that outlive the request (websockets)
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+// id of the request currently being handled, if any
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+pub fn middleware<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    let request_id = utils::random_string();
+    req.extensions_mut().insert(RequestId(request_id.clone()));
+
+    let span = tracing::info_span!("request", request_id = %request_id);
+    let fut = srv.call(req);
+
+    REQUEST_ID.scope(
+        request_id.clone(),
+        async move {
+            let mut res = fut.await?;
+            res.headers_mut().insert(
+                HeaderName::from_static("x-request-id"),
+                HeaderValue::from_str(&request_id).unwrap(),
+            );
+            Ok(res)
+        }
+        .instrument(span),
+    )
+}
@@ -0,0 +1,70 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for Rollover {
+    // select * from rollover order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> Rollover {
+        Rollover {
+            rollover_id: row.get("rollover_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            day: row.get("day"),
+        }
+    }
+}
+
+// records day as the last one rolled over, unless it already was, or a later one was
+// days are yyyy-mm-dd, so they sort as text
+#[tracing::instrument(skip(con))]
+pub async fn advance(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    day: &str,
+) -> Result<Option<Rollover>, tokio_postgres::Error> {
+    let result = con
+        .query_opt(
+            "INSERT INTO
+             rollover(
+                 creator_user_id,
+                 day
+             )
+             VALUES($1, $2)
+             ON CONFLICT (creator_user_id) DO UPDATE
+             SET day = $2
+             WHERE rollover.day < $2
+             RETURNING *
+            ",
+            &[&creator_user_id, &day],
+        )
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn get_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<Option<Rollover>, tokio_postgres::Error> {
+    let result = con
+        .query_opt(
+            "SELECT * FROM rollover WHERE creator_user_id=$1",
+            &[&creator_user_id],
+        )
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM rollover WHERE creator_user_id = $1",
+        &[&creator_user_id],
+    )
+    .await
+}
@@ -0,0 +1,148 @@
+// settings that can be changed without restarting: edit the settings file, then send SIGHUP or call
+// /admin/settings/reload
+// the file only needs the settings that differ from the command line, and settings removed from it
+// go back to what the command line says
+// open websockets keep the heartbeat and revalidate intervals they started with
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+use serde::{Deserialize, Serialize};
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::task_updates::SlowConsumerPolicy;
+use crate::telemetry::{self, LogFilterHandle};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    // which log records are emitted, like RUST_LOG (which is used if it's unset)
+    pub log_filter: Option<String>,
+    // requests an api key may make per window, on each instance (0 for unlimited)
+    pub rate_limit_requests: u32,
+    pub rate_limit_window_secs: u64,
+    pub heartbeat_interval_secs: u64,
+    pub client_timeout_secs: u64,
+    pub api_key_revalidate_interval_secs: u64,
+    pub outbound_high_water_mark: usize,
+    pub slow_consumer_policy: SlowConsumerPolicy,
+    // ops each user's sessions may fall behind before they start missing them
+    // workers that are already running keep the capacity they started with
+    pub broadcast_capacity: usize,
+    // per user (0 for unlimited)
+    pub max_ops_per_day: u64,
+    pub max_storage_bytes: u64,
+    // websockets open at once, on each instance
+    pub max_devices_per_user: usize,
+}
+
+impl Settings {
+    pub fn rate_limit_window(&self) -> Duration {
+        Duration::from_secs(self.rate_limit_window_secs)
+    }
+
+    pub fn heartbeat_interval(&self) -> Duration {
+        Duration::from_secs(self.heartbeat_interval_secs)
+    }
+
+    pub fn client_timeout(&self) -> Duration {
+        Duration::from_secs(self.client_timeout_secs)
+    }
+
+    pub fn api_key_revalidate_interval(&self) -> Duration {
+        Duration::from_secs(self.api_key_revalidate_interval_secs)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.heartbeat_interval_secs == 0 {
+            return Err("heartbeat interval must be at least one second".into());
+        }
+        if self.api_key_revalidate_interval_secs == 0 {
+            return Err("api key revalidate interval must be at least one second".into());
+        }
+        if self.broadcast_capacity == 0 {
+            return Err("broadcast capacity must be at least one op".into());
+        }
+        if self.rate_limit_requests > 0 && self.rate_limit_window_secs == 0 {
+            return Err("rate limit window must be at least one second".into());
+        }
+        if self.outbound_high_water_mark >= self.broadcast_capacity {
+            log::warn!("outbound high water mark isn't below the broadcast capacity; slow websockets will miss ops before the slow consumer policy applies");
+        }
+        if self.heartbeat_interval_secs * 2 > self.client_timeout_secs {
+            log::warn!("heartbeat interval is more than half the client timeout; clients may be disconnected spuriously");
+        }
+        Ok(())
+    }
+}
+
+pub struct RuntimeSettings {
+    // what the command line says
+    base: Settings,
+    // json object overriding some of base
+    file: Option<PathBuf>,
+    log_filter: LogFilterHandle,
+    current: ArcSwap<Settings>,
+}
+
+impl RuntimeSettings {
+    pub fn new(
+        base: Settings,
+        file: Option<PathBuf>,
+        log_filter: LogFilterHandle,
+    ) -> Result<RuntimeSettings, String> {
+        let settings = RuntimeSettings {
+            current: ArcSwap::from_pointee(base.clone()),
+            base,
+            file,
+            log_filter,
+        };
+        settings.reload()?;
+        Ok(settings)
+    }
+
+    pub fn get(&self) -> Arc<Settings> {
+        self.current.load_full()
+    }
+
+    // rereads the settings file; the current settings are kept if it isn't valid
+    pub fn reload(&self) -> Result<Arc<Settings>, String> {
+        let settings = match self.file {
+            Some(ref file) => {
+                let text = std::fs::read_to_string(file)
+                    .map_err(|e| format!("couldn't read {}: {e}", file.display()))?;
+                let overrides: serde_json::Map<String, serde_json::Value> =
+                    serde_json::from_str(&text)
+                        .map_err(|e| format!("couldn't parse {}: {e}", file.display()))?;
+                let mut settings = serde_json::to_value(&self.base).unwrap();
+                settings.as_object_mut().unwrap().extend(overrides);
+                serde_json::from_value(settings)
+                    .map_err(|e| format!("invalid settings in {}: {e}", file.display()))?
+            }
+            None => self.base.clone(),
+        };
+        settings.validate()?;
+        telemetry::set_log_filter(&self.log_filter, settings.log_filter.as_deref())?;
+        let settings = Arc::new(settings);
+        self.current.store(settings.clone());
+        Ok(settings)
+    }
+}
+
+// reloads the settings when the process gets SIGHUP
+pub async fn on_signal(settings: Arc<RuntimeSettings>) {
+    let mut signals = match signal(SignalKind::hangup()) {
+        Ok(signals) => signals,
+        Err(e) => {
+            log::error!("couldn't listen for SIGHUP: {e}");
+            return;
+        }
+    };
+    while signals.recv().await.is_some() {
+        match settings.reload() {
+            Ok(_) => log::info!("got SIGHUP; reloaded settings"),
+            Err(e) => log::error!("got SIGHUP, but couldn't reload settings: {e}"),
+        }
+    }
+}
@@ -0,0 +1,58 @@
+// an optional redis cache of each user's materialized state, so cold connects of users with
+// long op histories don't have to replay all of them
+// the cache is only an optimization: errors are logged and treated as misses
+use redis::AsyncCommands;
+
+use crate::user_worker::LoadedState;
+
+/// Cached states expire after this long, in case an invalidation was missed.
+const SNAPSHOT_CACHE_TTL_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Clone)]
+pub struct SnapshotCache {
+    pub con: redis::aio::MultiplexedConnection,
+}
+
+fn key(user_id: i64) -> String {
+    format!("todoproxy:snapshot:{user_id}")
+}
+
+impl SnapshotCache {
+    pub async fn get(&self, user_id: i64) -> Option<LoadedState> {
+        let mut con = self.con.clone();
+        let jsonval: Option<String> = match con.get(key(user_id)).await {
+            Ok(x) => x,
+            Err(e) => {
+                log::warn!("couldn't read cached snapshot for user {user_id}: {e}");
+                return None;
+            }
+        };
+        match serde_json::from_str(&jsonval?) {
+            Ok(state) => Some(state),
+            Err(e) => {
+                log::warn!("ignoring malformed cached snapshot for user {user_id}: {e}");
+                None
+            }
+        }
+    }
+
+    pub async fn put(&self, user_id: i64, state: &LoadedState) {
+        let mut con = self.con.clone();
+        let jsonval = serde_json::to_string(state).unwrap();
+        let result: Result<(), _> = con
+            .set_ex(key(user_id), jsonval, SNAPSHOT_CACHE_TTL_SECS)
+            .await;
+        if let Err(e) = result {
+            log::warn!("couldn't cache snapshot for user {user_id}: {e}");
+        }
+    }
+
+    // must be called whenever a checkpoint is written for the user
+    pub async fn invalidate(&self, user_id: i64) {
+        let mut con = self.con.clone();
+        let result: Result<(), _> = con.del(key(user_id)).await;
+        if let Err(e) = result {
+            log::warn!("couldn't invalidate cached snapshot for user {user_id}: {e}");
+        }
+    }
+}
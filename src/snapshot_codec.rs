@@ -0,0 +1,83 @@
+// checkpoints are stored as a format byte followed by the state in that format, so the format can
+// change without rewriting the checkpoints already stored
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use todoproxy_api::StateSnapshot;
+
+use crate::db_types::Checkpoint;
+use crate::handlers::{self, AppError};
+
+/// The state's json, compressed with zstd.
+const FORMAT_ZSTD_JSON: u8 = 1;
+
+/// Fast to compress at, and still shrinks json several times over.
+const ZSTD_LEVEL: i32 = 3;
+
+// what this instance has encoded since it started
+static SNAPSHOTS_ENCODED: AtomicU64 = AtomicU64::new(0);
+static JSON_BYTES: AtomicU64 = AtomicU64::new(0);
+static STORED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+pub struct CompressionStats {
+    pub snapshots: u64,
+    // before compression
+    pub json_bytes: u64,
+    // after compression, including the format byte
+    pub stored_bytes: u64,
+}
+
+pub fn stats() -> CompressionStats {
+    CompressionStats {
+        snapshots: SNAPSHOTS_ENCODED.load(Ordering::Relaxed),
+        json_bytes: JSON_BYTES.load(Ordering::Relaxed),
+        stored_bytes: STORED_BYTES.load(Ordering::Relaxed),
+    }
+}
+
+pub fn encode(snapshot: &StateSnapshot) -> Vec<u8> {
+    let json = serde_json::to_vec(snapshot).unwrap();
+    // compressing into memory only fails if zstd can't allocate
+    let compressed = zstd::bulk::compress(&json, ZSTD_LEVEL).unwrap();
+    let mut bytes = Vec::with_capacity(compressed.len() + 1);
+    bytes.push(FORMAT_ZSTD_JSON);
+    bytes.extend(compressed);
+
+    SNAPSHOTS_ENCODED.fetch_add(1, Ordering::Relaxed);
+    JSON_BYTES.fetch_add(json.len() as u64, Ordering::Relaxed);
+    STORED_BYTES.fetch_add(bytes.len() as u64, Ordering::Relaxed);
+    bytes
+}
+
+pub fn decode(bytes: &[u8]) -> Result<StateSnapshot, AppError> {
+    match bytes.split_first() {
+        Some((&FORMAT_ZSTD_JSON, compressed)) => {
+            let json = zstd::stream::decode_all(compressed).map_err(|e| {
+                log::error!("couldn't decompress snapshot: {e}");
+                AppError::InternalServerError
+            })?;
+            serde_json::from_slice(&json).map_err(handlers::report_internal_serde_error)
+        }
+        Some((format, _)) => {
+            log::error!("unknown snapshot format {format}");
+            Err(AppError::InternalServerError)
+        }
+        None => {
+            log::error!("empty snapshot");
+            Err(AppError::InternalServerError)
+        }
+    }
+}
+
+// the state stored in a checkpoint, however it was stored
+pub fn checkpoint_snapshot(checkpoint: &Checkpoint) -> Result<StateSnapshot, AppError> {
+    match (&checkpoint.snapshot, &checkpoint.jsonval) {
+        (Some(snapshot), _) => decode(snapshot),
+        (None, Some(jsonval)) => {
+            serde_json::from_str(jsonval).map_err(handlers::report_internal_serde_error)
+        }
+        (None, None) => {
+            log::error!("checkpoint {} has no state", checkpoint.checkpoint_id);
+            Err(AppError::InternalServerError)
+        }
+    }
+}
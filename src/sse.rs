@@ -0,0 +1,99 @@
+// server-sent events carrying the same ops as the websocket protocol, for networks that kill websockets
+// clients send ops with POST /public/ops/apply instead
+use actix_web::web::Bytes;
+use actix_web::{web, HttpResponse};
+use futures_util::stream;
+use serde::Serialize;
+use todoproxy_api::request::WebsocketInitMessage;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::handlers::{self, AppError};
+use crate::task_updates::SlowConsumerPolicy;
+use crate::user_worker::{self, BroadcastOp, Registration, Update};
+use crate::AppData;
+
+struct SseState {
+    // unregisters from the worker once the client goes away and the stream is dropped
+    registration: Registration,
+    updates_rx: broadcast::Receiver<Update>,
+    // the next op to send, before anything from updates_rx
+    pending: Option<BroadcastOp>,
+    // ops at or below this seq are already reflected in what the client has
+    last_seq_sent: i64,
+    // as it was when the stream started
+    slow_consumer_policy: SlowConsumerPolicy,
+    // comments keep proxies from timing out an idle stream
+    keepalive: tokio::time::Interval,
+}
+
+fn event(name: &str, x: &impl Serialize) -> Bytes {
+    let jsonval = serde_json::to_string(x).unwrap();
+    event_json(name, &jsonval)
+}
+
+fn event_json(name: &str, jsonval: &str) -> Bytes {
+    Bytes::from(format!("event: {name}\ndata: {jsonval}\n\n"))
+}
+
+// start an event stream: an OverwriteState with the current state, then every op applied after it,
+// with server events in between
+#[tracing::instrument(skip_all)]
+pub async fn sse_task_updates(
+    data: web::Data<AppData>,
+    query: web::Query<WebsocketInitMessage>,
+) -> Result<HttpResponse, AppError> {
+    let user_id = handlers::get_user_id_if_api_key_valid(&data, query.into_inner().api_key).await?;
+    let (worker, subscription) = user_worker::connect(&data, user_id).await?;
+
+    let state = SseState {
+        registration: Registration(worker),
+        updates_rx: subscription.updates_rx,
+        last_seq_sent: subscription.initial_op.seq.unwrap_or_default(),
+        pending: Some(BroadcastOp::unserialized(subscription.initial_op)),
+        keepalive: tokio::time::interval(data.settings.get().heartbeat_interval()),
+        slow_consumer_policy: data.settings.get().slow_consumer_policy,
+    };
+
+    let events = stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(op) = state.pending.take() {
+                if let Some(seq) = op.seq {
+                    state.last_seq_sent = seq;
+                }
+                let event = event_json("op", &op.serialized());
+                return Some((Ok::<_, actix_web::Error>(event), state));
+            }
+            tokio::select! {
+                _ = state.keepalive.tick() => {
+                    return Some((Ok(Bytes::from_static(b": keepalive\n\n")), state));
+                }
+                update = state.updates_rx.recv() => match update {
+                    Ok(Update::Event(x)) => return Some((Ok(event("server_event", &x)), state)),
+                    // already included in a resync
+                    Ok(Update::Op(op)) if op.seq.is_some_and(|seq| seq <= state.last_seq_sent) => {}
+                    Ok(Update::Op(op)) => state.pending = Some(op),
+                    // the client reconnects when it can keep up
+                    Err(RecvError::Lagged(_))
+                        if matches!(state.slow_consumer_policy, SlowConsumerPolicy::Disconnect) =>
+                    {
+                        return None
+                    }
+                    // we dropped ops the client needed, so send the current state instead
+                    Err(RecvError::Lagged(_)) => match state.registration.0.resync().await {
+                        Ok(op) => state.pending = Some(BroadcastOp::unserialized(op)),
+                        Err(_) => return None,
+                    },
+                    // the worker was evicted; the client should reconnect
+                    Err(RecvError::Closed) => return None,
+                },
+            }
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("cache-control", "no-cache"))
+        // stop nginx from buffering the stream
+        .insert_header(("x-accel-buffering", "no"))
+        .streaming(events))
+}
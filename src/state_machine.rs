@@ -0,0 +1,326 @@
+// the server's side of the state machine: which ops may be applied; how ops change the state is in
+// todoproxy_state, which the web app shares, so both sides apply every op the same way
+use todoproxy_api::{StateSnapshot, WebsocketOpKind};
+pub use todoproxy_state::{apply_operation, snapshot_checksum};
+
+use crate::protocol::{ExtOpKind, OpKind, OpRejectReason, DAY_FORMAT};
+
+/// Lane names may be at most this many bytes.
+const MAX_LANE_NAME_LEN: usize = 64;
+
+// ops that would make the snapshot inconsistent are rejected before they're stored
+// apply_operation never fails, since ops already in the log have to replay the same way
+pub fn check_operation(snapshot: &StateSnapshot, op: &OpKind) -> Result<(), OpRejectReason> {
+    let inserted_ids = match op {
+        OpKind::Base(WebsocketOpKind::InsLiveTask { id, .. }) => vec![id],
+        OpKind::Ext(ExtOpKind::InsLiveTaskBetween { id, .. }) => vec![id],
+        OpKind::Ext(ExtOpKind::InsLiveTasks { tasks }) => tasks.iter().map(|x| &x.id).collect(),
+        _ => vec![],
+    };
+    for (i, id) in inserted_ids.iter().enumerate() {
+        // or twice by the same op
+        if inserted_ids[..i].contains(id) {
+            return Err(OpRejectReason::DuplicateId);
+        }
+        if snapshot.live.iter().any(|x| &x.id == *id)
+            || snapshot.finished.iter().any(|x| &x.id == *id)
+        {
+            return Err(OpRejectReason::DuplicateId);
+        }
+    }
+    if let OpKind::Ext(
+        ExtOpKind::CommentAdd { task_id, .. } | ExtOpKind::LinkTaskGoal { task_id, .. },
+    ) = op
+    {
+        if !snapshot.live.iter().any(|x| &x.id == task_id)
+            && !snapshot.finished.iter().any(|x| &x.id == task_id)
+        {
+            return Err(OpRejectReason::NoSuchTask);
+        }
+    }
+    if let OpKind::Ext(ExtOpKind::SetFocusedTask {
+        live_task_id: Some(id),
+    }) = op
+    {
+        if !snapshot.live.iter().any(|x| &x.id == id) {
+            return Err(OpRejectReason::NoSuchTask);
+        }
+    }
+    if let OpKind::Ext(ExtOpKind::SetTaskEstimate { id, .. }) = op {
+        if !snapshot.live.iter().any(|x| &x.id == id) {
+            return Err(OpRejectReason::NoSuchTask);
+        }
+    }
+    if let OpKind::Ext(ExtOpKind::MvLiveTaskToLane { id, lane }) = op {
+        if !snapshot.live.iter().any(|x| &x.id == id) {
+            return Err(OpRejectReason::NoSuchTask);
+        }
+        if let Some(lane) = lane {
+            if lane.is_empty()
+                || lane.len() > MAX_LANE_NAME_LEN
+                || lane.chars().any(|c| c.is_control())
+            {
+                return Err(OpRejectReason::InvalidLane);
+            }
+        }
+    }
+    if let OpKind::Ext(ExtOpKind::RolloverDay { day, .. } | ExtOpKind::HabitCheckIn { day, .. }) =
+        op
+    {
+        if chrono::NaiveDate::parse_from_str(day, DAY_FORMAT).is_err() {
+            return Err(OpRejectReason::InvalidDay);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashSet, VecDeque};
+
+    use proptest::collection::vec;
+    use proptest::option;
+    use proptest::prelude::*;
+    use proptest::sample::Index;
+    use todoproxy_api::{FinishedTask, LiveTask, TaskStatus};
+
+    use super::*;
+    use crate::snapshot_codec;
+
+    // few enough that ops often name tasks that exist, and ids that are already taken
+    fn id() -> impl Strategy<Value = String> {
+        (0..8u8).prop_map(|x| format!("t{x}"))
+    }
+
+    fn value() -> impl Strategy<Value = String> {
+        "[a-z]{0,4}"
+    }
+
+    fn status() -> impl Strategy<Value = TaskStatus> {
+        prop_oneof![
+            Just(TaskStatus::Succeeded),
+            Just(TaskStatus::Failed),
+            Just(TaskStatus::Obsoleted),
+        ]
+    }
+
+    // a consistent state, for OverwriteState
+    fn snapshot() -> impl Strategy<Value = StateSnapshot> {
+        vec((id(), value(), option::of(status())), 0..6).prop_map(|tasks| {
+            let mut snapshot = empty();
+            let mut seen = HashSet::new();
+            for (id, value, status) in tasks {
+                if !seen.insert(id.clone()) {
+                    continue;
+                }
+                match status {
+                    Some(status) => snapshot
+                        .finished
+                        .push_back(FinishedTask { id, value, status }),
+                    None => snapshot.live.push_back(LiveTask { id, value }),
+                }
+            }
+            snapshot
+        })
+    }
+
+    fn base_op() -> impl Strategy<Value = WebsocketOpKind> {
+        prop_oneof![
+            1 => snapshot().prop_map(WebsocketOpKind::OverwriteState),
+            4 => (id(), value()).prop_map(|(id, value)| WebsocketOpKind::InsLiveTask { value, id }),
+            4 => id().prop_map(|id| WebsocketOpKind::RestoreFinishedTask { id }),
+            4 => (id(), value()).prop_map(|(id, value)| WebsocketOpKind::EditLiveTask { id, value }),
+            4 => id().prop_map(|id| WebsocketOpKind::DelLiveTask { id }),
+            4 => (id(), id())
+                .prop_map(|(id_ins, id_del)| WebsocketOpKind::MvLiveTask { id_ins, id_del }),
+            4 => (id(), id()).prop_map(|(id1, id2)| WebsocketOpKind::RevLiveTask { id1, id2 }),
+            4 => (id(), status())
+                .prop_map(|(id, status)| WebsocketOpKind::FinishLiveTask { id, status }),
+        ]
+    }
+
+    // InsLiveTaskNew picks a random id, so the worker stores it as an InsLiveTask instead, and the
+    // ops not generated here don't change the snapshot
+    fn ext_op() -> impl Strategy<Value = ExtOpKind> {
+        prop_oneof![
+            Just(ExtOpKind::ClearFinishedTasks),
+            (id(), value(), option::of(id()), option::of(id())).prop_map(
+                |(id, value, after, before)| ExtOpKind::InsLiveTaskBetween {
+                    id,
+                    value,
+                    after,
+                    before,
+                }
+            ),
+            (id(), option::of(id()), option::of(id()))
+                .prop_map(|(id, after, before)| ExtOpKind::MvLiveTaskBetween { id, after, before }),
+            id().prop_map(|id| ExtOpKind::DelLiveTaskSoft { id }),
+            vec((id(), value()), 0..3).prop_map(|tasks| ExtOpKind::InsLiveTasks {
+                tasks: tasks
+                    .into_iter()
+                    .map(|(id, value)| LiveTask { id, value })
+                    .collect(),
+            }),
+            (vec(id(), 0..4), status())
+                .prop_map(|(ids, status)| ExtOpKind::FinishManyLiveTasks { ids, status }),
+            (id(), id(), id()).prop_map(|(id_first, id_last, id_ins)| ExtOpKind::MvLiveTaskRange {
+                id_first,
+                id_last,
+                id_ins,
+            }),
+            (
+                prop_oneof![Just("2024-02-29"), Just("yesterday")],
+                any::<bool>()
+            )
+                .prop_map(|(day, requeue_failed)| ExtOpKind::RolloverDay {
+                    day: day.to_owned(),
+                    requeue_failed,
+                }),
+        ]
+    }
+
+    fn ops() -> impl Strategy<Value = Vec<OpKind>> {
+        vec(
+            prop_oneof![
+                base_op().prop_map(OpKind::Base),
+                ext_op().prop_map(OpKind::Ext),
+            ],
+            0..60,
+        )
+    }
+
+    fn empty() -> StateSnapshot {
+        StateSnapshot {
+            live: VecDeque::new(),
+            finished: VecDeque::new(),
+        }
+    }
+
+    // what the worker does with each op: rejected ones are dropped, the rest applied and logged
+    // every state along the way is checked
+    fn run(snapshot: &mut StateSnapshot, ops: Vec<OpKind>) -> Result<Vec<OpKind>, TestCaseError> {
+        let mut log = vec![];
+        for op in ops {
+            if check_operation(snapshot, &op).is_err() {
+                continue;
+            }
+            let before = snapshot.clone();
+            apply_operation(snapshot, op.clone());
+            check_step(&before, &op, snapshot)?;
+            log.push(op);
+        }
+        Ok(log)
+    }
+
+    fn check_step(
+        before: &StateSnapshot,
+        op: &OpKind,
+        after: &StateSnapshot,
+    ) -> Result<(), TestCaseError> {
+        let live = after.live.iter().map(|x| &x.id).collect::<HashSet<_>>();
+        let finished = after.finished.iter().map(|x| &x.id).collect::<HashSet<_>>();
+        prop_assert_eq!(
+            live.len(),
+            after.live.len(),
+            "duplicate live ids after {:?}",
+            op
+        );
+        prop_assert_eq!(
+            finished.len(),
+            after.finished.len(),
+            "duplicate finished ids after {:?}",
+            op
+        );
+        prop_assert!(
+            live.is_disjoint(&finished),
+            "ids in both lists after {:?}",
+            op
+        );
+
+        match op {
+            // moves only reorder the live list
+            OpKind::Base(WebsocketOpKind::MvLiveTask { .. })
+            | OpKind::Base(WebsocketOpKind::RevLiveTask { .. })
+            | OpKind::Ext(ExtOpKind::MvLiveTaskBetween { .. })
+            | OpKind::Ext(ExtOpKind::MvLiveTaskRange { .. }) => {
+                let mut ids_before = before.live.iter().map(|x| &x.id).collect::<Vec<_>>();
+                let mut ids_after = after.live.iter().map(|x| &x.id).collect::<Vec<_>>();
+                ids_before.sort();
+                ids_after.sort();
+                prop_assert_eq!(ids_before, ids_after, "live tasks changed by {:?}", op);
+                prop_assert_eq!(&before.finished.len(), &after.finished.len());
+            }
+            _ => {}
+        }
+        match op {
+            OpKind::Ext(ExtOpKind::InsLiveTaskBetween {
+                id,
+                after: a,
+                before: b,
+                ..
+            }) => placed_between(&after.live, id, a, b)?,
+            OpKind::Ext(ExtOpKind::MvLiveTaskBetween {
+                id,
+                after: a,
+                before: b,
+            }) if a.as_ref() != Some(id) && b.as_ref() != Some(id) => {
+                placed_between(&after.live, id, a, b)?
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // right after `after` if it's there, otherwise right before `before`, otherwise at the front
+    // an inserted task can't be anchored on itself, since it wasn't there yet
+    fn placed_between(
+        live: &VecDeque<LiveTask>,
+        id: &str,
+        after: &Option<String>,
+        before: &Option<String>,
+    ) -> Result<(), TestCaseError> {
+        let pos = |anchor: &str| live.iter().position(|x| x.id == anchor && anchor != id);
+        let Some(task_pos) = live.iter().position(|x| x.id == id) else {
+            return Ok(());
+        };
+        if let Some(after_pos) = after.as_deref().and_then(pos) {
+            prop_assert_eq!(task_pos, after_pos + 1);
+        } else if let Some(before_pos) = before.as_deref().and_then(pos) {
+            prop_assert_eq!(task_pos + 1, before_pos);
+        } else {
+            prop_assert_eq!(task_pos, 0);
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn ops_keep_the_state_consistent(ops in ops()) {
+            run(&mut empty(), ops)?;
+        }
+
+        #[test]
+        fn ops_keep_an_overwritten_state_consistent(initial in snapshot(), ops in ops()) {
+            run(&mut initial.clone(), ops)?;
+        }
+
+        #[test]
+        fn replaying_from_a_checkpoint_gives_the_same_state(ops in ops(), split in any::<Index>()) {
+            let mut snapshot = empty();
+            let log = run(&mut snapshot, ops)?;
+            let at = split.index(log.len() + 1);
+
+            let mut checkpoint = empty();
+            for op in &log[..at] {
+                apply_operation(&mut checkpoint, op.clone());
+            }
+            // stored and loaded the way the worker does, as are the ops after it
+            let mut replayed = snapshot_codec::decode(&snapshot_codec::encode(&checkpoint)).unwrap();
+            for op in &log[at..] {
+                let stored = serde_json::to_string(op).unwrap();
+                apply_operation(&mut replayed, serde_json::from_str(&stored).unwrap());
+            }
+            prop_assert_eq!(snapshot_checksum(&replayed), snapshot_checksum(&snapshot));
+        }
+    }
+}
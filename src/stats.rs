@@ -0,0 +1,291 @@
+// statistics about how a user's tasks changed over time, replayed from the op log
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use todoproxy_api::{StateSnapshot, TaskStatus, WebsocketOpKind};
+
+use crate::handlers::{self, AppError};
+use crate::operation_service;
+use crate::preferences;
+use crate::protocol::response::{
+    BurndownDay, Review, ReviewCompletedTask, ReviewStreak, ReviewTag, ReviewTask,
+};
+use crate::protocol::{ExtOpKind, Op, OpKind, Preferences, DAY_FORMAT, WEEK_FORMAT};
+use crate::state_machine::apply_operation;
+use crate::user_worker;
+
+/// How far before a review's week its streaks are followed, in days.
+const MAX_STREAK_DAYS: u64 = 366;
+
+fn parse_op(jsonval: &str) -> Result<Op, AppError> {
+    serde_json::from_str::<Op>(jsonval).map_err(handlers::report_internal_serde_error)
+}
+
+fn set_estimate(estimates: &mut HashMap<String, i64>, kind: &OpKind) {
+    if let OpKind::Ext(ExtOpKind::SetTaskEstimate { id, estimate }) = kind {
+        match estimate {
+            Some(estimate) => estimates.insert(id.clone(), *estimate as i64),
+            None => estimates.remove(id),
+        };
+    }
+}
+
+// the tasks the op finishes successfully
+pub fn succeeded_ids(kind: &OpKind) -> &[String] {
+    match kind {
+        OpKind::Base(WebsocketOpKind::FinishLiveTask {
+            id,
+            status: TaskStatus::Succeeded,
+        }) => std::slice::from_ref(id),
+        OpKind::Ext(ExtOpKind::FinishManyLiveTasks {
+            ids,
+            status: TaskStatus::Succeeded,
+        }) => &ids[..],
+        _ => &[],
+    }
+}
+
+// the estimates of the live tasks the op finishes successfully
+fn completed_effort(
+    snapshot: &StateSnapshot,
+    estimates: &HashMap<String, i64>,
+    kind: &OpKind,
+) -> i64 {
+    succeeded_ids(kind)
+        .iter()
+        .filter(|id| snapshot.live.iter().any(|x| &x.id == *id))
+        .filter_map(|id| estimates.get(id))
+        .sum()
+}
+
+// remaining estimated effort at the end of each day from from to to (or today, if that's sooner),
+// and the effort completed on it
+// tasks are counted from when they're estimated, so estimating more work makes the line go up
+pub async fn burndown(
+    con: &mut deadpool_postgres::Client,
+    user_id: i64,
+    preferences: &Preferences,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<BurndownDay>, AppError> {
+    let next_day = |day: NaiveDate| day.succ_opt().ok_or(AppError::BadRequest);
+    let start_time = preferences::day_start(preferences, from).timestamp_millis();
+    let end_time = preferences::day_start(preferences, next_day(to)?).timestamp_millis();
+
+    // the state and estimates as the first day started
+    let mut snapshot = match user_worker::load_state_at(con, user_id, start_time - 1).await? {
+        Some(x) => x.snapshot,
+        None => StateSnapshot {
+            live: VecDeque::new(),
+            finished: VecDeque::new(),
+        },
+    };
+    let mut estimates = HashMap::new();
+    for x in
+        operation_service::get_estimate_operations_by_user_id_before(&mut *con, user_id, start_time)
+            .await
+            .map_err(handlers::report_postgres_err)?
+    {
+        set_estimate(&mut estimates, &parse_op(&x.jsonval)?.kind);
+    }
+
+    let operations = operation_service::get_operations_by_user_id_between(
+        &mut *con, user_id, start_time, end_time,
+    )
+    .await
+    .map_err(handlers::report_postgres_err)?;
+    let mut operations = operations.into_iter().peekable();
+
+    let now = Utc::now().timestamp_millis();
+    let mut days = vec![];
+    let mut day = from;
+    while day <= to && preferences::day_start(preferences, day).timestamp_millis() <= now {
+        let day_end = preferences::day_start(preferences, next_day(day)?).timestamp_millis();
+        let mut completed = 0;
+        while let Some(x) = operations.next_if(|x| x.creation_time < day_end) {
+            let op = parse_op(&x.jsonval)?;
+            completed += completed_effort(&snapshot, &estimates, &op.kind);
+            set_estimate(&mut estimates, &op.kind);
+            apply_operation(&mut snapshot, op.kind);
+        }
+        let live_estimates = snapshot.live.iter().map(|x| estimates.get(&x.id));
+        days.push(BurndownDay {
+            day: day.format(DAY_FORMAT).to_string(),
+            remaining: live_estimates.clone().flatten().sum(),
+            completed,
+            unestimated: live_estimates.filter(|x| x.is_none()).count(),
+        });
+        day = next_day(day)?;
+    }
+    Ok(days)
+}
+
+// the #tags in a value, written the way quick add and templates write them, or None if there
+// aren't any
+fn tags_of(value: &str) -> Vec<Option<&str>> {
+    let mut tags = value
+        .split_whitespace()
+        .filter_map(|x| x.strip_prefix('#'))
+        .filter(|x| !x.is_empty())
+        .map(Some)
+        .collect::<Vec<_>>();
+    tags.sort_unstable();
+    tags.dedup();
+    if tags.is_empty() {
+        tags.push(None);
+    }
+    tags
+}
+
+fn tag_counts<'a>(
+    tags: &'a mut BTreeMap<Option<String>, ReviewTag>,
+    tag: Option<&str>,
+) -> &'a mut ReviewTag {
+    let tag = tag.map(|x| x.to_owned());
+    tags.entry(tag.clone()).or_insert_with(|| ReviewTag {
+        tag,
+        completed: 0,
+        carried_over: 0,
+    })
+}
+
+// how many days in a row up to and including last are in days
+fn days_in_a_row(days: &HashSet<NaiveDate>, last: NaiveDate) -> u64 {
+    let mut count = 0;
+    let mut day = Some(last);
+    while let Some(x) = day.filter(|x| days.contains(x)) {
+        count += 1;
+        day = x.pred_opt();
+    }
+    count
+}
+
+// how many days in a row up to today are in days; today not being in them yet doesn't end the
+// streak, since there's still time to keep it going
+pub fn current_streak(days: &HashSet<NaiveDate>, today: NaiveDate) -> u64 {
+    match days.contains(&today) {
+        true => days_in_a_row(days, today),
+        false => today.pred_opt().map_or(0, |x| days_in_a_row(days, x)),
+    }
+}
+
+// the most days in a row that are in days
+pub fn longest_streak(days: &HashSet<NaiveDate>) -> u64 {
+    days.iter()
+        // counted back from the last day of each run
+        .filter(|x| x.succ_opt().is_none_or(|next| !days.contains(&next)))
+        .map(|x| days_in_a_row(days, *x))
+        .max()
+        .unwrap_or(0)
+}
+
+// the user's week in review, for the week starting on the given monday
+// completed tasks are named by their value when they were finished, found by replaying the log
+pub async fn review(
+    con: &mut deadpool_postgres::Client,
+    user_id: i64,
+    preferences: &Preferences,
+    from: NaiveDate,
+) -> Result<Review, AppError> {
+    let to = from
+        .checked_add_days(Days::new(6))
+        .ok_or(AppError::BadRequest)?;
+    let next_day = |day: NaiveDate| day.succ_opt().ok_or(AppError::BadRequest);
+    let start_time = preferences::day_start(preferences, from).timestamp_millis();
+    let end_time = preferences::day_start(preferences, next_day(to)?).timestamp_millis();
+    let day_of = |time: i64| {
+        preferences::day_of(
+            preferences,
+            DateTime::from_timestamp_millis(time).unwrap_or_default(),
+        )
+    };
+
+    let mut snapshot = match user_worker::load_state_at(con, user_id, start_time - 1).await? {
+        Some(x) => x.snapshot,
+        None => StateSnapshot {
+            live: VecDeque::new(),
+            finished: VecDeque::new(),
+        },
+    };
+    let mut completed = vec![];
+    for x in operation_service::get_operations_by_user_id_between(
+        &mut *con, user_id, start_time, end_time,
+    )
+    .await
+    .map_err(handlers::report_postgres_err)?
+    {
+        let op = parse_op(&x.jsonval)?;
+        for id in succeeded_ids(&op.kind) {
+            if let Some(task) = snapshot.live.iter().find(|t| &t.id == id) {
+                completed.push(ReviewCompletedTask {
+                    id: id.clone(),
+                    value: task.value.clone(),
+                    finish_time: x.creation_time,
+                });
+            }
+        }
+        apply_operation(&mut snapshot, op.kind);
+    }
+    let carried_over = snapshot
+        .live
+        .iter()
+        .map(|x| ReviewTask {
+            id: x.id.clone(),
+            value: x.value.clone(),
+        })
+        .collect::<Vec<_>>();
+
+    // the days a task succeeded on, from far enough back to follow the streak into the week
+    let mut days = completed
+        .iter()
+        .map(|x| day_of(x.finish_time))
+        .collect::<HashSet<_>>();
+    let streak_start = from
+        .checked_sub_days(Days::new(MAX_STREAK_DAYS))
+        .ok_or(AppError::BadRequest)?;
+    for x in operation_service::get_finish_operations_by_user_id_between(
+        &mut *con,
+        user_id,
+        preferences::day_start(preferences, streak_start).timestamp_millis(),
+        start_time,
+    )
+    .await
+    .map_err(handlers::report_postgres_err)?
+    {
+        if !succeeded_ids(&parse_op(&x.jsonval)?.kind).is_empty() {
+            days.insert(day_of(x.creation_time));
+        }
+    }
+    let today = preferences::day_of(preferences, Utc::now());
+    let streak = ReviewStreak {
+        before: from.pred_opt().map_or(0, |x| days_in_a_row(&days, x)),
+        after: match to < today {
+            true => days_in_a_row(&days, to),
+            false => current_streak(&days, today),
+        },
+    };
+
+    let mut tags = BTreeMap::new();
+    for x in &completed {
+        for tag in tags_of(&x.value) {
+            tag_counts(&mut tags, tag).completed += 1;
+        }
+    }
+    for x in &carried_over {
+        for tag in tags_of(&x.value) {
+            tag_counts(&mut tags, tag).carried_over += 1;
+        }
+    }
+    let mut tags = tags.into_values().collect::<Vec<_>>();
+    tags.sort_by_key(|x| std::cmp::Reverse(x.completed));
+
+    Ok(Review {
+        week: from.format(WEEK_FORMAT).to_string(),
+        from: from.format(DAY_FORMAT).to_string(),
+        to: to.format(DAY_FORMAT).to_string(),
+        completed,
+        carried_over,
+        streak,
+        tags,
+    })
+}
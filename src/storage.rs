@@ -0,0 +1,133 @@
+// attachments are stored in an s3 compatible bucket, which clients upload to and download from
+// directly, with urls presigned here (aws signature version 4, path style)
+// the server itself only ever deletes objects
+use std::time::Duration;
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::utils;
+
+/// How long a presigned url can be used for.
+pub const PRESIGNED_URL_TTL: Duration = Duration::from_secs(15 * 60);
+
+pub struct ObjectStore {
+    // e.g. https://s3.us-east-1.amazonaws.com, without a trailing slash
+    endpoint: String,
+    // host[:port] of the endpoint, which is part of every signature
+    host: String,
+    bucket: String,
+    region: String,
+    access_key_id: String,
+    secret_access_key: String,
+    client: reqwest::Client,
+}
+
+impl ObjectStore {
+    pub fn new(
+        endpoint: String,
+        bucket: String,
+        region: String,
+        access_key_id: String,
+        secret_access_key: String,
+    ) -> Result<ObjectStore, String> {
+        let url = reqwest::Url::parse(&endpoint).map_err(|e| e.to_string())?;
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{host}:{port}"),
+            (Some(host), None) => host.to_owned(),
+            (None, _) => return Err(format!("{endpoint} has no host")),
+        };
+        Ok(ObjectStore {
+            endpoint: endpoint.trim_end_matches('/').to_owned(),
+            host,
+            bucket,
+            region,
+            access_key_id,
+            secret_access_key,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    // a url the client can PUT exactly content_length bytes to
+    pub fn presign_upload(&self, key: &str, content_length: i64) -> String {
+        self.presign("PUT", key, Some(content_length))
+    }
+
+    pub fn presign_download(&self, key: &str) -> String {
+        self.presign("GET", key, None)
+    }
+
+    // deleting an object that doesn't exist succeeds, so this can be retried
+    pub async fn delete(&self, key: &str) -> Result<(), reqwest::Error> {
+        self.client
+            .delete(self.presign("DELETE", key, None))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    fn presign(&self, method: &str, key: &str, content_length: Option<i64>) -> String {
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let scope = format!("{date}/{}/s3/aws4_request", self.region);
+
+        // slashes in the key separate path segments, so they're left as they are
+        let key = key.split('/').map(uri_encode).collect::<Vec<_>>().join("/");
+        let uri = format!("/{}/{key}", uri_encode(&self.bucket));
+        // headers are signed in order, and the client must send the same values
+        let (canonical_headers, signed_headers) = match content_length {
+            Some(n) => (
+                format!("content-length:{n}\nhost:{}\n", self.host),
+                "content-length;host",
+            ),
+            None => (format!("host:{}\n", self.host), "host"),
+        };
+        // parameters are sorted by name
+        let query = format!(
+            "X-Amz-Algorithm=AWS4-HMAC-SHA256&X-Amz-Credential={}&X-Amz-Date={amz_date}&X-Amz-Expires={}&X-Amz-SignedHeaders={}",
+            uri_encode(&format!("{}/{scope}", self.access_key_id)),
+            PRESIGNED_URL_TTL.as_secs(),
+            uri_encode(signed_headers),
+        );
+        let canonical_request = format!(
+            "{method}\n{uri}\n{query}\n{canonical_headers}\n{signed_headers}\nUNSIGNED-PAYLOAD"
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            utils::sha256_hex(canonical_request.as_bytes())
+        );
+
+        let key = format!("AWS4{}", self.secret_access_key);
+        let key = hmac_sha256(key.as_bytes(), date.as_bytes());
+        let key = hmac_sha256(&key, self.region.as_bytes());
+        let key = hmac_sha256(&key, b"s3");
+        let key = hmac_sha256(&key, b"aws4_request");
+        let signature: String = hmac_sha256(&key, string_to_sign.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+
+        format!("{}{uri}?{query}&X-Amz-Signature={signature}", self.endpoint)
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+// percent encodes everything but unreserved characters, as sigv4 requires
+fn uri_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
@@ -0,0 +1,144 @@
+use super::db_types::*;
+use std::collections::HashMap;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for TaskComment {
+    // select * from task_comment order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> TaskComment {
+        TaskComment {
+            task_comment_id: row.get("task_comment_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            comment_id: row.get("comment_id"),
+            task_id: row.get("task_id"),
+            value: row.get("value"),
+        }
+    }
+}
+
+// returns None if the user already has a comment with this id
+#[tracing::instrument(skip(con, value))]
+pub async fn add(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    comment_id: String,
+    task_id: String,
+    value: String,
+) -> Result<Option<TaskComment>, tokio_postgres::Error> {
+    let row = con
+        .query_opt(
+            "INSERT INTO
+             task_comment(
+                 creator_user_id,
+                 comment_id,
+                 task_id,
+                 value
+             )
+             VALUES($1, $2, $3, $4)
+             ON CONFLICT DO NOTHING
+             RETURNING task_comment_id, creation_time
+            ",
+            &[&creator_user_id, &comment_id, &task_id, &value],
+        )
+        .await?;
+
+    // return comment
+    Ok(row.map(|row| TaskComment {
+        task_comment_id: row.get(0),
+        creation_time: row.get(1),
+        creator_user_id,
+        comment_id,
+        task_id,
+        value,
+    }))
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn get_by_comment_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    comment_id: &str,
+) -> Result<Option<TaskComment>, tokio_postgres::Error> {
+    let result = con
+        .query_opt(
+            "SELECT * FROM task_comment WHERE creator_user_id=$1 AND comment_id=$2",
+            &[&creator_user_id, &comment_id],
+        )
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+// the task's comments after task_comment_id, oldest first
+#[tracing::instrument(skip(con))]
+pub async fn get_by_task_id_after(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    task_id: &str,
+    task_comment_id: i64,
+    limit: i64,
+) -> Result<Vec<TaskComment>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT * FROM task_comment
+             WHERE creator_user_id=$1 AND task_id=$2 AND task_comment_id > $3
+             ORDER BY task_comment_id
+             LIMIT $4",
+            &[&creator_user_id, &task_id, &task_comment_id, &limit],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// how many comments each of the user's tasks had at created_before; tasks without any are left out
+#[tracing::instrument(skip(con))]
+pub async fn count_by_task_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    created_before: i64,
+) -> Result<HashMap<String, i64>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT task_id, count(*) FROM task_comment
+             WHERE creator_user_id=$1 AND creation_time <= $2
+             GROUP BY task_id",
+            &[&creator_user_id, &created_before],
+        )
+        .await?
+        .into_iter()
+        .map(|x| (x.get(0), x.get(1)))
+        .collect();
+    Ok(result)
+}
+
+// returns whether the user had a comment with this id
+#[tracing::instrument(skip(con))]
+pub async fn remove(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    comment_id: &str,
+) -> Result<bool, tokio_postgres::Error> {
+    let removed = con
+        .execute(
+            "DELETE FROM task_comment WHERE creator_user_id = $1 AND comment_id = $2",
+            &[&creator_user_id, &comment_id],
+        )
+        .await?;
+    Ok(removed > 0)
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM task_comment WHERE creator_user_id = $1",
+        &[&creator_user_id],
+    )
+    .await
+}
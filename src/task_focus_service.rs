@@ -0,0 +1,104 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for TaskFocus {
+    // select * from task_focus order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> TaskFocus {
+        TaskFocus {
+            task_focus_id: row.get("task_focus_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            task_id: row.get("task_id"),
+            millis: row.get("millis"),
+            focused_since: row.get("focused_since"),
+        }
+    }
+}
+
+// the task the user is focused on, if any
+#[tracing::instrument(skip(con))]
+pub async fn get_focused_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<Option<TaskFocus>, tokio_postgres::Error> {
+    let result = con
+        .query_opt(
+            "SELECT * FROM task_focus WHERE creator_user_id = $1 AND focused_since IS NOT NULL",
+            &[&creator_user_id],
+        )
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+// adds the time since the focused task was focused to its total, and unfocuses it
+#[tracing::instrument(skip(con))]
+pub async fn unfocus(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    time: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "UPDATE task_focus
+         SET millis = millis + greatest($2 - focused_since, 0), focused_since = NULL
+         WHERE creator_user_id = $1 AND focused_since IS NOT NULL
+        ",
+        &[&creator_user_id, &time],
+    )
+    .await
+}
+
+// focuses the task from time on; unfocus the one that was focused first
+#[tracing::instrument(skip(con))]
+pub async fn focus(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    task_id: &str,
+    time: i64,
+) -> Result<(), tokio_postgres::Error> {
+    con.execute(
+        "INSERT INTO
+         task_focus(
+             creator_user_id,
+             task_id,
+             focused_since
+         )
+         VALUES($1, $2, $3)
+         ON CONFLICT (creator_user_id, task_id) DO UPDATE
+         SET focused_since = $3
+        ",
+        &[&creator_user_id, &task_id, &time],
+    )
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn get_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<Vec<TaskFocus>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT * FROM task_focus WHERE creator_user_id = $1",
+            &[&creator_user_id],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM task_focus WHERE creator_user_id = $1",
+        &[&creator_user_id],
+    )
+    .await
+}
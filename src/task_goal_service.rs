@@ -0,0 +1,153 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for TaskGoal {
+    // select * from task_goal order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> TaskGoal {
+        TaskGoal {
+            task_goal_id: row.get("task_goal_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            task_id: row.get("task_id"),
+            goal_id: row.get("goal_id"),
+            completed: row.get("completed"),
+        }
+    }
+}
+
+// links the task to goal, unlinking it from whichever it was linked to before
+#[tracing::instrument(skip(con))]
+pub async fn set(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    task_id: String,
+    goal_id: i64,
+    completed: bool,
+) -> Result<TaskGoal, tokio_postgres::Error> {
+    let row = con
+        .query_one(
+            "INSERT INTO
+             task_goal(
+                 creator_user_id,
+                 task_id,
+                 goal_id,
+                 completed
+             )
+             VALUES($1, $2, $3, $4)
+             ON CONFLICT (creator_user_id, task_id) DO UPDATE
+             SET goal_id = $3, completed = $4
+             RETURNING task_goal_id, creation_time
+            ",
+            &[&creator_user_id, &task_id, &goal_id, &completed],
+        )
+        .await?;
+
+    // return task goal
+    Ok(TaskGoal {
+        task_goal_id: row.get(0),
+        creation_time: row.get(1),
+        creator_user_id,
+        task_id,
+        goal_id,
+        completed,
+    })
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn get_by_task_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    task_id: &str,
+) -> Result<Option<TaskGoal>, tokio_postgres::Error> {
+    let result = con
+        .query_opt(
+            "SELECT * FROM task_goal WHERE creator_user_id = $1 AND task_id = $2",
+            &[&creator_user_id, &task_id],
+        )
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn get_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<Vec<TaskGoal>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT * FROM task_goal WHERE creator_user_id = $1",
+            &[&creator_user_id],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// returns the goal the task was linked to, if it was
+#[tracing::instrument(skip(con))]
+pub async fn remove(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    task_id: &str,
+) -> Result<Option<i64>, tokio_postgres::Error> {
+    let result = con
+        .query_opt(
+            "DELETE FROM task_goal WHERE creator_user_id = $1 AND task_id = $2 RETURNING goal_id",
+            &[&creator_user_id, &task_id],
+        )
+        .await?
+        .map(|x| x.get(0));
+    Ok(result)
+}
+
+// returns the goal the task is linked to, if it is and completed changed
+#[tracing::instrument(skip(con))]
+pub async fn set_completed(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    task_id: &str,
+    completed: bool,
+) -> Result<Option<i64>, tokio_postgres::Error> {
+    let result = con
+        .query_opt(
+            "UPDATE task_goal SET completed = $3
+             WHERE creator_user_id = $1 AND task_id = $2 AND completed <> $3
+             RETURNING goal_id
+            ",
+            &[&creator_user_id, &task_id, &completed],
+        )
+        .await?
+        .map(|x| x.get(0));
+    Ok(result)
+}
+
+// how many of the tasks linked to the goal are completed, and how many there are
+#[tracing::instrument(skip(con))]
+pub async fn get_progress(
+    con: &mut impl GenericClient,
+    goal_id: i64,
+) -> Result<(i64, i64), tokio_postgres::Error> {
+    let row = con
+        .query_one(
+            "SELECT count(*) FILTER (WHERE completed), count(*) FROM task_goal WHERE goal_id = $1",
+            &[&goal_id],
+        )
+        .await?;
+    Ok((row.get(0), row.get(1)))
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM task_goal WHERE creator_user_id = $1",
+        &[&creator_user_id],
+    )
+    .await
+}
@@ -0,0 +1,95 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for TaskLane {
+    // select * from task_lane order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> TaskLane {
+        TaskLane {
+            task_lane_id: row.get("task_lane_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            task_id: row.get("task_id"),
+            lane: row.get("lane"),
+        }
+    }
+}
+
+// moves the task into lane, from whichever it was in before
+#[tracing::instrument(skip(con))]
+pub async fn set(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    task_id: String,
+    lane: String,
+) -> Result<TaskLane, tokio_postgres::Error> {
+    let row = con
+        .query_one(
+            "INSERT INTO
+             task_lane(
+                 creator_user_id,
+                 task_id,
+                 lane
+             )
+             VALUES($1, $2, $3)
+             ON CONFLICT (creator_user_id, task_id) DO UPDATE
+             SET lane = $3
+             RETURNING task_lane_id, creation_time
+            ",
+            &[&creator_user_id, &task_id, &lane],
+        )
+        .await?;
+
+    // return task lane
+    Ok(TaskLane {
+        task_lane_id: row.get(0),
+        creation_time: row.get(1),
+        creator_user_id,
+        task_id,
+        lane,
+    })
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn get_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<Vec<TaskLane>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT * FROM task_lane WHERE creator_user_id = $1",
+            &[&creator_user_id],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// moves the task back to the default lane
+#[tracing::instrument(skip(con))]
+pub async fn remove(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    task_id: &str,
+) -> Result<(), tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM task_lane WHERE creator_user_id = $1 AND task_id = $2",
+        &[&creator_user_id, &task_id],
+    )
+    .await?;
+    Ok(())
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM task_lane WHERE creator_user_id = $1",
+        &[&creator_user_id],
+    )
+    .await
+}
@@ -1,32 +1,98 @@
 use actix_web::web;
 use auth_service_api::response::User;
 use futures_util::{stream, stream_select, StreamExt};
+use serde::{Deserialize, Serialize};
 
 use actix_ws::{CloseCode, CloseReason, Message, ProtocolError};
-use std::{
-    collections::{hash_map::Entry, HashMap, VecDeque},
-    sync::Arc,
-    time::{Duration, Instant},
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use todoproxy_api::{StateSnapshot, WebsocketOpKind};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_stream::wrappers::IntervalStream;
+
+use crate::handlers::{self, get_user_id_if_api_key_valid};
+use crate::protocol::request::WebsocketInit;
+use crate::protocol::{
+    ClientFrame, ClientRequest, ExtOpKind, FrameErrorCode, Op, OpKind, OpRejectReason, ServerEvent,
+    ServerNotice, SessionClose, SessionCloseCode, ValueEncoding, ENCRYPTED_VALUE_PREFIX,
+    MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
 };
-use todoproxy_api::{
-    request::WebsocketInitMessage, FinishedTask, LiveTask, StateSnapshot, WebsocketOp,
-    WebsocketOpKind,
+use crate::user_worker::{
+    self, ApplyOutcome, BroadcastOp, DeviceInfo, Subscription, Update, UserWorkerHandle,
 };
-use tokio::sync::{broadcast::Receiver, Mutex};
-use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream, IntervalStream};
-
-use crate::handlers::{self, get_user_if_api_key_valid};
-use crate::{checkpoint_service, operation_service, PerUserWorkerData};
 use crate::{db_types, utils};
 use crate::{handlers::AppError, AppData};
+use crate::{known_device_service, task_focus_service};
+
+/// How often each connection is sent the checksum of the state it should have.
+const CHECKSUM_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The most finished tasks sent in answer to one RequestMoreFinished.
+pub const MAX_FINISHED_CHUNK: usize = 1000;
+
+/// The first protocol version whose sessions are issued resume tokens.
+const RESUME_PROTOCOL_VERSION: u32 = 2;
+
+/// The first protocol version whose sessions are sent the live tasks' lanes.
+const LANES_PROTOCOL_VERSION: u32 = 3;
+
+/// The first protocol version whose sessions are sent the focused task.
+const FOCUS_PROTOCOL_VERSION: u32 = 4;
+
+/// How long after a session ends its resume token can still be used.
+pub const RESUME_TOKEN_TTL: Duration = Duration::from_secs(2 * 60);
+
+/// Websocket close reasons may be at most this many bytes.
+const MAX_CLOSE_REASON_LEN: usize = 123;
+
+/// Sessions are closed after this many frames in a row that couldn't be handled.
+const MAX_BAD_FRAMES: u32 = 5;
+
+/// A resuming client further behind than this gets the whole state instead of the ops it missed.
+const MAX_RESUME_OPS: i64 = 500;
 
-/// How often heartbeat pings are sent.
-///
-/// Should be half (or less) of the acceptable client timeout.
-const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+// what to do with a connection whose queue of undelivered ops passes the high water mark, or that
+// fell so far behind the broadcast channel that it missed ops
+// sse, grpc and graphql streams have no high water mark, so only the latter applies to them
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlowConsumerPolicy {
+    // send a fresh copy of the state instead of the queued ops
+    Resync,
+    // close the connection, so the client reconnects when it can keep up
+    Disconnect,
+}
+
+// lightweight metadata about an open websocket, for the admin api
+pub struct SessionInfo {
+    pub user_id: i64,
+    pub connected_time: i64,
+    // when the client last sent us a message
+    pub last_activity_time: Arc<AtomicI64>,
+    pub protocol_version: u32,
+    // how long the client took to answer the last heartbeat, or -1 until it answers one
+    pub rtt_ms: Arc<AtomicI64>,
+}
 
-/// How long before lack of client response causes a timeout.
-const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+// what a resume token stands in for, so resuming skips asking the auth service again
+pub struct ResumeToken {
+    pub user_id: i64,
+    // the resumed session keeps checking it's still valid
+    pub api_key: String,
+    pub read_only: bool,
+    // unset while the session it was issued to is open
+    pub expiry_time: Option<i64>,
+}
+
+// tokens are single use, and a resumed session is issued a new one
+fn take_resume_token(data: &AppData, token: &str) -> Option<ResumeToken> {
+    let (_, resume_token) = data.resume_tokens.remove(token)?;
+    match resume_token.expiry_time {
+        Some(expiry_time) if expiry_time <= utils::current_time_millis() => None,
+        _ => Some(resume_token),
+    }
+}
 
 struct ConnectionState {
     user: User,
@@ -34,183 +100,467 @@ struct ConnectionState {
 
 pub async fn manage_updates_ws(
     data: web::Data<AppData>,
-    init_msg: WebsocketInitMessage,
+    init_msg: WebsocketInit,
+    connection_id: String,
     mut session: actix_ws::Session,
     msg_stream: actix_ws::MessageStream,
 ) {
     log::info!("connected");
+    let finished_limit = init_msg.finished_limit;
 
-    // try block for app
-    let maybe_per_user_worker_data: Result<
-        (
-            Arc<Mutex<PerUserWorkerData>>,
-            Receiver<WebsocketOp>,
-            StateSnapshot,
-        ),
-        AppError,
-    > = try {
-        log::info!("trying to get user");
-        let user = get_user_if_api_key_valid(&data.auth_service, init_msg.api_key).await?;
-        log::info!("validated conenction for user {}", user.user_id);
-
-        let mut write_guard = data.user_worker_data.lock().await;
-        match write_guard.entry(user.user_id) {
-            Entry::Vacant(v) => {
-                // initialize connection
-                let con: &mut tokio_postgres::Client =
-                    &mut *data.pool.get().await.map_err(handlers::report_pool_err)?;
-
-                // get recent checkpoint
-                let preexisting_checkpoint =
-                    checkpoint_service::get_recent_by_user_id(&mut *con, user.user_id)
-                        .await
-                        .map_err(handlers::report_postgres_err)?;
-
-                // if it doesn't exist, create checkpoint
-                let recent_checkpoint = match preexisting_checkpoint {
-                    Some(x) => x,
-                    None => checkpoint_service::add(
-                        &mut *con,
-                        user.user_id,
-                        StateSnapshot {
-                            live: VecDeque::new(),
-                            finished: VecDeque::new(),
-                        },
-                    )
-                    .await
-                    .map_err(handlers::report_postgres_err)?,
-                };
-
-                // get all operations since this checkpoint
-                let operations_since_last_checkpoint = operation_service::get_operations_since(
-                    &mut *con,
-                    recent_checkpoint.checkpoint_id,
-                )
-                .await
-                .map_err(handlers::report_postgres_err)?;
-
-                // create channel
-                let (updates_tx, updates_rx) = tokio::sync::broadcast::channel(1000);
-
-                // create snapshot from checkpoint
-                let mut snapshot = serde_json::from_str(&recent_checkpoint.jsonval)
-                    .map_err(handlers::report_internal_serde_error)?;
-
-                for x in operations_since_last_checkpoint {
-                    let op = serde_json::from_str::<WebsocketOp>(&x.jsonval)
-                        .map_err(handlers::report_internal_serde_error)?;
-                    apply_operation(&mut snapshot, op.kind);
-                }
+    // the newest version both sides speak
+    let protocol_version = init_msg.protocol_version.unwrap_or(1).min(PROTOCOL_VERSION);
+    if protocol_version < MIN_PROTOCOL_VERSION {
+        log::info!("client speaks protocol version {protocol_version}; disconnecting");
+        let _ = session
+            .close(Some(close_reason(
+                SessionCloseCode::UnsupportedProtocol,
+                format!("Protocol version {protocol_version} is no longer supported"),
+            )))
+            .await;
+        return;
+    }
+    // clients that don't ask for a version may not know this notice
+    if init_msg.protocol_version.is_some() {
+        let notice = ServerNotice::ProtocolVersion {
+            version: protocol_version,
+        };
+        if session
+            .text(serde_json::to_string(&notice).unwrap())
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
 
-                let per_user_worker_data_ref = v.insert(Arc::new(Mutex::new(PerUserWorkerData {
-                    updates_tx,
-                    snapshot: snapshot.clone(),
-                    user,
-                    checkpoint_id: recent_checkpoint.checkpoint_id,
-                })));
+    let resumed = init_msg
+        .resume_token
+        .as_deref()
+        .and_then(|token| take_resume_token(&data, token));
+    let resume_seq = init_msg.resume_seq.filter(|_| resumed.is_some());
 
-                (per_user_worker_data_ref.clone(), updates_rx, snapshot)
+    // try block for app
+    let maybe_subscription: Result<(i64, String, bool, UserWorkerHandle, Subscription), AppError> = try {
+        let (user_id, api_key, read_only) = match resumed {
+            Some(ResumeToken {
+                user_id,
+                api_key,
+                read_only,
+                ..
+            }) => {
+                log::info!("resumed connection for user {}", user_id);
+                (user_id, api_key, read_only)
             }
-            Entry::Occupied(o) => {
-                let per_user_worker_data_ref = o.get().clone();
-                let lock = per_user_worker_data_ref.lock().await;
-                let receiver = lock.updates_tx.subscribe();
-                let snapshot = lock.snapshot.clone();
-                drop(lock);
-                (per_user_worker_data_ref, receiver, snapshot)
+            None => {
+                log::info!("trying to get user");
+                let read_only =
+                    handlers::api_key_is_read_only(&data.pool, &init_msg.api_key).await?;
+                let user_id = get_user_id_if_api_key_valid(&data, init_msg.api_key.clone()).await?;
+                log::info!("validated conenction for user {}", user_id);
+                (user_id, init_msg.api_key, read_only)
             }
-        }
+        };
+        tracing::Span::current().record("user_id", user_id);
+        tracing::Span::current().record("tenant_id", handlers::tenant_id_of(&data, user_id).await?);
+
+        let (worker, subscription) = user_worker::connect(&data, user_id).await?;
+        (user_id, api_key, read_only, worker, subscription)
     };
 
-    let (per_user_worker_data, updates_rx, snapshot) = match maybe_per_user_worker_data {
+    let (user_id, mut api_key, mut read_only, worker, subscription) = match maybe_subscription {
         Ok(v) => v,
         Err(e) => {
             // attempt to close connection gracefully
             let _ = session
-                .close(Some(CloseReason {
-                    code: CloseCode::Error,
-                    description: Some(e.to_string()),
-                }))
+                .close(Some(error_close_reason(e, &connection_id)))
                 .await;
             log::info!("disconnected init");
             return;
         }
     };
 
+    let device = DeviceInfo::new(
+        init_msg.device_label,
+        init_msg.device_id,
+        init_msg.device_platform,
+    );
+    if device_seen(&data, user_id, &device).await {
+        log::info!("device was revoked; disconnecting");
+        let _ = session
+            .close(Some(close_reason(
+                SessionCloseCode::DeviceRevoked,
+                "Device was revoked",
+            )))
+            .await;
+        worker.disconnect().await;
+        return;
+    }
+
+    let Subscription {
+        updates_rx,
+        initial_op,
+    } = subscription;
+    let current_seq = initial_op.seq.unwrap_or_default();
+    let current_checksum = initial_op.checksum.clone().unwrap_or_default();
+
+    // a resuming client that isn't too far behind is only sent the ops it missed
+    let missed_ops = match resume_seq {
+        Some(seq) if seq == current_seq => Some(vec![]),
+        Some(seq) if seq < current_seq => {
+            let result: Result<Option<Vec<Op>>, AppError> = try {
+                let mut con = data.pool.get().await.map_err(handlers::report_pool_err)?;
+                handlers::missed_ops(&mut con, user_id, seq, &initial_op, MAX_RESUME_OPS).await?
+            };
+            // the whole state will do
+            result.unwrap_or_else(|e| {
+                log::error!("couldn't read missed ops: {e}");
+                None
+            })
+        }
+        _ => None,
+    };
+    // ops at or below this seq are already reflected in what the client has
+    let (initial_ops, mut last_seq_sent) = match (missed_ops, resume_seq) {
+        (Some(ops), Some(seq)) => (ops, seq),
+        _ => (vec![initial_op], current_seq),
+    };
+    let mut last_checksum_sent = current_checksum;
+
+    let resume_token = if protocol_version >= RESUME_PROTOCOL_VERSION {
+        let token = utils::random_string();
+        data.resume_tokens.insert(
+            token.clone(),
+            ResumeToken {
+                user_id,
+                api_key: api_key.clone(),
+                read_only,
+                expiry_time: None,
+            },
+        );
+        let notice = ServerNotice::ResumeToken {
+            token: token.clone(),
+            valid_for_ms: RESUME_TOKEN_TTL.as_millis() as u64,
+        };
+        let _ = session.text(serde_json::to_string(&notice).unwrap()).await;
+        Some(token)
+    } else {
+        None
+    };
+
+    // let the user's other devices know, and find out which of them are already open
+    let max_devices = data.settings.get().max_devices_per_user;
+    match worker
+        .join_presence(connection_id.clone(), device.clone(), max_devices)
+        .await
+    {
+        Ok(Some(devices)) => {
+            for device in devices {
+                let _ = session.text(serde_json::to_string(&device).unwrap()).await;
+            }
+        }
+        Ok(None) => {
+            log::info!("user already has {max_devices} devices connected; disconnecting");
+            if let Some(token) = resume_token {
+                data.resume_tokens.remove(&token);
+            }
+            let _ = session
+                .close(Some(close_reason(
+                    SessionCloseCode::TooManyDevices,
+                    format!("At most {max_devices} devices may be connected at once"),
+                )))
+                .await;
+            worker.disconnect().await;
+            return;
+        }
+        Err(e) => log::error!("couldn't join presence: {e}"),
+    }
+
+    let last_activity_time = Arc::new(AtomicI64::new(utils::current_time_millis()));
+    let rtt_ms = Arc::new(AtomicI64::new(-1));
+    data.sessions.insert(
+        connection_id.clone(),
+        SessionInfo {
+            user_id,
+            connected_time: utils::current_time_millis(),
+            last_activity_time: last_activity_time.clone(),
+            protocol_version,
+            rtt_ms: rtt_ms.clone(),
+        },
+    );
+
     enum TaskUpdateKind {
         // we need to send a heartbeat
         NeedToSendHeartbeat,
+        // we need to send the checksum of the state the client should have
+        NeedToSendChecksum,
+        // we need to check the api key wasn't revoked since it was last checked
+        NeedToRevalidate,
         // we received a message from the client
         ClientMessage(Result<Message, ProtocolError>),
-        // we have to handle a broadcast from the server
-        ServerUpdate(Result<WebsocketOp, BroadcastStreamRecvError>),
+        // we have to handle a broadcast from the server, with the number of updates still queued behind it
+        ServerUpdate(Result<Update, RecvError>, usize),
+        // the worker shut down without us disconnecting, e.g. it was evicted
+        ServerClosed,
+        // the server started draining, so we need to warn the client
+        Draining,
+        // the server is draining, and the grace period is over
+        DrainDeadline,
     }
 
     let mut last_heartbeat = Instant::now();
 
-    let heartbeat_stream = IntervalStream::new(tokio::time::interval(HEARTBEAT_INTERVAL))
-        .map(|_| TaskUpdateKind::NeedToSendHeartbeat);
+    let settings = data.settings.get();
+    let heartbeat_stream =
+        IntervalStream::new(tokio::time::interval(settings.heartbeat_interval()))
+            .map(|_| TaskUpdateKind::NeedToSendHeartbeat);
+    let checksum_stream = IntervalStream::new(tokio::time::interval(CHECKSUM_INTERVAL))
+        .map(|_| TaskUpdateKind::NeedToSendChecksum);
+    // the key was just checked, so the first check is an interval from now
+    let revalidate_stream = IntervalStream::new(tokio::time::interval_at(
+        tokio::time::Instant::now() + settings.api_key_revalidate_interval(),
+        settings.api_key_revalidate_interval(),
+    ))
+    .map(|_| TaskUpdateKind::NeedToRevalidate);
     let client_message_stream = msg_stream.map(|x| TaskUpdateKind::ClientMessage(x));
 
-    // first emit the state set, then start producing actual things
-    let server_update_stream = stream::once(async {
-        Ok(WebsocketOp {
-            alleged_time: utils::current_time_millis(),
-            kind: WebsocketOpKind::OverwriteState(snapshot),
-        })
+    // first emit the state set, or the ops missed, then start producing actual things
+    let server_update_stream = stream::iter(initial_ops)
+        .map(|op| (Ok(Update::Op(BroadcastOp::unserialized(op))), 0))
+        .chain(stream::unfold(updates_rx, |mut rx| async move {
+            match rx.recv().await {
+                Err(RecvError::Closed) => None,
+                x => {
+                    let backlog = rx.len();
+                    Some(((x, backlog), rx))
+                }
+            }
+        }))
+        .map(|(x, backlog)| TaskUpdateKind::ServerUpdate(x, backlog))
+        .chain(stream::once(async { TaskUpdateKind::ServerClosed }));
+
+    // nothing until the server starts draining, then the warning, then the deadline
+    let mut draining_rx = data.drain.subscribe();
+    let grace_period = data.drain.grace_period();
+    let drain_stream = stream::once(async move {
+        let started = draining_rx.wait_for(|x| *x).await.is_ok();
+        started
     })
-    .chain(BroadcastStream::new(updates_rx))
-    .map(|x| TaskUpdateKind::ServerUpdate(x));
+    .filter(|started| std::future::ready(*started))
+    .flat_map(move |_| {
+        stream::iter([TaskUpdateKind::Draining]).chain(stream::once(async move {
+            tokio::time::sleep(grace_period).await;
+            TaskUpdateKind::DrainDeadline
+        }))
+    });
 
     // pin stream
     tokio::pin!(server_update_stream);
+    tokio::pin!(drain_stream);
 
     let mut joint_stream = stream_select!(
         heartbeat_stream,
+        checksum_stream,
+        revalidate_stream,
         client_message_stream,
-        server_update_stream
+        server_update_stream,
+        drain_stream
     );
 
+    // set if the session was closed because its api key or device was revoked
+    let mut revoked = false;
+    // frames in a row that couldn't be handled
+    let mut bad_frames = 0;
+
     let reason = loop {
         match joint_stream.next().await.unwrap() {
             // received message from WebSocket client
             TaskUpdateKind::ClientMessage(Ok(msg)) => {
                 log::debug!("msg: {msg:?}");
+                last_activity_time.store(utils::current_time_millis(), Ordering::Relaxed);
 
-                match msg {
+                // set if the client should be told its frame couldn't be handled
+                let bad_frame = match msg {
                     Message::Text(text) => {
-                        if let Err(e) =
-                            handle_ws_client_op(data.clone(), per_user_worker_data.clone(), &text)
-                                .await
-                        {
-                            break Some(CloseReason {
-                                code: CloseCode::Error,
-                                description: Some(e.to_string()),
-                            });
+                        let frame = serde_json::from_str::<ClientFrame>(&text);
+                        let result = match frame {
+                            Ok(ClientFrame::Op(op)) => {
+                                let wants_id = matches!(
+                                    op.kind,
+                                    OpKind::Ext(ExtOpKind::InsLiveTaskNew { .. })
+                                );
+                                let outcome = if read_only {
+                                    Ok(ApplyOutcome::Rejected(OpRejectReason::ReadOnly))
+                                } else {
+                                    worker.apply_op(op).await
+                                };
+                                match outcome.map(|x| op_notice(x, wants_id)) {
+                                    Ok(Some(notice)) => {
+                                        let jsonval = serde_json::to_string(&notice).unwrap();
+                                        match session.text(jsonval).await {
+                                            Ok(()) => Ok(()),
+                                            Err(_) => break None,
+                                        }
+                                    }
+                                    Ok(None) => Ok(()),
+                                    Err(e) => match frame_error(e, &connection_id) {
+                                        Ok(notice) => Err(notice),
+                                        Err(reason) => break Some(reason),
+                                    },
+                                }
+                            }
+                            Ok(ClientFrame::Request(ClientRequest::Resync)) => {
+                                // send the current state, and skip the queued ops it already contains
+                                let op = match worker.resync().await {
+                                    Ok(op) => op,
+                                    Err(e) => break Some(error_close_reason(e, &connection_id)),
+                                };
+                                last_seq_sent = op.seq.unwrap_or_default();
+                                last_checksum_sent = op.checksum.clone().unwrap_or_default();
+                                let notices =
+                                    state_notices(&data, user_id, protocol_version, &op).await;
+                                let op = op_for_session(op, finished_limit);
+                                let jsonval = serde_json::to_string(&op).unwrap();
+                                if session.text(jsonval).await.is_err() {
+                                    break None;
+                                }
+                                match send_notices(&mut session, notices).await {
+                                    Ok(()) => Ok(()),
+                                    Err(_) => break None,
+                                }
+                            }
+                            Ok(ClientFrame::Request(ClientRequest::RequestMoreFinished {
+                                after_id,
+                                limit,
+                            })) => {
+                                let limit = limit.min(MAX_FINISHED_CHUNK);
+                                let jsonval = match worker
+                                    .finished_chunk(after_id.clone(), limit)
+                                    .await
+                                {
+                                    Ok(Some((tasks, more))) => {
+                                        let notice = ServerNotice::FinishedChunk {
+                                            after_id,
+                                            tasks,
+                                            more,
+                                        };
+                                        serde_json::to_string(&notice).unwrap()
+                                    }
+                                    // after_id was restored or cleared, so the client's list is out of date
+                                    Ok(None) => {
+                                        let op = match worker.resync().await {
+                                            Ok(op) => op,
+                                            Err(e) => {
+                                                break Some(error_close_reason(e, &connection_id))
+                                            }
+                                        };
+                                        last_seq_sent = op.seq.unwrap_or_default();
+                                        last_checksum_sent =
+                                            op.checksum.clone().unwrap_or_default();
+                                        serde_json::to_string(&op_for_session(op, finished_limit))
+                                            .unwrap()
+                                    }
+                                    Err(e) => break Some(error_close_reason(e, &connection_id)),
+                                };
+                                match session.text(jsonval).await {
+                                    Ok(()) => Ok(()),
+                                    Err(_) => break None,
+                                }
+                            }
+                            // read only sessions can't be editing anything
+                            Ok(ClientFrame::Request(ClientRequest::Editing { .. }))
+                                if read_only =>
+                            {
+                                Ok(())
+                            }
+                            Ok(ClientFrame::Request(ClientRequest::Editing { task_id })) => {
+                                worker
+                                    .send_event(ServerEvent::Editing {
+                                        session_id: connection_id.clone(),
+                                        user_id,
+                                        task_id,
+                                    })
+                                    .await;
+                                Ok(())
+                            }
+                            Ok(ClientFrame::Request(ClientRequest::Reauth {
+                                api_key: new_key,
+                            })) => {
+                                let notice = match check_reauth(&data, user_id, &new_key).await {
+                                    Ok(Some(new_read_only)) => {
+                                        log::info!("reauthenticated");
+                                        api_key = new_key;
+                                        read_only = new_read_only;
+                                        // resuming should use the new key too
+                                        if let Some(mut x) = resume_token
+                                            .as_ref()
+                                            .and_then(|x| data.resume_tokens.get_mut(x))
+                                        {
+                                            x.api_key = api_key.clone();
+                                            x.read_only = read_only;
+                                        }
+                                        ServerNotice::Reauthed { read_only }
+                                    }
+                                    Ok(None) => ServerNotice::ReauthRejected,
+                                    Err(e) => break Some(error_close_reason(e, &connection_id)),
+                                };
+                                let jsonval = serde_json::to_string(&notice).unwrap();
+                                match session.text(jsonval).await {
+                                    Ok(()) => Ok(()),
+                                    Err(_) => break None,
+                                }
+                            }
+                            Err(e) => {
+                                log::info!("{e}");
+                                Err(ServerNotice::Error {
+                                    code: FrameErrorCode::InvalidFrame,
+                                    detail: e.to_string(),
+                                })
+                            }
+                        };
+                        if result.is_ok() {
+                            bad_frames = 0;
                         }
+                        result.err()
                     }
-                    Message::Binary(_) => {
-                        break Some(CloseReason {
-                            code: CloseCode::Unsupported,
-                            description: Some(String::from("Only text supported")),
-                        });
-                    }
+                    Message::Binary(_) => Some(ServerNotice::Error {
+                        code: FrameErrorCode::InvalidFrame,
+                        detail: String::from("Only text supported"),
+                    }),
                     Message::Close(_) => break None,
                     Message::Ping(bytes) => {
                         let _ = session.pong(&bytes).await;
+                        None
                     }
-                    Message::Pong(_) => {
+                    Message::Pong(bytes) => {
                         last_heartbeat = Instant::now();
+                        if let Some(rtt) = heartbeat_rtt(&bytes) {
+                            rtt_ms.store(rtt, Ordering::Relaxed);
+                        }
+                        None
                     }
                     Message::Continuation(_) => {
-                        break Some(CloseReason {
-                            code: CloseCode::Unsupported,
-                            description: Some(String::from("No support for continuation frame.")),
-                        });
+                        break Some(close_reason(
+                            SessionCloseCode::ProtocolError,
+                            "No support for continuation frame",
+                        ));
                     }
                     // no-op; ignore
-                    Message::Nop => {}
+                    Message::Nop => None,
                 };
+                if let Some(notice) = bad_frame {
+                    bad_frames += 1;
+                    if bad_frames > MAX_BAD_FRAMES {
+                        break Some(close_reason(
+                            SessionCloseCode::ProtocolError,
+                            "Too many frames that couldn't be handled",
+                        ));
+                    }
+                    let jsonval = serde_json::to_string(&notice).unwrap();
+                    if session.text(jsonval).await.is_err() {
+                        break None;
+                    }
+                }
             }
             // client WebSocket stream error
             TaskUpdateKind::ClientMessage(Err(err)) => {
@@ -220,134 +570,499 @@ pub async fn manage_updates_ws(
             // heartbeat interval ticked
             TaskUpdateKind::NeedToSendHeartbeat => {
                 // if no heartbeat ping/pong received recently, close the connection
-                if Instant::now().duration_since(last_heartbeat) > CLIENT_TIMEOUT {
+                let client_timeout = data.settings.get().client_timeout();
+                if Instant::now().duration_since(last_heartbeat) > client_timeout {
                     log::info!(
-                        "client has not sent heartbeat in over {CLIENT_TIMEOUT:?}; disconnecting"
+                        "client has not sent heartbeat in over {client_timeout:?}; disconnecting"
                     );
 
                     break None;
                 }
 
-                // send heartbeat ping
-                let _ = session.ping(b"").await;
+                // send heartbeat ping, with the time it was sent, which the pong echoes
+                let sent_time = utils::current_time_millis().to_string();
+                let _ = session.ping(sent_time.as_bytes()).await;
+            }
+            // the client doesn't have the whole state to check
+            TaskUpdateKind::NeedToSendChecksum if finished_limit.is_some() => {}
+            // only the last of the missed ops carries a checksum
+            TaskUpdateKind::NeedToSendChecksum if last_seq_sent < current_seq => {}
+            // checksum interval ticked
+            TaskUpdateKind::NeedToSendChecksum => {
+                let notice = ServerNotice::StateChecksum {
+                    seq: last_seq_sent,
+                    checksum: last_checksum_sent.clone(),
+                };
+                let jsonval = serde_json::to_string(&notice).unwrap();
+                if session.text(jsonval).await.is_err() {
+                    break None;
+                }
+            }
+            TaskUpdateKind::NeedToRevalidate => {
+                match handlers::revalidate_api_key(&data, api_key.clone()).await {
+                    Ok(x) if x == user_id => {}
+                    // the key was revoked, or now belongs to someone else
+                    Ok(_) | Err(AppError::Unauthorized) => {
+                        log::info!("api key is no longer valid; disconnecting");
+                        revoked = true;
+                        break Some(close_reason(
+                            SessionCloseCode::ApiKeyRevoked,
+                            "Api key was revoked",
+                        ));
+                    }
+                    // the auth backend being down isn't the client's fault
+                    Err(e) => log::error!("couldn't revalidate api key: {e}"),
+                }
+                // revoked on another instance, so the event didn't reach this one
+                if device_revoked(&data, user_id, &device).await {
+                    log::info!("device was revoked; disconnecting");
+                    revoked = true;
+                    break Some(close_reason(
+                        SessionCloseCode::DeviceRevoked,
+                        "Device was revoked",
+                    ));
+                }
+            }
+            TaskUpdateKind::ServerClosed if worker.account_deleted() => {
+                log::info!("account deleted; disconnecting");
+                break Some(close_reason(
+                    SessionCloseCode::AccountDeleted,
+                    "Account deleted",
+                ));
+            }
+            TaskUpdateKind::Draining => {
+                let event = ServerEvent::ServerRestarting {
+                    grace_ms: grace_period.as_millis() as u64,
+                };
+                let jsonval = serde_json::to_string(&event).unwrap();
+                if session.text(jsonval).await.is_err() {
+                    break None;
+                }
+            }
+            TaskUpdateKind::DrainDeadline => {
+                log::info!("server is draining; disconnecting");
+                break Some(close_reason(
+                    SessionCloseCode::Draining,
+                    "Server is restarting",
+                ));
+            }
+            // the client should reconnect, which starts a fresh worker
+            TaskUpdateKind::ServerClosed => {
+                log::info!("user worker shut down; disconnecting");
+                break Some(close_reason(
+                    SessionCloseCode::Reloading,
+                    "Server is reloading state",
+                ));
             }
             // got message from server
-            TaskUpdateKind::ServerUpdate(u) => match u {
-                Ok(op) => {
-                    let jsonval = serde_json::to_string(&op).unwrap();
-                    let send_result = session.text(jsonval).await;
-                    match send_result {
-                        Ok(()) => (),
-                        Err(_) => break None,
+            TaskUpdateKind::ServerUpdate(u, backlog) => {
+                let op = match u {
+                    // events aren't part of the state, so there's nothing to resync if they're dropped
+                    // the client knows it's connected
+                    Ok(Update::Event(
+                        ServerEvent::DeviceConnected { ref session_id, .. }
+                        | ServerEvent::Editing { ref session_id, .. },
+                    )) if *session_id == connection_id => continue,
+                    Ok(Update::Event(ServerEvent::DeviceRevoked { ref device_id }))
+                        if device.device_id.as_ref() == Some(device_id) =>
+                    {
+                        log::info!("device was revoked; disconnecting");
+                        revoked = true;
+                        break Some(close_reason(
+                            SessionCloseCode::DeviceRevoked,
+                            "Device was revoked",
+                        ));
+                    }
+                    Ok(Update::Event(event)) => {
+                        let jsonval = serde_json::to_string(&event).unwrap();
+                        if session.text(jsonval).await.is_err() {
+                            break None;
+                        }
+                        continue;
                     }
+                    // already included in a resync
+                    Ok(Update::Op(op)) if op.seq.is_some_and(|seq| seq <= last_seq_sent) => {
+                        continue
+                    }
+                    Ok(Update::Op(op))
+                        if backlog <= data.settings.get().outbound_high_water_mark =>
+                    {
+                        op
+                    }
+                    // the client isn't keeping up, or we've already dropped ops it needed
+                    _ => match data.settings.get().slow_consumer_policy {
+                        SlowConsumerPolicy::Resync => match worker.resync().await {
+                            Ok(op) => BroadcastOp::unserialized(op),
+                            Err(e) => break Some(error_close_reason(e, &connection_id)),
+                        },
+                        SlowConsumerPolicy::Disconnect => {
+                            log::info!("client fell {backlog} ops behind; disconnecting");
+                            break Some(close_reason(
+                                SessionCloseCode::SlowConsumer,
+                                "Client fell too far behind",
+                            ));
+                        }
+                    },
+                };
+                if let Some(seq) = op.seq {
+                    last_seq_sent = seq;
                 }
-                Err(BroadcastStreamRecvError::Lagged(_)) => {}
-            },
+                if let Some(ref checksum) = op.checksum {
+                    last_checksum_sent = checksum.clone();
+                }
+                let notices = state_notices(&data, user_id, protocol_version, &op).await;
+                // sessions without the whole state are sent a trimmed copy instead
+                let jsonval = match finished_limit {
+                    None => op.serialized().into_owned(),
+                    Some(_) => serde_json::to_string(&op_for_session(op.into_op(), finished_limit))
+                        .unwrap(),
+                };
+                let send_result = session.text(jsonval).await;
+                match send_result {
+                    Ok(()) => (),
+                    Err(_) => break None,
+                }
+                if send_notices(&mut session, notices).await.is_err() {
+                    break None;
+                }
+            }
         }
     };
 
     // attempt to close connection gracefully
     let _ = session.close(reason).await;
 
+    data.sessions.remove(&connection_id);
+    if let Some(token) = resume_token {
+        if worker.account_deleted() || revoked {
+            data.resume_tokens.remove(&token);
+        } else if let Some(mut x) = data.resume_tokens.get_mut(&token) {
+            x.expiry_time =
+                Some(utils::current_time_millis() + RESUME_TOKEN_TTL.as_millis() as i64);
+        }
+    }
+    worker.leave_presence(connection_id).await;
+    worker.disconnect().await;
+
     log::info!("disconnected");
 }
 
-pub async fn handle_ws_client_op(
-    data: web::Data<AppData>,
-    per_user_worker_data: Arc<Mutex<PerUserWorkerData>>,
-    req: &str,
-) -> Result<(), AppError> {
-    // try to parse request
-    let op = serde_json::from_str::<WebsocketOp>(req).map_err(handlers::report_serde_error)?;
-
-    // establish connection to database
-    let con: &mut tokio_postgres::Client =
-        &mut *data.pool.get().await.map_err(handlers::report_pool_err)?;
-    // lock the per-user lock
-    {
-        let mut lock = per_user_worker_data.lock().await;
-        // add to db
-        let dbop = operation_service::add(&mut *con, lock.checkpoint_id, op.clone())
+// records that the device connected, if it sent an id, and returns whether it was revoked
+// the database being unreachable doesn't keep devices from connecting
+async fn device_seen(data: &AppData, user_id: i64, device: &DeviceInfo) -> bool {
+    let Some(ref device_id) = device.device_id else {
+        return false;
+    };
+    let result: Result<bool, AppError> = try {
+        let mut con = data.pool.get().await.map_err(handlers::report_pool_err)?;
+        known_device_service::seen(
+            &mut **con,
+            user_id,
+            device_id,
+            device.label.as_deref(),
+            device.platform.as_deref(),
+            utils::current_time_millis(),
+        )
+        .await
+        .map_err(handlers::report_postgres_err)?
+        .revoked
+    };
+    result.unwrap_or_else(|e| {
+        log::error!("couldn't record device: {e}");
+        false
+    })
+}
+
+async fn device_revoked(data: &AppData, user_id: i64, device: &DeviceInfo) -> bool {
+    let Some(ref device_id) = device.device_id else {
+        return false;
+    };
+    let result: Result<bool, AppError> = try {
+        let mut con = data.pool.get().await.map_err(handlers::report_pool_err)?;
+        known_device_service::get_by_device_id(&mut **con, user_id, device_id)
             .await
-            .map_err(handlers::report_postgres_err)?;
-        // apply operation
-        apply_operation(&mut lock.snapshot, op.kind.clone());
-        // broadcast
-        lock.updates_tx.send(op);
+            .map_err(handlers::report_postgres_err)?
+            .is_some_and(|x| x.revoked)
+    };
+    result.unwrap_or_else(|e| {
+        log::error!("couldn't check device: {e}");
+        false
+    })
+}
+
+// whether the api key a session presented in a Reauth is read only, or None if it isn't a valid
+// key for the session's user
+pub async fn check_reauth(
+    data: &AppData,
+    user_id: i64,
+    api_key: &str,
+) -> Result<Option<bool>, AppError> {
+    match get_user_id_if_api_key_valid(data, api_key.to_owned()).await {
+        Ok(x) if x == user_id => Ok(Some(
+            handlers::api_key_is_read_only(&data.pool, api_key).await?,
+        )),
+        Ok(_) | Err(AppError::Unauthorized) => Ok(None),
+        Err(e) => Err(e),
     }
+}
 
-    // create thread server request
-    return Ok(());
+async fn send_notices(
+    session: &mut actix_ws::Session,
+    notices: Vec<ServerNotice>,
+) -> Result<(), actix_ws::Closed> {
+    for notice in notices {
+        session
+            .text(serde_json::to_string(&notice).unwrap())
+            .await?;
+    }
+    Ok(())
 }
 
-fn apply_operation(
-    StateSnapshot {
-        ref mut finished,
-        ref mut live,
-    }: &mut StateSnapshot,
-    op: WebsocketOpKind,
-) {
-    match op {
-        WebsocketOpKind::OverwriteState(s) => {
-            *live = s.live;
-            *finished = s.finished;
+// lanes and focus aren't part of the state, so sessions that know about them are sent them along
+// with it
+async fn state_notices(
+    data: &AppData,
+    user_id: i64,
+    protocol_version: u32,
+    op: &Op,
+) -> Vec<ServerNotice> {
+    let live = match op.kind {
+        OpKind::Base(WebsocketOpKind::OverwriteState(ref snapshot))
+            if protocol_version >= LANES_PROTOCOL_VERSION =>
+        {
+            &snapshot.live
         }
-        WebsocketOpKind::InsLiveTask { value, id } => {
-            live.push_front(LiveTask { id, value });
+        _ => return vec![],
+    };
+    let result: Result<Vec<ServerNotice>, AppError> = try {
+        let con: &mut tokio_postgres::Client =
+            &mut *data.pool.get().await.map_err(handlers::report_pool_err)?;
+        let lanes = handlers::lanes_by_task_id(con, user_id, live).await?;
+        let mut notices = vec![ServerNotice::Lanes { lanes }];
+        if protocol_version >= FOCUS_PROTOCOL_VERSION {
+            let focused = task_focus_service::get_focused_by_user_id(&mut *con, user_id)
+                .await
+                .map_err(handlers::report_postgres_err)?;
+            notices.push(ServerNotice::Focus {
+                live_task_id: focused.as_ref().map(|x| x.task_id.clone()),
+                since: focused.and_then(|x| x.focused_since),
+            });
         }
-        WebsocketOpKind::RestoreFinishedTask { id } => {
-            // if it was found in the finished list, push it to the front
-            if let Some(position) = finished.iter().position(|x| x.id == id) {
-                let FinishedTask { id, value, .. } = finished.remove(position).unwrap();
-                live.push_front(LiveTask { id, value });
-            }
+        notices
+    };
+    match result {
+        Ok(notices) => notices,
+        // the client can resync to try again
+        Err(e) => {
+            log::error!("couldn't read lanes and focus: {e}");
+            vec![]
         }
-        WebsocketOpKind::EditLiveTask { id, value } => {
-            for x in live.iter_mut() {
-                if x.id == id {
-                    x.value = value;
-                    break;
-                }
-            }
+    }
+}
+
+// the reason is a SessionClose as json
+fn close_reason(code: SessionCloseCode, detail: impl Into<String>) -> CloseReason {
+    let mut close = SessionClose {
+        code,
+        detail: detail.into(),
+    };
+    let mut description = serde_json::to_string(&close).unwrap();
+    // the error id in the detail can come from the client, so it may not fit
+    while description.len() > MAX_CLOSE_REASON_LEN && close.detail.pop().is_some() {
+        description = serde_json::to_string(&close).unwrap();
+    }
+    CloseReason {
+        code: CloseCode::from(code.close_code()),
+        description: Some(description),
+    }
+}
+
+// includes the connection id so that the user can report it
+fn error_close_reason(e: AppError, connection_id: &str) -> CloseReason {
+    let code = match e {
+        AppError::Unauthorized => SessionCloseCode::AuthFailed,
+        AppError::QuotaExceeded | AppError::TooManyRequests => SessionCloseCode::QuotaExceeded,
+        AppError::DecodeError | AppError::BadRequest => SessionCloseCode::ProtocolError,
+        AppError::Unavailable => SessionCloseCode::Unavailable,
+        AppError::InternalServerError | AppError::NotFound | AppError::Unknown => {
+            SessionCloseCode::ServerError
         }
-        WebsocketOpKind::DelLiveTask { id } => {
-            live.retain(|x| x.id != id);
+    };
+    close_reason(code, format!("{e} (error_id: {connection_id})"))
+}
+
+// errors handling a client's frame that the session can carry on after are sent to the client as
+// an Error, and the rest close the session
+fn frame_error(e: AppError, connection_id: &str) -> Result<ServerNotice, CloseReason> {
+    let code = match e {
+        AppError::BadRequest => FrameErrorCode::BadRequest,
+        AppError::NotFound => FrameErrorCode::NotFound,
+        AppError::TooManyRequests => FrameErrorCode::TooManyRequests,
+        AppError::QuotaExceeded => FrameErrorCode::QuotaExceeded,
+        _ => return Err(error_close_reason(e, connection_id)),
+    };
+    Ok(ServerNotice::Error {
+        code,
+        detail: format!("{e} (error_id: {connection_id})"),
+    })
+}
+
+// how long ago a heartbeat was sent, from the time in its pong
+// clients may also pong on their own, with whatever payload, so those are ignored
+fn heartbeat_rtt(payload: &[u8]) -> Option<i64> {
+    let sent_time = std::str::from_utf8(payload).ok()?.parse::<i64>().ok()?;
+    Some(utils::current_time_millis() - sent_time).filter(|x| *x >= 0)
+}
+
+// for sessions with a finished_limit: trims the finished list of states they're sent, and drops
+// checksums, which they can't check without the whole list
+// the finished tasks a session has are always the most recent ones, since tasks are only ever
+// finished onto the front, so older ones can be fetched after the oldest it has
+fn op_for_session(mut op: Op, finished_limit: Option<usize>) -> Op {
+    if let Some(limit) = finished_limit {
+        if let OpKind::Base(WebsocketOpKind::OverwriteState(ref mut snapshot)) = op.kind {
+            snapshot.finished.truncate(limit);
         }
-        WebsocketOpKind::MvLiveTask { id_ins, id_del } => {
-            let ins_pos = live.iter().position(|x| x.id == id_ins);
-            let del_pos = live.iter().position(|x| x.id == id_del);
+        op.checksum = None;
+    }
+    op
+}
 
-            if let (Some(ins_pos), Some(del_pos)) = (ins_pos, del_pos) {
-                let removed = live.remove(del_pos).unwrap();
-                live.insert(ins_pos, removed);
-            }
+pub fn overwrite_state_op(snapshot: StateSnapshot, seq: i64, checksum: String) -> Op {
+    Op {
+        alleged_time: utils::current_time_millis(),
+        kind: OpKind::Base(WebsocketOpKind::OverwriteState(snapshot)),
+        seq: Some(seq),
+        checksum: Some(checksum),
+        idempotency_key: None,
+        value_encoding: None,
+    }
+}
+
+// what to tell the client that sent an op, once the worker has dealt with it
+pub fn op_notice(outcome: ApplyOutcome, wants_id: bool) -> Option<ServerNotice> {
+    match outcome {
+        ApplyOutcome::Applied(Op {
+            seq: Some(seq),
+            kind: OpKind::Base(WebsocketOpKind::InsLiveTask { id, .. }),
+            ..
+        }) if wants_id => Some(ServerNotice::OpAck { seq, id }),
+        // everyone gets the op itself through the broadcast
+        ApplyOutcome::Applied(_) => None,
+        // the client already got whatever it needed the first time
+        ApplyOutcome::Duplicate => None,
+        ApplyOutcome::Rejected(reason) => Some(ServerNotice::OpRejected { reason }),
+    }
+}
+
+// every task value the op sets
+pub fn op_values(kind: &OpKind) -> Vec<&String> {
+    match kind {
+        OpKind::Base(WebsocketOpKind::OverwriteState(s)) => s
+            .live
+            .iter()
+            .map(|x| &x.value)
+            .chain(s.finished.iter().map(|x| &x.value))
+            .collect(),
+        OpKind::Base(WebsocketOpKind::InsLiveTask { value, .. }) => vec![value],
+        OpKind::Base(WebsocketOpKind::EditLiveTask { value, .. }) => vec![value],
+        OpKind::Ext(ExtOpKind::InsLiveTaskNew { value }) => vec![value],
+        OpKind::Ext(ExtOpKind::InsLiveTaskBetween { value, .. }) => vec![value],
+        OpKind::Ext(ExtOpKind::InsLiveTasks { tasks }) => tasks.iter().map(|x| &x.value).collect(),
+        OpKind::Ext(ExtOpKind::CommentAdd { value, .. }) => vec![value],
+        OpKind::Ext(ExtOpKind::HabitAdd { value, .. }) => vec![value],
+        OpKind::Ext(ExtOpKind::HabitEdit { value, .. }) => vec![value],
+        _ => vec![],
+    }
+}
+
+fn op_values_mut(kind: &mut OpKind) -> Vec<&mut String> {
+    match kind {
+        OpKind::Base(WebsocketOpKind::OverwriteState(s)) => s
+            .live
+            .iter_mut()
+            .map(|x| &mut x.value)
+            .chain(s.finished.iter_mut().map(|x| &mut x.value))
+            .collect(),
+        OpKind::Base(WebsocketOpKind::InsLiveTask { value, .. }) => vec![value],
+        OpKind::Base(WebsocketOpKind::EditLiveTask { value, .. }) => vec![value],
+        OpKind::Ext(ExtOpKind::InsLiveTaskNew { value }) => vec![value],
+        OpKind::Ext(ExtOpKind::InsLiveTaskBetween { value, .. }) => vec![value],
+        OpKind::Ext(ExtOpKind::InsLiveTasks { tasks }) => {
+            tasks.iter_mut().map(|x| &mut x.value).collect()
         }
-        WebsocketOpKind::RevLiveTask { id1, id2 } => {
-            let pos1 = live.iter().position(|x| x.id == id1);
-            let pos2 = live.iter().position(|x| x.id == id2);
-
-            // order
-            let (start_pos, end_pos) = if pos1 <= pos2 {
-                (pos1, pos2)
-            } else {
-                (pos2, pos1)
-            };
+        OpKind::Ext(ExtOpKind::CommentAdd { value, .. }) => vec![value],
+        OpKind::Ext(ExtOpKind::HabitAdd { value, .. }) => vec![value],
+        OpKind::Ext(ExtOpKind::HabitEdit { value, .. }) => vec![value],
+        _ => vec![],
+    }
+}
 
-            // reverse between specified indexes
-            if let (Some(start_pos), Some(end_pos)) = (start_pos, end_pos) {
-                live.make_contiguous();
-                let (s, _) = live.as_mut_slices();
-                s[start_pos..=end_pos].reverse();
-            }
+// strips control characters other than newlines and tabs; postgres can't store NUL in text at all
+pub fn sanitize_values(kind: &mut OpKind) {
+    for value in op_values_mut(kind) {
+        value.retain(|c| !c.is_control() || c == '\n' || c == '\t');
+    }
+}
+
+// bounds on how big a user's state can get, so one client can't bloat every snapshot and checkpoint
+#[derive(Clone, Copy, Debug)]
+pub struct TaskLimits {
+    // in bytes
+    pub max_value_len: usize,
+    pub max_live_tasks: Option<usize>,
+    pub max_finished_tasks: Option<usize>,
+}
+
+// lists that are already over a limit may still shrink, so lowering a limit doesn't lock anyone out
+pub fn check_limits(
+    snapshot: &StateSnapshot,
+    op: &OpKind,
+    limits: &TaskLimits,
+) -> Result<(), OpRejectReason> {
+    let max_len = limits.max_value_len;
+    if op_values(op).into_iter().any(|x| x.len() > max_len) {
+        return Err(OpRejectReason::ValueTooLong { max_len });
+    }
+
+    // how long each list would get, at most
+    let (live, finished) = (snapshot.live.len(), snapshot.finished.len());
+    let (new_live, new_finished) = match op {
+        OpKind::Base(WebsocketOpKind::OverwriteState(s)) => (s.live.len(), s.finished.len()),
+        OpKind::Base(WebsocketOpKind::InsLiveTask { .. }) => (live + 1, finished),
+        OpKind::Base(WebsocketOpKind::RestoreFinishedTask { .. }) => (live + 1, finished),
+        OpKind::Base(WebsocketOpKind::FinishLiveTask { .. }) => (live, finished + 1),
+        OpKind::Ext(ExtOpKind::InsLiveTaskNew { .. }) => (live + 1, finished),
+        OpKind::Ext(ExtOpKind::InsLiveTaskBetween { .. }) => (live + 1, finished),
+        OpKind::Ext(ExtOpKind::InsLiveTasks { tasks }) => (live + tasks.len(), finished),
+        OpKind::Ext(ExtOpKind::FinishManyLiveTasks { ids, .. }) => (live, finished + ids.len()),
+        _ => (live, finished),
+    };
+    if let Some(max) = limits.max_live_tasks {
+        if new_live > max && new_live > live {
+            return Err(OpRejectReason::TooManyLiveTasks { max });
         }
-        WebsocketOpKind::FinishLiveTask { id, status } => {
-            if let Some(pos_in_live) = live.iter().position(|x| x.id == id) {
-                finished.push_front(FinishedTask {
-                    id,
-                    value: live.remove(pos_in_live).unwrap().value,
-                    status,
-                });
-            }
+    }
+    if let Some(max) = limits.max_finished_tasks {
+        if new_finished > max && new_finished > finished {
+            return Err(OpRejectReason::TooManyFinishedTasks { max });
+        }
+    }
+    Ok(())
+}
+
+// the server never reads task values, but they must be marked consistently
+// so clients can tell which ones to decrypt
+pub fn check_value_encoding(op: &Op) -> Result<(), OpRejectReason> {
+    let encrypted = op.value_encoding == Some(ValueEncoding::Encrypted);
+    let valid = op_values(&op.kind).into_iter().all(|value| {
+        match value.strip_prefix(ENCRYPTED_VALUE_PREFIX) {
+            Some(ciphertext) => encrypted && utils::is_base64(ciphertext),
+            None => !encrypted,
         }
+    });
+    if !valid {
+        return Err(OpRejectReason::InvalidValueEncoding);
     }
+    Ok(())
 }
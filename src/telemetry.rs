@@ -0,0 +1,83 @@
+// sets up logging, and optionally exports tracing spans over OTLP
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+// changes which log records are emitted, while running
+pub type LogFilterHandle = reload::Handle<EnvFilter, Registry>;
+
+pub fn init(
+    log_format: LogFormat,
+    otlp_endpoint: Option<String>,
+) -> Result<LogFilterHandle, Box<dyn std::error::Error + 'static>> {
+    let otel_layer = match otlp_endpoint {
+        Some(otlp_endpoint) => {
+            let tracer = opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(
+                    opentelemetry_otlp::new_exporter()
+                        .tonic()
+                        .with_endpoint(otlp_endpoint),
+                )
+                .with_trace_config(opentelemetry_sdk::trace::config().with_resource(
+                    opentelemetry_sdk::Resource::new(vec![KeyValue::new(
+                        "service.name",
+                        super::SERVICE,
+                    )]),
+                ))
+                .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+            // spans are exported regardless of RUST_LOG
+            Some(
+                tracing_opentelemetry::layer()
+                    .with_tracer(tracer)
+                    .with_filter(LevelFilter::INFO),
+            )
+        }
+        None => None,
+    };
+
+    // log records are forwarded to tracing, so they pick up the fields of the current span
+    let fmt_layer = match log_format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed(),
+    };
+    // the filter can be swapped out when settings are reloaded
+    let (filter, log_filter) = reload::Layer::new(EnvFilter::from_default_env());
+    tracing_subscriber::registry()
+        .with(fmt_layer.with_filter(filter))
+        .with(otel_layer)
+        .try_init()?;
+
+    Ok(log_filter)
+}
+
+// directives are like RUST_LOG's, which is used instead if there aren't any
+pub fn set_log_filter(
+    log_filter: &LogFilterHandle,
+    directives: Option<&str>,
+) -> Result<(), String> {
+    let filter = match directives {
+        Some(directives) => EnvFilter::try_new(directives)
+            .map_err(|e| format!("invalid log filter {directives:?}: {e}"))?,
+        None => EnvFilter::from_default_env(),
+    };
+    log_filter.reload(filter).map_err(|e| e.to_string())
+}
+
+// flushes any spans that haven't been exported yet
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
@@ -0,0 +1,132 @@
+use super::db_types::*;
+use super::protocol::TaskTemplate;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for Template {
+    // select * from template order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> Template {
+        Template {
+            template_id: row.get("template_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            name: row.get("name"),
+            jsonval: row.get("jsonval"),
+        }
+    }
+}
+
+#[tracing::instrument(skip(con, template))]
+pub async fn add(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    name: String,
+    template: TaskTemplate,
+) -> Result<Template, tokio_postgres::Error> {
+    let jsonval = serde_json::to_string(&template).unwrap();
+
+    let row = con
+        .query_one(
+            "INSERT INTO
+             template(
+                 creator_user_id,
+                 name,
+                 jsonval
+             )
+             VALUES($1, $2, $3)
+             RETURNING template_id, creation_time
+            ",
+            &[&creator_user_id, &name, &jsonval],
+        )
+        .await?;
+
+    // return template
+    Ok(Template {
+        template_id: row.get(0),
+        creation_time: row.get(1),
+        creator_user_id,
+        name,
+        jsonval,
+    })
+}
+
+// only finds the user's own templates
+#[tracing::instrument(skip(con))]
+pub async fn get_by_template_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    template_id: i64,
+) -> Result<Option<Template>, tokio_postgres::Error> {
+    let result = con
+        .query_opt(
+            "SELECT * FROM template WHERE creator_user_id=$1 AND template_id=$2",
+            &[&creator_user_id, &template_id],
+        )
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn get_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<Vec<Template>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT * FROM template WHERE creator_user_id=$1 ORDER BY template_id",
+            &[&creator_user_id],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// returns whether the user had a template with this id
+#[tracing::instrument(skip(con, template))]
+pub async fn update(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    template_id: i64,
+    name: String,
+    template: TaskTemplate,
+) -> Result<bool, tokio_postgres::Error> {
+    let jsonval = serde_json::to_string(&template).unwrap();
+    let updated = con
+        .execute(
+            "UPDATE template SET name = $3, jsonval = $4 WHERE creator_user_id = $1 AND template_id = $2",
+            &[&creator_user_id, &template_id, &name, &jsonval],
+        )
+        .await?;
+    Ok(updated > 0)
+}
+
+// returns whether the user had a template with this id
+#[tracing::instrument(skip(con))]
+pub async fn remove(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    template_id: i64,
+) -> Result<bool, tokio_postgres::Error> {
+    let removed = con
+        .execute(
+            "DELETE FROM template WHERE creator_user_id = $1 AND template_id = $2",
+            &[&creator_user_id, &template_id],
+        )
+        .await?;
+    Ok(removed > 0)
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM template WHERE creator_user_id = $1",
+        &[&creator_user_id],
+    )
+    .await
+}
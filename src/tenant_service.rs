@@ -0,0 +1,97 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for Tenant {
+    // select * from tenant order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> Tenant {
+        Tenant {
+            tenant_id: row.get("tenant_id"),
+            creation_time: row.get("creation_time"),
+            name: row.get("name"),
+            admin_user_id: row.get("admin_user_id"),
+        }
+    }
+}
+
+// None if there's already a tenant with that name or admin
+#[tracing::instrument(skip(con))]
+pub async fn add(
+    con: &mut impl GenericClient,
+    name: String,
+    admin_user_id: i64,
+) -> Result<Option<Tenant>, tokio_postgres::Error> {
+    let row = con
+        .query_opt(
+            "INSERT INTO
+             tenant(
+                 name,
+                 admin_user_id
+             )
+             VALUES($1, $2)
+             ON CONFLICT DO NOTHING
+             RETURNING tenant_id, creation_time
+            ",
+            &[&name, &admin_user_id],
+        )
+        .await?;
+
+    // return tenant
+    Ok(row.map(|row| Tenant {
+        tenant_id: row.get(0),
+        creation_time: row.get(1),
+        name,
+        admin_user_id,
+    }))
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn get_by_tenant_id(
+    con: &mut impl GenericClient,
+    tenant_id: i64,
+) -> Result<Option<Tenant>, tokio_postgres::Error> {
+    let result = con
+        .query_opt("SELECT * FROM tenant WHERE tenant_id=$1", &[&tenant_id])
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn get_by_admin_user_id(
+    con: &mut impl GenericClient,
+    admin_user_id: i64,
+) -> Result<Option<Tenant>, tokio_postgres::Error> {
+    let result = con
+        .query_opt(
+            "SELECT * FROM tenant WHERE admin_user_id=$1",
+            &[&admin_user_id],
+        )
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+// oldest first
+#[tracing::instrument(skip(con))]
+pub async fn get_all(con: &mut impl GenericClient) -> Result<Vec<Tenant>, tokio_postgres::Error> {
+    let result = con
+        .query("SELECT * FROM tenant ORDER BY tenant_id", &[])
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// the tenant's users have to be removed first
+// returns whether there was a tenant with that id
+#[tracing::instrument(skip(con))]
+pub async fn remove(
+    con: &mut impl GenericClient,
+    tenant_id: i64,
+) -> Result<bool, tokio_postgres::Error> {
+    let removed = con
+        .execute("DELETE FROM tenant WHERE tenant_id = $1", &[&tenant_id])
+        .await?;
+    Ok(removed > 0)
+}
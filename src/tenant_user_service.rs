@@ -0,0 +1,105 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for TenantUser {
+    // select * from tenant_user order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> TenantUser {
+        TenantUser {
+            tenant_user_id: row.get("tenant_user_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            tenant_id: row.get("tenant_id"),
+        }
+    }
+}
+
+// moves the user into the tenant, out of the one they were in
+#[tracing::instrument(skip(con))]
+pub async fn set(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    tenant_id: i64,
+) -> Result<TenantUser, tokio_postgres::Error> {
+    let row = con
+        .query_one(
+            "INSERT INTO
+             tenant_user(
+                 creator_user_id,
+                 tenant_id
+             )
+             VALUES($1, $2)
+             ON CONFLICT (creator_user_id) DO UPDATE
+             SET creation_time = EXCLUDED.creation_time, tenant_id = EXCLUDED.tenant_id
+             RETURNING tenant_user_id, creation_time
+            ",
+            &[&creator_user_id, &tenant_id],
+        )
+        .await?;
+
+    // return tenant user
+    Ok(TenantUser {
+        tenant_user_id: row.get(0),
+        creation_time: row.get(1),
+        creator_user_id,
+        tenant_id,
+    })
+}
+
+#[tracing::instrument(skip(con))]
+pub async fn get_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<Option<TenantUser>, tokio_postgres::Error> {
+    let result = con
+        .query_opt(
+            "SELECT * FROM tenant_user WHERE creator_user_id=$1",
+            &[&creator_user_id],
+        )
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+// oldest first
+#[tracing::instrument(skip(con))]
+pub async fn get_all_by_tenant_id(
+    con: &mut impl GenericClient,
+    tenant_id: i64,
+) -> Result<Vec<TenantUser>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT * FROM tenant_user WHERE tenant_id = $1 ORDER BY tenant_user_id",
+            &[&tenant_id],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_tenant_id(
+    con: &mut impl GenericClient,
+    tenant_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM tenant_user WHERE tenant_id = $1",
+        &[&tenant_id],
+    )
+    .await
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM tenant_user WHERE creator_user_id = $1",
+        &[&creator_user_id],
+    )
+    .await
+}
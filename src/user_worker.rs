@@ -0,0 +1,1772 @@
+// every connected user has a worker task which owns their snapshot
+// connections send it commands over a channel, so the snapshot never needs to be locked,
+// and per-user background work has somewhere to live
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use deadpool_postgres::GenericClient;
+use serde::{Deserialize, Serialize};
+use todoproxy_api::{FinishedTask, LiveTask, StateSnapshot, TaskStatus, WebsocketOpKind};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tracing::Instrument;
+
+use crate::event_stream::TaskEvent;
+use crate::handlers::{self, AppError};
+use crate::job_queue::{self, Job};
+use crate::protocol::response::{AccountDelete, AdminUser};
+use crate::protocol::{
+    ExtOpKind, Op, OpKind, OpRejectReason, Priority, ServerEvent, TaskTemplate, DAY_FORMAT,
+    ENCRYPTED_VALUE_PREFIX,
+};
+use crate::snapshot_cache::SnapshotCache;
+use crate::state_machine::{apply_operation, check_operation, snapshot_checksum};
+use crate::task_updates::{
+    check_limits, check_value_encoding, overwrite_state_op, sanitize_values,
+};
+use crate::{
+    api_token_service, archived_task_service, attachment_service, audit, audit_event_service,
+    checkpoint_service, db_types, deleted_task_service, email_alias_service, external_task_service,
+    fanout, github, github_link_service, goal_service, habit_check_in_service,
+    habit_definition_service, idempotency_key_service, known_device_service,
+    notification_digest_service, op_usage_service, operation_service, preference_service,
+    preferences, push_subscription_service, quota, read_only_api_key_service, reminder_service,
+    rollover_service, snapshot_codec, stats, task_comment_service, task_estimate_service,
+    task_focus_service, task_goal_service, task_lane_service, template_service,
+    tenant_user_service, utils, webhook_service, AppData,
+};
+
+/// How many commands may be queued for a worker before senders have to wait.
+const COMMAND_CHANNEL_CAPACITY: usize = 100;
+
+/// How many idempotency keys a worker remembers; older ones are still checked against the database.
+const RECENT_IDEMPOTENCY_KEYS: usize = 1000;
+
+/// How long a worker with no connections stays loaded before it checkpoints and shuts down,
+/// so that a burst of one-off ops (REST, webhooks, jobs) shares a single load and checkpoint.
+const IDLE_EVICT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Device labels, ids and platforms are cut to this many characters.
+const MAX_DEVICE_LABEL_LEN: usize = 64;
+
+/// How many ops are fetched from the database at once when replaying a checkpoint.
+const REPLAY_BATCH_SIZE: i32 = 1000;
+
+/// How often a long replay logs how far it's got.
+const REPLAY_PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Streaks, in days, that sessions are told about when a habit reaches them.
+const STREAK_MILESTONES: &[u64] = &[7, 30, 100, 365];
+
+enum Command {
+    // a new connection wants the current state and to be subscribed to updates
+    Connect {
+        reply: oneshot::Sender<Result<Subscription, AppError>>,
+    },
+    // a connection closed
+    Disconnect,
+    // a websocket wants its user's other devices to know it's open
+    // replies with the devices that were already connected
+    // replies with None if the user already has max_devices open (0 for unlimited)
+    JoinPresence {
+        session_id: String,
+        device: DeviceInfo,
+        max_devices: usize,
+        reply: oneshot::Sender<Option<Vec<ServerEvent>>>,
+    },
+    // a websocket that joined presence is closing
+    LeavePresence {
+        session_id: String,
+    },
+    // a connection sent an op
+    ApplyOp {
+        op: Op,
+        span: tracing::Span,
+        origin: audit::Origin,
+        reply: oneshot::Sender<Result<ApplyOutcome, AppError>>,
+    },
+    // a connection wants the current state resent
+    Resync {
+        reply: oneshot::Sender<Op>,
+    },
+    // a connection that only has the most recent finished tasks wants older ones
+    FinishedChunk {
+        after_id: Option<String>,
+        limit: usize,
+        reply: oneshot::Sender<Option<(Vec<FinishedTask>, bool)>>,
+    },
+    // an admin wants to know what the worker is up to
+    Stats {
+        reply: oneshot::Sender<AdminUser>,
+    },
+    // the user wants all their data removed; the worker shuts down afterwards
+    DeleteAccount {
+        reply: oneshot::Sender<Result<AccountDelete, AppError>>,
+    },
+    // another instance stored ops for this user
+    CatchUp,
+    // something to tell every session, that isn't an op
+    SendEvent {
+        event: ServerEvent,
+    },
+    // an admin wants a checkpoint written now, and maybe the worker shut down afterwards
+    Checkpoint {
+        evict: bool,
+        reply: oneshot::Sender<Result<i64, AppError>>,
+    },
+}
+
+// what became of an op a connection sent
+pub enum ApplyOutcome {
+    // the op as it was stored and broadcast
+    Applied(Op),
+    // an op with the same idempotency key was already applied, so this one was dropped
+    Duplicate,
+    Rejected(OpRejectReason),
+}
+
+// what a worker broadcasts to its sessions
+#[derive(Clone, Debug)]
+pub enum Update {
+    Op(BroadcastOp),
+    Event(ServerEvent),
+}
+
+// an op along with its json, so every session that sends it as is shares one serialization
+// rather than each doing its own, which adds up for big OverwriteStates and users with many devices
+#[derive(Clone, Debug)]
+pub struct BroadcastOp {
+    op: Arc<Op>,
+    json: Option<Arc<str>>,
+}
+
+impl BroadcastOp {
+    pub fn new(op: Op) -> BroadcastOp {
+        let json = serde_json::to_string(&op).unwrap();
+        BroadcastOp {
+            op: Arc::new(op),
+            json: Some(json.into()),
+        }
+    }
+
+    // for ops only one session sends, like resyncs, which serializes it itself
+    pub fn unserialized(op: Op) -> BroadcastOp {
+        BroadcastOp {
+            op: Arc::new(op),
+            json: None,
+        }
+    }
+
+    pub fn serialized(&self) -> Cow<'_, str> {
+        match self.json {
+            Some(ref json) => Cow::Borrowed(json),
+            None => Cow::Owned(serde_json::to_string(&*self.op).unwrap()),
+        }
+    }
+
+    pub fn into_op(self) -> Op {
+        Arc::unwrap_or_clone(self.op)
+    }
+}
+
+impl Deref for BroadcastOp {
+    type Target = Op;
+
+    fn deref(&self) -> &Op {
+        &self.op
+    }
+}
+
+// what a websocket that joined presence said about its device
+#[derive(Clone, Debug, Default)]
+pub struct DeviceInfo {
+    pub label: Option<String>,
+    pub device_id: Option<String>,
+    pub platform: Option<String>,
+}
+
+impl DeviceInfo {
+    // without control characters, cut to length, and unset if that leaves nothing
+    pub fn new(
+        label: Option<String>,
+        device_id: Option<String>,
+        platform: Option<String>,
+    ) -> DeviceInfo {
+        let clean = |x: Option<String>| {
+            x.map(|x| {
+                x.chars()
+                    .filter(|c| !c.is_control())
+                    .take(MAX_DEVICE_LABEL_LEN)
+                    .collect::<String>()
+                    .trim()
+                    .to_owned()
+            })
+            .filter(|x| !x.is_empty())
+        };
+        DeviceInfo {
+            label: clean(label),
+            device_id: clean(device_id),
+            platform: clean(platform),
+        }
+    }
+
+    fn connected_event(&self, session_id: String) -> ServerEvent {
+        ServerEvent::DeviceConnected {
+            session_id,
+            label: self.label.clone(),
+            device_id: self.device_id.clone(),
+            platform: self.platform.clone(),
+        }
+    }
+}
+
+// what a new connection needs to get started
+pub struct Subscription {
+    // ops applied after initial_op, and events
+    pub updates_rx: broadcast::Receiver<Update>,
+    // an OverwriteState with the current state
+    pub initial_op: Op,
+}
+
+#[derive(Clone)]
+pub struct UserWorkerHandle {
+    tx: mpsc::Sender<Command>,
+    // set before the worker shuts down because the account was deleted
+    account_deleted: Arc<AtomicBool>,
+}
+
+impl UserWorkerHandle {
+    pub async fn disconnect(&self) {
+        let _ = self.tx.send(Command::Disconnect).await;
+    }
+
+    pub async fn apply_op(&self, op: Op) -> Result<ApplyOutcome, AppError> {
+        let (reply, rx) = oneshot::channel();
+        let span = tracing::Span::current();
+        let origin = audit::current();
+        self.tx
+            .send(Command::ApplyOp {
+                op,
+                span,
+                origin,
+                reply,
+            })
+            .await
+            .map_err(|_| AppError::InternalServerError)?;
+        rx.await.map_err(|_| AppError::InternalServerError)?
+    }
+
+    pub async fn stats(&self) -> Result<AdminUser, AppError> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(Command::Stats { reply })
+            .await
+            .map_err(|_| AppError::NotFound)?;
+        rx.await.map_err(|_| AppError::NotFound)
+    }
+
+    // returns the id of the new checkpoint
+    pub async fn checkpoint(&self, evict: bool) -> Result<i64, AppError> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(Command::Checkpoint { evict, reply })
+            .await
+            .map_err(|_| AppError::NotFound)?;
+        rx.await.map_err(|_| AppError::NotFound)?
+    }
+
+    // removes everything stored for the user, and disconnects all their sessions
+    pub async fn delete_account(&self) -> Result<AccountDelete, AppError> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(Command::DeleteAccount { reply })
+            .await
+            .map_err(|_| AppError::InternalServerError)?;
+        rx.await.map_err(|_| AppError::InternalServerError)?
+    }
+
+    // asks the worker to apply ops other instances stored; doesn't wait for it to happen
+    pub async fn catch_up(&self) {
+        let _ = self.tx.send(Command::CatchUp).await;
+    }
+
+    // tells the user's other websockets that this one is open
+    // returns a DeviceConnected event for each device that already was
+    pub async fn join_presence(
+        &self,
+        session_id: String,
+        device: DeviceInfo,
+        max_devices: usize,
+    ) -> Result<Option<Vec<ServerEvent>>, AppError> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(Command::JoinPresence {
+                session_id,
+                device,
+                max_devices,
+                reply,
+            })
+            .await
+            .map_err(|_| AppError::InternalServerError)?;
+        rx.await.map_err(|_| AppError::InternalServerError)
+    }
+
+    pub async fn leave_presence(&self, session_id: String) {
+        let _ = self.tx.send(Command::LeavePresence { session_id }).await;
+    }
+
+    // sessions that aren't connected when the worker gets to it miss the event
+    pub async fn send_event(&self, event: ServerEvent) {
+        let _ = self.tx.send(Command::SendEvent { event }).await;
+    }
+
+    // whether the worker shut down because the account was deleted, rather than to be reloaded
+    pub fn account_deleted(&self) -> bool {
+        self.account_deleted.load(Ordering::SeqCst)
+    }
+
+    pub async fn finished_chunk(
+        &self,
+        after_id: Option<String>,
+        limit: usize,
+    ) -> Result<Option<(Vec<FinishedTask>, bool)>, AppError> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(Command::FinishedChunk {
+                after_id,
+                limit,
+                reply,
+            })
+            .await
+            .map_err(|_| AppError::InternalServerError)?;
+        rx.await.map_err(|_| AppError::InternalServerError)
+    }
+
+    pub async fn resync(&self) -> Result<Op, AppError> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(Command::Resync { reply })
+            .await
+            .map_err(|_| AppError::InternalServerError)?;
+        rx.await.map_err(|_| AppError::InternalServerError)
+    }
+}
+
+// disconnects from the worker when dropped, for connections without an obvious place to do it
+pub struct Registration(pub UserWorkerHandle);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let worker = self.0.clone();
+        tokio::spawn(async move { worker.disconnect().await });
+    }
+}
+
+struct PerUserWorkerData {
+    // user
+    user_id: i64,
+    // websockets send to this channel when they receive an event
+    updates_tx: broadcast::Sender<Update>,
+    // snapshot at the current state of the channel
+    snapshot: StateSnapshot,
+    // checksum of snapshot
+    checksum: String,
+    // id of checkpoint
+    checkpoint_id: i64,
+    // seq (operation_id) of the last op applied to the snapshot
+    last_seq: i64,
+    // number of ops applied since the checkpoint was written
+    ops_since_checkpoint: usize,
+    // number of open websockets
+    connections: usize,
+    // devices of the websockets that joined presence, by session id
+    // only covers sessions connected to this instance
+    devices: HashMap<String, DeviceInfo>,
+    // when the user last connected, disconnected, or sent an op
+    last_activity_time: i64,
+    // idempotency keys of recently applied ops, oldest first
+    recent_idempotency_keys: VecDeque<String>,
+    recent_idempotency_key_set: HashSet<String>,
+}
+
+// registers a new connection with the user's worker, starting the worker if needed
+pub async fn connect(
+    data: &AppData,
+    user_id: i64,
+) -> Result<(UserWorkerHandle, Subscription), AppError> {
+    loop {
+        let handle = data
+            .user_worker_data
+            .entry(user_id)
+            .or_insert_with(|| spawn(data.clone(), user_id))
+            .clone();
+
+        let (reply, rx) = oneshot::channel();
+        // if the worker is shutting down it drops our command, and by then it has removed itself
+        // from the map, so the next attempt starts a fresh worker
+        if handle.tx.send(Command::Connect { reply }).await.is_err() {
+            continue;
+        }
+        match rx.await {
+            Ok(subscription) => return subscription.map(|s| (handle, s)),
+            Err(_) => continue,
+        }
+    }
+}
+
+fn spawn(data: AppData, user_id: i64) -> UserWorkerHandle {
+    let (tx, rx) = mpsc::channel(COMMAND_CHANNEL_CAPACITY);
+    let handle = UserWorkerHandle {
+        tx,
+        account_deleted: Arc::new(AtomicBool::new(false)),
+    };
+    let span = tracing::info_span!("user_worker", user_id, tenant_id = tracing::field::Empty);
+    tokio::spawn(run(data, user_id, handle.clone(), rx).instrument(span));
+    handle
+}
+
+async fn run(
+    data: AppData,
+    user_id: i64,
+    handle: UserWorkerHandle,
+    mut rx: mpsc::Receiver<Command>,
+) {
+    let load_error = match load(&data, user_id).await {
+        Ok(mut worker) => {
+            loop {
+                let command = if worker.connections > 0 {
+                    rx.recv().await
+                } else {
+                    let idle = Duration::from_millis(
+                        (utils::current_time_millis() - worker.last_activity_time).max(0) as u64,
+                    );
+                    match tokio::time::timeout(IDLE_EVICT_TIMEOUT.saturating_sub(idle), rx.recv())
+                        .await
+                    {
+                        Ok(command) => command,
+                        Err(_) => {
+                            worker.evict_idle(&data).await;
+                            break;
+                        }
+                    }
+                };
+                let Some(command) = command else { break };
+                match command {
+                    Command::Connect { reply } => {
+                        // if the connection went away while waiting, it won't disconnect either
+                        if reply.send(Ok(worker.subscribe())).is_ok() {
+                            worker.connections += 1;
+                            worker.last_activity_time = utils::current_time_millis();
+                        }
+                    }
+                    Command::ApplyOp {
+                        op,
+                        span,
+                        origin,
+                        reply,
+                    } => {
+                        let result = audit::scope(origin, worker.apply_op(&data, op))
+                            .instrument(span)
+                            .await;
+                        let _ = reply.send(result);
+                    }
+                    Command::Resync { reply } => {
+                        let _ = reply.send(worker.overwrite_state_op());
+                    }
+                    Command::FinishedChunk {
+                        after_id,
+                        limit,
+                        reply,
+                    } => {
+                        let _ = reply.send(worker.finished_chunk(after_id.as_deref(), limit));
+                    }
+                    Command::Stats { reply } => {
+                        let _ = reply.send(worker.stats());
+                    }
+                    Command::CatchUp => {
+                        let result: Result<(), AppError> = try {
+                            let con: &mut deadpool_postgres::Client =
+                                &mut data.pool.get().await.map_err(handlers::report_pool_err)?;
+                            worker.catch_up(con).await?
+                        };
+                        // the next op or notification tries again
+                        if let Err(e) = result {
+                            log::error!("couldn't catch up user {user_id}: {e}");
+                        }
+                    }
+                    Command::SendEvent { event } => {
+                        let _ = worker.updates_tx.send(Update::Event(event));
+                    }
+                    Command::JoinPresence {
+                        session_id,
+                        device,
+                        max_devices,
+                        reply,
+                    } => {
+                        let _ = reply.send(worker.join_presence(session_id, device, max_devices));
+                    }
+                    Command::LeavePresence { session_id } => {
+                        if worker.devices.remove(&session_id).is_some() {
+                            let _ = worker.updates_tx.send(Update::Event(
+                                ServerEvent::DeviceDisconnected { session_id },
+                            ));
+                        }
+                    }
+                    Command::DeleteAccount { reply } => {
+                        let result = worker.delete_account(&data).await;
+                        let ok = result.is_ok();
+                        if ok {
+                            handle.account_deleted.store(true, Ordering::SeqCst);
+                        }
+                        let _ = reply.send(result);
+                        // there's nothing left to checkpoint, so shut down and disconnect every session
+                        if ok {
+                            log::info!("deleted account of user {user_id}");
+                            break;
+                        }
+                    }
+                    Command::Checkpoint { evict, reply } => {
+                        let result = worker.checkpoint(&data).await;
+                        let ok = result.is_ok();
+                        let _ = reply.send(result);
+                        // dropping the worker closes updates_tx, which disconnects every session
+                        if evict && ok {
+                            log::info!("evicting user {user_id}");
+                            break;
+                        }
+                    }
+                    Command::Disconnect => {
+                        // the worker stays loaded for a while, in case the user comes straight back
+                        worker.connections -= 1;
+                        worker.last_activity_time = utils::current_time_millis();
+                    }
+                }
+            }
+            None
+        }
+        Err(e) => {
+            log::error!("couldn't load state for user {user_id}: {e}");
+            Some(e)
+        }
+    };
+
+    // from here on, connects start a new worker
+    data.user_worker_data
+        .remove_if(&user_id, |_, h| h.tx.same_channel(&handle.tx));
+
+    // commands that were already queued are dropped, so those connects retry with the new worker.
+    // if we couldn't load, fail them instead, since the new worker wouldn't do any better
+    rx.close();
+    while let Some(command) = rx.recv().await {
+        if let (Command::Connect { reply }, Some(e)) = (command, &load_error) {
+            let _ = reply.send(Err(e.clone()));
+        }
+    }
+}
+
+// a user's state as rebuilt from the database
+#[derive(Serialize, Deserialize)]
+pub struct LoadedState {
+    pub snapshot: StateSnapshot,
+    pub checkpoint_id: i64,
+    // the seq of the last op replayed, or 0 if there were none
+    pub last_seq: i64,
+    pub ops_since_checkpoint: usize,
+}
+
+// rebuild the user's state from their latest checkpoint and the ops applied since
+pub async fn load_state(
+    con: &mut deadpool_postgres::Client,
+    user_id: i64,
+) -> Result<LoadedState, AppError> {
+    load_state_from(con, user_id, true).await
+}
+
+// like load_state, but doesn't write, so it works against a read replica
+// users without a checkpoint yet get an empty state, instead of their first checkpoint
+pub async fn read_state(
+    con: &mut deadpool_postgres::Client,
+    user_id: i64,
+) -> Result<LoadedState, AppError> {
+    load_state_from(con, user_id, false).await
+}
+
+async fn load_state_from(
+    con: &mut deadpool_postgres::Client,
+    user_id: i64,
+    create_checkpoint: bool,
+) -> Result<LoadedState, AppError> {
+    // the ops are replayed through a portal, which needs a transaction
+    let mut tx = con
+        .transaction()
+        .await
+        .map_err(handlers::report_postgres_err)?;
+
+    // get recent checkpoint
+    let preexisting_checkpoint = checkpoint_service::get_recent_by_user_id(&mut tx, user_id)
+        .await
+        .map_err(handlers::report_postgres_err)?;
+
+    // if it doesn't exist, create checkpoint
+    let recent_checkpoint = match preexisting_checkpoint {
+        Some(x) => x,
+        // without a checkpoint there can't be any ops
+        None if !create_checkpoint => {
+            return Ok(LoadedState {
+                snapshot: StateSnapshot {
+                    live: VecDeque::new(),
+                    finished: VecDeque::new(),
+                },
+                checkpoint_id: 0,
+                last_seq: 0,
+                ops_since_checkpoint: 0,
+            })
+        }
+        None => checkpoint_service::add(
+            &mut tx,
+            user_id,
+            StateSnapshot {
+                live: VecDeque::new(),
+                finished: VecDeque::new(),
+            },
+        )
+        .await
+        .map_err(handlers::report_postgres_err)?,
+    };
+
+    // apply all operations since this checkpoint
+    let mut state = replay_since(&tx, recent_checkpoint).await?;
+
+    // seq has to keep counting up from the last op, even when it's before the checkpoint
+    if state.last_seq == 0 {
+        state.last_seq = operation_service::get_last_operation_id_by_user_id(&mut tx, user_id)
+            .await
+            .map_err(handlers::report_postgres_err)?
+            .unwrap_or(0);
+    }
+    tx.commit().await.map_err(handlers::report_postgres_err)?;
+    Ok(state)
+}
+
+// rebuild the user's state as it was at the given time, if they had any then
+pub async fn load_state_at(
+    con: &mut deadpool_postgres::Client,
+    user_id: i64,
+    time: i64,
+) -> Result<Option<LoadedState>, AppError> {
+    let checkpoint = checkpoint_service::get_recent_by_user_id_at(&mut *con, user_id, time)
+        .await
+        .map_err(handlers::report_postgres_err)?;
+
+    let checkpoint = match checkpoint {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    // ops made on a later checkpoint all come after it, so only this checkpoint's matter
+    let operations =
+        operation_service::get_operations_since_until(&mut *con, checkpoint.checkpoint_id, time)
+            .await
+            .map_err(handlers::report_postgres_err)?;
+
+    replay(checkpoint, operations).map(Some)
+}
+
+// rebuild the user's state as it was just before the op stored under seq, on the given checkpoint
+pub async fn load_state_before(
+    con: &mut deadpool_postgres::Client,
+    checkpoint_id: i64,
+    seq: i64,
+) -> Result<LoadedState, AppError> {
+    let checkpoint = checkpoint_service::get_by_checkpoint_id(&mut *con, checkpoint_id)
+        .await
+        .map_err(handlers::report_postgres_err)?
+        .ok_or(AppError::InternalServerError)?;
+    let operations =
+        operation_service::get_operations_by_checkpoint_id_before(&mut *con, checkpoint_id, seq)
+            .await
+            .map_err(handlers::report_postgres_err)?;
+    replay(checkpoint, operations)
+}
+
+// apply the ops made on a checkpoint to it, a batch at a time, so long logs aren't held in memory
+pub async fn replay_since(
+    tx: &deadpool_postgres::Transaction<'_>,
+    checkpoint: db_types::Checkpoint,
+) -> Result<LoadedState, AppError> {
+    let portal = operation_service::bind_operations_since(tx, checkpoint.checkpoint_id)
+        .await
+        .map_err(handlers::report_postgres_err)?;
+    let mut state = replay(checkpoint, vec![])?;
+
+    let start = Instant::now();
+    let mut last_progress = start;
+    loop {
+        let operations = operation_service::fetch_operations(tx, &portal, REPLAY_BATCH_SIZE)
+            .await
+            .map_err(handlers::report_postgres_err)?;
+        let done = operations.len() < REPLAY_BATCH_SIZE as usize;
+        replay_onto(&mut state, operations)?;
+        if done {
+            break;
+        }
+        if last_progress.elapsed() >= REPLAY_PROGRESS_INTERVAL {
+            log::info!(
+                "replaying checkpoint {}: {} ops so far",
+                state.checkpoint_id,
+                state.ops_since_checkpoint
+            );
+            last_progress = Instant::now();
+        }
+    }
+    // only long replays say they're done, since they're the ones that said they'd started
+    if last_progress != start {
+        log::info!(
+            "replayed {} ops onto checkpoint {} in {:?}",
+            state.ops_since_checkpoint,
+            state.checkpoint_id,
+            start.elapsed()
+        );
+    }
+    Ok(state)
+}
+
+// apply a checkpoint's ops to it
+pub fn replay(
+    checkpoint: db_types::Checkpoint,
+    operations: Vec<db_types::Operation>,
+) -> Result<LoadedState, AppError> {
+    // create snapshot from checkpoint
+    let snapshot = snapshot_codec::checkpoint_snapshot(&checkpoint)?;
+
+    let mut state = LoadedState {
+        snapshot,
+        checkpoint_id: checkpoint.checkpoint_id,
+        last_seq: 0,
+        ops_since_checkpoint: 0,
+    };
+    replay_onto(&mut state, operations)?;
+    Ok(state)
+}
+
+// apply ops that come after the state, in order
+fn replay_onto(
+    state: &mut LoadedState,
+    operations: Vec<db_types::Operation>,
+) -> Result<(), AppError> {
+    for x in operations {
+        let op = serde_json::from_str::<Op>(&x.jsonval)
+            .map_err(handlers::report_internal_serde_error)?;
+        apply_operation(&mut state.snapshot, op.kind);
+        // the seq of an op is its operation_id
+        state.last_seq = x.operation_id;
+        state.ops_since_checkpoint += 1;
+    }
+    Ok(())
+}
+
+// like load_state, but starts from the cached state if it's still based on the latest checkpoint
+async fn load_state_cached(
+    con: &mut deadpool_postgres::Client,
+    cache: &SnapshotCache,
+    user_id: i64,
+) -> Result<LoadedState, AppError> {
+    if let Some(mut state) = cache.get(user_id).await {
+        let checkpoint_id = checkpoint_service::get_recent_id_by_user_id(&mut *con, user_id)
+            .await
+            .map_err(handlers::report_postgres_err)?;
+        if checkpoint_id == Some(state.checkpoint_id) {
+            let operations = operation_service::get_operations_by_user_id_after(
+                &mut *con,
+                user_id,
+                state.last_seq,
+                i64::MAX,
+            )
+            .await
+            .map_err(handlers::report_postgres_err)?;
+            if !operations.is_empty() {
+                replay_onto(&mut state, operations)?;
+                cache.put(user_id, &state).await;
+            }
+            return Ok(state);
+        }
+    }
+
+    let state = load_state(con, user_id).await?;
+    // a state that's just the checkpoint is as quick to load from postgres
+    if state.ops_since_checkpoint > 0 {
+        cache.put(user_id, &state).await;
+    }
+    Ok(state)
+}
+
+async fn load(data: &AppData, user_id: i64) -> Result<PerUserWorkerData, AppError> {
+    // initialize connection
+    let mut con = data.pool.get().await.map_err(handlers::report_pool_err)?;
+
+    // so the worker's logs say which tenant the user belongs to
+    let tenant_user = tenant_user_service::get_by_user_id(&mut **con, user_id)
+        .await
+        .map_err(handlers::report_postgres_err)?;
+    tracing::Span::current().record("tenant_id", tenant_user.map(|x| x.tenant_id));
+
+    let LoadedState {
+        snapshot,
+        checkpoint_id,
+        last_seq,
+        ops_since_checkpoint,
+    } = match data.snapshot_cache {
+        Some(ref cache) => load_state_cached(&mut con, cache, user_id).await?,
+        None => load_state(&mut con, user_id).await?,
+    };
+
+    // create channel
+    let (updates_tx, _) = broadcast::channel(data.settings.get().broadcast_capacity);
+
+    let recent_idempotency_keys: VecDeque<String> = idempotency_key_service::get_recent_by_user_id(
+        &mut **con,
+        user_id,
+        RECENT_IDEMPOTENCY_KEYS as i64,
+    )
+    .await
+    .map_err(handlers::report_postgres_err)?
+    .into_iter()
+    .rev()
+    .map(|x| x.key)
+    .collect();
+
+    Ok(PerUserWorkerData {
+        user_id,
+        updates_tx,
+        checksum: snapshot_checksum(&snapshot),
+        snapshot,
+        checkpoint_id,
+        last_seq,
+        ops_since_checkpoint,
+        connections: 0,
+        devices: HashMap::new(),
+        last_activity_time: utils::current_time_millis(),
+        recent_idempotency_key_set: recent_idempotency_keys.iter().cloned().collect(),
+        recent_idempotency_keys,
+    })
+}
+
+impl PerUserWorkerData {
+    fn overwrite_state_op(&self) -> Op {
+        overwrite_state_op(self.snapshot.clone(), self.last_seq, self.checksum.clone())
+    }
+
+    // up to limit finished tasks after after_id, and whether there are more
+    // None if after_id isn't finished anymore
+    fn finished_chunk(
+        &self,
+        after_id: Option<&str>,
+        limit: usize,
+    ) -> Option<(Vec<FinishedTask>, bool)> {
+        let finished = &self.snapshot.finished;
+        let start = match after_id {
+            Some(id) => finished.iter().position(|x| x.id == id)? + 1,
+            None => 0,
+        };
+        let tasks = finished
+            .iter()
+            .skip(start)
+            .take(limit)
+            .cloned()
+            .collect::<Vec<_>>();
+        let more = start + tasks.len() < finished.len();
+        Some((tasks, more))
+    }
+
+    fn stats(&self) -> AdminUser {
+        AdminUser {
+            user_id: self.user_id,
+            connections: self.connections,
+            live_tasks: self.snapshot.live.len(),
+            finished_tasks: self.snapshot.finished.len(),
+            snapshot_bytes: serde_json::to_vec(&self.snapshot).unwrap().len(),
+            last_activity_time: self.last_activity_time,
+        }
+    }
+
+    fn join_presence(
+        &mut self,
+        session_id: String,
+        device: DeviceInfo,
+        max_devices: usize,
+    ) -> Option<Vec<ServerEvent>> {
+        if max_devices > 0 && self.devices.len() >= max_devices {
+            return None;
+        }
+        let others = self
+            .devices
+            .iter()
+            .map(|(session_id, device)| device.connected_event(session_id.clone()))
+            .collect();
+        let _ = self
+            .updates_tx
+            .send(Update::Event(device.connected_event(session_id.clone())));
+        self.devices.insert(session_id, device);
+        Some(others)
+    }
+
+    fn subscribe(&self) -> Subscription {
+        Subscription {
+            updates_rx: self.updates_tx.subscribe(),
+            initial_op: self.overwrite_state_op(),
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(seq))]
+    async fn apply_op(&mut self, data: &AppData, mut op: Op) -> Result<ApplyOutcome, AppError> {
+        // establish connection to database
+        let con: &mut deadpool_postgres::Client =
+            &mut data.pool.get().await.map_err(handlers::report_pool_err)?;
+        // the server is the source of truth for seq and checksum
+        op.seq = None;
+        op.checksum = None;
+        if let Err(reason) = check_value_encoding(&op) {
+            return Ok(ApplyOutcome::Rejected(reason));
+        }
+        // cheap check first, so most retries don't need the database
+        if let Some(ref key) = op.idempotency_key {
+            if self.recent_idempotency_key_set.contains(key) {
+                return Ok(ApplyOutcome::Duplicate);
+            }
+        }
+        let mut tx = con
+            .transaction()
+            .await
+            .map_err(handlers::report_postgres_err)?;
+        // ops other instances stored go first, so everyone applies them in the same order
+        if data.broadcast.is_shared() {
+            fanout::lock_user(&mut *tx, self.user_id)
+                .await
+                .map_err(handlers::report_postgres_err)?;
+            self.catch_up(&mut tx).await?;
+        }
+        // the key is recorded in the same transaction as the op, so a failed op can be retried
+        if let Some(ref key) = op.idempotency_key {
+            let added = idempotency_key_service::add(&mut *tx, self.user_id, key.clone())
+                .await
+                .map_err(handlers::report_postgres_err)?;
+            if added.is_none() {
+                return Ok(ApplyOutcome::Duplicate);
+            }
+        }
+        // the server picks ids for tasks that don't come with one
+        op.kind = match op.kind {
+            OpKind::Ext(ExtOpKind::InsLiveTaskNew { value }) => {
+                OpKind::Base(WebsocketOpKind::InsLiveTask {
+                    id: utils::random_string(),
+                    value,
+                })
+            }
+            kind => kind,
+        };
+        // templates are stored as the tasks they expanded to, so later edits don't change history
+        op.kind = match self.expand_template(&mut tx, op.kind).await? {
+            Some(kind) => kind,
+            None => return Ok(ApplyOutcome::Rejected(OpRejectReason::NoSuchTemplate)),
+        };
+        // ops that touch the trash are stored as the base op they amount to
+        op.kind = match self.move_through_trash(&mut tx, op.kind).await? {
+            Some(kind) => kind,
+            None => return Ok(ApplyOutcome::Rejected(OpRejectReason::NotInTrash)),
+        };
+        sanitize_values(&mut op.kind);
+        // dropping tx rolls back anything the trash did
+        if let Err(reason) = check_operation(&self.snapshot, &op.kind) {
+            return Ok(ApplyOutcome::Rejected(reason));
+        }
+        if let Err(reason) = check_limits(&self.snapshot, &op.kind, &data.task_limits) {
+            return Ok(ApplyOutcome::Rejected(reason));
+        }
+        let settings = data.settings.get();
+        if let Err(reason) =
+            quota::check(&mut *tx, self.user_id, &self.snapshot, &op.kind, &settings)
+                .await
+                .map_err(handlers::report_postgres_err)?
+        {
+            return Ok(ApplyOutcome::Rejected(reason));
+        }
+        if let Err(reason) = self.store_comment(&mut tx, &op.kind).await? {
+            return Ok(ApplyOutcome::Rejected(reason));
+        }
+        let streak_milestone = match self.store_habit(&mut tx, &op.kind).await? {
+            Ok(x) => x,
+            Err(reason) => return Ok(ApplyOutcome::Rejected(reason)),
+        };
+        if let Err(reason) = self.archive_finished(&mut tx, &op.kind).await? {
+            return Ok(ApplyOutcome::Rejected(reason));
+        }
+        if let Err(reason) = self.store_lane(&mut tx, &op.kind).await? {
+            return Ok(ApplyOutcome::Rejected(reason));
+        }
+        let goal_progress = match self.store_goal(&mut tx, &op.kind).await? {
+            Ok(x) => x,
+            Err(reason) => return Ok(ApplyOutcome::Rejected(reason)),
+        };
+        self.store_estimate(&mut tx, &op.kind).await?;
+        self.store_focus(&mut tx, &op.kind).await?;
+        self.finish_external(&mut tx, &op.kind).await?;
+        // add to db
+        let dbop = operation_service::add(&mut tx, self.checkpoint_id, op.clone())
+            .await
+            .map_err(handlers::report_postgres_err)?;
+        audit::record(
+            &mut *tx,
+            self.user_id,
+            &op.kind.name(),
+            Some(dbop.operation_id.to_string()),
+        )
+        .await
+        .map_err(handlers::report_postgres_err)?;
+        tx.commit().await.map_err(handlers::report_postgres_err)?;
+        // other instances find the op in the log anyway, the next time they're told to catch up
+        if let Err(e) = data.broadcast.publish(self.user_id).await {
+            log::error!("couldn't publish op for user {}: {e}", self.user_id);
+        }
+        // apply operation
+        apply_operation(&mut self.snapshot, op.kind.clone());
+        self.checksum = snapshot_checksum(&self.snapshot);
+        self.last_seq = dbop.operation_id;
+        self.ops_since_checkpoint += 1;
+        self.last_activity_time = utils::current_time_millis();
+        if let Some(ref key) = op.idempotency_key {
+            self.remember_idempotency_key(key.clone());
+        }
+        op.seq = Some(dbop.operation_id);
+        tracing::Span::current().record("seq", dbop.operation_id);
+        op.checksum = Some(self.checksum.clone());
+        // broadcast
+        let receivers = self.broadcast_op(op.clone());
+        tracing::info!(receivers, "broadcast op");
+        for event in goal_progress.into_iter().chain(streak_milestone) {
+            let _ = self.updates_tx.send(Update::Event(event));
+        }
+        // ops other instances stored are published by them
+        if let Some(ref events) = data.events {
+            events.emit(TaskEvent {
+                user_id: self.user_id,
+                seq: dbop.operation_id,
+                op: op.clone(),
+            });
+        }
+        Ok(ApplyOutcome::Applied(op))
+    }
+
+    // sends the op to every session, returning how many there were
+    // one-off connections (REST, webhooks, jobs) often have nobody to send to, so there's nothing
+    // worth serializing the op for
+    fn broadcast_op(&self, op: Op) -> usize {
+        let op = if self.updates_tx.receiver_count() > 0 {
+            BroadcastOp::new(op)
+        } else {
+            BroadcastOp::unserialized(op)
+        };
+        self.updates_tx.send(Update::Op(op)).unwrap_or(0)
+    }
+
+    // leaves the checkpoint to the job queue, which keeps retrying it
+    async fn enqueue_checkpoint(&self, data: &AppData) {
+        let result: Result<(), AppError> = try {
+            let con: &mut tokio_postgres::Client =
+                &mut *data.pool.get().await.map_err(handlers::report_pool_err)?;
+            let job = Job::Checkpoint {
+                user_id: self.user_id,
+            };
+            job_queue::enqueue(&mut *con, &job)
+                .await
+                .map_err(handlers::report_postgres_err)?
+        };
+        if let Err(e) = result {
+            log::error!("couldn't queue a checkpoint for user {}: {e}", self.user_id);
+        }
+    }
+
+    // apply and broadcast ops stored after last_seq, which other instances must have stored
+    async fn catch_up(&mut self, con: &mut impl GenericClient) -> Result<(), AppError> {
+        let operations = operation_service::get_operations_by_user_id_after(
+            &mut *con,
+            self.user_id,
+            self.last_seq,
+            i64::MAX,
+        )
+        .await
+        .map_err(handlers::report_postgres_err)?;
+
+        for x in operations {
+            let mut op = serde_json::from_str::<Op>(&x.jsonval)
+                .map_err(handlers::report_internal_serde_error)?;
+            apply_operation(&mut self.snapshot, op.kind.clone());
+            self.checksum = snapshot_checksum(&self.snapshot);
+            self.last_seq = x.operation_id;
+            self.ops_since_checkpoint += 1;
+            if let Some(ref key) = op.idempotency_key {
+                self.remember_idempotency_key(key.clone());
+            }
+            op.seq = Some(x.operation_id);
+            op.checksum = Some(self.checksum.clone());
+            self.broadcast_op(op);
+        }
+
+        // new ops have to be stored against the latest checkpoint, whoever wrote it
+        let recent_checkpoint = checkpoint_service::get_recent_by_user_id(&mut *con, self.user_id)
+            .await
+            .map_err(handlers::report_postgres_err)?;
+        if let Some(checkpoint) = recent_checkpoint {
+            if checkpoint.checkpoint_id != self.checkpoint_id {
+                self.checkpoint_id = checkpoint.checkpoint_id;
+                self.ops_since_checkpoint = 0;
+            }
+        }
+        Ok(())
+    }
+
+    fn remember_idempotency_key(&mut self, key: String) {
+        if self.recent_idempotency_keys.len() >= RECENT_IDEMPOTENCY_KEYS {
+            if let Some(oldest) = self.recent_idempotency_keys.pop_front() {
+                self.recent_idempotency_key_set.remove(&oldest);
+            }
+        }
+        self.recent_idempotency_key_set.insert(key.clone());
+        self.recent_idempotency_keys.push_back(key);
+    }
+
+    // expands templates into new tasks, or None if the user has no such template
+    async fn expand_template(
+        &self,
+        tx: &mut tokio_postgres::Transaction<'_>,
+        kind: OpKind,
+    ) -> Result<Option<OpKind>, AppError> {
+        match kind {
+            OpKind::Ext(ExtOpKind::InstantiateTemplate { template_id }) => {
+                let template = template_service::get_by_template_id(tx, self.user_id, template_id)
+                    .await
+                    .map_err(handlers::report_postgres_err)?;
+                let template = match template {
+                    Some(template) => serde_json::from_str::<TaskTemplate>(&template.jsonval)
+                        .map_err(handlers::report_internal_serde_error)?,
+                    None => return Ok(None),
+                };
+                let tasks = template_task_values(template)
+                    .into_iter()
+                    .map(|value| LiveTask {
+                        id: utils::random_string(),
+                        value,
+                    })
+                    .collect();
+                Ok(Some(OpKind::Ext(ExtOpKind::InsLiveTasks { tasks })))
+            }
+            kind => Ok(Some(kind)),
+        }
+    }
+
+    // comment ops are stored in the log like any other, and in task_comment so threads can be paged
+    async fn store_comment(
+        &self,
+        tx: &mut tokio_postgres::Transaction<'_>,
+        kind: &OpKind,
+    ) -> Result<Result<(), OpRejectReason>, AppError> {
+        match kind {
+            OpKind::Ext(ExtOpKind::CommentAdd { id, task_id, value }) => {
+                let added = task_comment_service::add(
+                    tx,
+                    self.user_id,
+                    id.clone(),
+                    task_id.clone(),
+                    value.clone(),
+                )
+                .await
+                .map_err(handlers::report_postgres_err)?;
+                Ok(added.map(|_| ()).ok_or(OpRejectReason::DuplicateId))
+            }
+            OpKind::Ext(ExtOpKind::CommentDel { id }) => {
+                let removed = task_comment_service::remove(tx, self.user_id, id)
+                    .await
+                    .map_err(handlers::report_postgres_err)?;
+                Ok(if removed {
+                    Ok(())
+                } else {
+                    Err(OpRejectReason::NoSuchComment)
+                })
+            }
+            _ => Ok(Ok(())),
+        }
+    }
+
+    // habit ops are stored in the log like any other, and in habit_definition and habit_check_in so
+    // habits and their streaks can be read without replaying it
+    // returns the milestone a check-in took the habit's streak to, if it reached one
+    async fn store_habit(
+        &self,
+        tx: &mut tokio_postgres::Transaction<'_>,
+        kind: &OpKind,
+    ) -> Result<Result<Option<ServerEvent>, OpRejectReason>, AppError> {
+        let user_id = self.user_id;
+        let mut milestone = None;
+        let found = match kind {
+            OpKind::Ext(ExtOpKind::HabitAdd { id, value }) => {
+                let added = habit_definition_service::add(tx, user_id, id.clone(), value.clone())
+                    .await
+                    .map_err(handlers::report_postgres_err)?;
+                return Ok(added.map(|_| None).ok_or(OpRejectReason::DuplicateId));
+            }
+            OpKind::Ext(ExtOpKind::HabitEdit { id, value }) => {
+                habit_definition_service::update(tx, user_id, id, value.clone())
+                    .await
+                    .map_err(handlers::report_postgres_err)?
+            }
+            OpKind::Ext(ExtOpKind::HabitDel { id }) => {
+                habit_check_in_service::remove_all_by_habit_id(tx, user_id, id)
+                    .await
+                    .map_err(handlers::report_postgres_err)?;
+                habit_definition_service::remove(tx, user_id, id)
+                    .await
+                    .map_err(handlers::report_postgres_err)?
+            }
+            OpKind::Ext(ExtOpKind::HabitCheckIn { id, day, done }) => {
+                let habit = habit_definition_service::get_by_habit_id(tx, user_id, id)
+                    .await
+                    .map_err(handlers::report_postgres_err)?;
+                if habit.is_some() {
+                    if *done {
+                        milestone = self.streak_milestone(tx, id, day).await?;
+                    }
+                    let result = match done {
+                        true => habit_check_in_service::add(tx, user_id, id, day).await,
+                        false => habit_check_in_service::remove(tx, user_id, id, day).await,
+                    };
+                    result.map_err(handlers::report_postgres_err)?;
+                }
+                habit.is_some()
+            }
+            _ => true,
+        };
+        Ok(if found {
+            Ok(milestone)
+        } else {
+            Err(OpRejectReason::NoSuchHabit)
+        })
+    }
+
+    // the highest milestone checking in on day takes the habit's streak to, if it takes it past one
+    // has to run before the check-in is stored
+    async fn streak_milestone(
+        &self,
+        tx: &mut tokio_postgres::Transaction<'_>,
+        habit_id: &str,
+        day: &str,
+    ) -> Result<Option<ServerEvent>, AppError> {
+        let parse = |x: &str| chrono::NaiveDate::parse_from_str(x, DAY_FORMAT).ok();
+        let Some(day) = parse(day) else {
+            return Ok(None);
+        };
+        let mut days = habit_check_in_service::get_all_by_habit_id(tx, self.user_id, habit_id)
+            .await
+            .map_err(handlers::report_postgres_err)?
+            .into_iter()
+            .filter_map(|x| parse(&x.day))
+            .collect::<HashSet<_>>();
+        let preferences = preferences::get(tx, self.user_id).await?;
+        let today = preferences::day_of(&preferences, chrono::Utc::now());
+        let before = stats::current_streak(&days, today);
+        days.insert(day);
+        let after = stats::current_streak(&days, today);
+        // checking in on a missed day can join two runs, passing more than one milestone at once
+        let milestone = STREAK_MILESTONES
+            .iter()
+            .rev()
+            .find(|&&x| before < x && x <= after);
+        Ok(milestone.map(|&days| ServerEvent::StreakMilestone {
+            habit_id: habit_id.to_owned(),
+            days,
+        }))
+    }
+
+    // estimates are stored in the log like any other op, where the burndown reads them from, and in
+    // task_estimate so the state can be sent with them
+    async fn store_estimate(
+        &self,
+        tx: &mut tokio_postgres::Transaction<'_>,
+        kind: &OpKind,
+    ) -> Result<(), AppError> {
+        if let OpKind::Ext(ExtOpKind::SetTaskEstimate { id, estimate }) = kind {
+            match estimate {
+                Some(estimate) => {
+                    task_estimate_service::set(tx, self.user_id, id.clone(), *estimate as i64)
+                        .await
+                        .map_err(handlers::report_postgres_err)?;
+                }
+                None => task_estimate_service::remove(tx, self.user_id, id)
+                    .await
+                    .map_err(handlers::report_postgres_err)?,
+            }
+        }
+        Ok(())
+    }
+
+    // focus changes are stored in the log like any other op, and in task_focus along with how long
+    // each task was focused for; focus ends by itself when the focused task stops being live
+    async fn store_focus(
+        &self,
+        tx: &mut tokio_postgres::Transaction<'_>,
+        kind: &OpKind,
+    ) -> Result<(), AppError> {
+        let now = utils::current_time_millis();
+        if let OpKind::Ext(ExtOpKind::SetFocusedTask { live_task_id }) = kind {
+            task_focus_service::unfocus(tx, self.user_id, now)
+                .await
+                .map_err(handlers::report_postgres_err)?;
+            if let Some(id) = live_task_id {
+                task_focus_service::focus(tx, self.user_id, id, now)
+                    .await
+                    .map_err(handlers::report_postgres_err)?;
+            }
+            return Ok(());
+        }
+        let may_remove = matches!(
+            kind,
+            OpKind::Base(
+                WebsocketOpKind::FinishLiveTask { .. }
+                    | WebsocketOpKind::DelLiveTask { .. }
+                    | WebsocketOpKind::OverwriteState(_)
+            ) | OpKind::Ext(ExtOpKind::FinishManyLiveTasks { .. })
+        );
+        if !may_remove {
+            return Ok(());
+        }
+        let focused = task_focus_service::get_focused_by_user_id(tx, self.user_id)
+            .await
+            .map_err(handlers::report_postgres_err)?;
+        if focused.is_some_and(|x| removes_live_task(kind, &x.task_id)) {
+            task_focus_service::unfocus(tx, self.user_id, now)
+                .await
+                .map_err(handlers::report_postgres_err)?;
+        }
+        Ok(())
+    }
+
+    // lane moves are stored in the log like any other op, and in task_lane so sessions can be sent them
+    // moves into a lane that's already at the user's limit for it are rejected
+    async fn store_lane(
+        &self,
+        tx: &mut tokio_postgres::Transaction<'_>,
+        kind: &OpKind,
+    ) -> Result<Result<(), OpRejectReason>, AppError> {
+        if let OpKind::Ext(ExtOpKind::MvLiveTaskToLane { id, lane }) = kind {
+            match lane {
+                Some(lane) => {
+                    let preferences = preferences::get(tx, self.user_id).await?;
+                    if let Some(&max) = preferences.lane_limits.get(lane) {
+                        // only live tasks count, and the task itself doesn't if it's already there
+                        let in_lane = task_lane_service::get_all_by_user_id(tx, self.user_id)
+                            .await
+                            .map_err(handlers::report_postgres_err)?
+                            .into_iter()
+                            .filter(|x| &x.lane == lane && &x.task_id != id)
+                            .filter(|x| self.snapshot.live.iter().any(|t| t.id == x.task_id))
+                            .count();
+                        if in_lane >= max {
+                            return Ok(Err(OpRejectReason::LaneFull {
+                                lane: lane.clone(),
+                                max,
+                            }));
+                        }
+                    }
+                    task_lane_service::set(tx, self.user_id, id.clone(), lane.clone())
+                        .await
+                        .map_err(handlers::report_postgres_err)?;
+                }
+                None => task_lane_service::remove(tx, self.user_id, id)
+                    .await
+                    .map_err(handlers::report_postgres_err)?,
+            }
+        }
+        Ok(Ok(()))
+    }
+
+    // goal links are stored in the log like any other op, and in task_goal along with whether each
+    // linked task was completed, which finishing, restoring and deleting tasks keep up to date
+    // returns the progress of the goals whose progress the op changed
+    async fn store_goal(
+        &self,
+        tx: &mut tokio_postgres::Transaction<'_>,
+        kind: &OpKind,
+    ) -> Result<Result<Vec<ServerEvent>, OpRejectReason>, AppError> {
+        let user_id = self.user_id;
+        let mut changed = vec![];
+        match kind {
+            OpKind::Ext(ExtOpKind::LinkTaskGoal { task_id, goal_id }) => {
+                let old = task_goal_service::get_by_task_id(tx, user_id, task_id)
+                    .await
+                    .map_err(handlers::report_postgres_err)?
+                    .map(|x| x.goal_id);
+                if old == *goal_id {
+                    return Ok(Ok(vec![]));
+                }
+                match goal_id {
+                    Some(goal_id) => {
+                        let goal = goal_service::get_by_goal_id(tx, user_id, *goal_id)
+                            .await
+                            .map_err(handlers::report_postgres_err)?;
+                        if goal.is_none() {
+                            return Ok(Err(OpRejectReason::NoSuchGoal));
+                        }
+                        let completed =
+                            self.snapshot.finished.iter().any(|x| {
+                                &x.id == task_id && matches!(x.status, TaskStatus::Succeeded)
+                            });
+                        task_goal_service::set(tx, user_id, task_id.clone(), *goal_id, completed)
+                            .await
+                            .map_err(handlers::report_postgres_err)?;
+                        changed.push(*goal_id);
+                    }
+                    None => {
+                        task_goal_service::remove(tx, user_id, task_id)
+                            .await
+                            .map_err(handlers::report_postgres_err)?;
+                    }
+                }
+                changed.extend(old);
+            }
+            OpKind::Base(WebsocketOpKind::FinishLiveTask { id, status }) => {
+                let completed = matches!(status, TaskStatus::Succeeded);
+                changed.extend(
+                    task_goal_service::set_completed(tx, user_id, id, completed)
+                        .await
+                        .map_err(handlers::report_postgres_err)?,
+                );
+            }
+            OpKind::Ext(ExtOpKind::FinishManyLiveTasks { ids, status }) => {
+                let completed = matches!(status, TaskStatus::Succeeded);
+                for id in ids {
+                    changed.extend(
+                        task_goal_service::set_completed(tx, user_id, id, completed)
+                            .await
+                            .map_err(handlers::report_postgres_err)?,
+                    );
+                }
+            }
+            OpKind::Base(WebsocketOpKind::RestoreFinishedTask { id }) => {
+                changed.extend(
+                    task_goal_service::set_completed(tx, user_id, id, false)
+                        .await
+                        .map_err(handlers::report_postgres_err)?,
+                );
+            }
+            OpKind::Base(WebsocketOpKind::DelLiveTask { id }) => {
+                changed.extend(
+                    task_goal_service::remove(tx, user_id, id)
+                        .await
+                        .map_err(handlers::report_postgres_err)?,
+                );
+            }
+            // tasks in the state being replaced follow it; ones only in the history are left alone
+            OpKind::Base(WebsocketOpKind::OverwriteState(snapshot)) => {
+                let links = task_goal_service::get_all_by_user_id(tx, user_id)
+                    .await
+                    .map_err(handlers::report_postgres_err)?;
+                for x in links {
+                    let in_state = |s: &StateSnapshot| {
+                        s.live.iter().any(|t| t.id == x.task_id)
+                            || s.finished.iter().any(|t| t.id == x.task_id)
+                    };
+                    if !in_state(&self.snapshot) {
+                        continue;
+                    }
+                    let result = if in_state(snapshot) {
+                        let completed = snapshot.finished.iter().any(|t| {
+                            t.id == x.task_id && matches!(t.status, TaskStatus::Succeeded)
+                        });
+                        task_goal_service::set_completed(tx, user_id, &x.task_id, completed).await
+                    } else {
+                        task_goal_service::remove(tx, user_id, &x.task_id).await
+                    };
+                    changed.extend(result.map_err(handlers::report_postgres_err)?);
+                }
+            }
+            _ => return Ok(Ok(vec![])),
+        }
+
+        changed.sort_unstable();
+        changed.dedup();
+        let mut events = vec![];
+        for goal_id in changed {
+            let (completed, total) = task_goal_service::get_progress(tx, goal_id)
+                .await
+                .map_err(handlers::report_postgres_err)?;
+            events.push(ServerEvent::GoalProgress {
+                goal_id,
+                completed,
+                total,
+            });
+        }
+        Ok(Ok(events))
+    }
+
+    // finishing a task that stands for a github issue queues a job to close or comment on the issue
+    // it's queued in the same transaction, so it happens if and only if the op is stored
+    async fn finish_external(
+        &self,
+        tx: &mut tokio_postgres::Transaction<'_>,
+        kind: &OpKind,
+    ) -> Result<(), AppError> {
+        let finished: Vec<(&String, &TaskStatus)> = match kind {
+            OpKind::Base(WebsocketOpKind::FinishLiveTask { id, status }) => vec![(id, status)],
+            OpKind::Ext(ExtOpKind::FinishManyLiveTasks { ids, status }) => {
+                ids.iter().map(|id| (id, status)).collect()
+            }
+            _ => return Ok(()),
+        };
+        for (id, status) in finished {
+            let external = external_task_service::get_by_task_id(tx, self.user_id, id)
+                .await
+                .map_err(handlers::report_postgres_err)?;
+            if let Some(external) = external.filter(|x| x.source == github::SOURCE) {
+                let job = Job::FinishGithubIssue {
+                    user_id: self.user_id,
+                    external_id: external.external_id,
+                    status: status.clone(),
+                };
+                job_queue::enqueue(tx, &job)
+                    .await
+                    .map_err(handlers::report_postgres_err)?;
+            }
+        }
+        Ok(())
+    }
+
+    // rolling over moves the finished tasks that aren't requeued into the history
+    // each day is only rolled over once, however many instances or clients try to
+    async fn archive_finished(
+        &self,
+        tx: &mut tokio_postgres::Transaction<'_>,
+        kind: &OpKind,
+    ) -> Result<Result<(), OpRejectReason>, AppError> {
+        if let OpKind::Ext(ExtOpKind::RolloverDay {
+            day,
+            requeue_failed,
+        }) = kind
+        {
+            let advanced = rollover_service::advance(tx, self.user_id, day)
+                .await
+                .map_err(handlers::report_postgres_err)?;
+            if advanced.is_none() {
+                return Ok(Err(OpRejectReason::AlreadyRolledOver));
+            }
+            for task in self.snapshot.finished.iter() {
+                if *requeue_failed && matches!(task.status, TaskStatus::Failed) {
+                    continue;
+                }
+                archived_task_service::add(tx, self.user_id, day.clone(), task.clone())
+                    .await
+                    .map_err(handlers::report_postgres_err)?;
+            }
+        }
+        Ok(Ok(()))
+    }
+
+    // moves soft deleted tasks into the trash and restored tasks out of it
+    async fn move_through_trash(
+        &self,
+        tx: &mut tokio_postgres::Transaction<'_>,
+        kind: OpKind,
+    ) -> Result<Option<OpKind>, AppError> {
+        let user_id = self.user_id;
+        match kind {
+            OpKind::Ext(ExtOpKind::DelLiveTaskSoft { id }) => {
+                if let Some(task) = self.snapshot.live.iter().find(|x| x.id == id) {
+                    deleted_task_service::add(tx, user_id, task.clone())
+                        .await
+                        .map_err(handlers::report_postgres_err)?;
+                }
+                Ok(Some(OpKind::Base(WebsocketOpKind::DelLiveTask { id })))
+            }
+            OpKind::Ext(ExtOpKind::RestoreDeletedTask { id }) => {
+                let deleted_task = deleted_task_service::get_recent_by_task_id(tx, user_id, &id)
+                    .await
+                    .map_err(handlers::report_postgres_err)?;
+                match deleted_task {
+                    Some(deleted_task) => {
+                        deleted_task_service::remove(tx, deleted_task.deleted_task_id)
+                            .await
+                            .map_err(handlers::report_postgres_err)?;
+                        Ok(Some(OpKind::Base(WebsocketOpKind::InsLiveTask {
+                            id,
+                            value: deleted_task.value,
+                        })))
+                    }
+                    None => Ok(None),
+                }
+            }
+            kind => Ok(Some(kind)),
+        }
+    }
+
+    // removes every row that belongs to the user, in one transaction
+    async fn delete_account(&self, data: &AppData) -> Result<AccountDelete, AppError> {
+        let con: &mut deadpool_postgres::Client =
+            &mut data.pool.get().await.map_err(handlers::report_pool_err)?;
+        let mut tx = con
+            .transaction()
+            .await
+            .map_err(handlers::report_postgres_err)?;
+
+        let user_id = self.user_id;
+        let result: Result<AccountDelete, tokio_postgres::Error> = try {
+            AccountDelete {
+                user_id,
+                deletion_time: utils::current_time_millis(),
+                // operations reference checkpoints, so they go first
+                operations: operation_service::remove_all_by_user_id(&mut tx, user_id).await?,
+                checkpoints: checkpoint_service::remove_all_by_user_id(&mut tx, user_id).await?,
+                deleted_tasks: deleted_task_service::remove_all_by_user_id(&mut *tx, user_id)
+                    .await?,
+                idempotency_keys: idempotency_key_service::remove_all_by_user_id(&mut *tx, user_id)
+                    .await?,
+                read_only_api_keys: read_only_api_key_service::remove_all_by_user_id(
+                    &mut *tx, user_id,
+                )
+                .await?,
+                api_tokens: api_token_service::remove_all_by_user_id(&mut *tx, user_id).await?,
+                push_subscriptions: push_subscription_service::remove_all_by_user_id(
+                    &mut *tx, user_id,
+                )
+                .await?,
+                reminders: reminder_service::remove_all_by_user_id(&mut *tx, user_id).await?,
+                templates: template_service::remove_all_by_user_id(&mut *tx, user_id).await?,
+                comments: task_comment_service::remove_all_by_user_id(&mut *tx, user_id).await?,
+                attachments: {
+                    let attachments =
+                        attachment_service::remove_all_by_user_id(&mut *tx, user_id).await?;
+                    // the objects are deleted once the rows are gone for good
+                    for x in &attachments {
+                        let job = Job::DeleteObject {
+                            object_key: x.object_key.clone(),
+                        };
+                        job_queue::enqueue(&mut *tx, &job).await?;
+                    }
+                    attachments.len() as u64
+                },
+                preferences: preference_service::remove_all_by_user_id(&mut *tx, user_id).await?,
+                archived_tasks: archived_task_service::remove_all_by_user_id(&mut *tx, user_id)
+                    .await?,
+                rollovers: rollover_service::remove_all_by_user_id(&mut *tx, user_id).await?,
+                task_lanes: task_lane_service::remove_all_by_user_id(&mut *tx, user_id).await?,
+                webhooks: webhook_service::remove_all_by_user_id(&mut *tx, user_id).await?,
+                email_aliases: email_alias_service::remove_all_by_user_id(&mut *tx, user_id)
+                    .await?,
+                github_links: github_link_service::remove_all_by_user_id(&mut *tx, user_id).await?,
+                external_tasks: external_task_service::remove_all_by_user_id(&mut *tx, user_id)
+                    .await?,
+                tenant_users: tenant_user_service::remove_all_by_user_id(&mut *tx, user_id).await?,
+                audit_events: audit_event_service::remove_all_by_user_id(&mut *tx, user_id).await?,
+                op_usage: op_usage_service::remove_all_by_user_id(&mut *tx, user_id).await?,
+                task_estimates: task_estimate_service::remove_all_by_user_id(&mut *tx, user_id)
+                    .await?,
+                task_focus: task_focus_service::remove_all_by_user_id(&mut *tx, user_id).await?,
+                // task_goal references goal, so it goes first
+                task_goals: task_goal_service::remove_all_by_user_id(&mut *tx, user_id).await?,
+                goals: goal_service::remove_all_by_user_id(&mut *tx, user_id).await?,
+                habits: habit_definition_service::remove_all_by_user_id(&mut *tx, user_id).await?,
+                habit_check_ins: habit_check_in_service::remove_all_by_user_id(&mut *tx, user_id)
+                    .await?,
+                notification_digests: notification_digest_service::remove_all_by_user_id(
+                    &mut *tx, user_id,
+                )
+                .await?,
+                known_devices: known_device_service::remove_all_by_user_id(&mut *tx, user_id)
+                    .await?,
+            }
+        };
+        let receipt = result.map_err(handlers::report_postgres_err)?;
+        tx.commit().await.map_err(handlers::report_postgres_err)?;
+        if let Some(ref cache) = data.snapshot_cache {
+            cache.invalidate(user_id).await;
+        }
+        Ok(receipt)
+    }
+
+    // nobody has used the worker for a while, so the next connect only has to read a single checkpoint
+    async fn evict_idle(&mut self, data: &AppData) {
+        if self.ops_since_checkpoint == 0 {
+            return;
+        }
+        let user_id = self.user_id;
+        match self.checkpoint(data).await {
+            Ok(checkpoint_id) => {
+                log::info!("wrote checkpoint {checkpoint_id} for idle user {user_id}")
+            }
+            // the op log is still complete, so the next connect will just have to replay it
+            Err(e) => {
+                log::error!("couldn't checkpoint idle user {user_id}: {e}");
+                self.enqueue_checkpoint(data).await;
+            }
+        }
+    }
+
+    // writes the snapshot as a new checkpoint, which later ops are recorded against
+    async fn checkpoint(&mut self, data: &AppData) -> Result<i64, AppError> {
+        let con: &mut deadpool_postgres::Client =
+            &mut data.pool.get().await.map_err(handlers::report_pool_err)?;
+        let mut tx = con
+            .transaction()
+            .await
+            .map_err(handlers::report_postgres_err)?;
+        // the checkpoint has to include every op stored before it, including other instances'
+        if data.broadcast.is_shared() {
+            fanout::lock_user(&mut *tx, self.user_id)
+                .await
+                .map_err(handlers::report_postgres_err)?;
+            self.catch_up(&mut tx).await?;
+        }
+        let checkpoint_id = checkpoint_service::add(&mut tx, self.user_id, self.snapshot.clone())
+            .await
+            .map_err(handlers::report_postgres_err)?
+            .checkpoint_id;
+        tx.commit().await.map_err(handlers::report_postgres_err)?;
+        // the cached state is based on the previous checkpoint
+        if let Some(ref cache) = data.snapshot_cache {
+            cache.invalidate(self.user_id).await;
+        }
+        self.checkpoint_id = checkpoint_id;
+        self.ops_since_checkpoint = 0;
+        Ok(checkpoint_id)
+    }
+}
+
+// the template's value with its tags and priority written in, then its checklist items
+fn template_task_values(template: TaskTemplate) -> Vec<String> {
+    let mut value = template.value;
+    // there's no writing into an encrypted value
+    if !value.starts_with(ENCRYPTED_VALUE_PREFIX) {
+        for tag in template.tags {
+            value.push_str(&format!(" #{tag}"));
+        }
+        match template.priority {
+            Some(Priority::Low) => value.push_str(" !low"),
+            Some(Priority::Medium) => value.push_str(" !medium"),
+            Some(Priority::High) => value.push_str(" !high"),
+            None => {}
+        }
+    }
+    std::iter::once(value).chain(template.checklist).collect()
+}
+
+// whether the op takes the task out of the live list
+fn removes_live_task(kind: &OpKind, id: &str) -> bool {
+    match kind {
+        OpKind::Base(WebsocketOpKind::FinishLiveTask { id: x, .. })
+        | OpKind::Base(WebsocketOpKind::DelLiveTask { id: x }) => x == id,
+        OpKind::Ext(ExtOpKind::FinishManyLiveTasks { ids, .. }) => ids.iter().any(|x| x == id),
+        OpKind::Base(WebsocketOpKind::OverwriteState(snapshot)) => {
+            !snapshot.live.iter().any(|x| x.id == id)
+        }
+        _ => false,
+    }
+}
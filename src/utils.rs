@@ -1,6 +1,9 @@
 use rand::distributions::Alphanumeric;
 use rand::Rng;
-use std::time::{SystemTime, UNIX_EPOCH};
+use sha2::{Digest, Sha256};
+use std::fmt::Display;
+use std::future::Future;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 pub fn current_time_millis() -> i64 {
     let since_the_epoch = SystemTime::now()
@@ -19,3 +22,74 @@ pub fn random_string() -> String {
         .collect();
     return s;
 }
+
+// hex encoded sha256 of bytes
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex(&Sha256::digest(bytes))
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// None if s isn't hex
+pub fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// whether s is standard base64, with padding
+pub fn is_base64(s: &str) -> bool {
+    let bytes = s.as_bytes();
+    if bytes.len() % 4 != 0 {
+        return false;
+    }
+    let data = s.trim_end_matches('=');
+    bytes.len() - data.len() <= 2
+        && data
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'+' || b == b'/')
+}
+
+// the longest prefix of s at most max_len bytes long
+pub fn truncate(s: &str, max_len: usize) -> &str {
+    let mut end = s.len().min(max_len);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+// calls f until it succeeds, doubling the delay between attempts (up to 10s)
+// gives up and returns the last error once the next attempt would start after timeout
+pub async fn retry_with_backoff<T, E, F, Fut>(
+    what: &str,
+    timeout: Duration,
+    mut f: F,
+) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: Display,
+{
+    let deadline = Instant::now() + timeout;
+    let mut delay = Duration::from_millis(250);
+    loop {
+        match f().await {
+            Ok(v) => return Ok(v),
+            Err(e) => {
+                if Instant::now() + delay > deadline {
+                    return Err(e);
+                }
+                log::warn!(target:"todoproxy::startup", "{} unavailable, retrying in {:?}: {}", what, delay, e);
+                tokio::time::sleep(delay).await;
+                delay = std::cmp::min(delay * 2, Duration::from_secs(10));
+            }
+        }
+    }
+}
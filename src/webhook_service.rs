@@ -0,0 +1,110 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for Webhook {
+    // select * from webhook order only, otherwise it will fail
+    fn from(row: tokio_postgres::Row) -> Webhook {
+        Webhook {
+            webhook_id: row.get("webhook_id"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+            token_hash: row.get("token_hash"),
+            source: row.get("source"),
+            max_per_hour: row.get("max_per_hour"),
+        }
+    }
+}
+
+#[tracing::instrument(skip(con, token_hash))]
+pub async fn add(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    token_hash: String,
+    source: Option<String>,
+    max_per_hour: i64,
+) -> Result<Webhook, tokio_postgres::Error> {
+    let row = con
+        .query_one(
+            "INSERT INTO
+             webhook(
+                 creator_user_id,
+                 token_hash,
+                 source,
+                 max_per_hour
+             )
+             VALUES($1, $2, $3, $4)
+             RETURNING webhook_id, creation_time
+            ",
+            &[&creator_user_id, &token_hash, &source, &max_per_hour],
+        )
+        .await?;
+
+    // return webhook
+    Ok(Webhook {
+        webhook_id: row.get(0),
+        creation_time: row.get(1),
+        creator_user_id,
+        token_hash,
+        source,
+        max_per_hour,
+    })
+}
+
+#[tracing::instrument(skip(con, token_hash))]
+pub async fn get_by_token_hash(
+    con: &mut impl GenericClient,
+    token_hash: &str,
+) -> Result<Option<Webhook>, tokio_postgres::Error> {
+    let result = con
+        .query_opt("SELECT * FROM webhook WHERE token_hash=$1", &[&token_hash])
+        .await?
+        .map(|x| x.into());
+    Ok(result)
+}
+
+// oldest first
+#[tracing::instrument(skip(con))]
+pub async fn get_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<Vec<Webhook>, tokio_postgres::Error> {
+    let result = con
+        .query(
+            "SELECT * FROM webhook WHERE creator_user_id = $1 ORDER BY webhook_id",
+            &[&creator_user_id],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+    Ok(result)
+}
+
+// returns whether the user had a webhook with that id
+#[tracing::instrument(skip(con))]
+pub async fn remove(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+    webhook_id: i64,
+) -> Result<bool, tokio_postgres::Error> {
+    let removed = con
+        .execute(
+            "DELETE FROM webhook WHERE creator_user_id = $1 AND webhook_id = $2",
+            &[&creator_user_id, &webhook_id],
+        )
+        .await?;
+    Ok(removed > 0)
+}
+
+// returns the number of rows removed
+#[tracing::instrument(skip(con))]
+pub async fn remove_all_by_user_id(
+    con: &mut impl GenericClient,
+    creator_user_id: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    con.execute(
+        "DELETE FROM webhook WHERE creator_user_id = $1",
+        &[&creator_user_id],
+    )
+    .await
+}
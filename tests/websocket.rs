@@ -0,0 +1,211 @@
+// drives real websocket clients against the server, checking every client ends up with the state
+// the server has
+mod common;
+
+use std::collections::HashSet;
+
+use common::{overwritten_state, Client, ServerState, TestServer};
+use todoproxy_api::{StateSnapshot, TaskStatus, WebsocketOpKind};
+
+fn ins(id: &str, value: &str) -> WebsocketOpKind {
+    WebsocketOpKind::InsLiveTask {
+        id: id.to_owned(),
+        value: value.to_owned(),
+    }
+}
+
+fn edit(id: &str, value: &str) -> WebsocketOpKind {
+    WebsocketOpKind::EditLiveTask {
+        id: id.to_owned(),
+        value: value.to_owned(),
+    }
+}
+
+fn live_ids(snapshot: &StateSnapshot) -> HashSet<&str> {
+    snapshot.live.iter().map(|x| x.id.as_str()).collect()
+}
+
+fn live_value<'a>(snapshot: &'a StateSnapshot, id: &str) -> Option<&'a str> {
+    snapshot
+        .live
+        .iter()
+        .find(|x| x.id == id)
+        .map(|x| x.value.as_str())
+}
+
+// edits the same task as everyone else, and adds some of its own
+async fn send_edits(client: &mut Client, name: &str, edits: usize) {
+    for i in 0..edits {
+        client.send(edit("shared", &format!("{name} {i}"))).await;
+        if i % 5 == 0 {
+            client.send(ins(&format!("{name} {i}"), name)).await;
+        }
+    }
+}
+
+fn assert_converged(client: &Client, state: &ServerState) {
+    assert_eq!(client.seq, state.seq);
+    assert_eq!(client.checksum, state.checksum);
+}
+
+#[tokio::test]
+async fn state_is_kept_across_connections() {
+    let Some(server) = TestServer::start(&[]).await else {
+        return;
+    };
+    let api_key = server.create_api_key(1).await;
+
+    let mut a = Client::connect(&server, &api_key, None).await;
+    let initial = overwritten_state(&a.recv_ops(1).await[0]).expect("no state was sent");
+    assert!(initial.live.is_empty() && initial.finished.is_empty());
+
+    a.send(ins("a", "first")).await;
+    a.send(ins("b", "second")).await;
+    a.send(ins("c", "third")).await;
+    a.send(WebsocketOpKind::FinishLiveTask {
+        id: "b".to_owned(),
+        status: TaskStatus::Succeeded,
+    })
+    .await;
+    a.recv_ops(4).await;
+    let state = server.state(&api_key).await;
+    assert_converged(&a, &state);
+    a.close().await;
+
+    // a new connection starts from what the last one left
+    let mut b = Client::connect(&server, &api_key, None).await;
+    let snapshot = overwritten_state(&b.recv_ops(1).await[0]).expect("no state was sent");
+    assert_eq!(live_ids(&snapshot), HashSet::from(["a", "c"]));
+    assert_eq!(snapshot.finished.len(), 1);
+    assert_eq!(snapshot.finished[0].id, "b");
+    assert_converged(&b, &state);
+}
+
+#[tokio::test]
+async fn reconnecting_client_is_sent_only_what_it_missed() {
+    let Some(server) = TestServer::start(&[]).await else {
+        return;
+    };
+    let api_key = server.create_api_key(1).await;
+
+    let mut a = Client::connect(&server, &api_key, None).await;
+    let mut b = Client::connect(&server, &api_key, None).await;
+    a.recv_ops(1).await;
+    b.recv_ops(1).await;
+
+    a.send(ins("a", "first")).await;
+    a.send(ins("b", "second")).await;
+    a.recv_ops(2).await;
+    b.recv_ops(2).await;
+    let resume_seq = b.seq;
+    b.close().await;
+
+    a.send(edit("a", "first, edited")).await;
+    a.send(ins("c", "third")).await;
+    a.send(WebsocketOpKind::DelLiveTask { id: "b".to_owned() })
+        .await;
+    a.recv_ops(3).await;
+
+    let mut b = Client::connect(&server, &api_key, Some(resume_seq)).await;
+    let missed = b.recv_ops(3).await;
+    assert!(missed.iter().all(|x| overwritten_state(x).is_none()));
+    assert!(missed
+        .iter()
+        .all(|x| x["seq"].as_i64().unwrap() > resume_seq));
+
+    let state = server.state(&api_key).await;
+    assert_converged(&a, &state);
+    assert_converged(&b, &state);
+    assert_eq!(live_ids(&state.snapshot), HashSet::from(["a", "c"]));
+}
+
+#[tokio::test]
+async fn concurrent_edits_converge() {
+    let Some(server) = TestServer::start(&[]).await else {
+        return;
+    };
+    let api_key = server.create_api_key(1).await;
+    const EDITS: usize = 25;
+
+    let mut a = Client::connect(&server, &api_key, None).await;
+    let mut b = Client::connect(&server, &api_key, None).await;
+    a.recv_ops(1).await;
+    b.recv_ops(1).await;
+
+    a.send(ins("shared", "")).await;
+    a.recv_ops(1).await;
+    b.recv_ops(1).await;
+
+    // neither waits to hear about the other's ops
+    tokio::join!(
+        send_edits(&mut a, "a", EDITS),
+        send_edits(&mut b, "b", EDITS)
+    );
+
+    let ops = 2 * (EDITS + EDITS.div_ceil(5));
+    let a_ops = a.recv_ops(ops).await.to_vec();
+    let b_ops = b.recv_ops(ops).await.to_vec();
+    // everyone sees the ops in the same order
+    assert_eq!(a_ops, b_ops);
+
+    let state = server.state(&api_key).await;
+    assert_converged(&a, &state);
+    assert_converged(&b, &state);
+    // the last edit applied wins
+    let last_edit = a_ops
+        .iter()
+        .rev()
+        .find_map(|x| match serde_json::from_value(x["kind"].clone()) {
+            Ok(WebsocketOpKind::EditLiveTask { value, .. }) => Some(value),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(
+        live_value(&state.snapshot, "shared"),
+        Some(last_edit.as_str())
+    );
+    assert_eq!(state.snapshot.live.len(), 1 + 2 * EDITS.div_ceil(5));
+}
+
+#[tokio::test]
+async fn lagging_client_is_resynced() {
+    let Some(server) = TestServer::start(&["--outbound-high-water-mark", "5"]).await else {
+        return;
+    };
+    let api_key = server.create_api_key(1).await;
+    // big enough that the server can't get them all into the socket while b isn't reading
+    const EDITS: usize = 300;
+    const VALUE_LEN: usize = 60_000;
+
+    let mut a = Client::connect(&server, &api_key, None).await;
+    let mut b = Client::connect(&server, &api_key, None).await;
+    a.recv_ops(1).await;
+    b.recv_ops(1).await;
+
+    a.send(ins("big", "")).await;
+    a.recv_ops(1).await;
+    b.recv_ops(1).await;
+
+    // a keeps up, so the server isn't stuck sending to it while it's sending
+    for i in 0..EDITS {
+        a.send(edit("big", &format!("{i:0>VALUE_LEN$}"))).await;
+        a.recv_ops(1).await;
+    }
+    let state = server.state(&api_key).await;
+    let last_value = format!("{:0>VALUE_LEN$}", EDITS - 1);
+    assert_eq!(
+        live_value(&state.snapshot, "big"),
+        Some(last_value.as_str())
+    );
+
+    b.recv_until(state.seq).await;
+    assert_converged(&a, &state);
+    assert_converged(&b, &state);
+    // b's first state, then the one it was resynced with
+    let resyncs = b
+        .ops
+        .iter()
+        .filter(|x| overwritten_state(x).is_some())
+        .count();
+    assert!(resyncs > 1, "b was never resynced");
+}
@@ -0,0 +1,370 @@
+// how ops change a user's state, shared by the server and the web app so the two can't disagree
+// about what a sequence of ops does
+// nothing here may need tokio, the network or the clock, since it's also built for wasm32
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+
+pub use todoproxy_api::{FinishedTask, LiveTask, StateSnapshot, TaskStatus, WebsocketOpKind};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ExtOpKind {
+    // removes every task from the finished list
+    ClearFinishedTasks,
+    // finishes all of the listed live tasks with the same status
+    FinishManyLiveTasks {
+        ids: Vec<String>,
+        status: TaskStatus,
+    },
+    // moves the block of live tasks between id_first and id_last (inclusive) to the position of id_ins
+    MvLiveTaskRange {
+        id_first: String,
+        id_last: String,
+        id_ins: String,
+    },
+    // inserts a live task at the front with an id picked by the server
+    // stored and broadcast as an InsLiveTask, and the sender gets the id in an OpAck
+    InsLiveTaskNew {
+        value: String,
+    },
+    // inserts a live task between two neighbours, rather than at the front
+    // anchoring on ids means concurrent edits from other devices can't shift where it lands:
+    // it goes right after `after` if that still exists, otherwise right before `before`,
+    // otherwise at the front
+    InsLiveTaskBetween {
+        id: String,
+        value: String,
+        after: Option<String>,
+        before: Option<String>,
+    },
+    // moves a live task between two neighbours, placed the same way as InsLiveTaskBetween
+    MvLiveTaskBetween {
+        id: String,
+        after: Option<String>,
+        before: Option<String>,
+    },
+    // deletes a live task, keeping it in the trash so it can be restored
+    // stored and broadcast as a DelLiveTask
+    DelLiveTaskSoft {
+        id: String,
+    },
+    // moves the most recently deleted task with this id back to the front of the live list
+    // stored and broadcast as an InsLiveTask
+    RestoreDeletedTask {
+        id: String,
+    },
+    // inserts the tasks one of the user's templates expands to at the front
+    // stored and broadcast as an InsLiveTasks
+    InstantiateTemplate {
+        template_id: i64,
+    },
+    // inserts several live tasks at the front at once, the first one ending up frontmost
+    InsLiveTasks {
+        tasks: Vec<LiveTask>,
+    },
+    // comments on a live or finished task; id is picked by the client, like task ids
+    // comments are kept in the database rather than the snapshot
+    CommentAdd {
+        id: String,
+        task_id: String,
+        value: String,
+    },
+    CommentDel {
+        id: String,
+    },
+    // ends the user's day: the finished list is moved into their history under day (yyyy-mm-dd)
+    // if requeue_failed is set, failed tasks go back to the front of the live list instead
+    // the server sends these at the start of each day for users who turn rollover on
+    RolloverDay {
+        day: String,
+        requeue_failed: bool,
+    },
+    // moves a live task into a kanban lane, or back to the default lane if lane is unset
+    // lanes are kept in the database; the task keeps its place in the live list,
+    // so clients without lanes still see every live task in one list
+    MvLiveTaskToLane {
+        id: String,
+        lane: Option<String>,
+    },
+    // sets how much effort a live task is estimated to take, in whatever unit the user plans in
+    // (e.g. story points), or clears it if estimate is unset
+    // estimates are kept in the database, like lanes
+    SetTaskEstimate {
+        id: String,
+        estimate: Option<u32>,
+    },
+    // marks the live task the user is working on now, so every device can highlight it, or
+    // clears it if live_task_id is unset; a focused task that stops being live isn't focused
+    // the focus, and how long each task was focused for, are kept in the database
+    SetFocusedTask {
+        live_task_id: Option<String>,
+    },
+    // counts a live or finished task toward one of the user's goals, or toward none if goal_id is
+    // unset; a task counts toward at most one goal
+    // links are kept in the database, and deleted tasks are unlinked
+    LinkTaskGoal {
+        task_id: String,
+        goal_id: Option<i64>,
+    },
+    // adds a habit, which is checked in on each day it's done rather than finished
+    // id is picked by the client, like task ids; habits are kept in the database, like comments
+    HabitAdd {
+        id: String,
+        value: String,
+    },
+    HabitEdit {
+        id: String,
+        value: String,
+    },
+    // removes the habit along with its check-ins
+    HabitDel {
+        id: String,
+    },
+    // marks the habit as done on day (yyyy-mm-dd, in the user's time zone), or as not done
+    HabitCheckIn {
+        id: String,
+        day: String,
+        done: bool,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum OpKind {
+    Base(WebsocketOpKind),
+    Ext(ExtOpKind),
+}
+
+impl OpKind {
+    // the variant's name, e.g. InsLiveTask
+    pub fn name(&self) -> String {
+        match serde_json::to_value(self).unwrap() {
+            serde_json::Value::String(name) => name,
+            serde_json::Value::Object(x) => x.keys().next().cloned().unwrap_or_default(),
+            _ => String::new(),
+        }
+    }
+}
+
+// hex encoded sha256 of the snapshot's json serialization
+pub fn snapshot_checksum(snapshot: &StateSnapshot) -> String {
+    let jsonval = serde_json::to_string(snapshot).unwrap();
+    Sha256::digest(jsonval.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub fn apply_operation(snapshot: &mut StateSnapshot, op: OpKind) {
+    match op {
+        OpKind::Base(op) => apply_base_operation(snapshot, op),
+        OpKind::Ext(op) => apply_ext_operation(snapshot, op),
+    }
+}
+
+fn apply_base_operation(
+    StateSnapshot {
+        ref mut finished,
+        ref mut live,
+    }: &mut StateSnapshot,
+    op: WebsocketOpKind,
+) {
+    match op {
+        WebsocketOpKind::OverwriteState(s) => {
+            *live = s.live;
+            *finished = s.finished;
+        }
+        WebsocketOpKind::InsLiveTask { value, id } => {
+            live.push_front(LiveTask { id, value });
+        }
+        WebsocketOpKind::RestoreFinishedTask { id } => {
+            // if it was found in the finished list, push it to the front
+            if let Some(position) = finished.iter().position(|x| x.id == id) {
+                let FinishedTask { id, value, .. } = finished.remove(position).unwrap();
+                live.push_front(LiveTask { id, value });
+            }
+        }
+        WebsocketOpKind::EditLiveTask { id, value } => {
+            for x in live.iter_mut() {
+                if x.id == id {
+                    x.value = value;
+                    break;
+                }
+            }
+        }
+        WebsocketOpKind::DelLiveTask { id } => {
+            live.retain(|x| x.id != id);
+        }
+        WebsocketOpKind::MvLiveTask { id_ins, id_del } => {
+            let ins_pos = live.iter().position(|x| x.id == id_ins);
+            let del_pos = live.iter().position(|x| x.id == id_del);
+
+            if let (Some(ins_pos), Some(del_pos)) = (ins_pos, del_pos) {
+                let removed = live.remove(del_pos).unwrap();
+                live.insert(ins_pos, removed);
+            }
+        }
+        WebsocketOpKind::RevLiveTask { id1, id2 } => {
+            let pos1 = live.iter().position(|x| x.id == id1);
+            let pos2 = live.iter().position(|x| x.id == id2);
+
+            // order
+            let (start_pos, end_pos) = if pos1 <= pos2 {
+                (pos1, pos2)
+            } else {
+                (pos2, pos1)
+            };
+
+            // reverse between specified indexes
+            if let (Some(start_pos), Some(end_pos)) = (start_pos, end_pos) {
+                live.make_contiguous();
+                let (s, _) = live.as_mut_slices();
+                s[start_pos..=end_pos].reverse();
+            }
+        }
+        WebsocketOpKind::FinishLiveTask { id, status } => {
+            if let Some(pos_in_live) = live.iter().position(|x| x.id == id) {
+                finished.push_front(FinishedTask {
+                    id,
+                    value: live.remove(pos_in_live).unwrap().value,
+                    status,
+                });
+            }
+        }
+    }
+}
+
+fn apply_ext_operation(
+    StateSnapshot {
+        ref mut finished,
+        ref mut live,
+    }: &mut StateSnapshot,
+    op: ExtOpKind,
+) {
+    match op {
+        ExtOpKind::ClearFinishedTasks => {
+            finished.clear();
+        }
+        ExtOpKind::InsLiveTaskBetween {
+            id,
+            value,
+            after,
+            before,
+        } => {
+            let pos = position_between(live, after, before);
+            live.insert(pos, LiveTask { id, value });
+        }
+        ExtOpKind::MvLiveTaskBetween { id, after, before } => {
+            // moving a task next to itself is a no-op
+            if after.as_ref() == Some(&id) || before.as_ref() == Some(&id) {
+                return;
+            }
+            if let Some(pos) = live.iter().position(|x| x.id == id) {
+                let task = live.remove(pos).unwrap();
+                let pos = position_between(live, after, before);
+                live.insert(pos, task);
+            }
+        }
+        // the server picks the id and stores it as an InsLiveTask, so it's never in the log
+        // or sent to clients as it is
+        ExtOpKind::InsLiveTaskNew { .. } => {}
+        ExtOpKind::DelLiveTaskSoft { id } => {
+            live.retain(|x| x.id != id);
+        }
+        // the task's value is only in the trash
+        ExtOpKind::RestoreDeletedTask { .. } => {}
+        // and the template's values are only in the database
+        ExtOpKind::InstantiateTemplate { .. } => {}
+        // as are comments and lanes
+        ExtOpKind::CommentAdd { .. } | ExtOpKind::CommentDel { .. } => {}
+        ExtOpKind::MvLiveTaskToLane { .. } => {}
+        ExtOpKind::SetTaskEstimate { .. } => {}
+        ExtOpKind::SetFocusedTask { .. } => {}
+        ExtOpKind::LinkTaskGoal { .. } => {}
+        ExtOpKind::HabitAdd { .. }
+        | ExtOpKind::HabitEdit { .. }
+        | ExtOpKind::HabitDel { .. }
+        | ExtOpKind::HabitCheckIn { .. } => {}
+        ExtOpKind::RolloverDay { requeue_failed, .. } => {
+            // the rest are only in the history now
+            let requeued = finished
+                .drain(..)
+                .filter(|x| requeue_failed && matches!(x.status, TaskStatus::Failed))
+                .map(|x| LiveTask {
+                    id: x.id,
+                    value: x.value,
+                })
+                .collect::<Vec<_>>();
+            for task in requeued.into_iter().rev() {
+                live.push_front(task);
+            }
+        }
+        ExtOpKind::InsLiveTasks { tasks } => {
+            for task in tasks.into_iter().rev() {
+                live.push_front(task);
+            }
+        }
+        ExtOpKind::FinishManyLiveTasks { ids, status } => {
+            // finish in the order given, so the last id ends up at the front of the finished list
+            for id in ids {
+                if let Some(pos_in_live) = live.iter().position(|x| x.id == id) {
+                    finished.push_front(FinishedTask {
+                        id,
+                        value: live.remove(pos_in_live).unwrap().value,
+                        status: status.clone(),
+                    });
+                }
+            }
+        }
+        ExtOpKind::MvLiveTaskRange {
+            id_first,
+            id_last,
+            id_ins,
+        } => {
+            let first_pos = live.iter().position(|x| x.id == id_first);
+            let last_pos = live.iter().position(|x| x.id == id_last);
+            let ins_pos = live.iter().position(|x| x.id == id_ins);
+
+            if let (Some(first_pos), Some(last_pos), Some(ins_pos)) = (first_pos, last_pos, ins_pos)
+            {
+                // order
+                let (start_pos, end_pos) = if first_pos <= last_pos {
+                    (first_pos, last_pos)
+                } else {
+                    (last_pos, first_pos)
+                };
+
+                // moving a block into itself is a no-op
+                if ins_pos >= start_pos && ins_pos <= end_pos {
+                    return;
+                }
+
+                let block: Vec<LiveTask> = live.drain(start_pos..=end_pos).collect();
+                // like MvLiveTask, the block ends up occupying the position id_ins had
+                let dest = if ins_pos < start_pos {
+                    ins_pos
+                } else {
+                    ins_pos + 1 - block.len()
+                };
+                for (i, task) in block.into_iter().enumerate() {
+                    live.insert(dest + i, task);
+                }
+            }
+        }
+    }
+}
+
+// where a task anchored between after and before should be inserted into live
+fn position_between(
+    live: &VecDeque<LiveTask>,
+    after: Option<String>,
+    before: Option<String>,
+) -> usize {
+    if let Some(pos) = after.and_then(|after| live.iter().position(|x| x.id == after)) {
+        return pos + 1;
+    }
+    if let Some(pos) = before.and_then(|before| live.iter().position(|x| x.id == before)) {
+        return pos;
+    }
+    0
+}